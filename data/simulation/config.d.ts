@@ -6,6 +6,11 @@ export interface Config {
   "relay-strategy": RelayStrategy;
   /** Only supported by Haskell simulation. */
   "tcp-congestion-control": boolean;
+  /**
+   * Whether to model TCP slow start and congestion control on every link,
+   * rather than sending at the full link bandwidth straight away.
+   * Only supported by Rust simulation. */
+  "rust-tcp-congestion-control": boolean;
   /** Only supported by Haskell simulation. */
   "multiplex-mini-protocols": boolean;
   /** Only supported by Rust simulation. */
@@ -26,6 +31,10 @@ export interface Config {
    * The smallest unit of time to simulate, in fractions of a millisecond.
    * Only supported by Rust simulation. */
   "timestamp-resolution-ms": number;
+  /**
+   * How nodes decide whether they have won the lotteries to produce RBs, IBs, EBs and votes.
+   * Only supported by Rust simulation. */
+  "lottery-model": LotteryModel;
 
   // Leios Protocol Configuration
   "leios-variant": LeiosVariant;
@@ -80,8 +89,22 @@ export interface Config {
    * 0 means the transaction is not over-collateralized, n means it has enough extra collateral to be included in n shards.
    * Only supported by Rust simulation.  */
   "tx-overcollateralization-factor-distribution": Distribution;
+  /**
+   * Distribution used to choose how many script execution steps a transaction uses.
+   * Transactions run no scripts if not set.
+   * Only supported by Rust simulation.  */
+  "tx-execution-steps-distribution"?: Distribution | null;
+  /**
+   * Distribution used to choose how many script memory units a transaction uses.
+   * Transactions run no scripts if not set.
+   * Only supported by Rust simulation.  */
+  "tx-execution-memory-distribution"?: Distribution | null;
   /** Only supported by Rust simulation. */
-  "tx-validation-cpu-time-ms": number;
+  "tx-validation-cpu-time-ms": CpuTime;
+  /**
+   * Extra CPU time to validate a transaction, per million script execution steps it uses.
+   * Only supported by Rust simulation.  */
+  "tx-validation-cpu-time-ms-per-million-steps"?: CpuTime | null;
   /** Only supported by Rust simulation. */
   "tx-max-size-bytes": bigint;
   /**
@@ -96,20 +119,36 @@ export interface Config {
    * The cutoff time after which transactions should not appear.
    * Only supported by Rust simulation.  */
   "tx-stop-time"?: number | null;
+  /**
+   * Segments which change the rate of transaction generation, and which nodes generate them, over time.
+   * Overlapping segments multiply together.
+   * Only supported by Rust simulation.  */
+  "tx-load-profile"?: TxLoadSegment[] | null;
 
   // Ranking Block Configuration
   "rb-generation-probability": number;
-  "rb-generation-cpu-time-ms": number;
-  "rb-head-validation-cpu-time-ms": number;
+  /** Distributions are only supported by Rust simulation. */
+  "rb-generation-cpu-time-ms": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "rb-head-validation-cpu-time-ms": CpuTime;
   "rb-head-size-bytes": bigint;
   "rb-body-max-size-bytes": bigint;
-  "rb-body-legacy-praos-payload-validation-cpu-time-ms-constant": number;
-  "rb-body-legacy-praos-payload-validation-cpu-time-ms-per-byte": number;
+  /**
+   * The maximum total script execution steps and memory units of transactions in an RB body.
+   * Unlimited if not set.
+   * Only supported by Rust simulation.  */
+  "rb-body-max-execution-steps"?: bigint | null;
+  "rb-body-max-execution-memory"?: bigint | null;
+  /** Distributions are only supported by Rust simulation. */
+  "rb-body-legacy-praos-payload-validation-cpu-time-ms-constant": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "rb-body-legacy-praos-payload-validation-cpu-time-ms-per-byte": CpuTime;
   "rb-body-legacy-praos-payload-avg-size-bytes": bigint;
 
   // Input Block Configuration
   "ib-generation-probability": number;
-  "ib-generation-cpu-time-ms": number;
+  /** Distributions are only supported by Rust simulation. */
+  "ib-generation-cpu-time-ms": CpuTime;
   /**
    * The total number of shards available for IBs.
    * Must be divisible by ib_shard_group_count.
@@ -129,12 +168,21 @@ export interface Config {
    * Only supported by Rust simulation. */
   "ib-shard-group-count": number;
   "ib-head-size-bytes": bigint;
-  "ib-head-validation-cpu-time-ms": number;
-  "ib-body-validation-cpu-time-ms-constant": number;
-  "ib-body-validation-cpu-time-ms-per-byte": number;
+  /** Distributions are only supported by Rust simulation. */
+  "ib-head-validation-cpu-time-ms": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "ib-body-validation-cpu-time-ms-constant": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "ib-body-validation-cpu-time-ms-per-byte": CpuTime;
   "ib-body-avg-size-bytes": bigint;
   /** Only supported by Rust simulation. */
   "ib-body-max-size-bytes": bigint;
+  /**
+   * The maximum total script execution steps and memory units of transactions in an IB.
+   * Unlimited if not set.
+   * Only supported by Rust simulation.  */
+  "ib-body-max-execution-steps"?: bigint | null;
+  "ib-body-max-execution-memory"?: bigint | null;
   "ib-diffusion-strategy": DiffusionStrategy;
   /** Only supported by Haskell simulation. */
   "ib-diffusion-max-window-size": bigint;
@@ -144,8 +192,10 @@ export interface Config {
 
   // Endorsement Block Configuration
   "eb-generation-probability": number;
-  "eb-generation-cpu-time-ms": number;
-  "eb-validation-cpu-time-ms": number;
+  /** Distributions are only supported by Rust simulation. */
+  "eb-generation-cpu-time-ms": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "eb-validation-cpu-time-ms": CpuTime;
   "eb-size-bytes-constant": bigint;
   "eb-size-bytes-per-ib": bigint;
   /** Only supported by Haskell simulation. */
@@ -186,15 +236,45 @@ export interface Config {
    * Only supported by Rust simulation.
    */
   "eb-referenced-txs-max-size-bytes": bigint;
+  /**
+   * The maximum total script execution steps and memory units of transactions which an EB can reference.
+   * Unlimited if not set.
+   * Only relevant when running with the "full-without-ibs" variant.
+   * Only supported by Rust simulation.  */
+  "eb-referenced-txs-max-execution-steps"?: bigint | null;
+  "eb-referenced-txs-max-execution-memory"?: bigint | null;
 
   // Vote Configuration
   "vote-generation-probability": number;
-  "vote-generation-cpu-time-ms-constant": number;
-  "vote-generation-cpu-time-ms-per-ib": number;
-  "vote-validation-cpu-time-ms": number;
+  /** Distributions are only supported by Rust simulation. */
+  "vote-generation-cpu-time-ms-constant": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "vote-generation-cpu-time-ms-per-ib": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "vote-validation-cpu-time-ms": CpuTime;
   "vote-threshold": bigint;
   "vote-bundle-size-bytes-constant": bigint;
   "vote-bundle-size-bytes-per-eb": bigint;
+  /**
+   * How the voting committee is chosen.
+   * Only supported by Rust simulation. */
+  "vote-committee-selection": VoteCommitteeSelection;
+  /**
+   * The number of seats on the voting committee.
+   * The committee is chosen once, and sits for the whole run.
+   * Only used with fait-accompli vote committee selection.
+   * Only supported by Rust simulation. */
+  "vote-committee-size": bigint;
+  /**
+   * The size of a persistent voter's vote for one EB.
+   * Only used with fait-accompli vote committee selection.
+   * Only supported by Rust simulation. */
+  "vote-bundle-size-bytes-per-eb-persistent": bigint;
+  /**
+   * The size of a non-persistent voter's vote for one EB.
+   * Only used with fait-accompli vote committee selection.
+   * Only supported by Rust simulation. */
+  "vote-bundle-size-bytes-per-eb-nonpersistent": bigint;
   /** Only supported by Haskell simulation. */
   "vote-diffusion-strategy": DiffusionStrategy;
   /** Only supported by Haskell simulation. */
@@ -205,12 +285,26 @@ export interface Config {
   "vote-diffusion-max-bodies-to-request": bigint;
 
   // Certificate Configuration
-  "cert-generation-cpu-time-ms-constant": number;
-  "cert-generation-cpu-time-ms-per-node": number;
-  "cert-validation-cpu-time-ms-constant": number;
-  "cert-validation-cpu-time-ms-per-node": number;
+  /** Distributions are only supported by Rust simulation. */
+  "cert-generation-cpu-time-ms-constant": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "cert-generation-cpu-time-ms-per-node": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "cert-validation-cpu-time-ms-constant": CpuTime;
+  /** Distributions are only supported by Rust simulation. */
+  "cert-validation-cpu-time-ms-per-node": CpuTime;
   "cert-size-bytes-constant": bigint;
   "cert-size-bytes-per-node": bigint;
+  /**
+   * The size of a certificate without any non-persistent voters,
+   * not counting the bitset of persistent voters.
+   * Only used with fait-accompli vote committee selection.
+   * Only supported by Rust simulation. */
+  "cert-size-bytes-fait-accompli-constant": bigint;
+  /**
+   * Only used with fait-accompli vote committee selection.
+   * Only supported by Rust simulation. */
+  "cert-size-bytes-per-nonpersistent-voter": bigint;
 }
 
 export type CleanupPolicies = "all" | CleanupPolicy[];
@@ -222,11 +316,23 @@ export type CleanupPolicy =
   | "cleanup-expired-vote"
   | "cleanup-expired-certificate";
 
+/**
+ * A CPU cost in milliseconds.
+ * Distributions are sampled for every task, and are only supported by Rust simulation.
+ */
+export type CpuTime = number | Distribution;
+
 export type Distribution =
   | NormalDistribution
   | ExpDistribution
   | LogNormalDistribution
-  | ConstantDistribution;
+  | ConstantDistribution
+  | UniformDistribution
+  | ParetoDistribution
+  | WeibullDistribution
+  | GammaDistribution
+  | MixtureDistribution
+  | EmpiricalDistribution;
 
 export interface NormalDistribution {
   distribution: "normal";
@@ -251,6 +357,67 @@ export interface ConstantDistribution {
   value: number;
 }
 
+export interface UniformDistribution {
+  distribution: "uniform";
+  min: number;
+  max: number;
+}
+
+export interface ParetoDistribution {
+  distribution: "pareto";
+  scale: number;
+  shape: number;
+}
+
+export interface WeibullDistribution {
+  distribution: "weibull";
+  scale: number;
+  shape: number;
+}
+
+export interface GammaDistribution {
+  distribution: "gamma";
+  shape: number;
+  scale: number;
+}
+
+/** Samples from one of several distributions, chosen by weight. */
+export interface MixtureDistribution {
+  distribution: "mixture";
+  components: (Distribution & { weight: number })[];
+}
+
+/**
+ * Follows a histogram, or a list of samples (each as likely as the others).
+ * Samples and bins can be given inline, or read from a file.
+ */
+export interface EmpiricalDistribution {
+  distribution: "empirical";
+  samples?: number[];
+  bins?: HistogramBin[];
+  /** A file with one sample per line, or the min, max and weight of a bin per line, after an optional header. Relative to the file which refers to it. */
+  path?: string;
+}
+
+export interface HistogramBin {
+  min: number;
+  max: number;
+  weight: number;
+}
+
+export interface TxLoadSegment {
+  "start-time"?: number;
+  "stop-time"?: number;
+  rate?: TxRateShape;
+  /** Multiplies the tx-generation-weight of every node in each of these groups while the segment is active. */
+  "group-weights"?: { [group: string]: number };
+}
+
+export type TxRateShape =
+  | { shape: "constant"; multiplier: number }
+  | { shape: "linear"; from: number; to: number }
+  | { shape: "sinusoid"; period: number; amplitude: number };
+
 export enum DiffusionStrategy {
   PeerOrder = "peer-order",
   FreshestFirst = "freshest-first",
@@ -273,6 +440,20 @@ export enum LeiosVariant {
   FullWithTXReferences = "full-with-tx-references"
 }
 
+export enum LotteryModel {
+  /** Each lottery is a single draw, won with probability proportional to stake. */
+  Approximate = "approximate",
+  /** Exact stake-based sortition, following the Praos leader check and the Leios voter check. */
+  Sortition = "sortition",
+}
+
+export enum VoteCommitteeSelection {
+  /** Every pool runs the vote lottery. */
+  Lottery = "lottery",
+  /** The largest pools are persistent voters, and the rest run local sortition. */
+  FaitAccompli = "fait-accompli",
+}
+
 export enum MempoolSamplingStrategy {
   /** Include transactions in order by ID (corresponds to generation time). */
   OrderedById = "ordered-by-id",
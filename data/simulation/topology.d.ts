@@ -1,5 +1,7 @@
 // This file contains TypeScript types for the configuration file formats.
 
+import { Distribution } from "./config";
+
 /**
  * The topology for a Leios simulation.
  *
@@ -33,17 +35,50 @@ export interface Node<Location> {
    * Only supported by Rust simulation.
    */
   "tx-generation-weight"?: number | null;
+  /**
+   * Which group this node belongs to, for the group-weights of tx-load-profile.
+   * Only supported by Rust simulation.
+   */
+  "tx-generation-group"?: string | null;
+  /**
+   * The total upload capacity of this node, shared between all of its outgoing connections.
+   * Each connection is still limited by its own bandwidth as well.
+   * Only supported by Rust simulation.
+   */
+  "upload-bandwidth-bytes-per-second"?: bigint | null;
+  /**
+   * The total download capacity of this node, shared between all of its incoming connections.
+   * Each connection is still limited by its own bandwidth as well.
+   * Only supported by Rust simulation.
+   */
+  "download-bandwidth-bytes-per-second"?: bigint | null;
   /** If not null, the node will behave according to the given Behaviour.
   *
   * Only supported by Haskell simulation.
   */
   adversarial?: Behaviour | null;
+  /** Any adversarial behaviours which this node should exhibit.
+   *
+   * Only supported by Rust simulation.
+   */
+  "adversarial-behaviours"?: AdversarialBehaviour[] | null;
 }
 
 /** Link information. */
 export interface LinkInfo {
   "latency-ms": number;
   "bandwidth-bytes-per-second"?: bigint | null;
+  /**
+   * The odds of any single TCP segment getting lost, and having to be resent.
+   * Only supported by Rust simulation.
+   */
+  "loss-rate"?: number | null;
+  /**
+   * Extra latency added to each message, in milliseconds.
+   * Messages over the same link still arrive in order.
+   * Only supported by Rust simulation.
+   */
+  "latency-jitter-ms"?: Distribution | null;
 }
 
 export type NodeName = string;
@@ -70,3 +105,53 @@ export interface UnboundedIbs {
   "slot-of-generated-ibs": number;
   "ibs-per-slot": number;
 }
+
+export type AdversarialBehaviour =
+  | WithholdIbBodies
+  | EquivocateIbs
+  | EquivocateEbs
+  | RefuseVoteRelay
+  | VoteForInvalidEbs
+  ;
+
+/** A node that announces the headers of its IBs, but never serves their bodies.
+
+Only supported by Rust simulation.
+*/
+export interface WithholdIbBodies {
+  behaviour: "withhold-ib-bodies";
+}
+
+/** A node that produces two different IBs for every IB header,
+and sends each one to a different half of its peers.
+
+Only supported by Rust simulation.
+*/
+export interface EquivocateIbs {
+  behaviour: "equivocate-ibs";
+}
+
+/** A node that produces two different EBs whenever it wins the EB lottery,
+and sends each one to a different half of its peers.
+
+Only supported by Rust simulation.
+*/
+export interface EquivocateEbs {
+  behaviour: "equivocate-ebs";
+}
+
+/** A node that never relays votes produced by other nodes.
+
+Only supported by Rust simulation.
+*/
+export interface RefuseVoteRelay {
+  behaviour: "refuse-vote-relay";
+}
+
+/** A node that votes for every EB it sees, even invalid ones.
+
+Only supported by Rust simulation.
+*/
+export interface VoteForInvalidEbs {
+  behaviour: "vote-for-invalid-ebs";
+}
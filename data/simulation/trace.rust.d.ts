@@ -42,7 +42,7 @@ interface CpuTaskFinishedEvent {
 
 type CpuTaskType = shared.CpuTaskType;
 
-type BlockEvent = shared.BlockEvent | LotteryWon | GeneratedTransaction | LostTransaction;
+type BlockEvent = shared.BlockEvent | LotteryWon | GeneratedTransaction | LostTransaction | RolledBackRankingBlock;
 
 interface LotteryWon {
     type: "IBLotteryWon" | "EBLotteryWon" | "VTLotteryWon";
@@ -59,6 +59,8 @@ interface GeneratedTransaction {
     input_id: number;
     shard: number;
     overcollateralization_factor: number;
+    execution_steps: number;
+    execution_memory: number;
 }
 
 interface LostTransaction {
@@ -67,6 +69,15 @@ interface LostTransaction {
     reason: string;
 }
 
+interface RolledBackRankingBlock {
+    type: "RBRolledBack";
+    node: string;
+    depth: number;
+    old_tip: string;
+    new_tip: string;
+    rolled_back: shared.BlockRef[];
+}
+
 interface NetworkEvent extends Omit<shared.NetworkEvent, "type"> {
     type: NetworkEventType
 }
@@ -37,7 +37,7 @@ fn main() -> Result<()> {
 
     let serialized = serde_yaml::to_string(&raw_topology.clone().into_topology())?;
 
-    let topology: Topology = raw_topology.into_topology().into();
+    let topology: Topology = raw_topology.into_topology().try_into()?;
     topology.validate()?;
 
     fs::write(args.path, serialized)?;
@@ -187,7 +187,7 @@ mod tests {
         };
 
         let raw = globe(&args).unwrap();
-        let topology: Topology = raw.into_topology().into();
+        let topology: Topology = raw.into_topology().try_into().unwrap();
         topology.validate().unwrap();
     }
 }
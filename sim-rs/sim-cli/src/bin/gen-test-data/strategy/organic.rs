@@ -172,7 +172,7 @@ mod tests {
         };
 
         let raw = organic(&args).unwrap();
-        let topology: Topology = raw.into_topology().into();
+        let topology: Topology = raw.into_topology().try_into().unwrap();
         topology.validate().unwrap();
     }
 }
@@ -112,7 +112,7 @@ mod tests {
         };
 
         let raw = random_graph(&args).unwrap();
-        let topology: Topology = raw.into_topology().into();
+        let topology: Topology = raw.into_topology().try_into().unwrap();
         topology.validate().unwrap();
     }
 }
@@ -136,7 +136,7 @@ mod tests {
         let args = SimplifiedArgs { pool_count: 1000 };
 
         let raw = simplified(&args).unwrap();
-        let topology: Topology = raw.into_topology().into();
+        let topology: Topology = raw.into_topology().try_into().unwrap();
         topology.validate().unwrap();
     }
 }
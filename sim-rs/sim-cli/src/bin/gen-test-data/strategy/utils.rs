@@ -172,6 +172,10 @@ impl GraphBuilder {
                     cpu_core_count: n.cores,
                     tx_conflict_fraction: None,
                     tx_generation_weight: None,
+                    tx_generation_group: None,
+                    adversarial_behaviours: vec![],
+                    upload_bandwidth_bytes_per_second: None,
+                    download_bandwidth_bytes_per_second: None,
                     producers: BTreeMap::new(),
                 };
                 (name, node)
@@ -183,6 +187,8 @@ impl GraphBuilder {
                 RawLinkInfo {
                     latency_ms: link.latency_ms as f64,
                     bandwidth_bytes_per_second: None,
+                    loss_rate: None,
+                    latency_jitter_ms: None,
                 },
             );
         }
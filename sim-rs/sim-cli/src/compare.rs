@@ -0,0 +1,456 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Result};
+use itertools::Itertools as _;
+use serde::Serialize;
+use serde_json::Value;
+use sim_core::{clock::Timestamp, config::NodeConfiguration, events::Event};
+use statrs::distribution::{ContinuousCDF as _, StudentsT};
+use tokio::sync::mpsc;
+use tracing::{info, info_span};
+
+use crate::{
+    summary::{flatten, RunReport},
+    trace::{TraceFormat, TraceReader, READ_BUFFER},
+};
+
+/// Whether a path holds a run's summary (as written by `--summary`), rather than its trace.
+pub fn is_summary(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "json" || ext == "csv")
+}
+
+/// Every numeric metric in a run's report, by its dotted name (e.g. "leios.txs_per_ib.p50").
+pub fn report_metrics(report: &RunReport) -> Result<BTreeMap<String, f64>> {
+    let mut rows = vec![];
+    flatten("", &serde_json::to_value(report)?, &mut rows);
+    Ok(numeric(rows))
+}
+
+/// Reads the metrics from a summary written by `--summary`, as either JSON or CSV.
+pub fn read_summary(path: &Path) -> Result<BTreeMap<String, f64>> {
+    let contents = fs::read_to_string(path)?;
+    let mut rows = vec![];
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => flatten("", &serde_json::from_str(&contents)?, &mut rows),
+        Some("csv") => {
+            for line in contents.lines().skip(1) {
+                let Some((metric, value)) = line.split_once(',') else {
+                    bail!("invalid summary row \"{line}\"");
+                };
+                rows.push((metric.to_string(), value.to_string()));
+            }
+        }
+        _ => bail!("summary must be a .csv or .json file"),
+    }
+    Ok(numeric(rows))
+}
+
+fn numeric(rows: Vec<(String, String)>) -> BTreeMap<String, f64> {
+    rows.into_iter()
+        .filter_map(|(metric, value)| Some((metric, value.parse().ok()?)))
+        .collect()
+}
+
+/// How one metric differs between two runs.
+#[derive(Clone, Debug, Serialize)]
+pub struct MetricComparison {
+    pub metric: String,
+    pub a: Option<f64>,
+    pub b: Option<f64>,
+    pub delta: Option<f64>,
+    pub relative_delta: Option<f64>,
+    /// For the mean of a distribution, how likely a difference at least this large would be
+    /// if both runs sampled from the same distribution (by Welch's t-test).
+    pub p_value: Option<f64>,
+}
+
+/// Lines up every metric from either run.
+pub fn compare_metrics(
+    a: &BTreeMap<String, f64>,
+    b: &BTreeMap<String, f64>,
+) -> Vec<MetricComparison> {
+    a.keys()
+        .chain(b.keys())
+        .unique()
+        .sorted()
+        .map(|metric| {
+            let (a_value, b_value) = (a.get(metric).copied(), b.get(metric).copied());
+            let delta = a_value.zip(b_value).map(|(a, b)| b - a);
+            let relative_delta = a_value
+                .zip(delta)
+                .filter(|(a, _)| *a != 0.0)
+                .map(|(a, delta)| delta / a.abs());
+            let p_value = metric
+                .strip_suffix(".mean")
+                .and_then(|distribution| welch_p_value(a, b, distribution));
+            MetricComparison {
+                metric: metric.clone(),
+                a: a_value,
+                b: b_value,
+                delta,
+                relative_delta,
+                p_value,
+            }
+        })
+        .collect()
+}
+
+// Only distributions which report their count, mean and standard deviation can be tested.
+fn welch_p_value(
+    a: &BTreeMap<String, f64>,
+    b: &BTreeMap<String, f64>,
+    distribution: &str,
+) -> Option<f64> {
+    let sample = |metrics: &BTreeMap<String, f64>| {
+        let get = |field: &str| metrics.get(&format!("{distribution}.{field}")).copied();
+        let count = get("count")?;
+        let mean = get("mean")?;
+        // Reports hold the population standard deviation, but the test needs the sample variance
+        let variance = get("std_dev")?.powi(2) * count / (count - 1.0);
+        (count >= 2.0 && mean.is_finite()).then_some((count, mean, variance))
+    };
+    let (n_a, mean_a, var_a) = sample(a)?;
+    let (n_b, mean_b, var_b) = sample(b)?;
+    let (se_a, se_b) = (var_a / n_a, var_b / n_b);
+    let se = se_a + se_b;
+    if se <= 0.0 {
+        return None;
+    }
+    let t = (mean_b - mean_a) / se.sqrt();
+    let dof = se.powi(2) / (se_a.powi(2) / (n_a - 1.0) + se_b.powi(2) / (n_b - 1.0));
+    let dist = StudentsT::new(0.0, 1.0, dof).ok()?;
+    Some(2.0 * (1.0 - dist.cdf(t.abs())))
+}
+
+/// Where two traces first stop matching, event by event.
+#[derive(Clone, Debug, Serialize)]
+pub struct Divergence {
+    /// How many events both traces had in common before this one.
+    pub index: u64,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+}
+
+/// Reads two traces side by side, and finds the first event where they differ.
+/// Runs with the same seed and parameters produce the same trace, so this is where a change took effect.
+/// Events are compared in the order they were traced, unless `ignore_simultaneous_order` is set.
+pub async fn first_divergence(
+    nodes: &[NodeConfiguration],
+    (a, a_format): (&Path, TraceFormat),
+    (b, b_format): (&Path, TraceFormat),
+    ignore_simultaneous_order: bool,
+) -> Result<Option<Divergence>> {
+    // Each reader only gets a little ahead of the comparison, rather than reading its whole trace
+    let (a_sink, a_source) = mpsc::channel(READ_BUFFER);
+    let (b_sink, b_source) = mpsc::channel(READ_BUFFER);
+    let (a_read, b_read, divergence) = tokio::join!(
+        TraceReader::new(nodes)
+            .with_format(a_format)
+            .read(a, a_sink),
+        TraceReader::new(nodes)
+            .with_format(b_format)
+            .read(b, b_sink),
+        compare_events(a_source, b_source, ignore_simultaneous_order),
+    );
+    a_read?;
+    b_read?;
+    Ok(divergence)
+}
+
+// Dropping the sources once they differ stops both readers.
+async fn compare_events(
+    a_source: mpsc::Receiver<(Event, Timestamp)>,
+    b_source: mpsc::Receiver<(Event, Timestamp)>,
+    ignore_simultaneous_order: bool,
+) -> Option<Divergence> {
+    let mut a_instants = Instants::new(a_source, ignore_simultaneous_order);
+    let mut b_instants = Instants::new(b_source, ignore_simultaneous_order);
+    let mut index = 0;
+    loop {
+        let (a, b) = tokio::join!(a_instants.next(), b_instants.next());
+        if a.is_empty() && b.is_empty() {
+            return None;
+        }
+        if a != b {
+            let offset = a.iter().zip(&b).take_while(|(a, b)| a == b).count();
+            // If one instant ran out first, its trace carries on with whatever happened next
+            let a = a.get(offset).cloned().or_else(|| a_instants.peek());
+            let b = b.get(offset).cloned().or_else(|| b_instants.peek());
+            return Some(Divergence {
+                index: index + offset as u64,
+                a,
+                b,
+            });
+        }
+        index += a.len() as u64;
+    }
+}
+
+/// Reads a trace one instant at a time. When sorting, each instant's events are sorted,
+/// so that traces which only differ in the order of simultaneous events still match.
+struct Instants {
+    source: mpsc::Receiver<(Event, Timestamp)>,
+    next: Option<(Event, Timestamp)>,
+    sort: bool,
+}
+
+impl Instants {
+    fn new(source: mpsc::Receiver<(Event, Timestamp)>, sort: bool) -> Self {
+        Self {
+            source,
+            next: None,
+            sort,
+        }
+    }
+
+    /// Every event at the next instant in the trace, or nothing at the end of the trace.
+    async fn next(&mut self) -> Vec<Value> {
+        let first = match self.next.take() {
+            Some(event) => Some(event),
+            None => self.source.recv().await,
+        };
+        let Some((event, time)) = first else {
+            return vec![];
+        };
+        let mut events = vec![event_value(&event, time)];
+        while let Some((event, next_time)) = self.source.recv().await {
+            if next_time != time {
+                self.next = Some((event, next_time));
+                break;
+            }
+            events.push(event_value(&event, time));
+        }
+        if self.sort {
+            events.sort_by_cached_key(|event| event.to_string());
+        }
+        events
+    }
+
+    /// The first event of the next instant, if it has been read yet.
+    fn peek(&self) -> Option<Value> {
+        self.next
+            .as_ref()
+            .map(|(event, time)| event_value(event, *time))
+    }
+}
+
+fn event_value(event: &Event, time: Timestamp) -> Value {
+    serde_json::json!({ "time_s": time, "message": event })
+}
+
+/// Logs every metric which differs between the two runs, and where their traces diverged.
+pub fn report(comparisons: &[MetricComparison], divergence: Option<&Option<Divergence>>) {
+    let format = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{v:.3}"));
+    info_span!("compare").in_scope(|| {
+        let mut unchanged = 0;
+        for comparison in comparisons {
+            if comparison.delta == Some(0.0) {
+                unchanged += 1;
+                continue;
+            }
+            let relative = comparison
+                .relative_delta
+                .map(|r| format!(" ({:+.1}%)", r * 100.0))
+                .unwrap_or_default();
+            let significance = comparison
+                .p_value
+                .map(|p| format!(", p = {p:.3}"))
+                .unwrap_or_default();
+            info!(
+                "{}: {} -> {}, delta {}{relative}{significance}",
+                comparison.metric,
+                format(comparison.a),
+                format(comparison.b),
+                format(comparison.delta),
+            );
+        }
+        info!("{unchanged} other metric(s) were the same in both runs.");
+        match divergence {
+            Some(Some(divergence)) => {
+                let describe = |event: &Option<Value>| {
+                    event
+                        .as_ref()
+                        .map_or("the end of the trace".to_string(), |e| e.to_string())
+                };
+                info!(
+                    "The traces diverged after {} event(s): {} vs {}.",
+                    divergence.index,
+                    describe(&divergence.a),
+                    describe(&divergence.b),
+                );
+            }
+            Some(None) => info!("The traces are identical."),
+            None => {}
+        }
+    });
+}
+
+/// Writes the comparison as JSON (including where the traces diverged), or as a CSV table of metrics.
+pub fn write_comparison(
+    path: &Path,
+    comparisons: &[MetricComparison],
+    divergence: Option<&Option<Divergence>>,
+) -> Result<()> {
+    let contents = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::to_string_pretty(&serde_json::json!({
+            "metrics": comparisons,
+            "divergence": divergence,
+        }))?,
+        Some("csv") => {
+            let field = |value: Option<f64>| value.map(|v| v.to_string()).unwrap_or_default();
+            let mut csv = "metric,a,b,delta,relative_delta,p_value\n".to_string();
+            for c in comparisons {
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{}\n",
+                    c.metric,
+                    field(c.a),
+                    field(c.b),
+                    field(c.delta),
+                    field(c.relative_delta),
+                    field(c.p_value),
+                ));
+            }
+            csv
+        }
+        _ => bail!("comparison must be a .csv or .json file"),
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use sim_core::{clock::Timestamp, events::Event};
+    use tokio::sync::mpsc;
+
+    use super::{compare_events, compare_metrics, Divergence};
+
+    fn metrics(values: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        values.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    // Traces of (time in seconds, slot) pairs
+    async fn divergence(a: &[(u64, u64)], b: &[(u64, u64)]) -> Option<Divergence> {
+        divergence_with(a, b, false).await
+    }
+
+    async fn divergence_with(
+        a: &[(u64, u64)],
+        b: &[(u64, u64)],
+        ignore_simultaneous_order: bool,
+    ) -> Option<Divergence> {
+        let source = |trace: &[(u64, u64)]| {
+            let (sink, source) = mpsc::channel(trace.len().max(1));
+            for &(time, slot) in trace {
+                sink.try_send((Event::GlobalSlot { slot }, Timestamp::from_secs(time)))
+                    .unwrap();
+            }
+            source
+        };
+        compare_events(source(a), source(b), ignore_simultaneous_order).await
+    }
+
+    #[tokio::test]
+    async fn should_only_ignore_the_order_of_simultaneous_events_when_asked() {
+        let a = [(0, 0), (1, 1), (1, 2), (1, 3), (2, 4)];
+        let b = [(0, 0), (1, 3), (1, 1), (1, 2), (2, 4)];
+        assert!(divergence_with(&a, &b, true).await.is_none());
+
+        let first = divergence(&a, &b).await.unwrap();
+        assert_eq!(first.index, 1);
+        assert_eq!(first.a.unwrap()["message"]["slot"], 1);
+        assert_eq!(first.b.unwrap()["message"]["slot"], 3);
+    }
+
+    #[tokio::test]
+    async fn should_find_where_traces_diverge() {
+        let a = [(0, 0), (1, 1), (1, 2), (2, 4)];
+        let b = [(0, 0), (1, 2), (1, 3), (2, 4)];
+        let first = divergence(&a, &b).await.unwrap();
+        assert_eq!(first.index, 1);
+        assert_eq!(first.a.unwrap()["message"]["slot"], 1);
+        assert_eq!(first.b.unwrap()["message"]["slot"], 2);
+
+        // An instant with an extra event is compared against whatever comes next in the other trace
+        let a = [(0, 0), (1, 1), (1, 2), (2, 4)];
+        let b = [(0, 0), (1, 1), (2, 4)];
+        let extra = divergence(&a, &b).await.unwrap();
+        assert_eq!(extra.index, 2);
+        assert_eq!(extra.a.unwrap()["message"]["slot"], 2);
+        assert_eq!(extra.b.unwrap()["message"]["slot"], 4);
+    }
+
+    #[tokio::test]
+    async fn should_stop_reading_once_traces_diverge() {
+        // Each trace keeps sending events until nobody is listening, and returns how many it sent
+        let trace = |diverge_at: Option<u64>| {
+            let (sink, source) = mpsc::channel(1);
+            let sent = tokio::spawn(async move {
+                let mut sent = 0;
+                for time in 0..1000 {
+                    let slot = if Some(time) == diverge_at { 1000 } else { time };
+                    let event = (Event::GlobalSlot { slot }, Timestamp::from_secs(time));
+                    if sink.send(event).await.is_err() {
+                        break;
+                    }
+                    sent += 1;
+                }
+                sent
+            });
+            (source, sent)
+        };
+        let (a_source, a_sent) = trace(None);
+        let (b_source, b_sent) = trace(Some(3));
+        let first = compare_events(a_source, b_source, false).await.unwrap();
+        assert_eq!(first.index, 3);
+        assert!(a_sent.await.unwrap() < 10);
+        assert!(b_sent.await.unwrap() < 10);
+    }
+
+    #[test]
+    fn should_line_up_metrics_from_both_runs() {
+        let a = metrics(&[("ibs_generated", 10.0), ("rollbacks", 0.0)]);
+        let b = metrics(&[("ibs_generated", 15.0), ("ebs_generated", 2.0)]);
+        let comparisons = compare_metrics(&a, &b);
+        let names: Vec<_> = comparisons.iter().map(|c| c.metric.as_str()).collect();
+        assert_eq!(names, vec!["ebs_generated", "ibs_generated", "rollbacks"]);
+
+        assert_eq!(comparisons[0].a, None);
+        assert_eq!(comparisons[0].delta, None);
+        assert_eq!(comparisons[1].delta, Some(5.0));
+        assert_eq!(comparisons[1].relative_delta, Some(0.5));
+        assert_eq!(comparisons[1].p_value, None);
+        // Nothing to be relative to
+        assert_eq!(comparisons[2].relative_delta, None);
+    }
+
+    #[test]
+    fn should_test_whether_distributions_differ() {
+        let distribution = |mean: f64| {
+            metrics(&[
+                ("latency.count", 1000.0),
+                ("latency.mean", mean),
+                ("latency.std_dev", 1.0),
+            ])
+        };
+        let same = compare_metrics(&distribution(5.0), &distribution(5.01));
+        let mean = same.iter().find(|c| c.metric == "latency.mean").unwrap();
+        assert!(mean.p_value.unwrap() > 0.5);
+
+        let different = compare_metrics(&distribution(5.0), &distribution(5.5));
+        let mean = different
+            .iter()
+            .find(|c| c.metric == "latency.mean")
+            .unwrap();
+        assert!(mean.p_value.unwrap() < 0.001);
+        // Only means are tested
+        let count = different
+            .iter()
+            .find(|c| c.metric == "latency.count")
+            .unwrap();
+        assert_eq!(count.p_value, None);
+    }
+}
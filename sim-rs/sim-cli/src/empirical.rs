@@ -0,0 +1,106 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context as _, Result};
+use serde_yaml::Value;
+use sim_core::config::EmpiricalDistribution;
+
+/// Reads a YAML file, along with any empirical distribution files it refers to.
+pub fn read_yaml(path: &Path) -> Result<Value> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("could not read {}", path.display()))?;
+    let mut yaml =
+        serde_yaml::from_str(&contents).with_context(|| format!("invalid {}", path.display()))?;
+    load_files(&mut yaml, path.parent().unwrap_or(Path::new("")))?;
+    Ok(yaml)
+}
+
+/// Replaces the `path` of every empirical distribution with the bins in that file,
+/// so that parsing doesn't touch the filesystem and the fingerprint of a run covers what was in the file.
+/// Relative paths are resolved from `dir`, which should hold whatever file referred to them.
+pub fn load_files(yaml: &mut Value, dir: &Path) -> Result<()> {
+    match yaml {
+        Value::Mapping(mapping) => {
+            let is_empirical = mapping
+                .get("distribution")
+                .is_some_and(|d| d.as_str() == Some("empirical"));
+            if let Some(path) = mapping.get("path").filter(|_| is_empirical) {
+                let Some(path) = path.as_str() else {
+                    bail!("empirical distribution path {path:?} must be a string");
+                };
+                let path = dir.join(path);
+                let contents = fs::read_to_string(&path).with_context(|| {
+                    format!("could not read empirical distribution {}", path.display())
+                })?;
+                let bins = EmpiricalDistribution::bins_from_file(&contents).with_context(|| {
+                    format!("invalid empirical distribution {}", path.display())
+                })?;
+                mapping.remove("path");
+                let existing = mapping
+                    .entry("bins".into())
+                    .or_insert_with(|| Value::Sequence(vec![]));
+                let Value::Sequence(existing) = existing else {
+                    bail!("empirical distribution bins must be a list");
+                };
+                for bin in bins {
+                    existing.push(serde_yaml::to_value(bin)?);
+                }
+            }
+            for (_, value) in mapping.iter_mut() {
+                load_files(value, dir)?;
+            }
+        }
+        Value::Sequence(values) => {
+            for value in values {
+                load_files(value, dir)?;
+            }
+        }
+        Value::Tagged(tagged) => load_files(&mut tagged.value, dir)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use sim_core::config::DistributionConfig;
+
+    use super::read_yaml;
+
+    #[test]
+    fn should_read_distribution_files_relative_to_the_parameters() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir(dir.path().join("params"))?;
+        std::fs::write(
+            dir.path().join("params/sizes.csv"),
+            "size\n100\n200,300,2\n",
+        )?;
+        let path = dir.path().join("params/config.yaml");
+        std::fs::write(
+            &path,
+            r#"
+            tx-size-bytes-distribution:
+              distribution: mixture
+              components:
+                - { weight: 1, distribution: empirical, path: sizes.csv, samples: [50] }
+            "#,
+        )?;
+
+        let yaml = read_yaml(&path)?;
+        let component = &yaml["tx-size-bytes-distribution"]["components"][0];
+        assert!(component.get("path").is_none());
+        assert_eq!(component["bins"].as_sequence().unwrap().len(), 2);
+        assert_eq!(component["bins"][1]["weight"].as_f64(), Some(2.0));
+
+        // The samples given inline are kept alongside the ones in the file
+        let distribution = serde_yaml::from_value(yaml["tx-size-bytes-distribution"].clone())?;
+        let DistributionConfig::Mixture { components } = distribution else {
+            panic!("expected a mixture");
+        };
+        let DistributionConfig::Empirical(empirical) = &components[0].distribution else {
+            panic!("expected an empirical distribution");
+        };
+        assert_eq!(empirical.bins.len(), 3);
+        Ok(())
+    }
+}
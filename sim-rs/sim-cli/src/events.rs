@@ -1,9 +1,18 @@
-use std::{collections::BTreeMap, path::PathBuf, pin::Pin, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+    iter,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{mpsc as std_mpsc, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
-use aggregate::TraceAggregator;
-use anyhow::Result;
+use aggregate::{AggregatedData, TraceAggregator};
+use anyhow::{anyhow, bail, Result};
 use async_compression::tokio::write::GzipEncoder;
-use average::Variance;
+use columnar::ColumnarTrace;
 use itertools::Itertools as _;
 use liveness::LivenessMonitor;
 use pretty_bytes_rust::{pretty_bytes, PrettyBytesOptions};
@@ -11,17 +20,22 @@ use serde::Serialize;
 use sim_core::{
     clock::Timestamp,
     config::{LeiosVariant, NodeId, SimConfiguration},
-    events::{BlockRef, Event, Node},
+    events::{BlockRef, Event, EventFilter, EventSink, EventTracker, Node},
     model::{BlockId, TransactionId},
 };
 use tokio::{
     fs::{self, File},
     io::{AsyncWrite, AsyncWriteExt as _, BufWriter},
-    sync::mpsc,
+    runtime, task,
 };
 use tracing::{info, info_span};
 
+use crate::summary::{
+    compute_stats, LeiosReport, MessageStats, PoolReport, PraosReport, RunReport, RunSummary,
+};
+
 mod aggregate;
+mod columnar;
 mod liveness;
 
 type InputBlockId = sim_core::model::InputBlockId<Node>;
@@ -42,23 +56,26 @@ enum OutputFormat {
     CborStream,
 }
 
+/// How many lines can be waiting for the trace writer before the simulation waits for it to catch up.
+const TRACE_BUFFER: usize = 1 << 16;
+
+/// Reports on the events of a run, and writes them to its trace.
+/// Everything is handled by sinks on the simulation's event tracker as events happen,
+/// so nothing piles up in between when the trace can't be written as fast as the simulation runs.
 pub struct EventMonitor {
     variant: LeiosVariant,
     node_ids: Vec<NodeId>,
     pool_ids: Vec<NodeId>,
     maximum_ib_age: u64,
     maximum_eb_age: u64,
-    events_source: LivenessMonitor,
-    output_path: Option<PathBuf>,
-    aggregate: bool,
+    recorder: Recorder,
+    liveness: Liveness,
+    aggregation: Option<Aggregation>,
+    writer: Option<(TraceWriter, JoinHandle<Result<()>>)>,
 }
 
 impl EventMonitor {
-    pub fn new(
-        config: &SimConfiguration,
-        events_source: mpsc::UnboundedReceiver<(Event, Timestamp)>,
-        output_path: Option<PathBuf>,
-    ) -> Self {
+    pub async fn new(config: &SimConfiguration, output_path: Option<PathBuf>) -> Result<Self> {
         let node_ids = config.nodes.iter().map(|p| p.id).collect();
         let pool_ids = config
             .nodes
@@ -67,326 +84,123 @@ impl EventMonitor {
             .collect();
         let stage_length = config.stage_length;
         let maximum_ib_age = stage_length * 3;
-        Self {
+
+        let output = OutputTarget::open(output_path, config.aggregate_events).await?;
+        let writer = match output {
+            OutputTarget::None => None,
+            output => {
+                let (sink, source) = std_mpsc::sync_channel(TRACE_BUFFER);
+                let live = config.timescale.is_some();
+                let handle = thread::spawn(move || write_trace(output, source, live));
+                Some((TraceWriter(sink), handle))
+            }
+        };
+        let aggregation = match &writer {
+            Some((writer, _)) if config.aggregate_events => Some(Aggregation {
+                aggregator: Arc::new(Mutex::new(TraceAggregator::new())),
+                writer: writer.clone(),
+            }),
+            _ => None,
+        };
+        let recorder = Recorder {
+            stats: Arc::new(Mutex::new(RunStats::default())),
+            // Aggregated traces only hold what the aggregator writes
+            writer: match &writer {
+                Some((writer, _)) if aggregation.is_none() => Some(writer.clone()),
+                _ => None,
+            },
+        };
+        let liveness = Liveness {
+            monitor: Arc::new(Mutex::new(LivenessMonitor::new(config))),
+            recorder: recorder.clone(),
+        };
+        Ok(Self {
             variant: config.variant,
             node_ids,
             pool_ids,
             maximum_ib_age,
             maximum_eb_age: config.max_eb_age,
-            events_source: LivenessMonitor::new(config, events_source),
-            output_path,
-            aggregate: config.aggregate_events,
-        }
+            recorder,
+            liveness,
+            aggregation,
+            writer,
+        })
     }
 
-    // Monitor and report any events emitted by the simulation,
-    // including any aggregated stats at the end.
-    pub async fn run(mut self) -> Result<()> {
-        let mut blocks_published: BTreeMap<NodeId, u64> = BTreeMap::new();
-        let mut blocks_rejected: BTreeMap<NodeId, u64> = BTreeMap::new();
-        let mut blocks: BTreeMap<u64, (NodeId, u64)> = BTreeMap::new();
-        let mut txs: BTreeMap<TransactionId, Transaction> = BTreeMap::new();
-        let mut ibs: BTreeMap<InputBlockId, InputBlock> = BTreeMap::new();
-        let mut ebs: BTreeMap<EndorserBlockId, EndorserBlock> = BTreeMap::new();
-        let mut seen_ibs: BTreeMap<NodeId, f64> = BTreeMap::new();
-        let mut ibs_containing_tx: BTreeMap<TransactionId, f64> = BTreeMap::new();
-        let mut ebs_containing_ib: BTreeMap<InputBlockId, f64> = BTreeMap::new();
-        let mut votes_per_bundle: BTreeMap<VoteBundleId, f64> = BTreeMap::new();
-        let mut votes_per_pool: BTreeMap<NodeId, f64> =
-            self.pool_ids.into_iter().map(|id| (id, 0.0)).collect();
-        let mut eb_votes: BTreeMap<EndorserBlockId, f64> = BTreeMap::new();
-
-        let mut last_timestamp = Timestamp::zero();
-        let mut total_slots = 0u64;
-        let mut total_votes = 0u64;
-        let mut leios_blocks_with_endorsements = 0u64;
-        let mut total_leios_txs = 0u64;
-        let mut total_leios_bytes = 0u64;
-        let mut tx_messages = MessageStats::default();
-        let mut ib_messages = MessageStats::default();
-        let mut eb_messages = MessageStats::default();
-        let mut vote_messages = MessageStats::default();
-
-        // Pretty print options for bytes
-        let pbo = Some(PrettyBytesOptions {
-            use_1024_instead_of_1000: Some(false),
-            number_of_decimal: Some(2),
-            remove_zero_decimal: Some(true),
-        });
-
-        if let Some(path) = &self.output_path {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent).await?;
-            }
+    /// Adds this monitor's sinks to a tracker.
+    pub fn subscribe(&self, tracker: EventTracker) -> Result<EventTracker> {
+        let mut tracker = tracker.with_sink(self.recorder.clone())?;
+        if let Some(aggregation) = &self.aggregation {
+            tracker = tracker.with_sink(aggregation.clone())?;
         }
+        tracker.with_filtered_sink(
+            EventFilter::all().kinds(LivenessMonitor::KINDS),
+            self.liveness.clone(),
+        )
+    }
 
-        let mut output = match self.output_path.as_mut() {
-            Some(path) => {
-                let file = File::create(&path).await?;
-
-                let mut gzipped = false;
-                if path
-                    .extension()
-                    .and_then(|e| e.to_str())
-                    .is_some_and(|ext| ext == "gz")
-                {
-                    path.set_extension("");
-                    gzipped = true;
-                }
-
-                let file: TraceSink = if gzipped {
-                    let encoder = GzipEncoder::new(file);
-                    Box::pin(BufWriter::new(encoder))
-                } else {
-                    Box::pin(BufWriter::new(file))
-                };
-
-                let format = if path
-                    .extension()
-                    .and_then(|e| e.to_str())
-                    .is_some_and(|ext| ext == "cbor")
-                {
-                    OutputFormat::CborStream
-                } else {
-                    OutputFormat::JsonStream
-                };
-                if self.aggregate {
-                    OutputTarget::AggregatedEventStream {
-                        aggregation: TraceAggregator::new(),
-                        format,
-                        file,
-                    }
-                } else {
-                    OutputTarget::EventStream { format, file }
-                }
-            }
-            None => OutputTarget::None,
-        };
-        while let Some((event, time)) = self.events_source.recv().await {
-            last_timestamp = time;
-            let output_event = OutputEvent {
-                time_s: time,
-                message: event.clone(),
-            };
-            output.write(output_event).await?;
-            match event {
-                Event::GlobalSlot { slot: number } => {
-                    info!("Slot {number} has begun.");
-                    total_slots = number + 1;
-                }
-                Event::Slot { .. } => {}
-                Event::CpuTaskScheduled { .. } => {}
-                Event::CpuTaskFinished { .. } => {}
-                Event::Cpu { .. } => {}
-                Event::TXGenerated { id, size_bytes, .. } => {
-                    txs.insert(id, Transaction::new(size_bytes, time));
-                }
-                Event::TXSent { .. } => {
-                    tx_messages.sent += 1;
-                }
-                Event::TXReceived { .. } => {
-                    tx_messages.received += 1;
-                }
-                Event::TXLost { .. } => {}
-                Event::RBLotteryWon { .. } => {}
-                Event::RBGenerated {
-                    id: BlockId { slot, producer },
-                    vrf,
-                    endorsement,
-                    transactions,
-                    ..
-                } => {
-                    info!(
-                        "Pool {} produced a praos block in slot {slot} with {} tx(s).",
-                        producer,
-                        transactions.len()
-                    );
-                    if let Some(endorsement) = endorsement {
-                        total_leios_bytes += endorsement.size_bytes;
-                        leios_blocks_with_endorsements += 1;
-
-                        let mut block_leios_txs = vec![];
-                        let mut eb_queue = vec![endorsement.eb.id.clone()];
-                        while let Some(eb_id) = eb_queue.pop() {
-                            let eb = ebs.get_mut(&eb_id).unwrap();
-                            if eb.included_in_block.is_some() {
-                                continue;
-                            }
-                            eb.included_in_block = Some(time);
-
-                            eb_queue.extend(eb.ebs.iter().cloned());
-
-                            for ib_id in &eb.ibs {
-                                let ib = ibs.get_mut(ib_id).unwrap();
-                                if ib.included_in_block.is_none() {
-                                    ib.included_in_block = Some(time);
-                                }
-                                for tx_id in &ib.txs {
-                                    block_leios_txs.push(*tx_id);
-                                    let tx = txs.get_mut(tx_id).unwrap();
-                                    if tx.included_in_block.is_none() {
-                                        tx.included_in_block = Some(time);
-                                        tx.tx_type = Some(TransactionType::Leios);
-                                    }
-                                }
-                            }
-                            for tx_id in &eb.txs {
-                                block_leios_txs.push(*tx_id);
-                                let tx = txs.get_mut(tx_id).unwrap();
-                                if tx.included_in_block.is_none() {
-                                    tx.included_in_block = Some(time);
-                                    tx.tx_type = Some(TransactionType::Leios);
-                                }
-                            }
-                        }
+    /// Handles an event which didn't come from a tracker, such as one read back from a trace.
+    pub fn handle(&self, event: Event, time: Timestamp) {
+        let watched = LivenessMonitor::KINDS.contains(&event.kind());
+        if let Some(aggregation) = &self.aggregation {
+            aggregation.handle(&event, time);
+        }
+        if watched {
+            self.recorder.handle(&event, time);
+            self.liveness.handle_owned(event, time);
+        } else {
+            self.recorder.handle_owned(event, time);
+        }
+    }
 
-                        total_leios_txs += block_leios_txs.len() as u64;
-                        let unique_block_leios_txs =
-                            block_leios_txs.iter().copied().sorted().dedup().count();
-                        info!(
-                            "This block had an additional {} leios tx(s) ({} unique).",
-                            block_leios_txs.len(),
-                            unique_block_leios_txs,
-                        );
-                    }
-                    for tx_id in &transactions {
-                        let tx = txs.get_mut(tx_id).unwrap();
-                        if tx.included_in_block.is_none() {
-                            tx.included_in_block = Some(time);
-                            tx.tx_type = Some(TransactionType::Praos);
-                        }
-                    }
-                    if let Some((old_producer, old_vrf)) = blocks.get(&slot) {
-                        if *old_vrf > vrf {
-                            *blocks_published.entry(producer.id).or_default() += 1;
-                            *blocks_published.entry(*old_producer).or_default() -= 1;
-                            *blocks_rejected.entry(*old_producer).or_default() += 1;
-                            blocks.insert(slot, (producer.id, vrf));
-                        } else {
-                            *blocks_rejected.entry(producer.id).or_default() += 1;
-                        }
-                    } else {
-                        *blocks_published.entry(producer.id).or_default() += 1;
-                        blocks.insert(slot, (producer.id, vrf));
-                    }
-                }
-                Event::RBSent { .. } => {}
-                Event::RBReceived { .. } => {}
-                Event::IBLotteryWon { .. } => {}
-                Event::IBGenerated {
-                    id,
-                    header_bytes,
-                    size_bytes,
-                    transactions,
-                    shard,
-                    ..
-                } => {
-                    ibs.insert(
-                        id.clone(),
-                        InputBlock::new(size_bytes, time, transactions.clone()),
-                    );
-                    total_leios_bytes += size_bytes;
-                    let mut tx_bytes = header_bytes;
-                    for tx_id in &transactions {
-                        *ibs_containing_tx.entry(*tx_id).or_default() += 1.;
-                        let tx = txs.get_mut(tx_id).unwrap();
-                        tx_bytes += tx.bytes;
-                        if tx.included_in_ib.is_none() {
-                            tx.included_in_ib = Some(time);
-                        }
-                    }
-                    *seen_ibs.entry(id.producer.id).or_default() += 1.;
-                    info!(
-                        "Pool {} generated an IB in shard {} with {} transaction(s) in slot {} ({}).",
-                        id.producer,
-                        shard,
-                        transactions.len(),
-                        id.slot,
-                        pretty_bytes(tx_bytes, pbo.clone()),
-                    )
-                }
-                Event::NoIBGenerated { .. } => {}
-                Event::IBSent { .. } => {
-                    ib_messages.sent += 1;
-                }
-                Event::IBReceived { recipient, .. } => {
-                    ib_messages.received += 1;
-                    *seen_ibs.entry(recipient.id).or_default() += 1.;
-                }
-                Event::EBLotteryWon { .. } => {}
-                Event::EBGenerated {
-                    id,
-                    transactions,
-                    input_blocks,
-                    endorser_blocks,
-                    size_bytes,
-                    ..
-                } => {
-                    ebs.insert(
-                        id.clone(),
-                        EndorserBlock::new(
-                            time,
-                            transactions.iter().map(|tx| tx.id).collect(),
-                            input_blocks.iter().map(|ib| ib.id.clone()).collect(),
-                            endorser_blocks.iter().map(|eb| eb.id.clone()).collect(),
-                        ),
-                    );
-                    total_leios_bytes += size_bytes;
-                    for BlockRef { id: tx_id } in &transactions {
-                        let tx = txs.get_mut(tx_id).unwrap();
-                        if tx.included_in_eb.is_none() {
-                            tx.included_in_eb = Some(time);
-                        }
-                    }
-                    for BlockRef { id: ib_id } in &input_blocks {
-                        let ib = ibs.get_mut(ib_id).unwrap();
-                        if ib.included_in_eb.is_none() {
-                            ib.included_in_eb = Some(time);
-                        }
-                        *ebs_containing_ib.entry(ib_id.clone()).or_default() += 1.0;
-                        for tx_id in &ib.txs {
-                            let tx = txs.get_mut(tx_id).unwrap();
-                            if tx.included_in_eb.is_none() {
-                                tx.included_in_eb = Some(time);
-                            }
-                        }
-                    }
-                    info!(
-                        "Pool {} generated an EB with {} IB(s) and {} TX(s) in slot {}.",
-                        id.producer,
-                        input_blocks.len(),
-                        transactions.len(),
-                        id.slot,
-                    )
-                }
-                Event::NoEBGenerated { .. } => {}
-                Event::EBSent { .. } => {
-                    eb_messages.sent += 1;
-                }
-                Event::EBReceived { .. } => {
-                    eb_messages.received += 1;
-                }
-                Event::VTLotteryWon { .. } => {}
-                Event::VTBundleGenerated { id, votes, .. } => {
-                    for (eb, count) in votes.0 {
-                        total_votes += count as u64;
-                        *votes_per_bundle.entry(id.clone()).or_default() += count as f64;
-                        *eb_votes.entry(eb).or_default() += count as f64;
-                        *votes_per_pool.entry(id.producer.id).or_default() += count as f64;
-                    }
-                }
-                Event::NoVTBundleGenerated { .. } => {}
-                Event::VTBundleNotGenerated { .. } => {}
-                Event::VTBundleSent { .. } => {
-                    vote_messages.sent += 1;
-                }
-                Event::VTBundleReceived { .. } => {
-                    vote_messages.received += 1;
-                }
+    // Waits for the trace to be written, including any aggregated stats at the end,
+    // then reports on everything the monitor has seen.
+    pub async fn finish(mut self) -> Result<RunReport> {
+        if let Some(aggregation) = &self.aggregation {
+            let aggregator = std::mem::take(&mut *aggregation.aggregator.lock().unwrap());
+            if let Some(summary) = aggregator.finish() {
+                aggregation.writer.send(TraceLine::Aggregated(summary));
             }
         }
+        if let Some((writer, handle)) = self.writer.take() {
+            writer.send(TraceLine::End);
+            task::spawn_blocking(move || handle.join())
+                .await?
+                .map_err(|_| anyhow!("the trace writer panicked"))??;
+        }
+        let stats = std::mem::take(&mut *self.recorder.stats.lock().unwrap());
+        Ok(self.report(stats))
+    }
 
-        output.flush().await?;
+    fn report(&self, stats: RunStats) -> RunReport {
+        let RunStats {
+            last_timestamp,
+            total_slots,
+            blocks,
+            rollback_depths,
+            txs,
+            ibs,
+            ebs,
+            seen_ibs,
+            ibs_containing_tx,
+            ebs_containing_ib,
+            votes_per_bundle,
+            mut votes_per_pool,
+            eb_votes,
+            total_votes,
+            leios_blocks_with_endorsements,
+            total_leios_txs,
+            total_leios_bytes,
+            tx_messages,
+            rb_messages,
+            ib_messages,
+            eb_messages,
+            vote_messages,
+        } = stats;
+        for id in &self.pool_ids {
+            votes_per_pool.entry(*id).or_default();
+        }
+        let pbo = pretty_bytes_options();
 
         let mut finalized_txs = 0;
         let mut finalized_tx_bytes = 0;
@@ -416,16 +230,88 @@ impl EventMonitor {
             }
         }
 
+        // The final chain is whichever fork the Praos chain selection rule would pick at the end of the run.
+        let mut chain: BTreeMap<BlockId, &RankingBlock> = BTreeMap::new();
+        let mut cursor = blocks
+            .iter()
+            .max_by_key(|(_, block)| (block.height, Reverse(block.vrf)))
+            .map(|(id, _)| *id);
+        while let Some((id, block)) = cursor.and_then(|id| Some((id, blocks.get(&id)?))) {
+            chain.insert(id, block);
+            cursor = block.parent;
+        }
+        let endorsed_on_chain: BTreeSet<&EndorserBlockId> = chain
+            .values()
+            .filter_map(|block| block.endorsed_eb.as_ref())
+            .collect();
+        let mut blocks_published: BTreeMap<NodeId, u64> = BTreeMap::new();
+        let mut blocks_orphaned: BTreeMap<NodeId, u64> = BTreeMap::new();
+        let mut certs_lost = 0;
+        for (id, block) in &blocks {
+            if chain.contains_key(id) {
+                *blocks_published.entry(id.producer).or_default() += 1;
+                continue;
+            }
+            *blocks_orphaned.entry(id.producer).or_default() += 1;
+            if block
+                .endorsed_eb
+                .as_ref()
+                .is_some_and(|eb| !endorsed_on_chain.contains(eb))
+            {
+                certs_lost += 1;
+            }
+        }
+        let fork_depths = compute_stats(rollback_depths.iter().map(|d| *d as f64));
+        let mut summary = RunSummary {
+            slots: total_slots,
+            txs_generated: txs.len() as u64,
+            txs_finalized: finalized_txs,
+            tx_throughput_bytes_per_second: finalized_tx_bytes as f64 / total_slots as f64,
+            ibs_generated: ibs.len() as u64,
+            ebs_generated: ebs.len() as u64,
+            eb_certification_rate: endorsed_on_chain.len() as f64 / ebs.len() as f64,
+            praos_blocks_on_chain: chain.len() as u64,
+            praos_blocks_orphaned: (blocks.len() - chain.len()) as u64,
+            rollbacks: rollback_depths.len() as u64,
+            ..RunSummary::default()
+        };
+        let praos = PraosReport {
+            txs_generated: txs.len() as u64,
+            blocks_published: blocks.len() as u64,
+            blocks_on_chain: chain.len() as u64,
+            empty_slots: total_slots - chain.len() as u64,
+            blocks_orphaned: (blocks.len() - chain.len()) as u64,
+            orphaned_blocks_with_lost_certs: certs_lost,
+            rollbacks: rollback_depths.len() as u64,
+            rollback_depth: fork_depths.clone(),
+            txs_finalized: finalized_txs,
+            tx_bytes_finalized: finalized_tx_bytes,
+            txs_pending: pending_txs,
+            tx_bytes_pending: pending_tx_bytes,
+        };
+
         info_span!("praos").in_scope(|| {
             info!("{} transactions(s) were generated in total.", txs.len());
-            info!("{} naive praos block(s) were published.", blocks.len());
+            info!("{} praos block(s) were published, and {} of them reached the final chain.", blocks.len(), chain.len());
+            info!(
+                "{} slot(s) had no praos blocks on the final chain.",
+                total_slots - chain.len() as u64
+            );
+            info!(
+                "{} praos block(s) were orphaned by forks; {} of them carried a certificate for an EB which never reached the final chain.",
+                blocks.len() - chain.len(),
+                certs_lost,
+            );
             info!(
-                "{} slot(s) had no naive praos blocks.",
-                total_slots - blocks.len() as u64
+                "Nodes rolled back their chains {} time(s); the deepest rollback was {} block(s) (mean {:.3}, stddev {:.3}).",
+                rollback_depths.len(),
+                rollback_depths.iter().max().copied().unwrap_or_default(),
+                fork_depths.mean,
+                fork_depths.std_dev,
             );
-            info!("{} transaction(s) ({}) finalized in a naive praos block.", finalized_txs, pretty_bytes(finalized_tx_bytes, pbo.clone()));
+            info!("{} transaction(s) ({}) finalized in a praos block.", finalized_txs, pretty_bytes(finalized_tx_bytes, pbo.clone()));
             info!(
-                "{} transaction(s) ({}) did not reach a naive praos block.",
+                "{} transaction(s) ({}) did not reach a praos block.",
                 pending_txs,
                 pretty_bytes(
                     pending_tx_bytes,
@@ -435,15 +321,15 @@ impl EventMonitor {
 
             for id in &self.node_ids {
                 if let Some(published) = blocks_published.get(id) {
-                    info!("Pool {id} published {published} naive praos block(s)");
+                    info!("Pool {id} published {published} praos block(s) on the final chain.");
                 }
-                if let Some(rejected) = blocks_rejected.get(id) {
-                    info!("Pool {id} failed to publish {rejected} naive praos block(s) due to slot battles.");
+                if let Some(orphaned) = blocks_orphaned.get(id) {
+                    info!("Pool {id} had {orphaned} praos block(s) orphaned by forks.");
                 }
             }
         });
 
-        info_span!("leios").in_scope(|| {
+        let leios = info_span!("leios").in_scope(|| {
             let times_to_reach_ib: Vec<_> = txs
                 .values()
                 .filter_map(|tx| {
@@ -484,10 +370,15 @@ impl EventMonitor {
                     .iter()
                     .map(|id| seen_ibs.get(id).copied().unwrap_or_default()),
             );
-            let votes_per_pool = compute_stats(votes_per_pool.into_values());
+            let votes_per_pool = compute_stats(votes_per_pool.values().copied());
             let votes_per_eb = compute_stats(eb_votes.into_values());
             let votes_per_bundle = compute_stats(votes_per_bundle.into_values());
             let space_efficiency = leios_tx_bytes as f64 / total_leios_bytes as f64;
+            summary.tx_time_to_ib_s = ib_time_stats.mean;
+            summary.tx_time_to_eb_s = eb_time_stats.mean;
+            summary.tx_time_to_block_s = block_time_stats.mean;
+            summary.votes_per_eb = votes_per_eb.mean;
+            summary.space_efficiency = space_efficiency;
 
             info!(
                 "{} IB(s) were generated, on average {:.3} IB(s) per slot.",
@@ -580,19 +471,521 @@ impl EventMonitor {
                 "Each transaction took an average of {:.3}s (stddev {:.3}) to be included in a block.",
                 block_time_stats.mean, block_time_stats.std_dev,
             );
+
+            LeiosReport {
+                ibs_generated: ibs.len() as u64,
+                ibs_per_slot: ibs.len() as f64 / total_slots as f64,
+                empty_ibs: ibs.values().filter(|ib| ib.is_empty()).count() as u64,
+                txs_in_ibs: times_to_reach_ib.len() as u64,
+                pending_tx_age_s: avg_age_stats,
+                ibs_per_tx,
+                txs_per_ib,
+                bytes_per_ib,
+                ibs_received_per_node: ibs_received,
+                ebs_generated: ebs.len() as u64,
+                ebs_per_slot: ebs.len() as f64 / total_slots as f64,
+                empty_ebs: empty_ebs as u64,
+                ibs_per_eb,
+                ebs_per_ib,
+                ibs_in_ebs: ibs.values().filter(|ib| ib.included_in_eb.is_some()).count() as u64,
+                expired_ibs: expired_ibs as u64,
+                expired_ebs: expired_ebs as u64,
+                txs_in_ebs: times_to_reach_eb.len() as u64,
+                votes_generated: total_votes,
+                votes_per_pool,
+                votes_per_eb,
+                vote_bundles: bundle_count as u64,
+                votes_per_bundle,
+                blocks_with_endorsements: leios_blocks_with_endorsements,
+                endorsed_txs: leios_txs,
+                endorsed_tx_bytes: leios_tx_bytes,
+                praos_txs,
+                praos_tx_bytes,
+                redundant_endorsed_txs: total_leios_txs - leios_txs,
+                space_efficiency,
+                tx_time_to_ib_s: ib_time_stats,
+                tx_time_to_eb_s: eb_time_stats,
+                tx_time_to_block_s: block_time_stats,
+            }
         });
 
+        let pools = self
+            .node_ids
+            .iter()
+            .filter_map(|id| {
+                let blocks_published = blocks_published.get(id).copied().unwrap_or_default();
+                let blocks_orphaned = blocks_orphaned.get(id).copied().unwrap_or_default();
+                let votes = votes_per_pool.get(id).copied();
+                if blocks_published == 0 && blocks_orphaned == 0 && votes.is_none() {
+                    return None;
+                }
+                Some(PoolReport {
+                    pool: id.to_inner(),
+                    blocks_published,
+                    blocks_orphaned,
+                    votes: votes.unwrap_or_default() as u64,
+                })
+            })
+            .collect();
+
         info_span!("network").in_scope(|| {
             tx_messages.display("TX");
+            rb_messages.display("RB");
             ib_messages.display("IB");
             eb_messages.display("EB");
             vote_messages.display("Vote");
         });
+        let network = [
+            ("tx", tx_messages),
+            ("rb", rb_messages),
+            ("ib", ib_messages),
+            ("eb", eb_messages),
+            ("vote", vote_messages),
+        ]
+        .into_iter()
+        .map(|(name, stats)| (name.to_string(), stats))
+        .collect();
 
-        Ok(())
+        RunReport {
+            summary,
+            praos,
+            leios,
+            pools,
+            network,
+        }
     }
 }
 
+fn pretty_bytes_options() -> Option<PrettyBytesOptions> {
+    Some(PrettyBytesOptions {
+        use_1024_instead_of_1000: Some(false),
+        number_of_decimal: Some(2),
+        remove_zero_decimal: Some(true),
+    })
+}
+
+/// Everything the report is built from, gathered as events are tracked.
+#[derive(Default)]
+struct RunStats {
+    last_timestamp: Timestamp,
+    total_slots: u64,
+    blocks: BTreeMap<BlockId, RankingBlock>,
+    rollback_depths: Vec<usize>,
+    txs: BTreeMap<TransactionId, Transaction>,
+    ibs: BTreeMap<InputBlockId, InputBlock>,
+    ebs: BTreeMap<EndorserBlockId, EndorserBlock>,
+    seen_ibs: BTreeMap<NodeId, f64>,
+    ibs_containing_tx: BTreeMap<TransactionId, f64>,
+    ebs_containing_ib: BTreeMap<InputBlockId, f64>,
+    votes_per_bundle: BTreeMap<VoteBundleId, f64>,
+    votes_per_pool: BTreeMap<NodeId, f64>,
+    eb_votes: BTreeMap<EndorserBlockId, f64>,
+    total_votes: u64,
+    leios_blocks_with_endorsements: u64,
+    total_leios_txs: u64,
+    total_leios_bytes: u64,
+    tx_messages: MessageStats,
+    rb_messages: MessageStats,
+    ib_messages: MessageStats,
+    eb_messages: MessageStats,
+    vote_messages: MessageStats,
+}
+
+impl RunStats {
+    fn record(&mut self, event: Event, time: Timestamp) {
+        let RunStats {
+            last_timestamp,
+            total_slots,
+            blocks,
+            rollback_depths,
+            txs,
+            ibs,
+            ebs,
+            seen_ibs,
+            ibs_containing_tx,
+            ebs_containing_ib,
+            votes_per_bundle,
+            votes_per_pool,
+            eb_votes,
+            total_votes,
+            leios_blocks_with_endorsements,
+            total_leios_txs,
+            total_leios_bytes,
+            tx_messages,
+            rb_messages,
+            ib_messages,
+            eb_messages,
+            vote_messages,
+        } = self;
+        *last_timestamp = time;
+        let pbo = pretty_bytes_options();
+        match event {
+            Event::GlobalSlot { slot: number } => {
+                info!("Slot {number} has begun.");
+                *total_slots = number + 1;
+            }
+            Event::Slot { .. } => {}
+            Event::CpuTaskScheduled { .. } => {}
+            Event::CpuTaskFinished { .. } => {}
+            Event::Cpu { .. } => {}
+            Event::TXGenerated { id, size_bytes, .. } => {
+                txs.insert(id, Transaction::new(size_bytes, time));
+            }
+            Event::TXSent { msg_size_bytes, .. } => {
+                tx_messages.sent += 1;
+                tx_messages.bytes_sent += msg_size_bytes;
+            }
+            Event::TXReceived { .. } => {
+                tx_messages.received += 1;
+            }
+            Event::TXLost { .. } => {}
+            Event::RBLotteryWon { .. } => {}
+            Event::RBGenerated {
+                id: BlockId { slot, producer },
+                vrf,
+                parent,
+                endorsement,
+                transactions,
+                ..
+            } => {
+                info!(
+                    "Pool {} produced a praos block in slot {slot} with {} tx(s).",
+                    producer,
+                    transactions.len()
+                );
+                let parent = parent.map(|BlockRef { id }| BlockId {
+                    slot: id.slot,
+                    producer: id.producer.id,
+                });
+                // A run resumed from a checkpoint can refer to things from before it started,
+                // which are left out of the statistics.
+                let height = parent
+                    .and_then(|id| blocks.get(&id))
+                    .map_or(0, |block| block.height)
+                    + 1;
+                blocks.insert(
+                    BlockId {
+                        slot,
+                        producer: producer.id,
+                    },
+                    RankingBlock {
+                        parent,
+                        height,
+                        vrf,
+                        endorsed_eb: endorsement.as_ref().map(|e| e.eb.id.clone()),
+                    },
+                );
+                if let Some(endorsement) = endorsement {
+                    *total_leios_bytes += endorsement.size_bytes;
+                    *leios_blocks_with_endorsements += 1;
+
+                    let mut block_leios_txs = vec![];
+                    let mut eb_queue = vec![endorsement.eb.id.clone()];
+                    while let Some(eb_id) = eb_queue.pop() {
+                        let Some(eb) = ebs.get_mut(&eb_id) else {
+                            continue;
+                        };
+                        if eb.included_in_block.is_some() {
+                            continue;
+                        }
+                        eb.included_in_block = Some(time);
+
+                        eb_queue.extend(eb.ebs.iter().cloned());
+
+                        for ib_id in &eb.ibs {
+                            let Some(ib) = ibs.get_mut(ib_id) else {
+                                continue;
+                            };
+                            if ib.included_in_block.is_none() {
+                                ib.included_in_block = Some(time);
+                            }
+                            for tx_id in &ib.txs {
+                                block_leios_txs.push(*tx_id);
+                                let Some(tx) = txs.get_mut(tx_id) else {
+                                    continue;
+                                };
+                                if tx.included_in_block.is_none() {
+                                    tx.included_in_block = Some(time);
+                                    tx.tx_type = Some(TransactionType::Leios);
+                                }
+                            }
+                        }
+                        for tx_id in &eb.txs {
+                            block_leios_txs.push(*tx_id);
+                            let Some(tx) = txs.get_mut(tx_id) else {
+                                continue;
+                            };
+                            if tx.included_in_block.is_none() {
+                                tx.included_in_block = Some(time);
+                                tx.tx_type = Some(TransactionType::Leios);
+                            }
+                        }
+                    }
+
+                    *total_leios_txs += block_leios_txs.len() as u64;
+                    let unique_block_leios_txs =
+                        block_leios_txs.iter().copied().sorted().dedup().count();
+                    info!(
+                        "This block had an additional {} leios tx(s) ({} unique).",
+                        block_leios_txs.len(),
+                        unique_block_leios_txs,
+                    );
+                }
+                for tx_id in &transactions {
+                    let Some(tx) = txs.get_mut(tx_id) else {
+                        continue;
+                    };
+                    if tx.included_in_block.is_none() {
+                        tx.included_in_block = Some(time);
+                        tx.tx_type = Some(TransactionType::Praos);
+                    }
+                }
+            }
+            Event::RBSent { msg_size_bytes, .. } => {
+                rb_messages.sent += 1;
+                rb_messages.bytes_sent += msg_size_bytes;
+            }
+            Event::RBReceived { .. } => {
+                rb_messages.received += 1;
+            }
+            Event::RBRolledBack { depth, .. } => {
+                rollback_depths.push(depth);
+            }
+            Event::IBLotteryWon { .. } => {}
+            Event::IBGenerated {
+                id,
+                header_bytes,
+                size_bytes,
+                transactions,
+                shard,
+                ..
+            } => {
+                ibs.insert(
+                    id.clone(),
+                    InputBlock::new(size_bytes, time, transactions.clone()),
+                );
+                *total_leios_bytes += size_bytes;
+                let mut tx_bytes = header_bytes;
+                for tx_id in &transactions {
+                    *ibs_containing_tx.entry(*tx_id).or_default() += 1.;
+                    let Some(tx) = txs.get_mut(tx_id) else {
+                        continue;
+                    };
+                    tx_bytes += tx.bytes;
+                    if tx.included_in_ib.is_none() {
+                        tx.included_in_ib = Some(time);
+                    }
+                }
+                *seen_ibs.entry(id.producer.id).or_default() += 1.;
+                info!(
+                    "Pool {} generated an IB in shard {} with {} transaction(s) in slot {} ({}).",
+                    id.producer,
+                    shard,
+                    transactions.len(),
+                    id.slot,
+                    pretty_bytes(tx_bytes, pbo.clone()),
+                )
+            }
+            Event::NoIBGenerated { .. } => {}
+            Event::IBSent { msg_size_bytes, .. } => {
+                ib_messages.sent += 1;
+                ib_messages.bytes_sent += msg_size_bytes;
+            }
+            Event::IBReceived { recipient, .. } => {
+                ib_messages.received += 1;
+                *seen_ibs.entry(recipient.id).or_default() += 1.;
+            }
+            Event::EBLotteryWon { .. } => {}
+            Event::EBGenerated {
+                id,
+                transactions,
+                input_blocks,
+                endorser_blocks,
+                size_bytes,
+                ..
+            } => {
+                ebs.insert(
+                    id.clone(),
+                    EndorserBlock::new(
+                        time,
+                        transactions.iter().map(|tx| tx.id).collect(),
+                        input_blocks.iter().map(|ib| ib.id.clone()).collect(),
+                        endorser_blocks.iter().map(|eb| eb.id.clone()).collect(),
+                    ),
+                );
+                *total_leios_bytes += size_bytes;
+                for BlockRef { id: tx_id } in &transactions {
+                    let Some(tx) = txs.get_mut(tx_id) else {
+                        continue;
+                    };
+                    if tx.included_in_eb.is_none() {
+                        tx.included_in_eb = Some(time);
+                    }
+                }
+                for BlockRef { id: ib_id } in &input_blocks {
+                    let Some(ib) = ibs.get_mut(ib_id) else {
+                        continue;
+                    };
+                    if ib.included_in_eb.is_none() {
+                        ib.included_in_eb = Some(time);
+                    }
+                    *ebs_containing_ib.entry(ib_id.clone()).or_default() += 1.0;
+                    for tx_id in &ib.txs {
+                        let Some(tx) = txs.get_mut(tx_id) else {
+                            continue;
+                        };
+                        if tx.included_in_eb.is_none() {
+                            tx.included_in_eb = Some(time);
+                        }
+                    }
+                }
+                info!(
+                    "Pool {} generated an EB with {} IB(s) and {} TX(s) in slot {}.",
+                    id.producer,
+                    input_blocks.len(),
+                    transactions.len(),
+                    id.slot,
+                )
+            }
+            Event::NoEBGenerated { .. } => {}
+            Event::EBSent { msg_size_bytes, .. } => {
+                eb_messages.sent += 1;
+                eb_messages.bytes_sent += msg_size_bytes;
+            }
+            Event::EBReceived { .. } => {
+                eb_messages.received += 1;
+            }
+            Event::VTLotteryWon { .. } => {}
+            Event::VTBundleGenerated { id, votes, .. } => {
+                for (eb, count) in votes.0 {
+                    *total_votes += count as u64;
+                    *votes_per_bundle.entry(id.clone()).or_default() += count as f64;
+                    *eb_votes.entry(eb).or_default() += count as f64;
+                    *votes_per_pool.entry(id.producer.id).or_default() += count as f64;
+                }
+            }
+            Event::NoVTBundleGenerated { .. } => {}
+            Event::VTBundleNotGenerated { .. } => {}
+            Event::VTBundleSent { msg_size_bytes, .. } => {
+                vote_messages.sent += 1;
+                vote_messages.bytes_sent += msg_size_bytes;
+            }
+            Event::VTBundleReceived { .. } => {
+                vote_messages.received += 1;
+            }
+        }
+    }
+}
+
+// Passes every event to the stats, and to the trace unless it's aggregated.
+#[derive(Clone)]
+struct Recorder {
+    stats: Arc<Mutex<RunStats>>,
+    writer: Option<TraceWriter>,
+}
+
+impl EventSink for Recorder {
+    fn handle(&self, event: &Event, time: Timestamp) {
+        self.handle_owned(event.clone(), time);
+    }
+
+    fn handle_owned(&self, event: Event, time: Timestamp) {
+        if let Some(writer) = &self.writer {
+            writer.send(TraceLine::Event(OutputEvent {
+                time_s: time,
+                message: event.clone(),
+            }));
+        }
+        self.stats.lock().unwrap().record(event, time);
+    }
+}
+
+// Records the events which the liveness monitor adds, right after whichever event caused them.
+#[derive(Clone)]
+struct Liveness {
+    monitor: Arc<Mutex<LivenessMonitor>>,
+    recorder: Recorder,
+}
+
+impl EventSink for Liveness {
+    fn handle(&self, event: &Event, time: Timestamp) {
+        let lost = self.monitor.lock().unwrap().handle(event, time);
+        for (event, time) in lost {
+            self.recorder.handle_owned(event, time);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Aggregation {
+    aggregator: Arc<Mutex<TraceAggregator>>,
+    writer: TraceWriter,
+}
+
+impl EventSink for Aggregation {
+    fn handle(&self, event: &Event, time: Timestamp) {
+        self.handle_owned(event.clone(), time);
+    }
+
+    fn handle_owned(&self, event: Event, time: Timestamp) {
+        let event = OutputEvent {
+            time_s: time,
+            message: event,
+        };
+        if let Some(summary) = self.aggregator.lock().unwrap().process(event) {
+            self.writer.send(TraceLine::Aggregated(summary));
+        }
+    }
+}
+
+enum TraceLine {
+    Event(OutputEvent),
+    Aggregated(AggregatedData),
+    End,
+}
+
+// Hands lines to the thread which writes the trace. Once the buffer is full, waits for the
+// thread to catch up, which holds up whichever actor tracked the event.
+#[derive(Clone)]
+struct TraceWriter(std_mpsc::SyncSender<TraceLine>);
+
+impl TraceWriter {
+    fn send(&self, line: TraceLine) {
+        // If the writer has stopped, it will report why once the run is over
+        let _ = self.0.send(line);
+    }
+}
+
+fn write_trace(
+    mut output: OutputTarget,
+    source: std_mpsc::Receiver<TraceLine>,
+    live: bool,
+) -> Result<()> {
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    while let Ok(line) = source.recv() {
+        let finished = runtime.block_on(async {
+            for line in iter::once(line).chain(source.try_iter()) {
+                match line {
+                    TraceLine::Event(event) => output.write(event).await?,
+                    TraceLine::Aggregated(summary) => output.write_aggregated(summary).await?,
+                    TraceLine::End => return Ok(true),
+                }
+            }
+            if live {
+                // When running in real time, make events visible to whoever's following the trace
+                output.flush_buffered().await?;
+            }
+            Ok::<_, anyhow::Error>(false)
+        })?;
+        if finished {
+            break;
+        }
+    }
+    runtime.block_on(output.flush())
+}
+
 #[derive(Clone)]
 struct Transaction {
     bytes: u64,
@@ -615,6 +1008,13 @@ impl Transaction {
     }
 }
 
+struct RankingBlock {
+    parent: Option<BlockId>,
+    height: u64,
+    vrf: u64,
+    endorsed_eb: Option<EndorserBlockId>,
+}
+
 #[derive(Clone, Copy)]
 enum TransactionType {
     Leios,
@@ -671,63 +1071,88 @@ impl EndorserBlock {
     }
 }
 
-#[derive(Default)]
-struct MessageStats {
-    sent: u64,
-    received: u64,
-}
-impl MessageStats {
-    fn display(&self, name: &str) {
-        let percent_received = self.received as f64 / self.sent as f64 * 100.0;
-        info!(
-            "{} {} message(s) were sent. {} of them were received ({:.3}%).",
-            self.sent, name, self.received, percent_received
-        );
-    }
-}
-
-struct Stats {
-    mean: f64,
-    std_dev: f64,
-}
-
-fn compute_stats<Iter: IntoIterator<Item = f64>>(data: Iter) -> Stats {
-    let v: Variance = data.into_iter().collect();
-    Stats {
-        mean: v.mean(),
-        std_dev: v.population_variance().sqrt(),
-    }
-}
-
 #[allow(clippy::large_enum_variant)]
 enum OutputTarget {
-    AggregatedEventStream {
-        aggregation: TraceAggregator,
-        format: OutputFormat,
-        file: TraceSink,
-    },
     EventStream {
         format: OutputFormat,
         file: TraceSink,
     },
+    Columnar(ColumnarTrace),
     None,
 }
 
+// Parquet traces are written as a directory, with one file for each table
+fn is_parquet(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "parquet")
+}
+
 impl OutputTarget {
+    async fn open(output_path: Option<PathBuf>, aggregate: bool) -> Result<Self> {
+        let Some(mut path) = output_path else {
+            return Ok(Self::None);
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        if is_parquet(&path) {
+            if aggregate {
+                bail!("aggregated traces can't be written as parquet");
+            }
+            return Ok(Self::Columnar(ColumnarTrace::create(&path).await?));
+        }
+        if path.to_str().is_some_and(|p| p.ends_with(".parquet.gz")) {
+            bail!("parquet traces are already compressed, and can't be gzipped");
+        }
+        let file = File::create(&path).await?;
+
+        let mut gzipped = false;
+        if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| ext == "gz")
+        {
+            path.set_extension("");
+            gzipped = true;
+        }
+
+        let file: TraceSink = if gzipped {
+            let encoder = GzipEncoder::new(file);
+            Box::pin(BufWriter::new(encoder))
+        } else {
+            Box::pin(BufWriter::new(file))
+        };
+
+        let format = if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| ext == "cbor")
+        {
+            OutputFormat::CborStream
+        } else {
+            OutputFormat::JsonStream
+        };
+        Ok(Self::EventStream { format, file })
+    }
+
     async fn write(&mut self, event: OutputEvent) -> Result<()> {
         match self {
-            Self::AggregatedEventStream {
-                aggregation,
-                format,
-                file,
-            } => {
-                if let Some(summary) = aggregation.process(event) {
-                    Self::write_line(*format, file, summary).await?;
-                }
-            }
             Self::EventStream { format, file } => {
                 Self::write_line(*format, file, event).await?;
             }
+            Self::Columnar(trace) => {
+                trace.write(&event.message, event.time_s).await?;
+            }
+            Self::None => {}
+        }
+        Ok(())
+    }
+
+    async fn write_aggregated(&mut self, summary: AggregatedData) -> Result<()> {
+        match self {
+            Self::EventStream { format, file } => {
+                Self::write_line(*format, file, summary).await?;
+            }
+            Self::Columnar(_) => bail!("aggregated traces can't be written as parquet"),
             Self::None => {}
         }
         Ok(())
@@ -752,21 +1177,26 @@ impl OutputTarget {
         Ok(())
     }
 
-    async fn flush(self) -> Result<()> {
+    async fn flush_buffered(&mut self) -> Result<()> {
         match self {
-            Self::AggregatedEventStream {
-                aggregation,
-                format,
-                mut file,
-            } => {
-                if let Some(summary) = aggregation.finish() {
-                    Self::write_line(format, &mut file, summary).await?;
-                }
-                file.shutdown().await?;
+            Self::EventStream { file, .. } => {
+                file.flush().await?;
             }
+            // Nothing in a parquet file can be read until it's finished
+            Self::Columnar(_) => {}
+            Self::None => {}
+        }
+        Ok(())
+    }
+
+    async fn flush(self) -> Result<()> {
+        match self {
             Self::EventStream { mut file, .. } => {
                 file.shutdown().await?;
             }
+            Self::Columnar(trace) => {
+                trace.close().await?;
+            }
             Self::None => {}
         };
         Ok(())
@@ -0,0 +1,935 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use arrow::datatypes::{FieldRef, Schema};
+use parquet::{arrow::AsyncArrowWriter, basic::Compression, file::properties::WriterProperties};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_arrow::schema::{SchemaLike, TracingOptions};
+use sim_core::{
+    clock::Timestamp,
+    events::{Event, Node},
+};
+use tokio::fs::{self, File};
+
+/// How many rows of a table are held in memory before they're written out.
+const ROWS_PER_BATCH: usize = 65_536;
+
+/// Writes a trace as a directory of Parquet files, with one table for each family of events.
+/// Every row has the time and type of its event, and the node it happened at;
+/// the other columns are only filled in for the types of event which have them.
+pub struct ColumnarTrace {
+    slot: Table<SlotRow>,
+    cpu: Table<CpuRow>,
+    tx: Table<TxRow>,
+    rb: Table<RbRow>,
+    ib: Table<IbRow>,
+    eb: Table<EbRow>,
+    vt: Table<VtRow>,
+}
+
+impl ColumnarTrace {
+    pub async fn create(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).await?;
+        Ok(Self {
+            slot: Table::new(dir, "slot")?,
+            cpu: Table::new(dir, "cpu")?,
+            tx: Table::new(dir, "tx")?,
+            rb: Table::new(dir, "rb")?,
+            ib: Table::new(dir, "ib")?,
+            eb: Table::new(dir, "eb")?,
+            vt: Table::new(dir, "vt")?,
+        })
+    }
+
+    pub async fn write(&mut self, event: &Event, time: Timestamp) -> Result<()> {
+        let time_s = (time - Timestamp::zero()).as_secs_f64();
+        let kind = event.kind().to_string();
+        match event {
+            Event::GlobalSlot { slot } => {
+                self.slot
+                    .push(SlotRow {
+                        time_s,
+                        kind,
+                        node: None,
+                        slot: *slot,
+                    })
+                    .await
+            }
+            Event::Slot { node, slot } => {
+                self.slot
+                    .push(SlotRow {
+                        time_s,
+                        kind,
+                        node: Some(name(node)),
+                        slot: *slot,
+                    })
+                    .await
+            }
+            Event::CpuTaskScheduled {
+                task,
+                task_type,
+                subtasks,
+            } => {
+                self.cpu
+                    .push(CpuRow {
+                        time_s,
+                        kind,
+                        node: name(&task.node),
+                        task_index: task.index,
+                        task_type: task_type.clone(),
+                        subtasks: Some(*subtasks as u64),
+                        ..CpuRow::default()
+                    })
+                    .await
+            }
+            Event::CpuTaskFinished {
+                task,
+                task_type,
+                cpu_time_s,
+                wall_time_s,
+                extra,
+            } => {
+                self.cpu
+                    .push(CpuRow {
+                        time_s,
+                        kind,
+                        node: name(&task.node),
+                        task_index: task.index,
+                        task_type: task_type.clone(),
+                        cpu_time_s: Some(cpu_time_s.as_secs_f64()),
+                        wall_time_s: Some(wall_time_s.as_secs_f64()),
+                        extra: Some(extra.clone()),
+                        ..CpuRow::default()
+                    })
+                    .await
+            }
+            Event::Cpu {
+                task,
+                cpu_time_s,
+                task_label,
+                task_type,
+                ..
+            } => {
+                self.cpu
+                    .push(CpuRow {
+                        time_s,
+                        kind,
+                        node: name(&task.node),
+                        task_index: task.index,
+                        task_type: task_type.clone(),
+                        cpu_time_s: Some(cpu_time_s.as_secs_f64()),
+                        task_label: Some(task_label.clone()),
+                        ..CpuRow::default()
+                    })
+                    .await
+            }
+            Event::TXGenerated {
+                id,
+                publisher,
+                size_bytes,
+                shard,
+                input_id,
+                overcollateralization_factor,
+                execution_steps,
+                execution_memory,
+            } => {
+                self.tx
+                    .push(TxRow {
+                        time_s,
+                        kind,
+                        tx_id: id.to_inner(),
+                        node: Some(name(publisher)),
+                        size_bytes: Some(*size_bytes),
+                        shard: Some(*shard),
+                        input_id: Some(*input_id),
+                        overcollateralization_factor: Some(*overcollateralization_factor),
+                        execution_steps: Some(*execution_steps),
+                        execution_memory: Some(*execution_memory),
+                        ..TxRow::default()
+                    })
+                    .await
+            }
+            Event::TXSent {
+                id,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => {
+                self.tx
+                    .push(TxRow {
+                        time_s,
+                        kind,
+                        tx_id: id.to_inner(),
+                        node: Some(name(sender)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        msg_size_bytes: Some(*msg_size_bytes),
+                        ..TxRow::default()
+                    })
+                    .await
+            }
+            Event::TXReceived {
+                id,
+                sender,
+                recipient,
+            } => {
+                self.tx
+                    .push(TxRow {
+                        time_s,
+                        kind,
+                        tx_id: id.to_inner(),
+                        node: Some(name(recipient)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        ..TxRow::default()
+                    })
+                    .await
+            }
+            Event::TXLost { id, reason } => {
+                self.tx
+                    .push(TxRow {
+                        time_s,
+                        kind,
+                        tx_id: id.to_inner(),
+                        reason: Some(format!("{reason:?}")),
+                        ..TxRow::default()
+                    })
+                    .await
+            }
+            Event::RBLotteryWon { id, slot, producer } => {
+                self.rb
+                    .push(RbRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: Some(*slot),
+                        producer: Some(name(producer)),
+                        ..RbRow::default()
+                    })
+                    .await
+            }
+            Event::RBGenerated {
+                id,
+                slot,
+                producer,
+                vrf,
+                parent,
+                header_bytes,
+                size_bytes,
+                endorsement,
+                transactions,
+            } => {
+                self.rb
+                    .push(RbRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: Some(*slot),
+                        producer: Some(name(producer)),
+                        vrf: Some(*vrf),
+                        parent: parent.as_ref().map(|parent| parent.id.to_string()),
+                        header_bytes: Some(*header_bytes),
+                        size_bytes: Some(*size_bytes),
+                        tx_count: Some(transactions.len() as u64),
+                        endorsed_eb: endorsement.as_ref().map(|e| e.eb.id.to_string()),
+                        endorsement_bytes: endorsement.as_ref().map(|e| e.size_bytes),
+                        ..RbRow::default()
+                    })
+                    .await
+            }
+            Event::RBSent {
+                id,
+                slot,
+                producer,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => {
+                self.rb
+                    .push(RbRow {
+                        time_s,
+                        kind,
+                        node: name(sender),
+                        id: Some(id.to_string()),
+                        slot: Some(*slot),
+                        producer: Some(name(producer)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        msg_size_bytes: Some(*msg_size_bytes),
+                        ..RbRow::default()
+                    })
+                    .await
+            }
+            Event::RBReceived {
+                id,
+                slot,
+                producer,
+                sender,
+                recipient,
+            } => {
+                self.rb
+                    .push(RbRow {
+                        time_s,
+                        kind,
+                        node: name(recipient),
+                        id: Some(id.to_string()),
+                        slot: Some(*slot),
+                        producer: Some(name(producer)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        ..RbRow::default()
+                    })
+                    .await
+            }
+            Event::RBRolledBack {
+                node,
+                depth,
+                old_tip,
+                new_tip,
+                ..
+            } => {
+                self.rb
+                    .push(RbRow {
+                        time_s,
+                        kind,
+                        node: name(node),
+                        depth: Some(*depth as u64),
+                        old_tip: Some(old_tip.to_string()),
+                        new_tip: Some(new_tip.to_string()),
+                        ..RbRow::default()
+                    })
+                    .await
+            }
+            Event::IBLotteryWon {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+            } => {
+                self.ib
+                    .push(IbRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        index: Some(*index),
+                        ..IbRow::default()
+                    })
+                    .await
+            }
+            Event::IBGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+                shard,
+                header_bytes,
+                tx_payload_bytes,
+                size_bytes,
+                transactions,
+                rb_ref,
+            } => {
+                self.ib
+                    .push(IbRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        index: Some(*index),
+                        shard: Some(*shard),
+                        header_bytes: Some(*header_bytes),
+                        tx_payload_bytes: Some(*tx_payload_bytes),
+                        size_bytes: Some(*size_bytes),
+                        tx_count: Some(transactions.len() as u64),
+                        rb_ref: rb_ref.as_ref().map(|id| id.to_string()),
+                        ..IbRow::default()
+                    })
+                    .await
+            }
+            Event::NoIBGenerated { node, slot } => {
+                self.ib
+                    .push(IbRow {
+                        time_s,
+                        kind,
+                        node: name(node),
+                        slot: *slot,
+                        ..IbRow::default()
+                    })
+                    .await
+            }
+            Event::IBSent {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => {
+                self.ib
+                    .push(IbRow {
+                        time_s,
+                        kind,
+                        node: name(sender),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        index: Some(*index),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        msg_size_bytes: Some(*msg_size_bytes),
+                        ..IbRow::default()
+                    })
+                    .await
+            }
+            Event::IBReceived {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+                sender,
+                recipient,
+            } => {
+                self.ib
+                    .push(IbRow {
+                        time_s,
+                        kind,
+                        node: name(recipient),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        index: Some(*index),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        ..IbRow::default()
+                    })
+                    .await
+            }
+            Event::EBLotteryWon {
+                id,
+                slot,
+                pipeline,
+                producer,
+            } => {
+                self.eb
+                    .push(EbRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        ..EbRow::default()
+                    })
+                    .await
+            }
+            Event::EBGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                transactions,
+                input_blocks,
+                endorser_blocks,
+            } => {
+                self.eb
+                    .push(EbRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        size_bytes: Some(*size_bytes),
+                        tx_count: Some(transactions.len() as u64),
+                        ib_count: Some(input_blocks.len() as u64),
+                        eb_count: Some(endorser_blocks.len() as u64),
+                        ..EbRow::default()
+                    })
+                    .await
+            }
+            Event::NoEBGenerated { node, slot } => {
+                self.eb
+                    .push(EbRow {
+                        time_s,
+                        kind,
+                        node: name(node),
+                        slot: *slot,
+                        ..EbRow::default()
+                    })
+                    .await
+            }
+            Event::EBSent {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => {
+                self.eb
+                    .push(EbRow {
+                        time_s,
+                        kind,
+                        node: name(sender),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        msg_size_bytes: Some(*msg_size_bytes),
+                        ..EbRow::default()
+                    })
+                    .await
+            }
+            Event::EBReceived {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+            } => {
+                self.eb
+                    .push(EbRow {
+                        time_s,
+                        kind,
+                        node: name(recipient),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        ..EbRow::default()
+                    })
+                    .await
+            }
+            Event::VTLotteryWon {
+                id,
+                slot,
+                pipeline,
+                producer,
+            } => {
+                self.vt
+                    .push(VtRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        ..VtRow::default()
+                    })
+                    .await
+            }
+            Event::VTBundleGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                votes,
+            } => {
+                self.vt
+                    .push(VtRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        size_bytes: Some(*size_bytes),
+                        vote_count: Some(votes.0.values().sum::<usize>() as u64),
+                        ..VtRow::default()
+                    })
+                    .await
+            }
+            Event::NoVTBundleGenerated { node, slot } => {
+                self.vt
+                    .push(VtRow {
+                        time_s,
+                        kind,
+                        node: name(node),
+                        slot: *slot,
+                        ..VtRow::default()
+                    })
+                    .await
+            }
+            Event::VTBundleNotGenerated {
+                slot,
+                pipeline,
+                producer,
+                eb,
+                reason,
+            } => {
+                self.vt
+                    .push(VtRow {
+                        time_s,
+                        kind,
+                        node: name(producer),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        eb: Some(eb.to_string()),
+                        reason: Some(format!("{reason:?}")),
+                        ..VtRow::default()
+                    })
+                    .await
+            }
+            Event::VTBundleSent {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => {
+                self.vt
+                    .push(VtRow {
+                        time_s,
+                        kind,
+                        node: name(sender),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        msg_size_bytes: Some(*msg_size_bytes),
+                        ..VtRow::default()
+                    })
+                    .await
+            }
+            Event::VTBundleReceived {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+            } => {
+                self.vt
+                    .push(VtRow {
+                        time_s,
+                        kind,
+                        node: name(recipient),
+                        id: Some(id.to_string()),
+                        slot: *slot,
+                        pipeline: Some(*pipeline),
+                        producer: Some(name(producer)),
+                        sender: Some(name(sender)),
+                        recipient: Some(name(recipient)),
+                        ..VtRow::default()
+                    })
+                    .await
+            }
+        }
+    }
+
+    /// Writes out whatever is left, and finishes every file.
+    pub async fn close(self) -> Result<()> {
+        self.slot.close().await?;
+        self.cpu.close().await?;
+        self.tx.close().await?;
+        self.rb.close().await?;
+        self.ib.close().await?;
+        self.eb.close().await?;
+        self.vt.close().await?;
+        Ok(())
+    }
+}
+
+fn name(node: &Node) -> String {
+    node.name.to_string()
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SlotRow {
+    time_s: f64,
+    kind: String,
+    node: Option<String>,
+    slot: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CpuRow {
+    time_s: f64,
+    kind: String,
+    node: String,
+    task_index: u64,
+    task_type: String,
+    subtasks: Option<u64>,
+    cpu_time_s: Option<f64>,
+    wall_time_s: Option<f64>,
+    task_label: Option<String>,
+    extra: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TxRow {
+    time_s: f64,
+    kind: String,
+    tx_id: u64,
+    node: Option<String>,
+    sender: Option<String>,
+    recipient: Option<String>,
+    size_bytes: Option<u64>,
+    msg_size_bytes: Option<u64>,
+    shard: Option<u64>,
+    input_id: Option<u64>,
+    overcollateralization_factor: Option<u64>,
+    execution_steps: Option<u64>,
+    execution_memory: Option<u64>,
+    reason: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RbRow {
+    time_s: f64,
+    kind: String,
+    node: String,
+    id: Option<String>,
+    slot: Option<u64>,
+    producer: Option<String>,
+    sender: Option<String>,
+    recipient: Option<String>,
+    vrf: Option<u64>,
+    parent: Option<String>,
+    header_bytes: Option<u64>,
+    size_bytes: Option<u64>,
+    msg_size_bytes: Option<u64>,
+    tx_count: Option<u64>,
+    endorsed_eb: Option<String>,
+    endorsement_bytes: Option<u64>,
+    depth: Option<u64>,
+    old_tip: Option<String>,
+    new_tip: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct IbRow {
+    time_s: f64,
+    kind: String,
+    node: String,
+    id: Option<String>,
+    slot: u64,
+    pipeline: Option<u64>,
+    producer: Option<String>,
+    index: Option<u64>,
+    sender: Option<String>,
+    recipient: Option<String>,
+    shard: Option<u64>,
+    header_bytes: Option<u64>,
+    tx_payload_bytes: Option<u64>,
+    size_bytes: Option<u64>,
+    msg_size_bytes: Option<u64>,
+    tx_count: Option<u64>,
+    rb_ref: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct EbRow {
+    time_s: f64,
+    kind: String,
+    node: String,
+    id: Option<String>,
+    slot: u64,
+    pipeline: Option<u64>,
+    producer: Option<String>,
+    sender: Option<String>,
+    recipient: Option<String>,
+    size_bytes: Option<u64>,
+    msg_size_bytes: Option<u64>,
+    tx_count: Option<u64>,
+    ib_count: Option<u64>,
+    eb_count: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct VtRow {
+    time_s: f64,
+    kind: String,
+    node: String,
+    id: Option<String>,
+    slot: u64,
+    pipeline: Option<u64>,
+    producer: Option<String>,
+    sender: Option<String>,
+    recipient: Option<String>,
+    size_bytes: Option<u64>,
+    msg_size_bytes: Option<u64>,
+    vote_count: Option<u64>,
+    eb: Option<String>,
+    reason: Option<String>,
+}
+
+// One Parquet file, which is only created once it has something to hold.
+struct Table<T> {
+    path: PathBuf,
+    fields: Vec<FieldRef>,
+    rows: Vec<T>,
+    writer: Option<AsyncArrowWriter<File>>,
+}
+
+impl<T: Serialize + DeserializeOwned> Table<T> {
+    fn new(dir: &Path, name: &str) -> Result<Self> {
+        // Node names, event types and IDs repeat a lot, so store each distinct one once
+        let options = TracingOptions::default().string_dictionary_encoding(true);
+        Ok(Self {
+            path: dir.join(format!("{name}.parquet")),
+            fields: Vec::<FieldRef>::from_type::<T>(options)?,
+            rows: vec![],
+            writer: None,
+        })
+    }
+
+    async fn push(&mut self, row: T) -> Result<()> {
+        self.rows.push(row);
+        if self.rows.len() >= ROWS_PER_BATCH {
+            self.write_batch().await?;
+        }
+        Ok(())
+    }
+
+    async fn write_batch(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let batch = serde_arrow::to_record_batch(&self.fields, &self.rows)?;
+        self.rows.clear();
+        if self.writer.is_none() {
+            self.writer = Some(self.create_writer().await?);
+        }
+        self.writer.as_mut().unwrap().write(&batch).await?;
+        Ok(())
+    }
+
+    async fn create_writer(&self) -> Result<AsyncArrowWriter<File>> {
+        let file = File::create(&self.path).await?;
+        let schema = Arc::new(Schema::new(self.fields.clone()));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        Ok(AsyncArrowWriter::try_new(file, schema, Some(props))?)
+    }
+
+    // Tables which never had anything in them are still written, so that every trace has the same tables
+    async fn close(mut self) -> Result<()> {
+        self.write_batch().await?;
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => self.create_writer().await?,
+        };
+        writer.close().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use sim_core::{
+        clock::Timestamp,
+        config::NodeId,
+        events::{Event, Node},
+        model::{InputBlockId, TransactionId},
+    };
+
+    use super::ColumnarTrace;
+
+    fn node(id: usize) -> Node {
+        Node {
+            id: NodeId::new(id),
+            name: Arc::new(format!("node-{id}")),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_write_one_table_per_family() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dir = temp_dir.path().join("trace.parquet");
+        let mut trace = ColumnarTrace::create(&dir).await?;
+        let ib = InputBlockId {
+            slot: 3,
+            pipeline: 0,
+            producer: node(0),
+            index: 0,
+        };
+        let events = [
+            Event::GlobalSlot { slot: 3 },
+            Event::TXSent {
+                id: TransactionId::new(7),
+                sender: node(0),
+                recipient: node(1),
+                msg_size_bytes: 300,
+            },
+            Event::IBReceived {
+                id: ib.clone(),
+                slot: 3,
+                pipeline: 0,
+                producer: node(0),
+                index: 0,
+                sender: node(0),
+                recipient: node(1),
+            },
+            Event::IBReceived {
+                id: ib,
+                slot: 3,
+                pipeline: 0,
+                producer: node(0),
+                index: 0,
+                sender: node(1),
+                recipient: node(2),
+            },
+        ];
+        for event in &events {
+            trace.write(event, Timestamp::from_secs(3)).await?;
+        }
+        trace.close().await?;
+
+        let rows = |table: &str| -> Result<usize> {
+            let file = std::fs::File::open(dir.join(format!("{table}.parquet")))?;
+            let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+            let mut rows = 0;
+            for batch in reader {
+                rows += batch?.num_rows();
+            }
+            Ok(rows)
+        };
+        assert_eq!(rows("slot")?, 1);
+        assert_eq!(rows("tx")?, 1);
+        assert_eq!(rows("ib")?, 2);
+        // Tables which never had anything in them are still there, just empty
+        assert_eq!(rows("eb")?, 0);
+        Ok(())
+    }
+}
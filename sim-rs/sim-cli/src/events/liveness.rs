@@ -1,19 +1,16 @@
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet};
 
 use sim_core::{
     clock::Timestamp,
     config::SimConfiguration,
-    events::{BlockRef, Event},
+    events::{BlockRef, Event, EventKind},
     model::{TransactionId, TransactionLostReason},
 };
-use tokio::sync::mpsc;
 
 use super::{EndorserBlockId, InputBlockId};
 
 /// Emits additional events when it is no longer possible for a transaction to reach the chain.
 pub struct LivenessMonitor {
-    events_source: mpsc::UnboundedReceiver<(Event, Timestamp)>,
-    queue: VecDeque<(Event, Timestamp)>,
     txs: BTreeMap<TransactionId, MonitoredTX>,
     ibs: BTreeMap<InputBlockId, MonitoredIB>,
     ebs: BTreeMap<EndorserBlockId, MonitoredEB>,
@@ -22,13 +19,18 @@ pub struct LivenessMonitor {
 }
 
 impl LivenessMonitor {
-    pub fn new(
-        config: &SimConfiguration,
-        events_source: mpsc::UnboundedReceiver<(Event, Timestamp)>,
-    ) -> Self {
+    /// The only events which the monitor needs to see.
+    pub const KINDS: [EventKind; 6] = [
+        EventKind::GlobalSlot,
+        EventKind::TXGenerated,
+        EventKind::RBGenerated,
+        EventKind::IBGenerated,
+        EventKind::EBGenerated,
+        EventKind::VTBundleGenerated,
+    ];
+
+    pub fn new(config: &SimConfiguration) -> Self {
         Self {
-            events_source,
-            queue: VecDeque::new(),
             txs: BTreeMap::new(),
             ibs: BTreeMap::new(),
             ebs: BTreeMap::new(),
@@ -37,12 +39,10 @@ impl LivenessMonitor {
         }
     }
 
-    pub async fn recv(&mut self) -> Option<(Event, Timestamp)> {
-        if let Some(next) = self.queue.pop_front() {
-            return Some(next);
-        }
-        let (event, time) = self.events_source.recv().await?;
-        match &event {
+    /// Returns the events which this one has made inevitable.
+    pub fn handle(&mut self, event: &Event, time: Timestamp) -> Vec<(Event, Timestamp)> {
+        let mut lost = vec![];
+        match event {
             Event::TXGenerated { id, .. } => {
                 self.txs.insert(*id, MonitoredTX::new());
             }
@@ -93,7 +93,9 @@ impl LivenessMonitor {
             }
             Event::VTBundleGenerated { votes, .. } => {
                 for (eb_id, count) in &votes.0 {
-                    let eb = self.ebs.get_mut(eb_id).unwrap();
+                    let Some(eb) = self.ebs.get_mut(eb_id) else {
+                        continue;
+                    };
                     eb.votes += *count as u64;
                     if eb.votes >= self.vote_threshold {
                         for ib_id in &eb.ibs {
@@ -138,15 +140,20 @@ impl LivenessMonitor {
             Event::GlobalSlot { slot } => {
                 if slot % self.stage_length == 0 {
                     let pipeline = slot / self.stage_length;
-                    self.handle_new_pipeline(pipeline, time);
+                    self.handle_new_pipeline(pipeline, time, &mut lost);
                 }
             }
             _ => {}
         }
-        Some((event, time))
+        lost
     }
 
-    fn handle_new_pipeline(&mut self, pipeline: u64, time: Timestamp) {
+    fn handle_new_pipeline(
+        &mut self,
+        pipeline: u64,
+        time: Timestamp,
+        lost: &mut Vec<(Event, Timestamp)>,
+    ) {
         self.txs.retain(|id, tx| {
             if tx.ebs.is_empty()
                 && tx
@@ -154,7 +161,7 @@ impl LivenessMonitor {
                     .is_some_and(|ib_pipeline| ib_pipeline + 4 < pipeline)
             {
                 // this transaction was only in IBs which never reached any EBs.
-                self.queue.push_back((
+                lost.push((
                     Event::TXLost {
                         id: *id,
                         reason: TransactionLostReason::IBExpired,
@@ -170,7 +177,7 @@ impl LivenessMonitor {
                     .is_some_and(|eb_pipeline| eb_pipeline + 1 < pipeline)
             {
                 // this transaction was only in EBs which were never certified
-                self.queue.push_back((
+                lost.push((
                     Event::TXLost {
                         id: *id,
                         reason: TransactionLostReason::EBExpired,
@@ -1,27 +1,48 @@
-use std::{fs, path::PathBuf, process};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+    thread,
+};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{bail, Context as _, Result};
+use clap::{Parser, Subcommand};
 use events::EventMonitor;
 use figment::{
-    providers::{Format as _, Yaml},
+    providers::{Format as _, Serialized, Yaml},
     Figment,
 };
+use serde_yaml::Mapping;
 use sim_core::{
+    checkpoint::Checkpoint,
     clock::ClockCoordinator,
-    config::{NodeId, RawParameters, RawTopology, SimConfiguration, Topology},
+    config::{
+        Engine, NodeId, RawParameters, RawScenario, RawTopology, Scenario, SimConfiguration,
+        Topology, TxReplay,
+    },
     events::EventTracker,
     sim::Simulation,
 };
+use summary::{RunReport, RunSummary};
+use sweep::{SweepResult, SweepSpec};
 use tokio::{
-    pin, select,
-    sync::{mpsc, oneshot},
+    join,
+    sync::{mpsc, Semaphore},
+    task::JoinSet,
 };
 use tokio_util::sync::CancellationToken;
-use tracing::{level_filters::LevelFilter, warn};
+use trace::{TraceFormat, TraceReader, READ_BUFFER};
+use tracing::{info, info_span, level_filters::LevelFilter, warn, Instrument as _, Span};
 use tracing_subscriber::{layer::SubscriberExt as _, util::SubscriberInitExt, EnvFilter};
 
+mod compare;
+mod empirical;
 mod events;
+mod replay;
+mod summary;
+mod sweep;
+mod trace;
 
 const DEFAULT_TOPOLOGY_PATHS: &[&str] = &[
     // Docker/production path
@@ -41,6 +62,10 @@ struct Args {
     #[clap(short, long)]
     timescale: Option<f64>,
     #[clap(long)]
+    engine: Option<Engine>,
+    #[clap(long)]
+    threads: Option<usize>,
+    #[clap(long)]
     trace_node: Vec<usize>,
     #[clap(short, long)]
     slots: Option<u64>,
@@ -48,6 +73,55 @@ struct Args {
     conformance_events: bool,
     #[clap(short, long)]
     aggregate_events: bool,
+    #[clap(long)]
+    scenario: Option<PathBuf>,
+    #[clap(long)]
+    tx_replay: Option<PathBuf>,
+    #[clap(long, default_value_t = 0)]
+    seed: u64,
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    runs: u64,
+    #[clap(long)]
+    summary: Option<PathBuf>,
+    #[clap(long)]
+    checkpoint: Option<PathBuf>,
+    #[clap(long)]
+    resume: Option<PathBuf>,
+    /// Resume from the checkpoint even if the parameters, topology or scenario have changed.
+    #[clap(long, requires = "resume")]
+    branch: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run every combination of the parameter values in a sweep file, and write a table of results.
+    Sweep { spec: PathBuf, results: PathBuf },
+    /// Read a trace written by an earlier run, and report on it as if that run had just finished.
+    /// The topology and parameters must be the ones the trace was written with.
+    Analyze {
+        trace: PathBuf,
+        /// Which simulator wrote the trace: "rust" or "haskell".
+        #[clap(long, default_value = "rust")]
+        format: TraceFormat,
+    },
+    /// Compare two runs metric by metric, from either their traces or their summaries.
+    /// When both are traces, also find the first event where they differ.
+    Compare {
+        a: PathBuf,
+        b: PathBuf,
+        results: Option<PathBuf>,
+        /// Which simulator wrote the first trace: "rust" or "haskell".
+        #[clap(long, default_value = "rust")]
+        a_format: TraceFormat,
+        /// Which simulator wrote the second trace: "rust" or "haskell".
+        #[clap(long, default_value = "rust")]
+        b_format: TraceFormat,
+        /// Treat traces which only differ in the order of simultaneous events as the same.
+        #[clap(long)]
+        ignore_simultaneous_order: bool,
+    },
 }
 
 fn get_default_topology() -> Result<String> {
@@ -70,14 +144,33 @@ fn get_default_topology() -> Result<String> {
     ))
 }
 
-fn read_config(args: &Args) -> Result<SimConfiguration> {
-    let topology_str = match &args.topology {
-        Some(path) => fs::read_to_string(path)?,
-        None => get_default_topology()?,
+// FNV-1a, which (unlike the standard library's hashers) gives the same hash in every build.
+fn fingerprint(inputs: &[&[u8]]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for input in inputs {
+        let length = (input.len() as u64).to_le_bytes();
+        for byte in length.iter().chain(input.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+fn read_config(args: &Args, overrides: Option<&Mapping>) -> Result<SimConfiguration> {
+    let topology_yaml = match &args.topology {
+        Some(path) => empirical::read_yaml(path)?,
+        None => {
+            let mut yaml = serde_yaml::from_str(&get_default_topology()?)?;
+            empirical::load_files(&mut yaml, Path::new(""))?;
+            yaml
+        }
     };
+    // Any empirical distribution files have been read into the YAML, so they're fingerprinted along with it
+    let topology_str = serde_yaml::to_string(&topology_yaml)?;
     let topology: Topology = {
-        let raw_topology: RawTopology = serde_yaml::from_str(&topology_str)?;
-        raw_topology.into()
+        let raw_topology: RawTopology = serde_yaml::from_value(topology_yaml)?;
+        raw_topology.try_into()?
     };
     topology.validate()?;
 
@@ -86,14 +179,34 @@ fn read_config(args: &Args) -> Result<SimConfiguration> {
     )));
 
     for params_file in &args.parameters {
-        raw_params = raw_params.merge(Yaml::file_exact(params_file));
+        raw_params = raw_params.merge(Serialized::defaults(empirical::read_yaml(params_file)?));
+    }
+    if let Some(overrides) = overrides {
+        raw_params = raw_params.merge(Serialized::defaults(overrides));
     }
 
     let params: RawParameters = raw_params.extract()?;
+    let params_json = serde_json::to_vec(&raw_params.extract::<serde_json::Value>()?)?;
     let mut config = SimConfiguration::build(params, topology)?;
+    config.seed = args.seed;
     if let Some(slots) = args.slots {
         config.slots = Some(slots);
     }
+    if let Some(timescale) = args.timescale {
+        if !(timescale.is_finite() && timescale > 0.0) {
+            bail!("timescale must be a positive number");
+        }
+        config.timescale = Some(timescale);
+    }
+    if let Some(engine) = args.engine {
+        config.engine = engine;
+    }
+    if let Some(threads) = args.threads {
+        if threads == 0 {
+            bail!("threads must be at least 1");
+        }
+        config.threads = Some(threads);
+    }
     if args.conformance_events {
         config.emit_conformance_events = true;
     }
@@ -103,9 +216,319 @@ fn read_config(args: &Args) -> Result<SimConfiguration> {
     for id in &args.trace_node {
         config.trace_nodes.insert(NodeId::new(*id));
     }
+    let mut scenario_str = String::new();
+    if let Some(path) = &args.scenario {
+        scenario_str = fs::read_to_string(path)?;
+        let raw_scenario: RawScenario = serde_yaml::from_str(&scenario_str)?;
+        config.scenario = Scenario::build(raw_scenario, &config.nodes, &config.links)?;
+    }
+    let mut tx_replay_bytes = vec![];
+    if let Some(path) = &args.tx_replay {
+        tx_replay_bytes = fs::read(path)?;
+        let raw_txs = replay::read_tx_replay(path)?;
+        let replay = TxReplay::build(raw_txs, &config)?;
+        config.replay_transactions(replay)?;
+    }
+    config.fingerprint = fingerprint(&[
+        topology_str.as_bytes(),
+        &params_json,
+        scenario_str.as_bytes(),
+        &tx_replay_bytes,
+    ]);
     Ok(config)
 }
 
+fn read_checkpoint(args: &Args) -> Result<Option<Arc<Checkpoint>>> {
+    let Some(path) = &args.resume else {
+        return Ok(None);
+    };
+    if args.runs > 1 {
+        bail!("runs resumed from a checkpoint carry on with its random state, so --runs would only repeat the same run");
+    }
+    if args.aggregate_events {
+        bail!("aggregated traces need every event from the start of the run, so they can't be resumed from a checkpoint");
+    }
+    Ok(Some(Arc::new(Checkpoint::read(path)?)))
+}
+
+// Each of the runs gets the next seed up from --seed.
+fn seeds(args: &Args) -> Result<Vec<u64>> {
+    let Some(last) = args.seed.checked_add(args.runs - 1) else {
+        bail!(
+            "--seed {} is too large for {} runs, as seeds can't go past {}",
+            args.seed,
+            args.runs,
+            u64::MAX
+        );
+    };
+    Ok((args.seed..=last).collect())
+}
+
+// Each run gets its own output file, named after its seed (or its index in a sweep).
+fn output_path_for_run(path: &Path, run: u64) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let file_name = match file_name.split_once('.') {
+        Some((stem, extensions)) => format!("{stem}-{run}.{extensions}"),
+        None => format!("{file_name}-{run}"),
+    };
+    path.with_file_name(file_name)
+}
+
+async fn run_simulation(run: Run, token: CancellationToken) -> Result<RunSummary> {
+    let config = run.config;
+    let monitor = EventMonitor::new(&config, run.output).await?;
+
+    let mut clock_coordinator = ClockCoordinator::new(config.timestamp_resolution);
+    if let Some(timescale) = config.timescale {
+        clock_coordinator = clock_coordinator.with_timescale(timescale)?;
+    }
+    let clock = clock_coordinator.clock();
+    let tracker = monitor.subscribe(EventTracker::without_sinks(clock.clone(), &config.nodes))?;
+    let mut simulation = Simulation::new(config, tracker, clock_coordinator).await?;
+    if let Some(checkpoint) = &run.resume {
+        if run.branch {
+            simulation.branch(checkpoint)?;
+        } else {
+            simulation
+                .restore(checkpoint)
+                .context("pass --branch to resume with different inputs")?;
+        }
+    }
+
+    simulation.run(token).await?;
+    if let Some(path) = &run.checkpoint {
+        simulation.checkpoint().await?.write(path)?;
+        info!("Saved a checkpoint to {}.", path.display());
+    }
+    simulation.shutdown()?;
+    let report = monitor.finish().await?;
+
+    if let Some(path) = run.summary {
+        summary::write_report(&path, &report)?;
+    }
+    Ok(report.summary)
+}
+
+// Replays a trace through the same monitor which reported on it when it was written.
+async fn report_on_trace(
+    config: &SimConfiguration,
+    trace: &Path,
+    format: TraceFormat,
+    output: Option<PathBuf>,
+) -> Result<RunReport> {
+    let monitor = EventMonitor::new(config, output).await?;
+    let (events_sink, mut events_source) = mpsc::channel(READ_BUFFER);
+
+    info!("Reading events from {}.", trace.display());
+    let reader = TraceReader::new(&config.nodes)
+        .with_format(format)
+        .read(trace, events_sink);
+    let handle = async {
+        while let Some((event, time)) = events_source.recv().await {
+            monitor.handle(event, time);
+        }
+    };
+    let (result, ()) = join!(reader, handle);
+    result?;
+    monitor.finish().await
+}
+
+async fn analyze(args: &Args, trace: &Path, format: TraceFormat) -> Result<()> {
+    let config = read_config(args, None)?;
+    let report = report_on_trace(&config, trace, format, args.output.clone()).await?;
+    if let Some(path) = &args.summary {
+        summary::write_report(path, &report)?;
+    }
+    Ok(())
+}
+
+async fn run_compare(
+    args: &Args,
+    (a, a_format): (&Path, TraceFormat),
+    (b, b_format): (&Path, TraceFormat),
+    results: Option<&Path>,
+    ignore_simultaneous_order: bool,
+) -> Result<()> {
+    // Summaries can be compared without knowing the topology, but traces can't be read without it
+    let config = if compare::is_summary(a) && compare::is_summary(b) {
+        None
+    } else {
+        Some(read_config(args, None)?)
+    };
+    let mut metrics = vec![];
+    for (name, path, format) in [("a", a, a_format), ("b", b, b_format)] {
+        metrics.push(match &config {
+            Some(config) if !compare::is_summary(path) => {
+                let report = report_on_trace(config, path, format, None)
+                    .instrument(info_span!("run", name))
+                    .await?;
+                compare::report_metrics(&report)?
+            }
+            _ => compare::read_summary(path)?,
+        });
+    }
+    let comparisons = compare::compare_metrics(&metrics[0], &metrics[1]);
+
+    let divergence = match &config {
+        Some(config) if !compare::is_summary(a) && !compare::is_summary(b) => {
+            let divergence = compare::first_divergence(
+                &config.nodes,
+                (a, a_format),
+                (b, b_format),
+                ignore_simultaneous_order,
+            );
+            Some(divergence.await?)
+        }
+        _ => None,
+    };
+
+    compare::report(&comparisons, divergence.as_ref());
+    if let Some(path) = results {
+        compare::write_comparison(path, &comparisons, divergence.as_ref())?;
+        info!("Wrote the comparison to {}.", path.display());
+    }
+    Ok(())
+}
+
+struct Run {
+    config: SimConfiguration,
+    output: Option<PathBuf>,
+    summary: Option<PathBuf>,
+    checkpoint: Option<PathBuf>,
+    resume: Option<Arc<Checkpoint>>,
+    branch: bool,
+    span: Span,
+}
+
+// Runs as many simulations at once as we have cores for.
+// Returns each run's summary, in order, or None if the run was cancelled before it started.
+async fn run_in_parallel(
+    runs: Vec<Run>,
+    token: CancellationToken,
+) -> Result<Vec<Option<RunSummary>>> {
+    let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+    let permits = Arc::new(Semaphore::new(parallelism));
+    let mut tasks = JoinSet::new();
+    for (index, run) in runs.into_iter().enumerate() {
+        let permits = permits.clone();
+        let token = token.clone();
+        let span = run.span.clone();
+        tasks.spawn(
+            async move {
+                let _permit = permits.acquire_owned().await?;
+                if token.is_cancelled() {
+                    return Ok((index, None));
+                }
+                let summary = run_simulation(run, token).await?;
+                anyhow::Ok((index, Some(summary)))
+            }
+            .instrument(span),
+        );
+    }
+
+    let mut results = vec![];
+    while let Some(result) = tasks.join_next().await {
+        results.push(result??);
+    }
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, summary)| summary).collect())
+}
+
+async fn run_many(args: Args, token: CancellationToken) -> Result<()> {
+    let resume = read_checkpoint(&args)?;
+    let mut runs = vec![];
+    let seeds = seeds(&args)?;
+    for &seed in &seeds {
+        let mut config = read_config(&args, None)?;
+        config.seed = seed;
+        let output = args
+            .output
+            .as_ref()
+            .map(|path| output_path_for_run(path, seed));
+        let summary = args
+            .summary
+            .as_ref()
+            .map(|path| output_path_for_run(path, seed));
+        let span = info_span!("run", seed);
+        runs.push(Run {
+            config,
+            output,
+            summary,
+            checkpoint: None,
+            resume: resume.clone(),
+            branch: args.branch,
+            span,
+        });
+    }
+
+    let (seeds, summaries): (Vec<u64>, Vec<RunSummary>) = seeds
+        .into_iter()
+        .zip(run_in_parallel(runs, token).await?)
+        .filter_map(|(seed, summary)| Some((seed, summary?)))
+        .unzip();
+    summary::report(&seeds, &summaries, args.summary.as_deref())
+}
+
+async fn run_sweep(
+    args: &Args,
+    spec: &Path,
+    results: &Path,
+    token: CancellationToken,
+) -> Result<()> {
+    let combinations = SweepSpec::read(spec)?.combinations()?;
+    let seeds = seeds(args)?;
+    let resume = read_checkpoint(args)?;
+    let mut runs = vec![];
+    let mut rows = vec![];
+    for parameters in combinations {
+        for &seed in &seeds {
+            let run = rows.len();
+            let mut config = read_config(args, Some(&parameters))?;
+            config.seed = seed;
+            let output = args
+                .output
+                .as_ref()
+                .map(|path| output_path_for_run(path, run as u64));
+            let summary = args
+                .summary
+                .as_ref()
+                .map(|path| output_path_for_run(path, run as u64));
+            let span = info_span!("run", run, seed);
+            runs.push(Run {
+                config,
+                output,
+                summary,
+                checkpoint: None,
+                resume: resume.clone(),
+                branch: args.branch,
+                span,
+            });
+            rows.push((run, seed, parameters.clone()));
+        }
+    }
+    info!("Sweeping over {} run(s).", runs.len());
+
+    let results_table: Vec<SweepResult> = rows
+        .into_iter()
+        .zip(run_in_parallel(runs, token).await?)
+        .filter_map(|((run, seed, parameters), summary)| {
+            Some(SweepResult {
+                run,
+                seed,
+                parameters,
+                summary: summary?,
+            })
+        })
+        .collect();
+    sweep::write_results(results, &results_table)?;
+    info!(
+        "Wrote {} row(s) to {}.",
+        results_table.len(),
+        results.display()
+    );
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let fmt_layer = tracing_subscriber::fmt::layer().compact().without_time();
@@ -120,48 +543,68 @@ async fn main() -> Result<()> {
     let token = CancellationToken::new();
 
     // Handle ctrl+c (SIGINT) at an application level, so we can report on necessary stats before shutting down.
-    let (ctrlc_sink, ctrlc_source) = oneshot::channel();
-    let mut ctrlc_sink = Some(ctrlc_sink);
     let ctrlc_token = token.clone();
     ctrlc::set_handler(move || {
-        ctrlc_token.cancel();
-        if let Some(sink) = ctrlc_sink.take() {
-            let _ = sink.send(());
-        } else {
+        if ctrlc_token.is_cancelled() {
             warn!("force quitting");
             process::exit(0);
         }
+        ctrlc_token.cancel();
     })?;
 
     let args = Args::parse();
-    let config = read_config(&args)?;
-
-    let (events_sink, events_source) = mpsc::unbounded_channel();
-    let monitor = tokio::spawn(EventMonitor::new(&config, events_source, args.output).run());
-    pin!(monitor);
-
-    let clock_coordinator = ClockCoordinator::new(config.timestamp_resolution);
-    let clock = clock_coordinator.clock();
-    let tracker = EventTracker::new(events_sink, clock.clone(), &config.nodes);
-    let mut simulation = Simulation::new(config, tracker, clock_coordinator).await?;
+    if args.checkpoint.is_some() && (args.runs > 1 || args.command.is_some()) {
+        bail!("--checkpoint can only be used for a single run");
+    }
+    match &args.command {
+        Some(Command::Sweep { spec, results }) => {
+            return run_sweep(&args, spec, results, token).await;
+        }
+        Some(Command::Analyze { trace, format }) => {
+            if args.resume.is_some() || args.runs > 1 {
+                bail!(
+                    "analyze reads a single trace, so it can't be combined with --resume or --runs"
+                );
+            }
+            return analyze(&args, trace, *format).await;
+        }
+        Some(Command::Compare {
+            a,
+            b,
+            results,
+            a_format,
+            b_format,
+            ignore_simultaneous_order,
+        }) => {
+            let (a, b) = ((a.as_path(), *a_format), (b.as_path(), *b_format));
+            return run_compare(&args, a, b, results.as_deref(), *ignore_simultaneous_order).await;
+        }
+        None => {}
+    }
+    if args.runs > 1 {
+        return run_many(args, token).await;
+    }
 
-    select! {
-        result = simulation.run(token) => { result? }
-        result = &mut monitor => { result?? }
-        _ = ctrlc_source => {}
+    let run = Run {
+        config: read_config(&args, None)?,
+        resume: read_checkpoint(&args)?,
+        branch: args.branch,
+        output: args.output,
+        summary: args.summary,
+        checkpoint: args.checkpoint,
+        span: Span::current(),
     };
-
-    simulation.shutdown()?;
-    monitor.await??;
+    run_simulation(run, token).await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use clap::Parser as _;
     use std::fs;
 
-    use crate::{read_config, Args};
+    use crate::{read_config, seeds, Args};
 
     #[test]
     fn should_parse_topologies() -> Result<()> {
@@ -172,13 +615,37 @@ mod tests {
                 output: None,
                 parameters: vec![],
                 timescale: None,
+                engine: None,
+                threads: None,
                 trace_node: vec![],
                 slots: None,
                 conformance_events: false,
                 aggregate_events: false,
+                scenario: None,
+                tx_replay: None,
+                seed: 0,
+                runs: 1,
+                summary: None,
+                checkpoint: None,
+                resume: None,
+                branch: false,
+                command: None,
             };
-            read_config(&args)?;
+            read_config(&args, None)?;
         }
         Ok(())
     }
+
+    #[test]
+    fn should_reject_seeds_that_run_out() -> Result<()> {
+        assert!(Args::try_parse_from(["sim-cli", "--runs", "0"]).is_err());
+
+        let args =
+            Args::try_parse_from(["sim-cli", "--seed", "18446744073709551614", "--runs", "2"])?;
+        assert_eq!(seeds(&args)?, vec![u64::MAX - 1, u64::MAX]);
+        let args =
+            Args::try_parse_from(["sim-cli", "--seed", "18446744073709551614", "--runs", "3"])?;
+        assert!(seeds(&args).is_err());
+        Ok(())
+    }
 }
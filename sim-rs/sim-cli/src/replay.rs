@@ -0,0 +1,119 @@
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use sim_core::config::RawReplayedTransaction;
+
+/// Reads a recorded workload of transactions, from either a CSV file (with a header row) or JSON lines.
+/// Each transaction has a `time_s`, `node` and `size_bytes`, and optionally an `input_id`,
+/// `overcollateralization_factor`, `shard`, `execution_steps` and `execution_memory`.
+pub fn read_tx_replay(path: &Path) -> Result<Vec<RawReplayedTransaction>> {
+    let contents = fs::read_to_string(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => parse_csv(&contents),
+        Some("jsonl") => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| anyhow!("invalid transaction \"{line}\": {e}"))
+            })
+            .collect(),
+        _ => bail!("transaction replay must be a .csv or .jsonl file"),
+    }
+}
+
+const COLUMNS: &[&str] = &[
+    "time_s",
+    "node",
+    "size_bytes",
+    "input_id",
+    "overcollateralization_factor",
+    "shard",
+    "execution_steps",
+    "execution_memory",
+];
+
+fn parse_csv(contents: &str) -> Result<Vec<RawReplayedTransaction>> {
+    let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+    let Some(header) = lines.next() else {
+        return Ok(vec![]);
+    };
+    let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+    // A misspelled column would otherwise be ignored, and silently leave its values out.
+    if let Some(column) = columns.iter().find(|column| !COLUMNS.contains(column)) {
+        bail!(
+            "unknown column \"{column}\" in transaction replay (expected some of {})",
+            COLUMNS.join(", ")
+        );
+    }
+    lines
+        .map(|line| {
+            let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+            let cell = |name: &str| {
+                columns
+                    .iter()
+                    .position(|column| *column == name)
+                    .and_then(|index| cells.get(index).copied())
+                    .filter(|cell| !cell.is_empty())
+            };
+            let required = |name: &str| {
+                cell(name).ok_or_else(|| anyhow!("transaction \"{line}\" has no {name}"))
+            };
+            anyhow::Ok(RawReplayedTransaction {
+                time_s: parse(required("time_s")?, "time_s", line)?,
+                node: required("node")?.to_string(),
+                size_bytes: parse(required("size_bytes")?, "size_bytes", line)?,
+                input_id: cell("input_id")
+                    .map(|value| parse(value, "input_id", line))
+                    .transpose()?,
+                overcollateralization_factor: cell("overcollateralization_factor")
+                    .map(|value| parse(value, "overcollateralization_factor", line))
+                    .transpose()?,
+                shard: cell("shard")
+                    .map(|value| parse(value, "shard", line))
+                    .transpose()?,
+                execution_steps: cell("execution_steps")
+                    .map(|value| parse(value, "execution_steps", line))
+                    .transpose()?,
+                execution_memory: cell("execution_memory")
+                    .map(|value| parse(value, "execution_memory", line))
+                    .transpose()?,
+            })
+        })
+        .collect()
+}
+
+fn parse<T: FromStr>(value: &str, name: &str, line: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("transaction \"{line}\" has an invalid {name} \"{value}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::parse_csv;
+
+    #[test]
+    fn should_read_transactions_from_csv() -> Result<()> {
+        let txs = parse_csv(
+            "time_s,node,size_bytes,input_id\n\
+             0.5,node-1,1500,7\n\
+             1.25,node-2,300,\n",
+        )?;
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].time_s, 0.5);
+        assert_eq!(txs[0].node, "node-1");
+        assert_eq!(txs[0].size_bytes, 1500);
+        assert_eq!(txs[0].input_id, Some(7));
+        assert_eq!(txs[1].input_id, None);
+        assert_eq!(txs[1].overcollateralization_factor, None);
+
+        assert!(parse_csv("time_s,node\n1,node-1\n").is_err());
+        assert!(parse_csv("time_s,node,size_bytes\nsoon,node-1,10\n").is_err());
+        assert!(parse_csv("time_s,node,size-bytes\n1,node-1,10\n").is_err());
+        assert!(parse_csv("time_s,node,size_bytes,input-id\n1,node-1,10,3\n").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,384 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Result};
+use average::Variance;
+use serde::Serialize;
+use serde_json::Value;
+use statrs::distribution::{ContinuousCDF as _, StudentsT};
+use tracing::{info, info_span};
+
+/// The headline numbers from a single simulation run.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub slots: u64,
+    pub txs_generated: u64,
+    pub txs_finalized: u64,
+    pub tx_throughput_bytes_per_second: f64,
+    pub tx_time_to_ib_s: f64,
+    pub tx_time_to_eb_s: f64,
+    pub tx_time_to_block_s: f64,
+    pub ibs_generated: u64,
+    pub ebs_generated: u64,
+    pub eb_certification_rate: f64,
+    pub votes_per_eb: f64,
+    pub space_efficiency: f64,
+    pub praos_blocks_on_chain: u64,
+    pub praos_blocks_orphaned: u64,
+    pub rollbacks: u64,
+}
+
+impl RunSummary {
+    pub fn metrics(&self) -> Result<BTreeMap<String, f64>> {
+        let serde_json::Value::Object(fields) = serde_json::to_value(self)? else {
+            unreachable!("summaries are always serialized as objects");
+        };
+        Ok(fields
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value.as_f64()?)))
+            .collect())
+    }
+}
+
+/// Everything we know about a single run, as reported at the end of it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunReport {
+    pub summary: RunSummary,
+    pub praos: PraosReport,
+    pub leios: LeiosReport,
+    pub pools: Vec<PoolReport>,
+    pub network: BTreeMap<String, MessageStats>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PraosReport {
+    pub txs_generated: u64,
+    pub blocks_published: u64,
+    pub blocks_on_chain: u64,
+    pub empty_slots: u64,
+    pub blocks_orphaned: u64,
+    pub orphaned_blocks_with_lost_certs: u64,
+    pub rollbacks: u64,
+    pub rollback_depth: Stats,
+    pub txs_finalized: u64,
+    pub tx_bytes_finalized: u64,
+    pub txs_pending: u64,
+    pub tx_bytes_pending: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LeiosReport {
+    pub ibs_generated: u64,
+    pub ibs_per_slot: f64,
+    pub empty_ibs: u64,
+    pub txs_in_ibs: u64,
+    pub pending_tx_age_s: Stats,
+    pub ibs_per_tx: Stats,
+    pub txs_per_ib: Stats,
+    pub bytes_per_ib: Stats,
+    pub ibs_received_per_node: Stats,
+    pub ebs_generated: u64,
+    pub ebs_per_slot: f64,
+    pub empty_ebs: u64,
+    pub ibs_per_eb: Stats,
+    pub ebs_per_ib: Stats,
+    pub ibs_in_ebs: u64,
+    pub expired_ibs: u64,
+    pub expired_ebs: u64,
+    pub txs_in_ebs: u64,
+    pub votes_generated: u64,
+    pub votes_per_pool: Stats,
+    pub votes_per_eb: Stats,
+    pub vote_bundles: u64,
+    pub votes_per_bundle: Stats,
+    pub blocks_with_endorsements: u64,
+    pub endorsed_txs: u64,
+    pub endorsed_tx_bytes: u64,
+    pub praos_txs: u64,
+    pub praos_tx_bytes: u64,
+    pub redundant_endorsed_txs: u64,
+    pub space_efficiency: f64,
+    pub tx_time_to_ib_s: Stats,
+    pub tx_time_to_eb_s: Stats,
+    pub tx_time_to_block_s: Stats,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PoolReport {
+    pub pool: usize,
+    pub blocks_published: u64,
+    pub blocks_orphaned: u64,
+    pub votes: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MessageStats {
+    pub sent: u64,
+    pub received: u64,
+    pub bytes_sent: u64,
+}
+impl MessageStats {
+    pub fn display(&self, name: &str) {
+        let percent_received = self.received as f64 / self.sent as f64 * 100.0;
+        info!(
+            "{} {} message(s) were sent, totalling {} byte(s). {} of them were received ({:.3}%).",
+            self.sent, name, self.bytes_sent, self.received, percent_received
+        );
+    }
+}
+
+/// The distribution of some value across a run. Percentiles are NaN when there's no data.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Stats {
+    pub count: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+pub fn compute_stats<Iter: IntoIterator<Item = f64>>(data: Iter) -> Stats {
+    let mut values: Vec<f64> = data.into_iter().collect();
+    values.sort_by(f64::total_cmp);
+    let v: Variance = values.iter().copied().collect();
+    Stats {
+        count: values.len(),
+        mean: v.mean(),
+        std_dev: v.population_variance().sqrt(),
+        min: percentile(&values, 0.0),
+        p50: percentile(&values, 0.5),
+        p90: percentile(&values, 0.9),
+        p95: percentile(&values, 0.95),
+        p99: percentile(&values, 0.99),
+        max: percentile(&values, 1.0),
+    }
+}
+
+// Linearly interpolates between the closest ranks.
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = p * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[low] + (sorted[high] - sorted[low]) * (rank - low as f64)
+}
+
+/// Writes a report as JSON, or as a CSV of metric names and values,
+/// depending on the file extension.
+pub fn write_report<T: Serialize>(path: &Path, report: &T) -> Result<()> {
+    let contents = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::to_string_pretty(report)?,
+        Some("csv") => {
+            let mut rows = vec![];
+            flatten("", &serde_json::to_value(report)?, &mut rows);
+            let mut csv = "metric,value\n".to_string();
+            for (metric, value) in rows {
+                csv.push_str(&format!("{metric},{value}\n"));
+            }
+            csv
+        }
+        _ => bail!("summary must be a .csv or .json file"),
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+// Turns nested objects and lists into dotted metric names, e.g. "leios.txs_per_ib.p50".
+pub fn flatten(prefix: &str, value: &Value, rows: &mut Vec<(String, String)>) {
+    let name = |key: &dyn std::fmt::Display| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{prefix}.{key}")
+        }
+    };
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten(&name(key), value, rows);
+            }
+        }
+        Value::Array(items) => {
+            for (index, value) in items.iter().enumerate() {
+                flatten(&name(&index), value, rows);
+            }
+        }
+        Value::Null => rows.push((prefix.to_string(), String::new())),
+        Value::String(s) => rows.push((prefix.to_string(), s.clone())),
+        other => rows.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+/// A metric aggregated across several runs, with a 95% confidence interval on its mean.
+#[derive(Clone, Debug, Serialize)]
+pub struct AggregateMetric {
+    pub runs: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub ci_low: f64,
+    pub ci_high: f64,
+}
+
+impl AggregateMetric {
+    pub fn new(values: &[f64]) -> Self {
+        let runs = values.len();
+        let mean = values.iter().sum::<f64>() / runs as f64;
+        if runs < 2 {
+            return Self {
+                runs,
+                mean,
+                std_dev: 0.0,
+                ci_low: mean,
+                ci_high: mean,
+            };
+        }
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (runs - 1) as f64;
+        let std_dev = variance.sqrt();
+        let t = StudentsT::new(0.0, 1.0, (runs - 1) as f64)
+            .unwrap()
+            .inverse_cdf(0.975);
+        let half_width = t * std_dev / (runs as f64).sqrt();
+        Self {
+            runs,
+            mean,
+            std_dev,
+            ci_low: mean - half_width,
+            ci_high: mean + half_width,
+        }
+    }
+}
+
+pub fn aggregate(summaries: &[RunSummary]) -> Result<BTreeMap<String, AggregateMetric>> {
+    let mut values: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for summary in summaries {
+        for (name, value) in summary.metrics()? {
+            // Some metrics are undefined for some runs (e.g. when nothing was generated).
+            if value.is_finite() {
+                values.entry(name).or_default().push(value);
+            }
+        }
+    }
+    Ok(values
+        .into_iter()
+        .map(|(name, values)| (name, AggregateMetric::new(&values)))
+        .collect())
+}
+
+/// The headline metrics of several runs, aggregated across them.
+#[derive(Clone, Debug, Serialize)]
+pub struct AggregateReport {
+    pub seeds: Vec<u64>,
+    pub metrics: BTreeMap<String, AggregateMetric>,
+}
+
+pub fn report(seeds: &[u64], summaries: &[RunSummary], path: Option<&Path>) -> Result<()> {
+    let metrics = aggregate(summaries)?;
+    info_span!("summary").in_scope(|| {
+        info!(
+            "Aggregated {} run(s) with seeds {}.",
+            summaries.len(),
+            seeds
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        for (name, metric) in &metrics {
+            info!(
+                "{name}: mean {:.3} (stddev {:.3}), 95% CI [{:.3}, {:.3}] over {} run(s).",
+                metric.mean, metric.std_dev, metric.ci_low, metric.ci_high, metric.runs,
+            );
+        }
+    });
+    if let Some(path) = path {
+        let report = AggregateReport {
+            seeds: seeds.to_vec(),
+            metrics,
+        };
+        write_report(path, &report)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate, compute_stats, flatten, AggregateMetric, RunSummary};
+
+    #[test]
+    fn should_not_report_uncertainty_for_a_single_run() {
+        let metric = AggregateMetric::new(&[4.0]);
+        assert_eq!(metric.mean, 4.0);
+        assert_eq!(metric.ci_low, 4.0);
+        assert_eq!(metric.ci_high, 4.0);
+    }
+
+    #[test]
+    fn should_compute_student_t_confidence_interval() {
+        let metric = AggregateMetric::new(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(metric.mean, 3.0);
+        // t(0.975, 4) = 2.776, and the standard error is sqrt(2.5 / 5)
+        let half_width = 2.776 * (2.5f64 / 5.0).sqrt();
+        assert!((metric.ci_low - (3.0 - half_width)).abs() < 0.001);
+        assert!((metric.ci_high - (3.0 + half_width)).abs() < 0.001);
+    }
+
+    #[test]
+    fn should_skip_undefined_metrics() {
+        let runs = [
+            RunSummary {
+                slots: 10,
+                space_efficiency: f64::NAN,
+                ..RunSummary::default()
+            },
+            RunSummary {
+                slots: 20,
+                space_efficiency: 0.5,
+                ..RunSummary::default()
+            },
+        ];
+        let metrics = aggregate(&runs).unwrap();
+        assert_eq!(metrics["slots"].runs, 2);
+        assert_eq!(metrics["slots"].mean, 15.0);
+        assert_eq!(metrics["space_efficiency"].runs, 1);
+    }
+
+    #[test]
+    fn should_compute_percentiles() {
+        let stats = compute_stats((1..=101).rev().map(|i| i as f64));
+        assert_eq!(stats.count, 101);
+        assert_eq!(stats.mean, 51.0);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.p50, 51.0);
+        assert_eq!(stats.p95, 96.0);
+        assert_eq!(stats.max, 101.0);
+
+        let stats = compute_stats([1.0, 2.0]);
+        assert_eq!(stats.p50, 1.5);
+
+        let stats = compute_stats([]);
+        assert_eq!(stats.count, 0);
+        assert!(stats.p50.is_nan());
+    }
+
+    #[test]
+    fn should_flatten_nested_metrics() {
+        let value = serde_json::json!({
+            "leios": { "txs_per_ib": { "p50": 2.5, "p99": null } },
+            "pools": [{ "pool": 3 }],
+        });
+        let mut rows = vec![];
+        flatten("", &value, &mut rows);
+        assert_eq!(
+            rows,
+            vec![
+                ("leios.txs_per_ib.p50".to_string(), "2.5".to_string()),
+                ("leios.txs_per_ib.p99".to_string(), String::new()),
+                ("pools.0.pool".to_string(), "3".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Result};
+use itertools::Itertools as _;
+use serde::{
+    de::{self, Visitor},
+    forward_to_deserialize_any, Deserialize, Deserializer,
+};
+use serde_json::{Map, Value};
+use serde_yaml::Mapping;
+use sim_core::config::RawParameters;
+
+use crate::{empirical, summary::RunSummary};
+
+/// Describes which parameters to vary in a sweep.
+/// Every combination of the values in `grid` is run once for each of the `points`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SweepSpec {
+    #[serde(default)]
+    pub grid: Mapping,
+    #[serde(default)]
+    pub points: Vec<Mapping>,
+}
+
+impl SweepSpec {
+    pub fn read(path: &Path) -> Result<Self> {
+        Ok(serde_yaml::from_value(empirical::read_yaml(path)?)?)
+    }
+
+    /// Returns the parameter overrides for every run of the sweep.
+    pub fn combinations(&self) -> Result<Vec<Mapping>> {
+        // Only check against what this simulation reads; the shared schema has keys which it ignores.
+        let known = parameter_names();
+        let check_key = |key: &serde_yaml::Value| -> Result<()> {
+            let Some(name) = key.as_str() else {
+                bail!("parameter names must be strings, found {key:?}");
+            };
+            if !known.contains(&name) {
+                bail!("unknown parameter \"{name}\"");
+            }
+            Ok(())
+        };
+
+        let mut axes = vec![];
+        for (key, values) in &self.grid {
+            check_key(key)?;
+            let Some(values) = values.as_sequence() else {
+                bail!("grid values for {key:?} must be a list");
+            };
+            if values.is_empty() {
+                bail!("grid values for {key:?} must not be empty");
+            }
+            axes.push(values.iter().map(|value| (key.clone(), value.clone())));
+        }
+        for point in &self.points {
+            for key in point.keys() {
+                check_key(key)?;
+                // Otherwise the grid would silently override whatever the point set.
+                if self.grid.contains_key(key) {
+                    bail!("{key:?} is set by both a point and the grid");
+                }
+            }
+        }
+
+        let points = if self.points.is_empty() {
+            vec![Mapping::new()]
+        } else {
+            self.points.clone()
+        };
+        let grid: Vec<Vec<_>> = axes.into_iter().multi_cartesian_product().collect();
+        let grid = if grid.is_empty() { vec![vec![]] } else { grid };
+
+        let mut combinations = vec![];
+        for point in &points {
+            for cell in &grid {
+                let mut combination = point.clone();
+                combination.extend(cell.iter().cloned());
+                combinations.push(combination);
+            }
+        }
+        Ok(combinations)
+    }
+}
+
+/// Lists the fields of `RawParameters`, by asking it to deserialize itself from a struct.
+fn parameter_names() -> &'static [&'static str] {
+    let mut names = &[][..];
+    let _ = RawParameters::deserialize(FieldNames(&mut names));
+    names
+}
+
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de> Deserializer<'de> for FieldNames<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("only structs have field names"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("only the field names were needed"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// One row of the results table.
+pub struct SweepResult {
+    pub run: usize,
+    pub seed: u64,
+    pub parameters: Mapping,
+    pub summary: RunSummary,
+}
+
+impl SweepResult {
+    fn to_row(&self) -> Result<Vec<(String, Value)>> {
+        let mut row = vec![
+            ("run".to_string(), self.run.into()),
+            ("seed".to_string(), self.seed.into()),
+        ];
+        for (key, value) in &self.parameters {
+            let key = key.as_str().unwrap_or_default().to_string();
+            row.push((key, serde_json::to_value(value)?));
+        }
+        let Value::Object(metrics) = serde_json::to_value(&self.summary)? else {
+            unreachable!("summaries are always serialized as objects");
+        };
+        row.extend(metrics);
+        Ok(row)
+    }
+}
+
+/// Writes the results table as JSON or CSV, depending on the file extension.
+pub fn write_results(path: &Path, results: &[SweepResult]) -> Result<()> {
+    let rows = results
+        .iter()
+        .map(|r| r.to_row())
+        .collect::<Result<Vec<_>>>()?;
+    let contents = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => {
+            let rows: Vec<Map<String, Value>> =
+                rows.into_iter().map(|r| r.into_iter().collect()).collect();
+            serde_json::to_string_pretty(&rows)?
+        }
+        Some("csv") => to_csv(&rows),
+        _ => bail!("results table must be a .csv or .json file"),
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+fn to_csv(rows: &[Vec<(String, Value)>]) -> String {
+    // Runs can vary different parameters, so the header is the union of every row's columns.
+    let mut columns: Vec<&String> = vec![];
+    for row in rows {
+        for (column, _) in row {
+            if !columns.contains(&column) {
+                columns.push(column);
+            }
+        }
+    }
+    let mut csv = columns.iter().map(|c| csv_field(c)).join(",");
+    csv.push('\n');
+    for row in rows {
+        let value = |column: &String| row.iter().find(|(c, _)| c == column).map(|(_, v)| v);
+        let fields = columns.iter().map(|column| match value(column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(s)) => csv_field(s),
+            Some(value) => csv_field(&value.to_string()),
+        });
+        csv.push_str(&fields.collect::<Vec<_>>().join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::Mapping;
+
+    use super::{write_results, SweepResult, SweepSpec};
+    use crate::summary::RunSummary;
+
+    fn spec(yaml: &str) -> SweepSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn should_run_every_grid_combination_for_every_point() {
+        let spec = spec(
+            r#"
+grid:
+  ib-generation-probability: [1, 5]
+  leios-stage-length-slots: [10, 20, 30]
+points:
+  - tx-size-bytes-distribution: { distribution: constant, value: 1024 }
+  - tx-size-bytes-distribution: { distribution: constant, value: 2048 }
+"#,
+        );
+        let combinations = spec.combinations().unwrap();
+        assert_eq!(combinations.len(), 12);
+        assert!(combinations.iter().all(|c| c.len() == 3));
+        assert_eq!(combinations[0]["ib-generation-probability"], 1);
+        assert_eq!(combinations[0]["leios-stage-length-slots"], 10);
+        assert_eq!(combinations[11]["ib-generation-probability"], 5);
+        assert_eq!(combinations[11]["leios-stage-length-slots"], 30);
+    }
+
+    #[test]
+    fn should_run_once_for_an_empty_spec() {
+        let combinations = SweepSpec::default().combinations().unwrap();
+        assert_eq!(combinations, vec![Mapping::new()]);
+    }
+
+    #[test]
+    fn should_reject_unknown_parameters() {
+        let typo = spec("grid:\n  ib-generation-probabilty: [1, 2]\n");
+        assert!(typo.combinations().is_err());
+        // Only the Haskell simulation reads this one
+        let haskell_only = spec("points:\n  - tcp-congestion-control: true\n");
+        assert!(haskell_only.combinations().is_err());
+    }
+
+    #[test]
+    fn should_reject_parameters_set_by_both_a_point_and_the_grid() {
+        let overlap = spec(
+            r#"
+grid:
+  ib-generation-probability: [1, 2]
+points:
+  - eb-generation-probability: 1
+  - ib-generation-probability: 3
+"#,
+        );
+        assert!(overlap.combinations().is_err());
+    }
+
+    #[test]
+    fn should_write_csv_with_quoted_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.csv");
+        let parameters: Mapping = serde_yaml::from_str(
+            "tx-size-bytes-distribution: { distribution: constant, value: 1024 }",
+        )
+        .unwrap();
+        let results = [SweepResult {
+            run: 0,
+            seed: 3,
+            parameters,
+            summary: RunSummary {
+                slots: 10,
+                space_efficiency: f64::NAN,
+                ..RunSummary::default()
+            },
+        }];
+        write_results(&path, &results).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+
+        let mut lines = csv.lines();
+        let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+        assert_eq!(header[..3], ["run", "seed", "tx-size-bytes-distribution"]);
+        let row = lines.next().unwrap();
+        assert!(row.starts_with(r#"0,3,"{""distribution"":""constant"",""value"":1024}","#));
+        assert!(header.contains(&"space_efficiency"));
+        assert!(!row.contains("NaN"));
+    }
+}
@@ -0,0 +1,1244 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use async_compression::tokio::bufread::GzipDecoder;
+use haskell::{HaskellOutputEvent, HaskellState};
+use serde::Deserialize;
+use sim_core::{
+    clock::Timestamp,
+    config::{NodeConfiguration, NodeId},
+    events::{BlockRef, Endorsement, Event, Node, Votes},
+    model::{NoVoteReason, TransactionId, TransactionLostReason},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufRead, AsyncBufReadExt as _, BufReader},
+    sync::mpsc,
+};
+
+mod haskell;
+
+type BlockId = sim_core::model::BlockId<Node>;
+type CpuTaskId = sim_core::model::CpuTaskId<Node>;
+type InputBlockId = sim_core::model::InputBlockId<Node>;
+type EndorserBlockId = sim_core::model::EndorserBlockId<Node>;
+type VoteBundleId = sim_core::model::VoteBundleId<Node>;
+
+/// How many events can be waiting to be handled before the reader waits for them to catch up.
+pub const READ_BUFFER: usize = 1 << 10;
+
+/// Which simulator wrote a trace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    #[default]
+    Rust,
+    /// The Haskell simulator's traces, in the format shared by both simulators.
+    Haskell,
+}
+
+impl FromStr for TraceFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "rust" => Ok(Self::Rust),
+            "haskell" => Ok(Self::Haskell),
+            other => bail!("unknown trace format \"{other}\" (expected \"rust\" or \"haskell\")"),
+        }
+    }
+}
+
+/// Reads a trace written by an earlier run back into events.
+///
+/// Traces only refer to nodes by name, so they can only be read with the topology they were written with.
+/// They also leave the pipeline out of IB and EB IDs, so the reader remembers the pipeline of every IB
+/// and EB it has seen, to fill it in wherever they're referred to later on.
+pub struct TraceReader {
+    format: TraceFormat,
+    nodes: HashMap<String, Node>,
+    ib_pipelines: HashMap<(u64, NodeId, u64), u64>,
+    eb_pipelines: HashMap<(u64, NodeId), u64>,
+    haskell: HaskellState,
+}
+
+impl TraceReader {
+    pub fn new(nodes: &[NodeConfiguration]) -> Self {
+        let nodes = nodes
+            .iter()
+            .map(|node| {
+                let name = Arc::new(node.name.clone());
+                (node.name.clone(), Node { id: node.id, name })
+            })
+            .collect();
+        Self {
+            format: TraceFormat::Rust,
+            nodes,
+            ib_pipelines: HashMap::new(),
+            eb_pipelines: HashMap::new(),
+            haskell: HaskellState::default(),
+        }
+    }
+
+    pub fn with_format(mut self, format: TraceFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sends every event in the trace to `sink`, in order. Reads JSON lines by default,
+    /// or CBOR if the path ends in `.cbor`, and either can be gzipped.
+    /// Reading waits whenever `sink` is full, so only part of the trace is ever in memory.
+    pub async fn read(mut self, path: &Path, sink: mpsc::Sender<(Event, Timestamp)>) -> Result<()> {
+        let file = File::open(path)
+            .await
+            .with_context(|| format!("could not open trace {}", path.display()))?;
+
+        let mut path = path.to_path_buf();
+        let mut gzipped = false;
+        if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| ext == "gz")
+        {
+            path.set_extension("");
+            gzipped = true;
+        }
+        let mut reader: Pin<Box<dyn AsyncBufRead + Send>> = if gzipped {
+            Box::pin(BufReader::new(GzipDecoder::new(BufReader::new(file))))
+        } else {
+            Box::pin(BufReader::new(file))
+        };
+
+        if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|ext| ext == "cbor")
+        {
+            // CBOR values aren't delimited, so bytes are buffered until the next value is complete
+            let mut buffer = vec![];
+            let mut event_number = 0;
+            loop {
+                let bytes = reader.fill_buf().await?;
+                let finished = bytes.is_empty();
+                let read = bytes.len();
+                buffer.extend_from_slice(bytes);
+                reader.as_mut().consume(read);
+
+                let mut start = 0;
+                while start < buffer.len() {
+                    let mut value = minicbor_serde::Deserializer::new(&buffer[start..]);
+                    match value.decoder_mut().skip() {
+                        Ok(()) => {}
+                        Err(error) if error.is_end_of_input() && !finished => break,
+                        Err(error) => bail!("invalid event {}: {error}", event_number + 1),
+                    }
+                    let end = start + value.decoder().position();
+                    event_number += 1;
+                    let events = self
+                        .decode(&mut minicbor_serde::Deserializer::new(&buffer[start..end]))
+                        .with_context(|| format!("invalid event {event_number}"))?;
+                    if !send(events, &sink).await {
+                        return Ok(());
+                    }
+                    start = end;
+                }
+                buffer.drain(..start);
+                if finished {
+                    break;
+                }
+            }
+        } else {
+            let mut lines = reader.lines();
+            let mut line_number = 0;
+            while let Some(line) = lines.next_line().await? {
+                line_number += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let events = self
+                    .decode(&mut serde_json::Deserializer::from_str(&line))
+                    .with_context(|| format!("invalid event on line {line_number}"))?;
+                if !send(events, &sink).await {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode<'de, D>(&mut self, deserializer: D) -> Result<Vec<(Event, Timestamp)>>
+    where
+        D: serde::Deserializer<'de>,
+        D::Error: Send + Sync + 'static,
+    {
+        match self.format {
+            TraceFormat::Rust => {
+                let raw = RawOutputEvent::deserialize(deserializer)?;
+                let time = time(raw.time_s)?;
+                Ok(vec![(self.event(raw.message)?, time)])
+            }
+            TraceFormat::Haskell => {
+                self.haskell_events(HaskellOutputEvent::deserialize(deserializer)?)
+            }
+        }
+    }
+
+    fn event(&mut self, raw: RawEvent) -> Result<Event> {
+        Ok(match raw {
+            RawEvent::GlobalSlot { slot } => Event::GlobalSlot { slot },
+            RawEvent::Slot { node, slot } => Event::Slot {
+                node: self.node(&node)?,
+                slot,
+            },
+            RawEvent::CpuTaskScheduled {
+                task,
+                task_type,
+                subtasks,
+            } => Event::CpuTaskScheduled {
+                task: self.cpu_task(task)?,
+                task_type,
+                subtasks,
+            },
+            RawEvent::CpuTaskFinished {
+                task,
+                task_type,
+                cpu_time_s,
+                wall_time_s,
+                extra,
+            } => Event::CpuTaskFinished {
+                task: self.cpu_task(task)?,
+                task_type,
+                cpu_time_s: secs(cpu_time_s)?,
+                wall_time_s: secs(wall_time_s)?,
+                extra,
+            },
+            RawEvent::Cpu {
+                task,
+                node,
+                cpu_time_s,
+                task_label,
+                task_type,
+                id,
+            } => Event::Cpu {
+                task: self.cpu_task(task)?,
+                node,
+                cpu_time_s: secs(cpu_time_s)?,
+                task_label,
+                task_type,
+                id,
+            },
+            RawEvent::TXGenerated {
+                id,
+                publisher,
+                size_bytes,
+                shard,
+                input_id,
+                overcollateralization_factor,
+                execution_steps,
+                execution_memory,
+            } => Event::TXGenerated {
+                id: tx_id(&id)?,
+                publisher: self.node(&publisher)?,
+                size_bytes,
+                shard,
+                input_id,
+                overcollateralization_factor,
+                execution_steps,
+                execution_memory,
+            },
+            RawEvent::TXSent {
+                id,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => Event::TXSent {
+                id: tx_id(&id)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+                msg_size_bytes,
+            },
+            RawEvent::TXReceived {
+                id,
+                sender,
+                recipient,
+            } => Event::TXReceived {
+                id: tx_id(&id)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+            },
+            RawEvent::TXLost { id, reason } => Event::TXLost {
+                id: tx_id(&id)?,
+                reason,
+            },
+            RawEvent::RBLotteryWon { id, slot, producer } => Event::RBLotteryWon {
+                id: self.block_id(&id)?,
+                slot,
+                producer: self.node(&producer)?,
+            },
+            RawEvent::RBGenerated {
+                id,
+                slot,
+                producer,
+                vrf,
+                parent,
+                header_bytes,
+                size_bytes,
+                endorsement,
+                transactions,
+            } => Event::RBGenerated {
+                id: self.block_id(&id)?,
+                slot,
+                producer: self.node(&producer)?,
+                vrf,
+                parent: parent
+                    .map(|parent| self.block_id(&parent.id).map(|id| BlockRef { id }))
+                    .transpose()?,
+                header_bytes,
+                size_bytes,
+                endorsement: endorsement
+                    .map(|endorsement| self.endorsement(endorsement))
+                    .transpose()?,
+                transactions: transactions
+                    .iter()
+                    .map(|id| tx_id(id))
+                    .collect::<Result<_>>()?,
+            },
+            RawEvent::RBSent {
+                id,
+                slot,
+                producer,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => Event::RBSent {
+                id: self.block_id(&id)?,
+                slot,
+                producer: self.node(&producer)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+                msg_size_bytes,
+            },
+            RawEvent::RBReceived {
+                id,
+                slot,
+                producer,
+                sender,
+                recipient,
+            } => Event::RBReceived {
+                id: self.block_id(&id)?,
+                slot,
+                producer: self.node(&producer)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+            },
+            RawEvent::RBRolledBack {
+                node,
+                depth,
+                old_tip,
+                new_tip,
+                rolled_back,
+            } => Event::RBRolledBack {
+                node: self.node(&node)?,
+                depth,
+                old_tip: self.block_id(&old_tip)?,
+                new_tip: self.block_id(&new_tip)?,
+                rolled_back: rolled_back
+                    .iter()
+                    .map(|block| self.block_id(&block.id).map(|id| BlockRef { id }))
+                    .collect::<Result<_>>()?,
+            },
+            RawEvent::IBLotteryWon {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+            } => Event::IBLotteryWon {
+                id: self.ib_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                index,
+            },
+            RawEvent::IBGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+                shard,
+                header_bytes,
+                tx_payload_bytes,
+                size_bytes,
+                transactions,
+                rb_ref,
+            } => Event::IBGenerated {
+                id: self.ib_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                index,
+                shard,
+                header_bytes,
+                tx_payload_bytes,
+                size_bytes,
+                transactions: transactions
+                    .iter()
+                    .map(|id| tx_id(id))
+                    .collect::<Result<_>>()?,
+                rb_ref: rb_ref.map(|id| self.block_id(&id)).transpose()?,
+            },
+            RawEvent::NoIBGenerated { node, slot } => Event::NoIBGenerated {
+                node: self.node(&node)?,
+                slot,
+            },
+            RawEvent::IBSent {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => Event::IBSent {
+                id: self.ib_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                index,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+                msg_size_bytes,
+            },
+            RawEvent::IBReceived {
+                id,
+                slot,
+                pipeline,
+                producer,
+                index,
+                sender,
+                recipient,
+            } => Event::IBReceived {
+                id: self.ib_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                index,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+            },
+            RawEvent::EBLotteryWon {
+                id,
+                slot,
+                pipeline,
+                producer,
+            } => Event::EBLotteryWon {
+                id: self.eb_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+            },
+            RawEvent::EBGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                transactions,
+                input_blocks,
+                endorser_blocks,
+            } => Event::EBGenerated {
+                id: self.eb_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                size_bytes,
+                transactions: transactions
+                    .iter()
+                    .map(|tx| tx_id(&tx.id).map(|id| BlockRef { id }))
+                    .collect::<Result<_>>()?,
+                input_blocks: input_blocks
+                    .iter()
+                    .map(|ib| self.ib_id(&ib.id, None).map(|id| BlockRef { id }))
+                    .collect::<Result<_>>()?,
+                endorser_blocks: endorser_blocks
+                    .iter()
+                    .map(|eb| self.eb_id(&eb.id, None).map(|id| BlockRef { id }))
+                    .collect::<Result<_>>()?,
+            },
+            RawEvent::NoEBGenerated { node, slot } => Event::NoEBGenerated {
+                node: self.node(&node)?,
+                slot,
+            },
+            RawEvent::EBSent {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => Event::EBSent {
+                id: self.eb_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+                msg_size_bytes,
+            },
+            RawEvent::EBReceived {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+            } => Event::EBReceived {
+                id: self.eb_id(&id, Some(pipeline))?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+            },
+            RawEvent::VTLotteryWon {
+                id,
+                slot,
+                pipeline,
+                producer,
+            } => Event::VTLotteryWon {
+                id: self.vt_id(&id, pipeline)?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+            },
+            RawEvent::VTBundleGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                votes,
+            } => Event::VTBundleGenerated {
+                id: self.vt_id(&id, pipeline)?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                size_bytes,
+                votes: Votes(
+                    votes
+                        .iter()
+                        .map(|(eb, count)| anyhow::Ok((self.eb_id(eb, None)?, *count)))
+                        .collect::<Result<_>>()?,
+                ),
+            },
+            RawEvent::NoVTBundleGenerated { node, slot } => Event::NoVTBundleGenerated {
+                node: self.node(&node)?,
+                slot,
+            },
+            RawEvent::VTBundleNotGenerated {
+                slot,
+                pipeline,
+                producer,
+                eb,
+                reason,
+            } => Event::VTBundleNotGenerated {
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                eb: self.eb_id(&eb, None)?,
+                reason,
+            },
+            RawEvent::VTBundleSent {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+                msg_size_bytes,
+            } => Event::VTBundleSent {
+                id: self.vt_id(&id, pipeline)?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+                msg_size_bytes,
+            },
+            RawEvent::VTBundleReceived {
+                id,
+                slot,
+                pipeline,
+                producer,
+                sender,
+                recipient,
+            } => Event::VTBundleReceived {
+                id: self.vt_id(&id, pipeline)?,
+                slot,
+                pipeline,
+                producer: self.node(&producer)?,
+                sender: self.node(&sender)?,
+                recipient: self.node(&recipient)?,
+            },
+        })
+    }
+
+    fn node(&self, name: &str) -> Result<Node> {
+        self.nodes.get(name).cloned().ok_or_else(|| {
+            anyhow!("the trace mentions node \"{name}\", which isn't in the topology")
+        })
+    }
+
+    fn cpu_task(&self, task: RawCpuTask) -> Result<CpuTaskId> {
+        Ok(CpuTaskId {
+            node: self.node(&task.node)?,
+            index: task.index,
+        })
+    }
+
+    fn endorsement(&mut self, endorsement: RawEndorsement) -> Result<Endorsement<Node>> {
+        Ok(Endorsement {
+            eb: BlockRef {
+                id: self.eb_id(&endorsement.eb.id, None)?,
+            },
+            size_bytes: endorsement.size_bytes,
+            votes: endorsement
+                .votes
+                .iter()
+                .map(|(node, count)| anyhow::Ok((self.node(node)?, *count)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    // Block IDs are written as "<slot>-<producer>". Node names can contain dashes, slots can't.
+    fn slot_and_producer(&self, id: &str) -> Result<(u64, Node)> {
+        let Some((slot, producer)) = id.split_once('-') else {
+            bail!("invalid block ID \"{id}\"");
+        };
+        let slot = slot
+            .parse()
+            .with_context(|| format!("invalid block ID \"{id}\""))?;
+        Ok((slot, self.node(producer)?))
+    }
+
+    fn block_id(&self, id: &str) -> Result<BlockId> {
+        let (slot, producer) = self.slot_and_producer(id)?;
+        Ok(BlockId { slot, producer })
+    }
+
+    // IB IDs are written as "<slot>-<producer>-<index>".
+    fn ib_id(&mut self, id: &str, pipeline: Option<u64>) -> Result<InputBlockId> {
+        let Some((rest, index)) = id.rsplit_once('-') else {
+            bail!("invalid IB ID \"{id}\"");
+        };
+        let index = index
+            .parse()
+            .with_context(|| format!("invalid IB ID \"{id}\""))?;
+        let (slot, producer) = self.slot_and_producer(rest)?;
+        let key = (slot, producer.id, index);
+        let pipeline = match pipeline {
+            Some(pipeline) => *self.ib_pipelines.entry(key).or_insert(pipeline),
+            // An IB which was never seen is still given the same ID everywhere it's mentioned
+            None => self.ib_pipelines.get(&key).copied().unwrap_or_default(),
+        };
+        Ok(InputBlockId {
+            slot,
+            pipeline,
+            producer,
+            index,
+        })
+    }
+
+    fn eb_id(&mut self, id: &str, pipeline: Option<u64>) -> Result<EndorserBlockId> {
+        let (slot, producer) = self.slot_and_producer(id)?;
+        let key = (slot, producer.id);
+        let pipeline = match pipeline {
+            Some(pipeline) => *self.eb_pipelines.entry(key).or_insert(pipeline),
+            None => self.eb_pipelines.get(&key).copied().unwrap_or_default(),
+        };
+        Ok(EndorserBlockId {
+            slot,
+            pipeline,
+            producer,
+        })
+    }
+
+    fn vt_id(&self, id: &str, pipeline: u64) -> Result<VoteBundleId> {
+        let (slot, producer) = self.slot_and_producer(id)?;
+        Ok(VoteBundleId {
+            slot,
+            pipeline,
+            producer,
+        })
+    }
+}
+
+// Returns false once nobody is listening anymore.
+async fn send(events: Vec<(Event, Timestamp)>, sink: &mpsc::Sender<(Event, Timestamp)>) -> bool {
+    for event in events {
+        if sink.send(event).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+fn secs(secs: f64) -> Result<Duration> {
+    Duration::try_from_secs_f64(secs).map_err(|_| anyhow!("invalid number of seconds {secs}"))
+}
+
+fn time(secs: f64) -> Result<Timestamp> {
+    if !(secs.is_finite() && secs >= 0.0) {
+        bail!("invalid time {secs}");
+    }
+    Ok(Timestamp::from_secs_f64(secs))
+}
+
+fn tx_id(id: &str) -> Result<TransactionId> {
+    let id = id
+        .parse()
+        .with_context(|| format!("invalid transaction ID \"{id}\""))?;
+    Ok(TransactionId::new(id))
+}
+
+// The shape of events in a trace, before node names and IDs are resolved.
+
+#[derive(Deserialize)]
+struct RawOutputEvent {
+    time_s: f64,
+    message: RawEvent,
+}
+
+#[derive(Deserialize)]
+struct RawRef {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct RawCpuTask {
+    node: String,
+    index: u64,
+}
+
+#[derive(Deserialize)]
+struct RawEndorsement {
+    eb: RawRef,
+    size_bytes: u64,
+    votes: BTreeMap<String, usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum RawEvent {
+    GlobalSlot {
+        slot: u64,
+    },
+    Slot {
+        node: String,
+        slot: u64,
+    },
+    CpuTaskScheduled {
+        task: RawCpuTask,
+        task_type: String,
+        subtasks: usize,
+    },
+    CpuTaskFinished {
+        task: RawCpuTask,
+        task_type: String,
+        cpu_time_s: f64,
+        wall_time_s: f64,
+        extra: String,
+    },
+    Cpu {
+        task: RawCpuTask,
+        node: String,
+        cpu_time_s: f64,
+        task_label: String,
+        task_type: String,
+        id: String,
+    },
+    TXGenerated {
+        id: String,
+        publisher: String,
+        size_bytes: u64,
+        shard: u64,
+        input_id: u64,
+        overcollateralization_factor: u64,
+        // Older traces predate execution budgets
+        #[serde(default)]
+        execution_steps: u64,
+        #[serde(default)]
+        execution_memory: u64,
+    },
+    TXSent {
+        id: String,
+        sender: String,
+        recipient: String,
+        msg_size_bytes: u64,
+    },
+    TXReceived {
+        id: String,
+        sender: String,
+        recipient: String,
+    },
+    TXLost {
+        id: String,
+        reason: TransactionLostReason,
+    },
+    RBLotteryWon {
+        id: String,
+        slot: u64,
+        producer: String,
+    },
+    RBGenerated {
+        id: String,
+        slot: u64,
+        producer: String,
+        vrf: u64,
+        parent: Option<RawRef>,
+        header_bytes: u64,
+        size_bytes: u64,
+        endorsement: Option<RawEndorsement>,
+        transactions: Vec<String>,
+    },
+    RBSent {
+        id: String,
+        slot: u64,
+        producer: String,
+        sender: String,
+        recipient: String,
+        msg_size_bytes: u64,
+    },
+    RBReceived {
+        id: String,
+        slot: u64,
+        producer: String,
+        sender: String,
+        recipient: String,
+    },
+    RBRolledBack {
+        node: String,
+        depth: usize,
+        old_tip: String,
+        new_tip: String,
+        rolled_back: Vec<RawRef>,
+    },
+    IBLotteryWon {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        index: u64,
+    },
+    IBGenerated {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        index: u64,
+        shard: u64,
+        header_bytes: u64,
+        tx_payload_bytes: u64,
+        size_bytes: u64,
+        transactions: Vec<String>,
+        rb_ref: Option<String>,
+    },
+    NoIBGenerated {
+        node: String,
+        slot: u64,
+    },
+    IBSent {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        index: u64,
+        sender: String,
+        recipient: String,
+        msg_size_bytes: u64,
+    },
+    IBReceived {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        index: u64,
+        sender: String,
+        recipient: String,
+    },
+    EBLotteryWon {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+    },
+    EBGenerated {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        size_bytes: u64,
+        transactions: Vec<RawRef>,
+        input_blocks: Vec<RawRef>,
+        endorser_blocks: Vec<RawRef>,
+    },
+    NoEBGenerated {
+        node: String,
+        slot: u64,
+    },
+    EBSent {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        sender: String,
+        recipient: String,
+        msg_size_bytes: u64,
+    },
+    EBReceived {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        sender: String,
+        recipient: String,
+    },
+    VTLotteryWon {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+    },
+    VTBundleGenerated {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        size_bytes: u64,
+        votes: BTreeMap<String, usize>,
+    },
+    NoVTBundleGenerated {
+        node: String,
+        slot: u64,
+    },
+    VTBundleNotGenerated {
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        eb: String,
+        reason: NoVoteReason,
+    },
+    VTBundleSent {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        sender: String,
+        recipient: String,
+        msg_size_bytes: u64,
+    },
+    VTBundleReceived {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        sender: String,
+        recipient: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc, time::Duration};
+
+    use anyhow::Result;
+    use serde::Serialize;
+    use sim_core::{
+        clock::Timestamp,
+        config::{NodeConfiguration, NodeId},
+        events::{BlockRef, Event, EventKind, Node, Votes},
+        model::{CpuTaskId, EndorserBlockId, InputBlockId},
+    };
+    use tokio::sync::mpsc;
+
+    use super::{TraceFormat, TraceReader, READ_BUFFER};
+
+    fn node_config(id: usize) -> NodeConfiguration {
+        NodeConfiguration {
+            id: NodeId::new(id),
+            name: format!("node-{id}"),
+            stake: 1,
+            cpu_multiplier: 1.0,
+            cores: None,
+            tx_conflict_fraction: None,
+            tx_generation_weight: None,
+            tx_generation_group: None,
+            adversarial_behaviours: vec![],
+            upload_bandwidth_bps: None,
+            download_bandwidth_bps: None,
+            consumers: vec![],
+        }
+    }
+
+    fn node(id: usize) -> Node {
+        Node {
+            id: NodeId::new(id),
+            name: Arc::new(format!("node-{id}")),
+        }
+    }
+
+    async fn read_all(reader: TraceReader, path: &Path) -> Result<Vec<(Event, Timestamp)>> {
+        let (sink, mut source) = mpsc::channel(READ_BUFFER);
+        let handle = async {
+            let mut events = vec![];
+            while let Some(event) = source.recv().await {
+                events.push(event);
+            }
+            events
+        };
+        let (result, events) = tokio::join!(reader.read(path, sink), handle);
+        result?;
+        Ok(events)
+    }
+
+    #[tokio::test]
+    async fn should_read_back_the_events_it_wrote() -> Result<()> {
+        let ib = InputBlockId {
+            slot: 10,
+            pipeline: 2,
+            producer: node(0),
+            index: 1,
+        };
+        let eb = EndorserBlockId {
+            slot: 15,
+            pipeline: 3,
+            producer: node(1),
+        };
+        let events = vec![
+            Event::IBGenerated {
+                id: ib.clone(),
+                slot: 10,
+                pipeline: 2,
+                producer: node(0),
+                index: 1,
+                shard: 0,
+                header_bytes: 300,
+                tx_payload_bytes: 0,
+                size_bytes: 300,
+                transactions: vec![],
+                rb_ref: None,
+            },
+            Event::EBGenerated {
+                id: eb.clone(),
+                slot: 15,
+                pipeline: 3,
+                producer: node(1),
+                size_bytes: 240,
+                transactions: vec![],
+                input_blocks: vec![BlockRef { id: ib }],
+                endorser_blocks: vec![],
+            },
+            Event::VTBundleReceived {
+                id: sim_core::model::VoteBundleId {
+                    slot: 17,
+                    pipeline: 3,
+                    producer: node(0),
+                },
+                slot: 17,
+                pipeline: 3,
+                producer: node(0),
+                sender: node(0),
+                recipient: node(1),
+            },
+            Event::VTBundleGenerated {
+                id: sim_core::model::VoteBundleId {
+                    slot: 17,
+                    pipeline: 3,
+                    producer: node(0),
+                },
+                slot: 17,
+                pipeline: 3,
+                producer: node(0),
+                size_bytes: 100,
+                votes: Votes([(eb, 2)].into_iter().collect()),
+            },
+        ];
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("trace.jsonl");
+        let mut contents = String::new();
+        for (index, event) in events.iter().enumerate() {
+            let time = Timestamp::from_secs(index as u64);
+            let line = serde_json::json!({ "time_s": time, "message": event });
+            contents.push_str(&line.to_string());
+            contents.push('\n');
+        }
+        std::fs::write(&path, contents)?;
+
+        let reader = TraceReader::new(&[node_config(0), node_config(1)]);
+        let read = read_all(reader, &path).await?;
+        assert_eq!(read.len(), events.len());
+        for (index, ((event, time), expected)) in read.iter().zip(&events).enumerate() {
+            assert_eq!(*time, Timestamp::from_secs(index as u64));
+            assert_eq!(
+                serde_json::to_value(event)?,
+                serde_json::to_value(expected)?
+            );
+        }
+
+        // Pipelines aren't in the trace, so they come from where each block was generated
+        let Event::EBGenerated { input_blocks, .. } = &read[1].0 else {
+            panic!("expected an EB");
+        };
+        assert_eq!(input_blocks[0].id.pipeline, 2);
+        let Event::VTBundleGenerated { votes, .. } = &read[3].0 else {
+            panic!("expected a vote bundle");
+        };
+        assert!(votes.0.keys().all(|eb| eb.pipeline == 3));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_stream_cbor_traces() -> Result<()> {
+        #[derive(Serialize)]
+        struct OutputEvent {
+            time_s: Timestamp,
+            message: Event,
+        }
+        // Enough events that they can't all fit in one read
+        let mut bytes = vec![];
+        for slot in 0..5000 {
+            let event = OutputEvent {
+                time_s: Timestamp::from_secs(slot),
+                message: Event::GlobalSlot { slot },
+            };
+            bytes.extend(minicbor_serde::to_vec(&event)?);
+        }
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("trace.cbor");
+        std::fs::write(&path, &bytes)?;
+
+        let mut slots = vec![];
+        for (event, time) in read_all(TraceReader::new(&[]), &path).await? {
+            let Event::GlobalSlot { slot } = event else {
+                panic!("expected a slot");
+            };
+            assert_eq!(time, Timestamp::from_secs(slot));
+            slots.push(slot);
+        }
+        assert_eq!(slots, (0..5000).collect::<Vec<_>>());
+
+        // A value cut off part way through isn't just ignored
+        std::fs::write(&path, &bytes[..bytes.len() - 1])?;
+        let (sink, _source) = mpsc::channel(READ_BUFFER);
+        let error = TraceReader::new(&[]).read(&path, sink).await.unwrap_err();
+        assert!(error.to_string().contains("event 5000"), "{error}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_reject_negative_durations() -> Result<()> {
+        let event = Event::CpuTaskFinished {
+            task: CpuTaskId {
+                node: node(0),
+                index: 0,
+            },
+            task_type: "ValIB".to_string(),
+            cpu_time_s: Duration::from_millis(5),
+            wall_time_s: Duration::from_millis(5),
+            extra: String::new(),
+        };
+        let mut message = serde_json::to_value(&event)?;
+        message["cpu_time_s"] = (-0.005).into();
+        let lines = [
+            serde_json::json!({ "time_s": 1.0, "message": { "type": "GlobalSlot", "slot": 1 } }),
+            serde_json::json!({ "time_s": 1.5, "message": message }),
+        ];
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("negative.jsonl");
+        std::fs::write(&path, lines.map(|line| line.to_string()).join("\n"))?;
+
+        let (sink, _source) = mpsc::channel(READ_BUFFER);
+        let error = TraceReader::new(&[node_config(0)])
+            .read(&path, sink)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid event on line 2");
+        assert!(format!("{error:#}").contains("-0.005"), "{error:#}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_reject_negative_durations_in_haskell_traces() -> Result<()> {
+        let lines = [
+            r#"{"time_s":1.5,"message":{"type":"Cpu","node":"node-0","cpu_time_s":0.001,"task_type":"ValIB","id":"ib-1"}}"#,
+            r#"{"time_s":1.6,"message":{"type":"Cpu","node":"node-0","cpu_time_s":-0.001,"task_type":"ValIB","id":"ib-2"}}"#,
+        ];
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("haskell-cpu.jsonl");
+        std::fs::write(&path, lines.join("\n"))?;
+
+        let (sink, _source) = mpsc::channel(READ_BUFFER);
+        let error = TraceReader::new(&[node_config(0)])
+            .with_format(TraceFormat::Haskell)
+            .read(&path, sink)
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "invalid event on line 2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_translate_haskell_traces() -> Result<()> {
+        let lines = [
+            r#"{"time_s":1.5,"message":{"type":"IBGenerated","id":"ib-7","slot":1,"pipeline":0,"producer":"node-0","size_bytes":1300,"tx_payload_bytes":1000}}"#,
+            r#"{"time_s":1.6,"message":{"type":"SomethingHaskellOnly","node":"node-0"}}"#,
+            r#"{"time_s":1.7,"message":{"type":"IBSent","sender":"node-0","recipient":"node-1","msg_size_bytes":1300,"id":"ib-7"}}"#,
+            r#"{"time_s":1.9,"message":{"type":"IBReceived","recipient":"node-1","id":"ib-7"}}"#,
+            r#"{"time_s":2.5,"message":{"type":"EBGenerated","id":"eb-3","slot":2,"pipeline":0,"producer":"node-1","size_bytes":240,"input_blocks":[{"id":"ib-7"},{"id":"ib-unknown"}],"endorser_blocks":[]}}"#,
+        ];
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("haskell.jsonl");
+        std::fs::write(&path, lines.join("\n"))?;
+
+        let reader =
+            TraceReader::new(&[node_config(0), node_config(1)]).with_format(TraceFormat::Haskell);
+        let events = read_all(reader, &path).await?;
+        let kinds: Vec<_> = events.iter().map(|(event, _)| event.kind()).collect();
+        // Slots are filled in as time passes, and events which only Haskell has are skipped
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::GlobalSlot,
+                EventKind::GlobalSlot,
+                EventKind::IBGenerated,
+                EventKind::IBSent,
+                EventKind::IBReceived,
+                EventKind::GlobalSlot,
+                EventKind::EBGenerated
+            ]
+        );
+        assert_eq!(events[5].1, Timestamp::from_secs(2));
+
+        let Event::IBReceived { sender, .. } = &events[4].0 else {
+            panic!("expected an IB to be received");
+        };
+        assert_eq!(sender.name.as_str(), "node-0");
+        let Event::EBGenerated { input_blocks, .. } = &events[6].0 else {
+            panic!("expected an EB");
+        };
+        assert_eq!(input_blocks.len(), 1);
+        assert_eq!(input_blocks[0].id.producer.name.as_str(), "node-0");
+        assert_eq!(input_blocks[0].id.slot, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use serde::Deserialize;
+use sim_core::{
+    clock::Timestamp,
+    config::NodeId,
+    events::{BlockRef, Endorsement, Event, Node, Votes},
+    model::CpuTaskId,
+};
+
+use super::{secs, time, BlockId, EndorserBlockId, InputBlockId, TraceReader, VoteBundleId};
+
+/// What the reader has learned from a Haskell trace so far.
+///
+/// The Haskell simulator gives blocks IDs of its own, and only says which slot and producer
+/// a block belongs to when it's generated. Every block is given a Rust ID when it's generated,
+/// and anything which refers to it later on is translated to that ID.
+#[derive(Default)]
+pub(super) struct HaskellState {
+    next_global_slot: u64,
+    rbs: HashMap<String, BlockId>,
+    ibs: HashMap<String, InputBlockId>,
+    ebs: HashMap<String, EndorserBlockId>,
+    vts: HashMap<String, VoteBundleId>,
+    ib_indices: HashMap<(u64, NodeId), u64>,
+    cpu_tasks: HashMap<NodeId, u64>,
+    // Who sent each block to each node, for "Received" events which leave out the sender
+    senders: HashMap<(String, NodeId), Node>,
+}
+
+#[derive(Clone, Copy)]
+enum BlockKind {
+    Rb,
+    Ib,
+    Eb,
+    Vt,
+}
+
+impl TraceReader {
+    /// Translates one event from a Haskell trace. Some Haskell events have no Rust equivalent,
+    /// and some (such as a message which carries several blocks) stand for several Rust events.
+    pub(super) fn haskell_events(
+        &mut self,
+        raw: HaskellOutputEvent,
+    ) -> Result<Vec<(Event, Timestamp)>> {
+        let time = time(raw.time_s)?;
+        let mut events = vec![];
+
+        // The Haskell simulator doesn't mark the start of each slot, but every slot is one second
+        while Timestamp::from_secs(self.haskell.next_global_slot) <= time {
+            let slot = self.haskell.next_global_slot;
+            events.push((Event::GlobalSlot { slot }, Timestamp::from_secs(slot)));
+            self.haskell.next_global_slot += 1;
+        }
+
+        match raw.message {
+            HaskellEvent::Slot { node, slot } => {
+                let node = self.node(&node)?;
+                events.push((Event::Slot { node, slot }, time));
+            }
+            HaskellEvent::NoIBGenerated { node, slot } => {
+                let node = self.node(&node)?;
+                events.push((Event::NoIBGenerated { node, slot }, time));
+            }
+            HaskellEvent::NoEBGenerated { node, slot } => {
+                let node = self.node(&node)?;
+                events.push((Event::NoEBGenerated { node, slot }, time));
+            }
+            HaskellEvent::NoVTBundleGenerated { node, slot } => {
+                let node = self.node(&node)?;
+                events.push((Event::NoVTBundleGenerated { node, slot }, time));
+            }
+            HaskellEvent::Cpu {
+                node,
+                cpu_time_s,
+                task_type,
+                id,
+            } => {
+                let node = self.node(&node)?;
+                let index = self.haskell.cpu_tasks.entry(node.id).or_default();
+                let task = CpuTaskId {
+                    node: node.clone(),
+                    index: *index,
+                };
+                *index += 1;
+                let event = Event::Cpu {
+                    task,
+                    node: node.to_string(),
+                    cpu_time_s: secs(cpu_time_s)?,
+                    task_label: format!("{task_type}: {id}"),
+                    task_type,
+                    id,
+                };
+                events.push((event, time));
+            }
+            HaskellEvent::RBGenerated {
+                id,
+                slot,
+                producer,
+                size_bytes,
+                tx_payload_bytes,
+                endorsement,
+                endorsements,
+                parent,
+            } => {
+                let producer = self.node(&producer)?;
+                let rb_id = BlockId {
+                    slot,
+                    producer: producer.clone(),
+                };
+                self.haskell.rbs.insert(id, rb_id.clone());
+                let endorsement = endorsement
+                    .into_iter()
+                    .chain(endorsements.into_iter().flatten())
+                    .find_map(|endorsement| self.haskell.ebs.get(&endorsement.eb.id).cloned())
+                    .map(|eb| Endorsement {
+                        eb: BlockRef { id: eb },
+                        size_bytes: 0,
+                        votes: BTreeMap::new(),
+                    });
+                let parent = parent
+                    .and_then(|parent| self.haskell.rbs.get(&parent.id).cloned())
+                    .map(|id| BlockRef { id });
+                let event = Event::RBGenerated {
+                    id: rb_id,
+                    slot,
+                    producer,
+                    vrf: 0,
+                    parent,
+                    header_bytes: size_bytes.saturating_sub(tx_payload_bytes),
+                    size_bytes,
+                    endorsement,
+                    transactions: vec![],
+                };
+                events.push((event, time));
+            }
+            HaskellEvent::IBGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                tx_payload_bytes,
+                rb_ref,
+            } => {
+                let producer = self.node(&producer)?;
+                let index = self
+                    .haskell
+                    .ib_indices
+                    .entry((slot, producer.id))
+                    .or_default();
+                let ib_id = InputBlockId {
+                    slot,
+                    pipeline,
+                    producer: producer.clone(),
+                    index: *index,
+                };
+                *index += 1;
+                self.haskell.ibs.insert(id, ib_id.clone());
+                let event = Event::IBGenerated {
+                    index: ib_id.index,
+                    id: ib_id,
+                    slot,
+                    pipeline,
+                    producer,
+                    shard: 0,
+                    header_bytes: size_bytes.saturating_sub(tx_payload_bytes),
+                    tx_payload_bytes,
+                    size_bytes,
+                    transactions: vec![],
+                    rb_ref: rb_ref.and_then(|rb| self.haskell.rbs.get(&rb).cloned()),
+                };
+                events.push((event, time));
+            }
+            HaskellEvent::EBGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                input_blocks,
+                endorser_blocks,
+            } => {
+                let producer = self.node(&producer)?;
+                let eb_id = EndorserBlockId {
+                    slot,
+                    pipeline,
+                    producer: producer.clone(),
+                };
+                self.haskell.ebs.insert(id, eb_id.clone());
+                // Blocks from before the trace started can't be referred to
+                let input_blocks = input_blocks
+                    .iter()
+                    .filter_map(|ib| self.haskell.ibs.get(&ib.id).cloned())
+                    .map(|id| BlockRef { id })
+                    .collect();
+                let endorser_blocks = endorser_blocks
+                    .iter()
+                    .filter_map(|eb| self.haskell.ebs.get(&eb.id).cloned())
+                    .map(|id| BlockRef { id })
+                    .collect();
+                let event = Event::EBGenerated {
+                    id: eb_id,
+                    slot,
+                    pipeline,
+                    producer,
+                    size_bytes,
+                    transactions: vec![],
+                    input_blocks,
+                    endorser_blocks,
+                };
+                events.push((event, time));
+            }
+            HaskellEvent::VTBundleGenerated {
+                id,
+                slot,
+                pipeline,
+                producer,
+                size_bytes,
+                votes,
+            } => {
+                let producer = self.node(&producer)?;
+                let vt_id = VoteBundleId {
+                    slot,
+                    pipeline,
+                    producer: producer.clone(),
+                };
+                self.haskell.vts.insert(id, vt_id.clone());
+                let votes = votes
+                    .iter()
+                    .filter_map(|(eb, count)| Some((self.haskell.ebs.get(eb)?.clone(), *count)))
+                    .collect();
+                let event = Event::VTBundleGenerated {
+                    id: vt_id,
+                    slot,
+                    pipeline,
+                    producer,
+                    size_bytes,
+                    votes: Votes(votes),
+                };
+                events.push((event, time));
+            }
+            HaskellEvent::RBSent(sent) => {
+                self.haskell_sent(BlockKind::Rb, sent, time, &mut events)?
+            }
+            HaskellEvent::IBSent(sent) => {
+                self.haskell_sent(BlockKind::Ib, sent, time, &mut events)?
+            }
+            HaskellEvent::EBSent(sent) => {
+                self.haskell_sent(BlockKind::Eb, sent, time, &mut events)?
+            }
+            HaskellEvent::VTBundleSent(sent) => {
+                self.haskell_sent(BlockKind::Vt, sent, time, &mut events)?
+            }
+            HaskellEvent::RBReceived(received) => {
+                self.haskell_received(BlockKind::Rb, received, time, &mut events)?
+            }
+            HaskellEvent::IBReceived(received) => {
+                self.haskell_received(BlockKind::Ib, received, time, &mut events)?
+            }
+            HaskellEvent::EBReceived(received) => {
+                self.haskell_received(BlockKind::Eb, received, time, &mut events)?
+            }
+            HaskellEvent::VTBundleReceived(received) => {
+                self.haskell_received(BlockKind::Vt, received, time, &mut events)?
+            }
+            HaskellEvent::Unknown => {}
+        }
+        Ok(events)
+    }
+
+    fn haskell_sent(
+        &mut self,
+        kind: BlockKind,
+        sent: HaskellSent,
+        time: Timestamp,
+        events: &mut Vec<(Event, Timestamp)>,
+    ) -> Result<()> {
+        let sender = self.node(&sent.sender)?;
+        let recipient = self.node(&sent.recipient)?;
+        // One message can carry several blocks, but its size is only counted once
+        let mut msg_size_bytes = sent.msg_size_bytes;
+        for id in block_ids(sent.id, sent.ids) {
+            self.haskell
+                .senders
+                .insert((id.clone(), recipient.id), sender.clone());
+            let sender = sender.clone();
+            let recipient = recipient.clone();
+            let event = match kind {
+                BlockKind::Rb => self.haskell.rbs.get(&id).map(|id| Event::RBSent {
+                    id: id.clone(),
+                    slot: id.slot,
+                    producer: id.producer.clone(),
+                    sender,
+                    recipient,
+                    msg_size_bytes,
+                }),
+                BlockKind::Ib => self.haskell.ibs.get(&id).map(|id| Event::IBSent {
+                    id: id.clone(),
+                    slot: id.slot,
+                    pipeline: id.pipeline,
+                    producer: id.producer.clone(),
+                    index: id.index,
+                    sender,
+                    recipient,
+                    msg_size_bytes,
+                }),
+                BlockKind::Eb => self.haskell.ebs.get(&id).map(|id| Event::EBSent {
+                    id: id.clone(),
+                    slot: id.slot,
+                    pipeline: id.pipeline,
+                    producer: id.producer.clone(),
+                    sender,
+                    recipient,
+                    msg_size_bytes,
+                }),
+                BlockKind::Vt => self.haskell.vts.get(&id).map(|id| Event::VTBundleSent {
+                    id: id.clone(),
+                    slot: id.slot,
+                    pipeline: id.pipeline,
+                    producer: id.producer.clone(),
+                    sender,
+                    recipient,
+                    msg_size_bytes,
+                }),
+            };
+            if let Some(event) = event {
+                events.push((event, time));
+                msg_size_bytes = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn haskell_received(
+        &mut self,
+        kind: BlockKind,
+        received: HaskellReceived,
+        time: Timestamp,
+        events: &mut Vec<(Event, Timestamp)>,
+    ) -> Result<()> {
+        let recipient = self.node(&received.recipient)?;
+        let sender = received
+            .sender
+            .map(|sender| self.node(&sender))
+            .transpose()?;
+        for id in block_ids(received.id, received.ids) {
+            // Without a sender, assume the block came from whoever last sent it here.
+            // It's forgotten either way, so the senders of received blocks don't pile up.
+            let last_sender = self.haskell.senders.remove(&(id.clone(), recipient.id));
+            let sender = sender.clone().or(last_sender);
+            let recipient = recipient.clone();
+            let event = match kind {
+                BlockKind::Rb => self.haskell.rbs.get(&id).map(|id| Event::RBReceived {
+                    id: id.clone(),
+                    slot: id.slot,
+                    producer: id.producer.clone(),
+                    sender: sender.unwrap_or_else(|| id.producer.clone()),
+                    recipient,
+                }),
+                BlockKind::Ib => self.haskell.ibs.get(&id).map(|id| Event::IBReceived {
+                    id: id.clone(),
+                    slot: id.slot,
+                    pipeline: id.pipeline,
+                    producer: id.producer.clone(),
+                    index: id.index,
+                    sender: sender.unwrap_or_else(|| id.producer.clone()),
+                    recipient,
+                }),
+                BlockKind::Eb => self.haskell.ebs.get(&id).map(|id| Event::EBReceived {
+                    id: id.clone(),
+                    slot: id.slot,
+                    pipeline: id.pipeline,
+                    producer: id.producer.clone(),
+                    sender: sender.unwrap_or_else(|| id.producer.clone()),
+                    recipient,
+                }),
+                BlockKind::Vt => self.haskell.vts.get(&id).map(|id| Event::VTBundleReceived {
+                    id: id.clone(),
+                    slot: id.slot,
+                    pipeline: id.pipeline,
+                    producer: id.producer.clone(),
+                    sender: sender.unwrap_or_else(|| id.producer.clone()),
+                    recipient,
+                }),
+            };
+            events.extend(event.map(|event| (event, time)));
+        }
+        Ok(())
+    }
+}
+
+// Messages which carry several blocks list all of them in `ids`.
+fn block_ids(id: String, ids: Option<Vec<String>>) -> Vec<String> {
+    match ids {
+        Some(ids) if !ids.is_empty() => ids,
+        _ => vec![id],
+    }
+}
+
+// The shape of events in a Haskell trace, as described by data/simulation/trace.shared.d.ts.
+
+#[derive(Deserialize)]
+pub(super) struct HaskellOutputEvent {
+    time_s: f64,
+    message: HaskellEvent,
+}
+
+#[derive(Deserialize)]
+struct HaskellRef {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct HaskellEndorsement {
+    eb: HaskellRef,
+}
+
+#[derive(Deserialize)]
+struct HaskellSent {
+    sender: String,
+    recipient: String,
+    msg_size_bytes: u64,
+    id: String,
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+struct HaskellReceived {
+    sender: Option<String>,
+    recipient: String,
+    id: String,
+    ids: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum HaskellEvent {
+    Slot {
+        node: String,
+        slot: u64,
+    },
+    NoIBGenerated {
+        node: String,
+        slot: u64,
+    },
+    NoEBGenerated {
+        node: String,
+        slot: u64,
+    },
+    NoVTBundleGenerated {
+        node: String,
+        slot: u64,
+    },
+    Cpu {
+        node: String,
+        cpu_time_s: f64,
+        task_type: String,
+        id: String,
+    },
+    RBGenerated {
+        id: String,
+        slot: u64,
+        producer: String,
+        size_bytes: u64,
+        tx_payload_bytes: u64,
+        endorsement: Option<HaskellEndorsement>,
+        endorsements: Option<Vec<HaskellEndorsement>>,
+        parent: Option<HaskellRef>,
+    },
+    IBGenerated {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        size_bytes: u64,
+        tx_payload_bytes: u64,
+        rb_ref: Option<String>,
+    },
+    EBGenerated {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        size_bytes: u64,
+        input_blocks: Vec<HaskellRef>,
+        endorser_blocks: Vec<HaskellRef>,
+    },
+    VTBundleGenerated {
+        id: String,
+        slot: u64,
+        pipeline: u64,
+        producer: String,
+        size_bytes: u64,
+        votes: BTreeMap<String, usize>,
+    },
+    RBSent(HaskellSent),
+    IBSent(HaskellSent),
+    EBSent(HaskellSent),
+    VTBundleSent(HaskellSent),
+    RBReceived(HaskellReceived),
+    IBReceived(HaskellReceived),
+    EBReceived(HaskellReceived),
+    VTBundleReceived(HaskellReceived),
+    // The Haskell simulator logs plenty of events which have nothing to do with the shared format
+    #[serde(other)]
+    Unknown,
+}
@@ -0,0 +1,510 @@
+use std::{
+    any::Any,
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
+    fs,
+    hash::Hash,
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use priority_queue::PriorityQueue;
+use rand_chacha::{rand_core::SeedableRng as _, ChaChaRng};
+use tokio::sync::oneshot;
+
+use crate::{clock::Timestamp, config::NodeId};
+
+const MAGIC: &[u8] = b"leios-sim-checkpoint";
+const VERSION: u32 = 7;
+
+/// The complete state of a paused simulation, which can be saved to disk and resumed later.
+pub struct Checkpoint {
+    time: Timestamp,
+    fingerprint: u64,
+    state: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// The simulated time at which the checkpoint was taken.
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    /// The fingerprint of the configuration the checkpointed simulation was running with.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            bail!("{} is not a simulation checkpoint", path.display());
+        };
+        let mut reader = CheckpointReader::new(rest);
+        let version = u32::load(&mut reader)?;
+        if version != VERSION {
+            bail!("checkpoint has version {version}, but only version {VERSION} is supported");
+        }
+        let time = Timestamp::load(&mut reader)?;
+        let fingerprint = u64::load(&mut reader)?;
+        let state = reader.bytes.to_vec();
+        Ok(Self {
+            time,
+            fingerprint,
+            state,
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut writer = CheckpointWriter::new();
+        writer.bytes.extend_from_slice(MAGIC);
+        VERSION.save(&mut writer);
+        self.time.save(&mut writer);
+        self.fingerprint.save(&mut writer);
+        writer.bytes.extend_from_slice(&self.state);
+        fs::write(path, writer.bytes)?;
+        Ok(())
+    }
+
+    pub(crate) fn new(time: Timestamp, fingerprint: u64, writer: CheckpointWriter) -> Self {
+        Self {
+            time,
+            fingerprint,
+            state: writer.bytes,
+        }
+    }
+
+    pub(crate) fn reader(&self) -> CheckpointReader<'_> {
+        CheckpointReader::new(&self.state)
+    }
+}
+
+/// Asks a running actor to write its state, and hand the writer back when it's done.
+pub(crate) type CheckpointRequest = (CheckpointWriter, oneshot::Sender<CheckpointWriter>);
+
+pub(crate) struct CheckpointWriter {
+    bytes: Vec<u8>,
+    // Every Arc written so far, by address, so that values shared between actors are only written once.
+    // Holding onto them keeps their addresses from being reused while the checkpoint is taken.
+    shared: HashMap<usize, (u64, Arc<dyn Any + Send + Sync>)>,
+}
+
+impl CheckpointWriter {
+    pub fn new() -> Self {
+        Self {
+            bytes: vec![],
+            shared: HashMap::new(),
+        }
+    }
+}
+
+pub(crate) struct CheckpointReader<'a> {
+    bytes: &'a [u8],
+    shared: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+impl<'a> CheckpointReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            shared: vec![],
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let Some((bytes, rest)) = self.bytes.split_first_chunk() else {
+            bail!("checkpoint ended unexpectedly");
+        };
+        self.bytes = rest;
+        Ok(*bytes)
+    }
+
+    /// Fails unless everything in the checkpoint has been read.
+    pub fn finish(self) -> Result<()> {
+        if !self.bytes.is_empty() {
+            bail!(
+                "checkpoint has {} unexpected bytes at the end",
+                self.bytes.len()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// State which can be saved to a checkpoint, and loaded back exactly as it was.
+pub(crate) trait Persist: Sized {
+    fn save(&self, w: &mut CheckpointWriter);
+    fn load(r: &mut CheckpointReader) -> Result<Self>;
+}
+
+/// Implements `Persist` for a struct by saving each of its fields in order.
+macro_rules! persist_struct {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::checkpoint::Persist for $name {
+            fn save(&self, w: &mut $crate::checkpoint::CheckpointWriter) {
+                $($crate::checkpoint::Persist::save(&self.$field, w);)*
+            }
+            fn load(r: &mut $crate::checkpoint::CheckpointReader) -> anyhow::Result<Self> {
+                Ok(Self {
+                    $($field: $crate::checkpoint::Persist::load(r)?,)*
+                })
+            }
+        }
+    };
+}
+
+/// Implements `Persist` for an enum by saving the index of its variant, and then the variant's fields.
+/// Every variant must be listed, in order.
+macro_rules! persist_enum {
+    ($name:ident { $($variant:ident $(( $($field:ident),* ))? $({ $($named:ident),* })?),* $(,)? }) => {
+        impl $crate::checkpoint::Persist for $name {
+            #[allow(unused_assignments)]
+            fn save(&self, w: &mut $crate::checkpoint::CheckpointWriter) {
+                let mut tag = 0u8;
+                $(
+                    if let Self::$variant $(( $($field),* ))? $({ $($named),* })? = self {
+                        $crate::checkpoint::Persist::save(&tag, w);
+                        $($($crate::checkpoint::Persist::save($field, w);)*)?
+                        $($($crate::checkpoint::Persist::save($named, w);)*)?
+                        return;
+                    }
+                    tag += 1;
+                )*
+            }
+            #[allow(unused_assignments)]
+            fn load(r: &mut $crate::checkpoint::CheckpointReader) -> anyhow::Result<Self> {
+                let tag: u8 = $crate::checkpoint::Persist::load(r)?;
+                let mut index = 0u8;
+                $(
+                    if tag == index {
+                        return Ok(Self::$variant
+                            $(( $({
+                                let _ = stringify!($field);
+                                $crate::checkpoint::Persist::load(r)?
+                            }),* ))?
+                            $({ $($named: $crate::checkpoint::Persist::load(r)?),* })?);
+                    }
+                    index += 1;
+                )*
+                anyhow::bail!("unknown {} variant {tag} in checkpoint", stringify!($name))
+            }
+        }
+    };
+}
+
+pub(crate) use persist_enum;
+pub(crate) use persist_struct;
+
+macro_rules! persist_int {
+    ($($int:ty),*) => {
+        $(
+            impl Persist for $int {
+                fn save(&self, w: &mut CheckpointWriter) {
+                    w.bytes.extend_from_slice(&self.to_le_bytes());
+                }
+                fn load(r: &mut CheckpointReader) -> Result<Self> {
+                    Ok(Self::from_le_bytes(r.take()?))
+                }
+            }
+        )*
+    };
+}
+
+persist_int!(u8, u32, u64, u128, f64);
+
+impl Persist for usize {
+    fn save(&self, w: &mut CheckpointWriter) {
+        (*self as u64).save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(u64::load(r)?.try_into()?)
+    }
+}
+
+impl Persist for bool {
+    fn save(&self, w: &mut CheckpointWriter) {
+        u8::from(*self).save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(u8::load(r)? != 0)
+    }
+}
+
+impl Persist for String {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.len().save(w);
+        w.bytes.extend_from_slice(self.as_bytes());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        let len = usize::load(r)?;
+        if r.bytes.len() < len {
+            bail!("checkpoint ended unexpectedly");
+        }
+        let (bytes, rest) = r.bytes.split_at(len);
+        r.bytes = rest;
+        Ok(String::from_utf8(bytes.to_vec())?)
+    }
+}
+
+impl Persist for Duration {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.as_nanos().save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Duration::from_nanos(u128::load(r)?.try_into()?))
+    }
+}
+
+impl Persist for NodeId {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.to_inner().save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(NodeId::new(usize::load(r)?))
+    }
+}
+
+impl Persist for ChaChaRng {
+    fn save(&self, w: &mut CheckpointWriter) {
+        w.bytes.extend_from_slice(&self.get_seed());
+        self.get_stream().save(w);
+        self.get_word_pos().save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        let mut rng = ChaChaRng::from_seed(r.take()?);
+        rng.set_stream(u64::load(r)?);
+        rng.set_word_pos(u128::load(r)?);
+        Ok(rng)
+    }
+}
+
+impl<T: Persist> Persist for Option<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.is_some().save(w);
+        if let Some(value) = self {
+            value.save(w);
+        }
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(match bool::load(r)? {
+            true => Some(T::load(r)?),
+            false => None,
+        })
+    }
+}
+
+impl<T: Persist> Persist for Reverse<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.0.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Reverse(T::load(r)?))
+    }
+}
+
+impl<A: Persist, B: Persist> Persist for (A, B) {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok((A::load(r)?, B::load(r)?))
+    }
+}
+
+impl<A: Persist, B: Persist, C: Persist> Persist for (A, B, C) {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.0.save(w);
+        self.1.save(w);
+        self.2.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok((A::load(r)?, B::load(r)?, C::load(r)?))
+    }
+}
+
+/// Arcs which are shared between different parts of the simulation stay shared when loaded.
+impl<T: Persist + Send + Sync + 'static> Persist for Arc<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        let address = Arc::as_ptr(self) as usize;
+        if let Some((index, _)) = w.shared.get(&address) {
+            let index = *index;
+            true.save(w);
+            index.save(w);
+            return;
+        }
+        false.save(w);
+        self.as_ref().save(w);
+        // Indices are assigned after the contents are written, to match the order they are loaded in.
+        let index = w.shared.len() as u64;
+        w.shared.insert(address, (index, self.clone()));
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        if bool::load(r)? {
+            let index = usize::load(r)?;
+            let shared = r
+                .shared
+                .get(index)
+                .ok_or_else(|| anyhow!("checkpoint refers to unknown value {index}"))?;
+            return shared
+                .clone()
+                .downcast()
+                .map_err(|_| anyhow!("checkpoint value {index} has the wrong type"));
+        }
+        let value = Arc::new(T::load(r)?);
+        r.shared.push(value.clone());
+        Ok(value)
+    }
+}
+
+fn save_all<'a, T: Persist + 'a>(
+    w: &mut CheckpointWriter,
+    len: usize,
+    values: impl Iterator<Item = &'a T>,
+) {
+    len.save(w);
+    for value in values {
+        value.save(w);
+    }
+}
+
+fn load_all<T: Persist, C: FromIterator<T>>(r: &mut CheckpointReader) -> Result<C> {
+    let len = usize::load(r)?;
+    (0..len).map(|_| T::load(r)).collect()
+}
+
+fn save_pairs<'a, K: Persist + 'a, V: Persist + 'a>(
+    w: &mut CheckpointWriter,
+    len: usize,
+    pairs: impl Iterator<Item = (&'a K, &'a V)>,
+) {
+    len.save(w);
+    for (key, value) in pairs {
+        key.save(w);
+        value.save(w);
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_all(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+impl<T: Persist> Persist for VecDeque<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_all(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+impl<T: Persist + Ord> Persist for BTreeSet<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_all(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+impl<T: Persist + Eq + Hash> Persist for HashSet<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_all(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+/// Heaps are saved in their internal order, so that equal elements still come out in the same order.
+impl<T: Persist + Ord> Persist for BinaryHeap<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_all(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(BinaryHeap::from(load_all::<T, Vec<T>>(r)?))
+    }
+}
+
+impl<K: Persist + Ord, V: Persist> Persist for BTreeMap<K, V> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_pairs(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+impl<K: Persist + Eq + Hash, V: Persist> Persist for HashMap<K, V> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_pairs(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+impl<I: Persist + Eq + Hash, P: Persist + Ord> Persist for PriorityQueue<I, P> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        save_pairs(w, self.len(), self.iter());
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        load_all(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use anyhow::Result;
+    use rand::RngCore as _;
+    use rand_chacha::{rand_core::SeedableRng as _, ChaChaRng};
+
+    use super::{CheckpointReader, CheckpointWriter, Persist};
+
+    fn round_trip<T: Persist>(value: &T) -> Result<T> {
+        let mut writer = CheckpointWriter::new();
+        value.save(&mut writer);
+        let mut reader = CheckpointReader::new(&writer.bytes);
+        let loaded = T::load(&mut reader)?;
+        reader.finish()?;
+        Ok(loaded)
+    }
+
+    #[test]
+    fn should_keep_shared_values_shared() -> Result<()> {
+        let inner = Arc::new("shared".to_string());
+        let outer = Arc::new(vec![inner.clone(), inner.clone()]);
+        let value: BTreeMap<u64, Arc<Vec<Arc<String>>>> =
+            [(1, outer.clone()), (2, outer), (3, Arc::new(vec![inner]))].into();
+
+        let loaded = round_trip(&value)?;
+        assert_eq!(loaded, value);
+        assert!(Arc::ptr_eq(&loaded[&1], &loaded[&2]));
+        assert!(!Arc::ptr_eq(&loaded[&1], &loaded[&3]));
+        assert!(Arc::ptr_eq(&loaded[&1][0], &loaded[&3][0]));
+        Ok(())
+    }
+
+    #[test]
+    fn should_continue_random_streams() -> Result<()> {
+        let mut rng = ChaChaRng::seed_from_u64(7);
+        rng.set_stream(3);
+        for _ in 0..5 {
+            rng.next_u32();
+        }
+        let mut loaded = round_trip(&rng)?;
+        for _ in 0..100 {
+            assert_eq!(loaded.next_u64(), rng.next_u64());
+        }
+        Ok(())
+    }
+}
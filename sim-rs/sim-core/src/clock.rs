@@ -7,14 +7,19 @@ use std::{
     time::Duration,
 };
 
+use anyhow::Result;
 pub use coordinator::ClockCoordinator;
 use coordinator::ClockEvent;
 use futures::FutureExt;
+pub(crate) use pacer::Pacer;
 use timestamp::AtomicTimestamp;
 pub use timestamp::Timestamp;
 use tokio::sync::{mpsc, oneshot};
 
+use crate::checkpoint::{CheckpointReader, CheckpointWriter, Persist};
+
 mod coordinator;
+mod pacer;
 mod timestamp;
 
 // wrapper struct which holds a SimulationEvent,
@@ -47,10 +52,21 @@ impl<T> Ord for FutureEvent<T> {
     }
 }
 
+impl<T: Persist> Persist for FutureEvent<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Self(Timestamp::load(r)?, T::load(r)?))
+    }
+}
+
 #[derive(Clone)]
 pub struct Clock {
     timestamp_resolution: Duration,
     time: Arc<AtomicTimestamp>,
+    phase: Arc<AtomicUsize>,
     waiters: Arc<AtomicUsize>,
     tasks: Arc<AtomicUsize>,
     tx: mpsc::UnboundedSender<ClockEvent>,
@@ -60,6 +76,7 @@ impl Clock {
     fn new(
         timestamp_resolution: Duration,
         time: Arc<AtomicTimestamp>,
+        phase: Arc<AtomicUsize>,
         waiters: Arc<AtomicUsize>,
         tasks: Arc<AtomicUsize>,
         tx: mpsc::UnboundedSender<ClockEvent>,
@@ -67,6 +84,7 @@ impl Clock {
         Self {
             timestamp_resolution,
             time,
+            phase,
             waiters,
             tasks,
             tx,
@@ -78,23 +96,41 @@ impl Clock {
     }
 
     pub fn barrier(&self) -> ClockBarrier {
+        self.barrier_in_phase(0)
+    }
+
+    /// A barrier for an actor which is only woken up at an instant once every actor in an earlier phase
+    /// has finished with it, and everything they started has finished too.
+    pub(crate) fn barrier_in_phase(&self, phase: usize) -> ClockBarrier {
         let id = self
             .waiters
             .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
         ClockBarrier {
             id,
+            phase,
             timestamp_resolution: self.timestamp_resolution,
             time: self.time.clone(),
+            current_phase: self.phase.clone(),
             tasks: self.tasks.clone(),
             tx: self.tx.clone(),
         }
     }
+
+    /// Stops time from advancing past `limit` (or lets it run freely, if `limit` is None).
+    /// The receiver resolves once everything scheduled up to the limit has happened.
+    pub(crate) fn pause_at(&self, limit: Option<Timestamp>) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.tx.send(ClockEvent::SetLimit { limit, reached: tx });
+        rx
+    }
 }
 
 pub struct ClockBarrier {
     id: usize,
+    phase: usize,
     timestamp_resolution: Duration,
     time: Arc<AtomicTimestamp>,
+    current_phase: Arc<AtomicUsize>,
     tasks: Arc<AtomicUsize>,
     tx: mpsc::UnboundedSender<ClockEvent>,
 }
@@ -122,11 +158,17 @@ impl ClockBarrier {
 
     fn wait(&mut self, until: Option<Timestamp>) -> Waiter {
         let (tx, rx) = oneshot::channel();
-        let done = until.is_some_and(|ts| ts == self.now())
+        // Time is published after the phase, so reading it first means the phase is at least as new.
+        let now = self.now();
+        let phase = self
+            .current_phase
+            .load(std::sync::atomic::Ordering::Acquire);
+        let done = until.is_some_and(|ts| ts == now && self.phase <= phase)
             || self
                 .tx
                 .send(ClockEvent::Wait {
                     actor: self.id,
+                    phase: self.phase,
                     until,
                     done: tx,
                 })
@@ -7,17 +7,27 @@ use std::{
     time::Duration,
 };
 
-use tokio::sync::{mpsc, oneshot};
+use anyhow::{bail, Result};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+    time::{self, Instant},
+};
 
-use super::{timestamp::AtomicTimestamp, Clock, Timestamp};
+use super::{timestamp::AtomicTimestamp, Clock, Pacer, Timestamp};
 
 pub struct ClockCoordinator {
     timestamp_resolution: Duration,
     time: Arc<AtomicTimestamp>,
+    // Which phase of actors is running at the current time.
+    phase: Arc<AtomicUsize>,
     tx: mpsc::UnboundedSender<ClockEvent>,
     rx: mpsc::UnboundedReceiver<ClockEvent>,
     waiter_count: Arc<AtomicUsize>,
     tasks: Arc<AtomicUsize>,
+    timescale: Option<f64>,
+    limit: Option<Timestamp>,
+    limit_reached: Option<oneshot::Sender<()>>,
 }
 
 impl ClockCoordinator {
@@ -29,17 +39,44 @@ impl ClockCoordinator {
         Self {
             timestamp_resolution,
             time,
+            phase: Arc::new(AtomicUsize::new(0)),
             tx,
             rx,
             waiter_count,
             tasks,
+            timescale: None,
+            limit: None,
+            limit_reached: None,
+        }
+    }
+
+    /// Pace the simulation against the wall clock, running `timescale` simulated seconds
+    /// for every real second. If the simulation can't keep up, it runs as fast as it can.
+    pub fn with_timescale(mut self, timescale: f64) -> Result<Self> {
+        if !(timescale.is_finite() && timescale > 0.0) {
+            bail!("timescale must be a positive number, not {timescale}");
         }
+        self.timescale = Some(timescale);
+        Ok(self)
+    }
+
+    /// Moves the clock straight to the given time. Used to start from a checkpoint,
+    /// and by engines which decide when time moves on without calling `run`.
+    pub(crate) fn set_time(&mut self, time: Timestamp) {
+        self.phase.store(0, Ordering::Release);
+        self.time.store(time, Ordering::Release);
+    }
+
+    /// Paces the simulation from the current time.
+    pub(crate) fn pacer(&self) -> Pacer {
+        Pacer::new(self.time.load(Ordering::Acquire), self.timescale)
     }
 
     pub fn clock(&self) -> Clock {
         Clock::new(
             self.timestamp_resolution,
             self.time.clone(),
+            self.phase.clone(),
             self.waiter_count.clone(),
             self.tasks.clone(),
             self.tx.clone(),
@@ -52,36 +89,62 @@ impl ClockCoordinator {
             waiters.push(None);
         }
 
-        let mut queue: BTreeMap<Timestamp, Vec<usize>> = BTreeMap::new();
+        // Pacing starts from wherever the clock is now, which might not be zero after a checkpoint.
+        let mut pacer = self.pacer();
+        // Actors which are due at the same time are woken up one phase at a time.
+        let mut queue: BTreeMap<(Timestamp, usize), Vec<usize>> = BTreeMap::new();
         let mut running = waiters.len();
-        while let Some(event) = self.rx.recv().await {
+        let mut paused_until = None;
+        loop {
+            let event = match paused_until {
+                Some(deadline) => select! {
+                    event = self.rx.recv() => event,
+                    _ = time::sleep_until(deadline) => {
+                        paused_until = None;
+                        self.advance_time(pacer, &mut queue, &mut waiters, &mut running, &mut paused_until);
+                        continue;
+                    }
+                },
+                None => self.rx.recv().await,
+            };
+            let Some(event) = event else {
+                break;
+            };
             match event {
-                ClockEvent::Wait { actor, until, done } => {
+                ClockEvent::Wait {
+                    actor,
+                    phase,
+                    until,
+                    done,
+                } => {
                     assert!(until.is_none_or(|t| t >= self.time.load(Ordering::Acquire)));
                     if waiters[actor].replace(Waiter { until, done }).is_some() {
                         panic!("An actor has somehow managed to wait twice");
                     }
                     running -= 1;
                     if let Some(timestamp) = until {
-                        queue.entry(timestamp).or_default().push(actor);
-                    }
-                    while running == 0 && self.tasks.load(Ordering::Acquire) == 0 {
-                        // advance time
-                        let (timestamp, waiter_ids) = queue.pop_first().unwrap();
-                        self.time.store(timestamp, Ordering::Release);
-
-                        for id in waiter_ids {
-                            if waiters[id]
-                                .as_ref()
-                                .and_then(|w| w.until)
-                                .is_some_and(|ts| ts == timestamp)
-                            {
-                                running += 1;
-                                let waiter = waiters[id].take().unwrap();
-                                let _ = waiter.done.send(());
-                            }
-                        }
+                        queue.entry((timestamp, phase)).or_default().push(actor);
                     }
+                    self.advance_time(
+                        pacer,
+                        &mut queue,
+                        &mut waiters,
+                        &mut running,
+                        &mut paused_until,
+                    );
+                }
+                ClockEvent::SetLimit { limit, reached } => {
+                    self.limit = limit;
+                    self.limit_reached = Some(reached);
+                    // The clock may have been paused for a while, so don't try to catch up.
+                    pacer = self.pacer();
+                    self.advance_time(
+                        pacer,
+                        &mut queue,
+                        &mut waiters,
+                        &mut running,
+                        &mut paused_until,
+                    );
                 }
                 ClockEvent::CancelWait { actor } => {
                     if waiters[actor].take().is_some() {
@@ -98,6 +161,49 @@ impl ClockCoordinator {
             }
         }
     }
+
+    fn advance_time(
+        &mut self,
+        pacer: Pacer,
+        queue: &mut BTreeMap<(Timestamp, usize), Vec<usize>>,
+        waiters: &mut [Option<Waiter>],
+        running: &mut usize,
+        paused_until: &mut Option<Instant>,
+    ) {
+        while *running == 0 && self.tasks.load(Ordering::Acquire) == 0 {
+            let (&(timestamp, _), _) = queue.first_key_value().unwrap();
+            if let Some(limit) = self.limit.filter(|limit| timestamp > *limit) {
+                // Everything up to the limit has happened, so catch the clock up to it and stop
+                if limit > self.time.load(Ordering::Acquire) {
+                    self.time.store(limit, Ordering::Release);
+                }
+                if let Some(reached) = self.limit_reached.take() {
+                    let _ = reached.send(());
+                }
+                return;
+            }
+            if let Some(deadline) = pacer.deadline(timestamp) {
+                *paused_until = Some(deadline);
+                return;
+            }
+
+            let ((timestamp, phase), waiter_ids) = queue.pop_first().unwrap();
+            self.phase.store(phase, Ordering::Release);
+            self.time.store(timestamp, Ordering::Release);
+
+            for id in waiter_ids {
+                if waiters[id]
+                    .as_ref()
+                    .and_then(|w| w.until)
+                    .is_some_and(|ts| ts == timestamp)
+                {
+                    *running += 1;
+                    let waiter = waiters[id].take().unwrap();
+                    let _ = waiter.done.send(());
+                }
+            }
+        }
+    }
 }
 
 struct Waiter {
@@ -109,12 +215,17 @@ struct Waiter {
 pub enum ClockEvent {
     Wait {
         actor: usize,
+        phase: usize,
         until: Option<Timestamp>,
         done: oneshot::Sender<()>,
     },
     CancelWait {
         actor: usize,
     },
+    SetLimit {
+        limit: Option<Timestamp>,
+        reached: oneshot::Sender<()>,
+    },
     FinishTask,
 }
 
@@ -123,9 +234,9 @@ mod tests {
     use std::{task::Poll, time::Duration};
 
     use futures::poll;
-    use tokio::pin;
+    use tokio::{pin, time};
 
-    use super::ClockCoordinator;
+    use super::{ClockCoordinator, Timestamp};
 
     const TIMESTAMP_RESOLUTION: Duration = Duration::from_nanos(1);
 
@@ -156,6 +267,36 @@ mod tests {
         assert_eq!(poll!(wait1), Poll::Ready(())); // the 5ms wait is done
     }
 
+    #[tokio::test]
+    async fn should_wake_actors_one_phase_at_a_time() {
+        let mut coordinator = ClockCoordinator::new(TIMESTAMP_RESOLUTION);
+        let clock = coordinator.clock();
+        let t0 = clock.now();
+        let t1 = t0 + Duration::from_millis(5);
+        let t2 = t0 + Duration::from_millis(10);
+        let mut late = clock.barrier_in_phase(1);
+        let mut early = clock.barrier_in_phase(0);
+
+        let run_future = coordinator.run();
+        pin!(run_future);
+
+        let mut wait_late = late.wait_until(t1);
+        assert_eq!(poll!(&mut wait_late), Poll::Pending);
+        let mut wait_early = early.wait_until(t1);
+        assert_eq!(poll!(&mut wait_early), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending); // try advancing time
+        assert_eq!(clock.now(), t1);
+        assert_eq!(poll!(wait_early), Poll::Ready(())); // the earlier phase goes first
+        assert_eq!(poll!(&mut wait_late), Poll::Pending); // the later phase waits for it
+
+        let mut wait_early = early.wait_until(t2);
+        assert_eq!(poll!(&mut wait_early), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending); // try advancing time
+        assert_eq!(clock.now(), t1); // still the same instant
+        assert_eq!(poll!(wait_late), Poll::Ready(()));
+        assert_eq!(poll!(&mut wait_early), Poll::Pending);
+    }
+
     #[tokio::test]
     async fn should_cancel_wait_when_wait_future_is_dropped() {
         let mut coordinator = ClockCoordinator::new(TIMESTAMP_RESOLUTION);
@@ -276,4 +417,140 @@ mod tests {
         assert_eq!(poll!(&mut wait2), Poll::Pending); // the eternal wait is still pending
         assert_eq!(poll!(wait1), Poll::Ready(())); // the 5ms wait is done
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_pace_time_against_the_wall_clock() {
+        let mut coordinator = ClockCoordinator::new(TIMESTAMP_RESOLUTION)
+            .with_timescale(2.0)
+            .unwrap();
+        let clock = coordinator.clock();
+        let t0 = clock.now();
+        let t1 = t0 + Duration::from_secs(1);
+        let mut actor = clock.barrier();
+
+        let run_future = coordinator.run();
+        pin!(run_future);
+
+        let mut wait = actor.wait_until(t1);
+        assert_eq!(poll!(&mut wait), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending); // try advancing time
+        assert_eq!(clock.now(), t0); // at 2x speed, one simulated second takes half a real second
+
+        time::advance(Duration::from_millis(400)).await;
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t0); // not yet...
+
+        time::advance(Duration::from_millis(100)).await;
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t1); // now!
+        assert_eq!(poll!(wait), Poll::Ready(()));
+    }
+
+    #[tokio::test]
+    async fn should_stop_at_the_limit() {
+        let mut coordinator = ClockCoordinator::new(TIMESTAMP_RESOLUTION);
+        let clock = coordinator.clock();
+        let t0 = clock.now();
+        let t1 = t0 + Duration::from_millis(5);
+        let limit = t0 + Duration::from_millis(7);
+        let t2 = t0 + Duration::from_millis(10);
+        let mut actor = clock.barrier();
+        let mut reached = clock.pause_at(Some(limit));
+
+        let run_future = coordinator.run();
+        pin!(run_future);
+
+        let mut wait1 = actor.wait_until(t1);
+        assert_eq!(poll!(&mut wait1), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t1); // time can advance up to the limit
+        assert_eq!(poll!(wait1), Poll::Ready(()));
+
+        let mut wait2 = actor.wait_until(t2);
+        assert_eq!(poll!(&mut wait2), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), limit); // but no further
+        assert_eq!(poll!(&mut reached), Poll::Ready(Ok(())));
+        assert_eq!(poll!(&mut wait2), Poll::Pending);
+
+        // lifting the limit lets time move on
+        drop(clock.pause_at(None));
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t2);
+        assert_eq!(poll!(wait2), Poll::Ready(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_pace_time_from_where_the_clock_starts() {
+        let mut coordinator = ClockCoordinator::new(TIMESTAMP_RESOLUTION)
+            .with_timescale(2.0)
+            .unwrap();
+        let t0 = Timestamp::from_secs(500);
+        let t1 = t0 + Duration::from_secs(1);
+        coordinator.set_time(t0); // as if resuming from a checkpoint
+        let clock = coordinator.clock();
+        let mut actor = clock.barrier();
+
+        let run_future = coordinator.run();
+        pin!(run_future);
+
+        let mut wait = actor.wait_until(t1);
+        assert_eq!(poll!(&mut wait), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t0);
+
+        // The first 500 simulated seconds never happened, so there's nothing to wait for
+        time::advance(Duration::from_millis(500)).await;
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t1);
+        assert_eq!(poll!(wait), Poll::Ready(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_not_catch_up_on_time_spent_paused() {
+        let mut coordinator = ClockCoordinator::new(TIMESTAMP_RESOLUTION)
+            .with_timescale(2.0)
+            .unwrap();
+        let clock = coordinator.clock();
+        let t0 = clock.now();
+        let t1 = t0 + Duration::from_secs(1);
+        let t2 = t0 + Duration::from_secs(2);
+        let mut actor = clock.barrier();
+        let mut reached = clock.pause_at(Some(t1));
+
+        let run_future = coordinator.run();
+        pin!(run_future);
+
+        let mut wait = actor.wait_until(t2);
+        assert_eq!(poll!(&mut wait), Poll::Pending);
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        time::advance(Duration::from_millis(500)).await;
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t1);
+        assert_eq!(poll!(&mut reached), Poll::Ready(Ok(())));
+
+        // Stay paused for a while, then carry on at the same pace as before
+        time::advance(Duration::from_secs(10)).await;
+        drop(clock.pause_at(None));
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t1);
+
+        time::advance(Duration::from_millis(400)).await;
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t1); // not yet...
+
+        time::advance(Duration::from_millis(100)).await;
+        assert_eq!(poll!(&mut run_future), Poll::Pending);
+        assert_eq!(clock.now(), t2);
+        assert_eq!(poll!(wait), Poll::Ready(()));
+    }
+
+    #[test]
+    fn should_reject_timescales_that_cannot_pace_anything() {
+        for timescale in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(ClockCoordinator::new(TIMESTAMP_RESOLUTION)
+                .with_timescale(timescale)
+                .is_err());
+        }
+    }
 }
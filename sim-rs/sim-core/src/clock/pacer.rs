@@ -0,0 +1,45 @@
+use tokio::{
+    select,
+    time::{self, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+use super::Timestamp;
+
+/// Keeps simulated time from getting ahead of the wall clock, when the simulation has a timescale.
+/// Pacing starts from when the pacer is made, so that time spent paused or restored from a checkpoint
+/// isn't caught up on.
+#[derive(Clone, Copy)]
+pub(crate) struct Pacer {
+    started: (Instant, Timestamp),
+    timescale: Option<f64>,
+}
+
+impl Pacer {
+    pub(crate) fn new(now: Timestamp, timescale: Option<f64>) -> Self {
+        Self {
+            started: (Instant::now(), now),
+            timescale,
+        }
+    }
+
+    /// When the simulation can reach the given time, if it has to wait for it at all.
+    pub(crate) fn deadline(&self, timestamp: Timestamp) -> Option<Instant> {
+        let timescale = self.timescale?;
+        let deadline = self.started.0 + (timestamp - self.started.1).div_f64(timescale);
+        (deadline > Instant::now()).then_some(deadline)
+    }
+
+    /// Waits until the simulation can reach the given time, and returns false if the token is cancelled first.
+    /// Even without a timescale, everything else on this runtime (such as event consumers) gets a chance to run.
+    pub(crate) async fn wait_until(&self, timestamp: Timestamp, token: &CancellationToken) -> bool {
+        if let Some(deadline) = self.deadline(timestamp) {
+            select! {
+                _ = token.cancelled() => return false,
+                _ = time::sleep_until(deadline) => {}
+            }
+        }
+        tokio::task::yield_now().await;
+        true
+    }
+}
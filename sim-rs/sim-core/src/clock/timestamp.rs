@@ -4,8 +4,11 @@ use std::{
     time::Duration,
 };
 
+use anyhow::Result;
 use serde::Serialize;
 
+use crate::checkpoint::{CheckpointReader, CheckpointWriter, Persist};
+
 const NANOS_PER_SEC: u64 = 1_000_000_000;
 
 /// A timestamp tracks the time from the start of the simulation.
@@ -21,6 +24,11 @@ impl Timestamp {
         Self(secs * NANOS_PER_SEC)
     }
 
+    /// The inverse of how timestamps are serialized, rounded to the nearest nanosecond.
+    pub fn from_secs_f64(secs: f64) -> Self {
+        Self((secs * NANOS_PER_SEC as f64).round() as u64)
+    }
+
     pub fn checked_sub_duration(self, rhs: Duration) -> Option<Self> {
         Some(Self(self.0.checked_sub(rhs.as_nanos() as u64)?))
     }
@@ -69,6 +77,15 @@ impl Serialize for Timestamp {
     }
 }
 
+impl Persist for Timestamp {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.0.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Self(u64::load(r)?))
+    }
+}
+
 pub struct AtomicTimestamp(AtomicU64);
 impl AtomicTimestamp {
     pub fn new(val: Timestamp) -> Self {
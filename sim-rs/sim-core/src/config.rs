@@ -1,16 +1,21 @@
 use std::{
+    cmp::Reverse,
     collections::{BTreeMap, HashSet, VecDeque},
     fmt::Display,
+    path::PathBuf,
+    str::FromStr,
     sync::{atomic::AtomicU64, Arc},
     time::Duration,
 };
 
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context as _, Result};
+use rand::Rng;
+use rand_distr::Distribution as _;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     clock::Timestamp,
-    model::{Transaction, TransactionId},
+    model::{ExecutionUnits, Transaction, TransactionId},
     probability::FloatDistribution,
 };
 
@@ -30,16 +35,51 @@ impl NodeId {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "distribution", rename_all = "kebab-case")]
 pub enum DistributionConfig {
-    Normal { mean: f64, std_dev: f64 },
-    Exp { lambda: f64, scale: Option<f64> },
-    LogNormal { mu: f64, sigma: f64 },
-    Constant { value: f64 },
+    Normal {
+        mean: f64,
+        std_dev: f64,
+    },
+    Exp {
+        lambda: f64,
+        scale: Option<f64>,
+    },
+    LogNormal {
+        mu: f64,
+        sigma: f64,
+    },
+    Constant {
+        value: f64,
+    },
+    Uniform {
+        min: f64,
+        max: f64,
+    },
+    Pareto {
+        scale: f64,
+        shape: f64,
+    },
+    Weibull {
+        scale: f64,
+        shape: f64,
+    },
+    Gamma {
+        shape: f64,
+        scale: f64,
+    },
+    /// Samples from one of several distributions, chosen by weight.
+    Mixture {
+        components: Vec<MixtureComponent>,
+    },
+    /// Follows a histogram or a list of samples, given inline or read from a file.
+    Empirical(EmpiricalDistribution),
 }
-impl From<DistributionConfig> for FloatDistribution {
-    fn from(value: DistributionConfig) -> Self {
+impl TryFrom<DistributionConfig> for FloatDistribution {
+    type Error = anyhow::Error;
+
+    fn try_from(value: DistributionConfig) -> Result<Self> {
         match value {
             DistributionConfig::Normal { mean, std_dev } => {
                 FloatDistribution::normal(mean, std_dev)
@@ -48,19 +88,166 @@ impl From<DistributionConfig> for FloatDistribution {
                 FloatDistribution::scaled_exp(lambda, scale.unwrap_or(1.))
             }
             DistributionConfig::LogNormal { mu, sigma } => FloatDistribution::log_normal(mu, sigma),
-            DistributionConfig::Constant { value } => FloatDistribution::constant(value),
+            DistributionConfig::Constant { value } => Ok(FloatDistribution::constant(value)),
+            DistributionConfig::Uniform { min, max } => FloatDistribution::uniform(min, max),
+            DistributionConfig::Pareto { scale, shape } => FloatDistribution::pareto(scale, shape),
+            DistributionConfig::Weibull { scale, shape } => {
+                FloatDistribution::weibull(scale, shape)
+            }
+            DistributionConfig::Gamma { shape, scale } => FloatDistribution::gamma(shape, scale),
+            DistributionConfig::Mixture { components } => FloatDistribution::mixture(
+                components
+                    .into_iter()
+                    .map(|c| Ok((c.weight, c.distribution.try_into()?)))
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            DistributionConfig::Empirical(empirical) => FloatDistribution::histogram(
+                empirical
+                    .bins
+                    .into_iter()
+                    .map(|b| ((b.min, b.max), b.weight)),
+            ),
         }
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MixtureComponent {
+    pub weight: f64,
+    #[serde(flatten)]
+    pub distribution: DistributionConfig,
+}
+
+/// The values of an empirical distribution, as a histogram.
+/// Samples are turned into bins as soon as they're parsed. Files are read before that,
+/// by whoever reads the parameters, so that relative paths can be resolved from where they were written.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(try_from = "RawEmpiricalDistribution")]
+pub struct EmpiricalDistribution {
+    pub bins: Vec<HistogramBin>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistogramBin {
+    pub min: f64,
+    pub max: f64,
+    pub weight: f64,
+}
+
+#[derive(Deserialize)]
+struct RawEmpiricalDistribution {
+    #[serde(default)]
+    samples: Vec<f64>,
+    #[serde(default)]
+    bins: Vec<HistogramBin>,
+    path: Option<PathBuf>,
+}
+
+impl EmpiricalDistribution {
+    /// Reads the bins in the contents of an empirical distribution file.
+    /// Each line holds either one sample, or the min, max and weight of a bin,
+    /// and the first line can be a header instead.
+    pub fn bins_from_file(contents: &str) -> Result<Vec<HistogramBin>> {
+        let mut bins = vec![];
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            // Anything with a number in it is data, even if it isn't valid data
+            if index == 0 && fields.iter().all(|f| f.parse::<f64>().is_err()) {
+                continue;
+            }
+            let values: Result<Vec<f64>, _> = fields.iter().map(|f| f.parse()).collect();
+            match values.as_deref() {
+                Ok([sample]) => bins.push(HistogramBin::sample(*sample)),
+                Ok([min, max, weight]) => bins.push(HistogramBin {
+                    min: *min,
+                    max: *max,
+                    weight: *weight,
+                }),
+                _ => bail!("invalid line {}: \"{line}\"", index + 1),
+            }
+        }
+        Ok(bins)
+    }
+}
+
+impl HistogramBin {
+    fn sample(sample: f64) -> Self {
+        Self {
+            min: sample,
+            max: sample,
+            weight: 1.0,
+        }
+    }
+}
+
+impl TryFrom<RawEmpiricalDistribution> for EmpiricalDistribution {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawEmpiricalDistribution) -> Result<Self> {
+        if let Some(path) = raw.path {
+            bail!(
+                "empirical distribution {path:?} has to be read before the parameters are parsed"
+            );
+        }
+        let mut bins = raw.bins;
+        bins.extend(raw.samples.into_iter().map(HistogramBin::sample));
+        let is_valid = |b: &HistogramBin| {
+            b.min.is_finite() && b.max.is_finite() && b.min <= b.max && b.weight >= 0.0
+        };
+        if let Some(bin) = bins.iter().find(|b| !is_valid(b)) {
+            bail!("invalid empirical distribution bin {bin:?}");
+        }
+        if !bins.iter().any(|b| b.weight > 0.0) {
+            bail!("empirical distribution has no samples or bins");
+        }
+        Ok(Self { bins })
+    }
+}
+
+/// A CPU cost in milliseconds, which is either fixed or sampled afresh for every task.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RawCpuTime {
+    Fixed(f64),
+    Distribution(DistributionConfig),
+}
+
+/// One stretch of a transaction load profile.
+/// While it's active, it scales the rate of transaction generation and the weights of node groups.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RawTxLoadSegment {
+    pub start_time: Option<f64>,
+    pub stop_time: Option<f64>,
+    pub rate: Option<TxRateShape>,
+    #[serde(default)]
+    pub group_weights: BTreeMap<String, f64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(tag = "shape", rename_all = "kebab-case")]
+pub enum TxRateShape {
+    /// Multiplies the rate by a fixed amount.
+    Constant { multiplier: f64 },
+    /// Moves the multiplier from one value to another, between the start and stop time.
+    Linear { from: f64, to: f64 },
+    /// Multiplies the rate by `1 + amplitude * sin(2π * t / period)`, where t is seconds since the start.
+    Sinusoid { period: f64, amplitude: f64 },
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct RawParameters {
     // Simulation Configuration
     pub leios_variant: LeiosVariant,
     pub relay_strategy: RelayStrategy,
+    pub rust_tcp_congestion_control: bool,
     pub simulate_transactions: bool,
     pub timestamp_resolution_ms: f64,
+    pub lottery_model: LotteryModel,
 
     // Leios protocol configuration
     pub leios_stage_length_slots: u64,
@@ -76,63 +263,79 @@ pub struct RawParameters {
     pub tx_generation_distribution: DistributionConfig,
     pub tx_size_bytes_distribution: DistributionConfig,
     pub tx_overcollateralization_factor_distribution: DistributionConfig,
-    pub tx_validation_cpu_time_ms: f64,
+    pub tx_validation_cpu_time_ms: RawCpuTime,
+    pub tx_validation_cpu_time_ms_per_million_steps: Option<RawCpuTime>,
+    pub tx_execution_steps_distribution: Option<DistributionConfig>,
+    pub tx_execution_memory_distribution: Option<DistributionConfig>,
     pub tx_max_size_bytes: u64,
     pub tx_conflict_fraction: Option<f64>,
     pub tx_start_time: Option<f64>,
     pub tx_stop_time: Option<f64>,
+    pub tx_load_profile: Option<Vec<RawTxLoadSegment>>,
 
     // Ranking block configuration
     pub rb_generation_probability: f64,
-    pub rb_generation_cpu_time_ms: f64,
-    pub rb_head_validation_cpu_time_ms: f64,
+    pub rb_generation_cpu_time_ms: RawCpuTime,
+    pub rb_head_validation_cpu_time_ms: RawCpuTime,
     pub rb_head_size_bytes: u64,
     pub rb_body_max_size_bytes: u64,
+    pub rb_body_max_execution_steps: Option<u64>,
+    pub rb_body_max_execution_memory: Option<u64>,
 
-    pub rb_body_legacy_praos_payload_validation_cpu_time_ms_constant: f64,
-    pub rb_body_legacy_praos_payload_validation_cpu_time_ms_per_byte: f64,
+    pub rb_body_legacy_praos_payload_validation_cpu_time_ms_constant: RawCpuTime,
+    pub rb_body_legacy_praos_payload_validation_cpu_time_ms_per_byte: RawCpuTime,
     pub rb_body_legacy_praos_payload_avg_size_bytes: u64,
 
     // Input block configuration
     pub ib_generation_probability: f64,
-    pub ib_generation_cpu_time_ms: f64,
+    pub ib_generation_cpu_time_ms: RawCpuTime,
     pub ib_shards: u64,
     pub ib_shard_period_length_slots: u64,
     pub ib_shard_group_count: u64,
     pub ib_head_size_bytes: u64,
-    pub ib_head_validation_cpu_time_ms: f64,
-    pub ib_body_validation_cpu_time_ms_constant: f64,
-    pub ib_body_validation_cpu_time_ms_per_byte: f64,
+    pub ib_head_validation_cpu_time_ms: RawCpuTime,
+    pub ib_body_validation_cpu_time_ms_constant: RawCpuTime,
+    pub ib_body_validation_cpu_time_ms_per_byte: RawCpuTime,
     pub ib_body_avg_size_bytes: u64,
     pub ib_body_max_size_bytes: u64,
+    pub ib_body_max_execution_steps: Option<u64>,
+    pub ib_body_max_execution_memory: Option<u64>,
     pub ib_diffusion_strategy: DiffusionStrategy,
     pub ib_diffusion_max_bodies_to_request: u64,
 
     // Endorsement block configuration
     pub eb_generation_probability: f64,
-    pub eb_generation_cpu_time_ms: f64,
-    pub eb_validation_cpu_time_ms: f64,
+    pub eb_generation_cpu_time_ms: RawCpuTime,
+    pub eb_validation_cpu_time_ms: RawCpuTime,
     pub eb_size_bytes_constant: u64,
     pub eb_size_bytes_per_ib: u64,
     pub eb_max_age_slots: u64,
     pub eb_referenced_txs_max_size_bytes: u64,
+    pub eb_referenced_txs_max_execution_steps: Option<u64>,
+    pub eb_referenced_txs_max_execution_memory: Option<u64>,
 
     // Vote configuration
     pub vote_generation_probability: f64,
-    pub vote_generation_cpu_time_ms_constant: f64,
-    pub vote_generation_cpu_time_ms_per_ib: f64,
-    pub vote_validation_cpu_time_ms: f64,
+    pub vote_generation_cpu_time_ms_constant: RawCpuTime,
+    pub vote_generation_cpu_time_ms_per_ib: RawCpuTime,
+    pub vote_validation_cpu_time_ms: RawCpuTime,
     pub vote_threshold: u64,
     pub vote_bundle_size_bytes_constant: u64,
     pub vote_bundle_size_bytes_per_eb: u64,
+    pub vote_committee_selection: VoteCommitteeSelection,
+    pub vote_committee_size: u64,
+    pub vote_bundle_size_bytes_per_eb_persistent: u64,
+    pub vote_bundle_size_bytes_per_eb_nonpersistent: u64,
 
     // Certificate configuration
-    pub cert_generation_cpu_time_ms_constant: f64,
-    pub cert_generation_cpu_time_ms_per_node: f64,
-    pub cert_validation_cpu_time_ms_constant: f64,
-    pub cert_validation_cpu_time_ms_per_node: f64,
+    pub cert_generation_cpu_time_ms_constant: RawCpuTime,
+    pub cert_generation_cpu_time_ms_per_node: RawCpuTime,
+    pub cert_validation_cpu_time_ms_constant: RawCpuTime,
+    pub cert_validation_cpu_time_ms_per_node: RawCpuTime,
     pub cert_size_bytes_constant: u64,
     pub cert_size_bytes_per_node: u64,
+    pub cert_size_bytes_fait_accompli_constant: u64,
+    pub cert_size_bytes_per_nonpersistent_voter: u64,
 }
 
 #[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
@@ -159,6 +362,24 @@ pub enum RelayStrategy {
     RequestFromFirst,
 }
 
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LotteryModel {
+    /// Each lottery is a single draw, won with probability proportional to stake.
+    Approximate,
+    /// Exact stake-based sortition, following the Praos leader check and the Leios voter check.
+    Sortition,
+}
+
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum VoteCommitteeSelection {
+    /// Every pool runs the vote lottery.
+    Lottery,
+    /// The largest pools are persistent voters, and the rest run local sortition.
+    FaitAccompli,
+}
+
 #[derive(Debug, Copy, Clone, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum MempoolSamplingStrategy {
@@ -184,9 +405,32 @@ pub struct RawNode {
     pub tx_conflict_fraction: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tx_generation_weight: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_generation_group: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adversarial_behaviours: Vec<AdversarialBehaviour>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_bandwidth_bytes_per_second: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_bandwidth_bytes_per_second: Option<u64>,
     pub producers: BTreeMap<String, RawLinkInfo>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "behaviour", rename_all = "kebab-case")]
+pub enum AdversarialBehaviour {
+    /// Announce IB headers, but never serve the bodies of our own IBs.
+    WithholdIbBodies,
+    /// Produce two different IBs with the same header, and send each to a different half of our peers.
+    EquivocateIbs,
+    /// Produce two different EBs with the same ID, and send each to a different half of our peers.
+    EquivocateEbs,
+    /// Never relay votes produced by other nodes.
+    RefuseVoteRelay,
+    /// Vote for EBs even if they are invalid.
+    VoteForInvalidEbs,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case", untagged)]
 pub enum RawNodeLocation {
@@ -200,6 +444,221 @@ pub struct RawLinkInfo {
     pub latency_ms: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bandwidth_bytes_per_second: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loss_rate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_jitter_ms: Option<DistributionConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RawScenario {
+    pub events: Vec<RawScenarioEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RawScenarioEvent {
+    pub start_time: Option<f64>,
+    pub stop_time: Option<f64>,
+    #[serde(flatten)]
+    pub change: RawScenarioChange,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "kebab-case",
+    rename_all_fields = "kebab-case"
+)]
+pub enum RawScenarioChange {
+    NodeOutage {
+        nodes: Vec<String>,
+    },
+    Partition {
+        groups: Vec<Vec<String>>,
+    },
+    LinkBandwidth {
+        link: (String, String),
+        bandwidth_bytes_per_second: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub(crate) events: Vec<ScenarioEvent>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ScenarioEvent {
+    pub start: Timestamp,
+    pub stop: Option<Timestamp>,
+    pub change: ScenarioChange,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ScenarioChange {
+    NodeOutage(Vec<NodeId>),
+    Partition(Vec<Vec<NodeId>>),
+    LinkBandwidth {
+        nodes: (NodeId, NodeId),
+        bandwidth_bps: Option<u64>,
+    },
+}
+
+impl Scenario {
+    pub fn build(
+        raw: RawScenario,
+        nodes: &[NodeConfiguration],
+        links: &[LinkConfiguration],
+    ) -> Result<Self> {
+        let node_ids: BTreeMap<&str, NodeId> =
+            nodes.iter().map(|n| (n.name.as_str(), n.id)).collect();
+        let find_node = |name: &String| {
+            node_ids
+                .get(name.as_str())
+                .copied()
+                .ok_or_else(|| anyhow!("Scenario refers to unknown node \"{name}\""))
+        };
+        let mut events = vec![];
+        for event in raw.events {
+            let mut times = event.start_time.iter().chain(&event.stop_time);
+            if times.any(|t| !(t.is_finite() && *t >= 0.0)) {
+                bail!("Scenario event {event:?} has an invalid time");
+            }
+            let start = Timestamp::zero() + Duration::from_secs_f64(event.start_time.unwrap_or(0.));
+            let stop = event
+                .stop_time
+                .map(|t| Timestamp::zero() + Duration::from_secs_f64(t));
+            if stop.is_some_and(|stop| stop <= start) {
+                bail!("Scenario event {event:?} stops before it starts");
+            }
+            let change = match &event.change {
+                RawScenarioChange::NodeOutage { nodes } => {
+                    ScenarioChange::NodeOutage(nodes.iter().map(find_node).collect::<Result<_>>()?)
+                }
+                RawScenarioChange::Partition { groups } => ScenarioChange::Partition(
+                    groups
+                        .iter()
+                        .map(|group| group.iter().map(find_node).collect())
+                        .collect::<Result<_>>()?,
+                ),
+                RawScenarioChange::LinkBandwidth {
+                    link: (from, to),
+                    bandwidth_bytes_per_second,
+                } => {
+                    let mut ids = [find_node(from)?, find_node(to)?];
+                    ids.sort();
+                    if !links.iter().any(|l| l.nodes == (ids[0], ids[1])) {
+                        bail!("Scenario refers to unknown link between \"{from}\" and \"{to}\"");
+                    }
+                    ScenarioChange::LinkBandwidth {
+                        nodes: (ids[0], ids[1]),
+                        bandwidth_bps: *bandwidth_bytes_per_second,
+                    }
+                }
+            };
+            events.push(ScenarioEvent {
+                start,
+                stop,
+                change,
+            });
+        }
+        Ok(Self { events })
+    }
+
+    pub(crate) fn is_node_offline(&self, node: NodeId, now: Timestamp) -> bool {
+        self.events.iter().any(|event| {
+            let ScenarioChange::NodeOutage(nodes) = &event.change else {
+                return false;
+            };
+            event.start <= now && event.stop.is_none_or(|stop| now < stop) && nodes.contains(&node)
+        })
+    }
+}
+
+/// One transaction from a recorded workload.
+/// Transactions which spend the same `input_id` conflict with each other.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RawReplayedTransaction {
+    pub time_s: f64,
+    pub node: String,
+    pub size_bytes: u64,
+    pub input_id: Option<u64>,
+    pub overcollateralization_factor: Option<u64>,
+    pub shard: Option<u64>,
+    pub execution_steps: Option<u64>,
+    pub execution_memory: Option<u64>,
+}
+
+/// A recorded workload, which is replayed instead of generating random transactions.
+#[derive(Debug, Clone)]
+pub struct TxReplay {
+    pub(crate) txs: Arc<Vec<ReplayedTransaction>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ReplayedTransaction {
+    pub time: Timestamp,
+    pub node: NodeId,
+    pub bytes: u64,
+    pub shard: Option<u64>,
+    pub input_id: u64,
+    pub overcollateralization_factor: u64,
+    pub execution_units: ExecutionUnits,
+}
+
+impl TxReplay {
+    pub fn build(raw: Vec<RawReplayedTransaction>, config: &SimConfiguration) -> Result<Self> {
+        let node_ids: BTreeMap<&str, NodeId> = config
+            .nodes
+            .iter()
+            .map(|n| (n.name.as_str(), n.id))
+            .collect();
+        // Transactions without an input get one of their own, which nothing else spends
+        let mut next_input_id = raw
+            .iter()
+            .filter_map(|tx| tx.input_id)
+            .max()
+            .map_or(0, |id| id + 1);
+        let mut txs = vec![];
+        for tx in raw {
+            if !(tx.time_s.is_finite() && tx.time_s >= 0.0) {
+                bail!("Replayed transaction {tx:?} has an invalid time");
+            }
+            let Some(node) = node_ids.get(tx.node.as_str()) else {
+                bail!(
+                    "Replayed transaction refers to unknown node \"{}\"",
+                    tx.node
+                );
+            };
+            if tx.shard.is_some_and(|shard| shard >= config.ib_shards) {
+                bail!(
+                    "Replayed transaction {tx:?} is in a shard which doesn't exist (ib-shards is {})",
+                    config.ib_shards
+                );
+            }
+            let input_id = tx.input_id.unwrap_or_else(|| {
+                next_input_id += 1;
+                next_input_id - 1
+            });
+            txs.push(ReplayedTransaction {
+                time: Timestamp::from_secs_f64(tx.time_s),
+                node: *node,
+                bytes: tx.size_bytes,
+                shard: tx.shard,
+                input_id,
+                overcollateralization_factor: tx.overcollateralization_factor.unwrap_or_default(),
+                execution_units: ExecutionUnits {
+                    steps: tx.execution_steps.unwrap_or_default(),
+                    memory: tx.execution_memory.unwrap_or_default(),
+                },
+            });
+        }
+        txs.sort_by_key(|tx| tx.time);
+        Ok(Self { txs: Arc::new(txs) })
+    }
 }
 
 pub struct Topology {
@@ -242,12 +701,24 @@ impl Topology {
         if connected_nodes.len() < self.nodes.len() {
             bail!("Graph must be fully connected!");
         }
+        for link in &self.links {
+            if !(0.0..1.0).contains(&link.loss_rate) {
+                bail!(
+                    "Link between nodes {} and {} has a loss rate of {}, which must be at least 0 and less than 1",
+                    link.nodes.0,
+                    link.nodes.1,
+                    link.loss_rate
+                );
+            }
+        }
         Ok(())
     }
 }
 
-impl From<RawTopology> for Topology {
-    fn from(value: RawTopology) -> Self {
+impl TryFrom<RawTopology> for Topology {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RawTopology) -> Result<Self> {
         let mut node_ids = BTreeMap::new();
         let mut nodes = BTreeMap::new();
         for (index, (name, node)) in value.nodes.iter().enumerate() {
@@ -263,6 +734,10 @@ impl From<RawTopology> for Topology {
                     cores: node.cpu_core_count,
                     tx_conflict_fraction: node.tx_conflict_fraction,
                     tx_generation_weight: node.tx_generation_weight,
+                    tx_generation_group: node.tx_generation_group.clone(),
+                    adversarial_behaviours: node.adversarial_behaviours.clone(),
+                    upload_bandwidth_bps: node.upload_bandwidth_bytes_per_second,
+                    download_bandwidth_bps: node.download_bandwidth_bytes_per_second,
                     consumers: vec![],
                 },
             );
@@ -286,68 +761,166 @@ impl From<RawTopology> for Topology {
                         nodes: (ids[0], ids[1]),
                         latency: duration_ms(producer_info.latency_ms),
                         bandwidth_bps: producer_info.bandwidth_bytes_per_second,
+                        loss_rate: producer_info.loss_rate.unwrap_or_default(),
+                        latency_jitter: producer_info
+                            .latency_jitter_ms
+                            .map(|d| d.try_into())
+                            .transpose()
+                            .with_context(|| {
+                                format!("invalid latency-jitter-ms from {producer_name} to {consumer_name}")
+                            })?,
                     },
                 );
             }
         }
         let links = links.into_values().collect();
-        Self {
+        Ok(Self {
             nodes: nodes.into_values().collect(),
             links,
-        }
+        })
     }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct CpuTimeConfig {
-    pub tx_validation: Duration,
-    pub rb_generation: Duration,
-    pub rb_validation_constant: Duration,
-    pub rb_validation_per_byte: Duration,
-    pub ib_generation: Duration,
-    pub ib_head_validation: Duration,
-    pub ib_body_validation_constant: Duration,
-    pub ib_body_validation_per_byte: Duration,
-    pub eb_generation: Duration,
-    pub eb_validation: Duration,
-    pub vote_generation_constant: Duration,
-    pub vote_generation_per_ib: Duration,
-    pub vote_validation: Duration,
-    pub cert_generation_constant: Duration,
-    pub cert_generation_per_node: Duration,
-    pub cert_validation_constant: Duration,
-    pub cert_validation_per_node: Duration,
+    pub tx_validation: CpuTime,
+    pub tx_validation_per_million_steps: CpuTime,
+    pub rb_generation: CpuTime,
+    pub rb_validation_constant: CpuTime,
+    pub rb_validation_per_byte: CpuTime,
+    pub ib_generation: CpuTime,
+    pub ib_head_validation: CpuTime,
+    pub ib_body_validation_constant: CpuTime,
+    pub ib_body_validation_per_byte: CpuTime,
+    pub eb_generation: CpuTime,
+    pub eb_validation: CpuTime,
+    pub vote_generation_constant: CpuTime,
+    pub vote_generation_per_ib: CpuTime,
+    pub vote_validation: CpuTime,
+    pub cert_generation_constant: CpuTime,
+    pub cert_generation_per_node: CpuTime,
+    pub cert_validation_constant: CpuTime,
+    pub cert_validation_per_node: CpuTime,
 }
 impl CpuTimeConfig {
-    fn new(params: &RawParameters) -> Self {
-        Self {
-            tx_validation: duration_ms(params.tx_validation_cpu_time_ms),
-            rb_generation: duration_ms(params.rb_generation_cpu_time_ms),
-            rb_validation_constant: duration_ms(
-                params.rb_head_validation_cpu_time_ms
-                    + params.rb_body_legacy_praos_payload_validation_cpu_time_ms_constant,
-            ),
-            rb_validation_per_byte: duration_ms(
-                params.rb_body_legacy_praos_payload_validation_cpu_time_ms_per_byte,
-            ),
-            ib_generation: duration_ms(params.ib_generation_cpu_time_ms),
-            ib_head_validation: duration_ms(params.ib_head_validation_cpu_time_ms),
-            ib_body_validation_constant: duration_ms(
-                params.ib_body_validation_cpu_time_ms_constant,
-            ),
-            ib_body_validation_per_byte: duration_ms(
-                params.ib_body_validation_cpu_time_ms_per_byte,
-            ),
-            eb_generation: duration_ms(params.eb_generation_cpu_time_ms),
-            eb_validation: duration_ms(params.eb_validation_cpu_time_ms),
-            vote_generation_constant: duration_ms(params.vote_generation_cpu_time_ms_constant),
-            vote_generation_per_ib: duration_ms(params.vote_generation_cpu_time_ms_per_ib),
-            vote_validation: duration_ms(params.vote_validation_cpu_time_ms),
-            cert_generation_constant: duration_ms(params.cert_generation_cpu_time_ms_constant),
-            cert_generation_per_node: duration_ms(params.cert_generation_cpu_time_ms_per_node),
-            cert_validation_constant: duration_ms(params.cert_validation_cpu_time_ms_constant),
-            cert_validation_per_node: duration_ms(params.cert_validation_cpu_time_ms_per_node),
-        }
+    fn new(params: &RawParameters) -> Result<Self> {
+        Ok(Self {
+            tx_validation: CpuTime::new(
+                &params.tx_validation_cpu_time_ms,
+                "tx-validation-cpu-time-ms",
+            )?,
+            tx_validation_per_million_steps: params
+                .tx_validation_cpu_time_ms_per_million_steps
+                .as_ref()
+                .map(|raw| CpuTime::new(raw, "tx-validation-cpu-time-ms-per-million-steps"))
+                .transpose()?
+                .unwrap_or_default(),
+            rb_generation: CpuTime::new(
+                &params.rb_generation_cpu_time_ms,
+                "rb-generation-cpu-time-ms",
+            )?,
+            rb_validation_constant: CpuTime::new(
+                &params.rb_head_validation_cpu_time_ms,
+                "rb-head-validation-cpu-time-ms",
+            )?
+            .plus(CpuTime::new(
+                &params.rb_body_legacy_praos_payload_validation_cpu_time_ms_constant,
+                "rb-body-legacy-praos-payload-validation-cpu-time-ms-constant",
+            )?),
+            rb_validation_per_byte: CpuTime::new(
+                &params.rb_body_legacy_praos_payload_validation_cpu_time_ms_per_byte,
+                "rb-body-legacy-praos-payload-validation-cpu-time-ms-per-byte",
+            )?,
+            ib_generation: CpuTime::new(
+                &params.ib_generation_cpu_time_ms,
+                "ib-generation-cpu-time-ms",
+            )?,
+            ib_head_validation: CpuTime::new(
+                &params.ib_head_validation_cpu_time_ms,
+                "ib-head-validation-cpu-time-ms",
+            )?,
+            ib_body_validation_constant: CpuTime::new(
+                &params.ib_body_validation_cpu_time_ms_constant,
+                "ib-body-validation-cpu-time-ms-constant",
+            )?,
+            ib_body_validation_per_byte: CpuTime::new(
+                &params.ib_body_validation_cpu_time_ms_per_byte,
+                "ib-body-validation-cpu-time-ms-per-byte",
+            )?,
+            eb_generation: CpuTime::new(
+                &params.eb_generation_cpu_time_ms,
+                "eb-generation-cpu-time-ms",
+            )?,
+            eb_validation: CpuTime::new(
+                &params.eb_validation_cpu_time_ms,
+                "eb-validation-cpu-time-ms",
+            )?,
+            vote_generation_constant: CpuTime::new(
+                &params.vote_generation_cpu_time_ms_constant,
+                "vote-generation-cpu-time-ms-constant",
+            )?,
+            vote_generation_per_ib: CpuTime::new(
+                &params.vote_generation_cpu_time_ms_per_ib,
+                "vote-generation-cpu-time-ms-per-ib",
+            )?,
+            vote_validation: CpuTime::new(
+                &params.vote_validation_cpu_time_ms,
+                "vote-validation-cpu-time-ms",
+            )?,
+            cert_generation_constant: CpuTime::new(
+                &params.cert_generation_cpu_time_ms_constant,
+                "cert-generation-cpu-time-ms-constant",
+            )?,
+            cert_generation_per_node: CpuTime::new(
+                &params.cert_generation_cpu_time_ms_per_node,
+                "cert-generation-cpu-time-ms-per-node",
+            )?,
+            cert_validation_constant: CpuTime::new(
+                &params.cert_validation_cpu_time_ms_constant,
+                "cert-validation-cpu-time-ms-constant",
+            )?,
+            cert_validation_per_node: CpuTime::new(
+                &params.cert_validation_cpu_time_ms_per_node,
+                "cert-validation-cpu-time-ms-per-node",
+            )?,
+        })
+    }
+}
+
+/// How long some work takes: a fixed number of milliseconds, plus any number of random ones.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CpuTime {
+    fixed_ms: f64,
+    sampled_ms: Vec<FloatDistribution>,
+}
+impl CpuTime {
+    fn new(raw: &RawCpuTime, name: &str) -> Result<Self> {
+        Ok(match raw {
+            RawCpuTime::Fixed(ms) => Self {
+                fixed_ms: *ms,
+                sampled_ms: vec![],
+            },
+            RawCpuTime::Distribution(distribution) => Self {
+                fixed_ms: 0.0,
+                sampled_ms: vec![distribution
+                    .clone()
+                    .try_into()
+                    .with_context(|| format!("invalid {name}"))?],
+            },
+        })
+    }
+
+    fn plus(mut self, other: Self) -> Self {
+        self.fixed_ms += other.fixed_ms;
+        self.sampled_ms.extend(other.sampled_ms);
+        self
+    }
+
+    /// Fixed costs don't use any randomness, so they don't change what else gets sampled from `rng`.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> Duration {
+        let sampled_ms: f64 = self.sampled_ms.iter().map(|d| d.sample(rng)).sum();
+        // A distribution like the normal can go below zero, but no task is faster than instant
+        duration_ms(self.fixed_ms + sampled_ms)
     }
 }
 
@@ -362,6 +935,10 @@ pub(crate) struct BlockSizeConfig {
     eb_per_ib: u64,
     vote_constant: u64,
     vote_per_eb: u64,
+    vote_per_eb_persistent: u64,
+    vote_per_eb_nonpersistent: u64,
+    cert_fait_accompli_constant: u64,
+    cert_per_nonpersistent_voter: u64,
 }
 
 impl BlockSizeConfig {
@@ -376,6 +953,10 @@ impl BlockSizeConfig {
             eb_per_ib: params.eb_size_bytes_per_ib,
             vote_constant: params.vote_bundle_size_bytes_constant,
             vote_per_eb: params.vote_bundle_size_bytes_per_eb,
+            vote_per_eb_persistent: params.vote_bundle_size_bytes_per_eb_persistent,
+            vote_per_eb_nonpersistent: params.vote_bundle_size_bytes_per_eb_nonpersistent,
+            cert_fait_accompli_constant: params.cert_size_bytes_fait_accompli_constant,
+            cert_per_nonpersistent_voter: params.cert_size_bytes_per_nonpersistent_voter,
         }
     }
 
@@ -397,24 +978,62 @@ impl BlockSizeConfig {
     pub fn vote_bundle(&self, ebs: usize) -> u64 {
         self.vote_constant + self.vote_per_eb * ebs as u64
     }
+
+    pub fn fait_accompli_cert(&self, persistent_seats: usize, nonpersistent_voters: usize) -> u64 {
+        // Persistent voters are recorded in a bitset, non-persistent voters need an ID and an eligibility proof.
+        self.cert_fait_accompli_constant
+            + persistent_seats.div_ceil(8) as u64
+            + self.cert_per_nonpersistent_voter * nonpersistent_voters as u64
+    }
+
+    pub fn fait_accompli_vote_bundle(&self, ebs: usize, persistent: bool) -> u64 {
+        let per_eb = if persistent {
+            self.vote_per_eb_persistent
+        } else {
+            self.vote_per_eb_nonpersistent
+        };
+        self.vote_constant + per_eb * ebs as u64
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) enum TransactionConfig {
-    Real(RealTransactionConfig),
+    Real(Box<RealTransactionConfig>),
     Mock(MockTransactionConfig),
 }
 
 impl TransactionConfig {
-    fn new(params: &RawParameters) -> Self {
-        if params.simulate_transactions {
-            Self::Real(RealTransactionConfig {
+    fn new(params: &RawParameters, nodes: &[NodeConfiguration]) -> Result<Self> {
+        Ok(if params.simulate_transactions {
+            Self::Real(Box::new(RealTransactionConfig {
                 max_size: params.tx_max_size_bytes,
-                frequency_ms: params.tx_generation_distribution.into(),
-                size_bytes: params.tx_size_bytes_distribution.into(),
+                frequency_ms: params
+                    .tx_generation_distribution
+                    .clone()
+                    .try_into()
+                    .context("invalid tx-generation-distribution")?,
+                size_bytes: params
+                    .tx_size_bytes_distribution
+                    .clone()
+                    .try_into()
+                    .context("invalid tx-size-bytes-distribution")?,
                 overcollateralization_factor: params
                     .tx_overcollateralization_factor_distribution
-                    .into(),
+                    .clone()
+                    .try_into()
+                    .context("invalid tx-overcollateralization-factor-distribution")?,
+                execution_steps: params
+                    .tx_execution_steps_distribution
+                    .clone()
+                    .map(|d| d.try_into())
+                    .transpose()
+                    .context("invalid tx-execution-steps-distribution")?,
+                execution_memory: params
+                    .tx_execution_memory_distribution
+                    .clone()
+                    .map(|d| d.try_into())
+                    .transpose()
+                    .context("invalid tx-execution-memory-distribution")?,
                 conflict_fraction: params.tx_conflict_fraction.unwrap_or_default(),
                 start_time: params
                     .tx_start_time
@@ -422,14 +1041,19 @@ impl TransactionConfig {
                 stop_time: params
                     .tx_stop_time
                     .map(|t| Timestamp::zero() + Duration::from_secs_f64(t)),
-            })
+                load_profile: TxLoadProfile::build(
+                    params.tx_load_profile.as_deref().unwrap_or_default(),
+                    nodes,
+                )?,
+                replay: None,
+            }))
         } else {
             Self::Mock(MockTransactionConfig {
                 next_id: Arc::new(AtomicU64::new(0)),
                 ib_size: params.ib_body_avg_size_bytes,
                 rb_size: params.rb_body_legacy_praos_payload_avg_size_bytes,
             })
-        }
+        })
     }
 }
 
@@ -439,9 +1063,167 @@ pub(crate) struct RealTransactionConfig {
     pub frequency_ms: FloatDistribution,
     pub size_bytes: FloatDistribution,
     pub overcollateralization_factor: FloatDistribution,
+    pub execution_steps: Option<FloatDistribution>,
+    pub execution_memory: Option<FloatDistribution>,
     pub conflict_fraction: f64,
     pub start_time: Option<Timestamp>,
     pub stop_time: Option<Timestamp>,
+    pub load_profile: TxLoadProfile,
+    pub replay: Option<TxReplay>,
+}
+
+/// How the rate of transaction generation, and which nodes generate them, change over time.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TxLoadProfile {
+    segments: Vec<TxLoadSegment>,
+}
+
+#[derive(Debug, Clone)]
+struct TxLoadSegment {
+    start: Timestamp,
+    stop: Option<Timestamp>,
+    rate: Option<TxRateShape>,
+    group_weights: BTreeMap<String, f64>,
+}
+
+impl TxLoadSegment {
+    fn is_active(&self, now: Timestamp) -> bool {
+        self.start <= now && self.stop.is_none_or(|stop| now < stop)
+    }
+
+    fn rate_multiplier(&self, now: Timestamp) -> f64 {
+        let elapsed = (now - self.start).as_secs_f64();
+        match self.rate {
+            None => 1.0,
+            Some(TxRateShape::Constant { multiplier }) => multiplier,
+            Some(TxRateShape::Linear { from, to }) => {
+                // Linear segments always have a stop time
+                let length = (self.stop.unwrap() - self.start).as_secs_f64();
+                from + (to - from) * elapsed / length
+            }
+            Some(TxRateShape::Sinusoid { period, amplitude }) => {
+                1.0 + amplitude * (std::f64::consts::TAU * elapsed / period).sin()
+            }
+        }
+    }
+
+    /// The highest rate multiplier at any point in this segment.
+    fn max_rate_multiplier(&self) -> f64 {
+        match self.rate {
+            None => 1.0,
+            Some(TxRateShape::Constant { multiplier }) => multiplier,
+            Some(TxRateShape::Linear { from, to }) => from.max(to),
+            Some(TxRateShape::Sinusoid { amplitude, .. }) => 1.0 + amplitude,
+        }
+    }
+}
+
+impl TxLoadProfile {
+    fn build(raw: &[RawTxLoadSegment], nodes: &[NodeConfiguration]) -> Result<Self> {
+        let groups: HashSet<&str> = nodes
+            .iter()
+            .filter_map(|n| n.tx_generation_group.as_deref())
+            .collect();
+        let mut segments = vec![];
+        for segment in raw {
+            let mut times = segment.start_time.iter().chain(&segment.stop_time);
+            if times.any(|t| !(t.is_finite() && *t >= 0.0)) {
+                bail!("tx-load-profile segment {segment:?} has an invalid time");
+            }
+            let start =
+                Timestamp::zero() + Duration::from_secs_f64(segment.start_time.unwrap_or(0.));
+            let stop = segment
+                .stop_time
+                .map(|t| Timestamp::zero() + Duration::from_secs_f64(t));
+            if stop.is_some_and(|stop| stop <= start) {
+                bail!("tx-load-profile segment {segment:?} stops before it starts");
+            }
+            // An infinite rate would generate transactions forever without time moving on.
+            let valid_multiplier = |multiplier: f64| multiplier.is_finite() && multiplier >= 0.0;
+            match segment.rate {
+                Some(TxRateShape::Constant { multiplier }) if !valid_multiplier(multiplier) => {
+                    bail!("tx-load-profile segment {segment:?} must have a finite, non-negative multiplier");
+                }
+                Some(TxRateShape::Linear { from, to }) => {
+                    if stop.is_none() {
+                        bail!("tx-load-profile segment {segment:?} is linear, so it needs a stop-time");
+                    }
+                    if !(valid_multiplier(from) && valid_multiplier(to)) {
+                        bail!("tx-load-profile segment {segment:?} must have finite, non-negative multipliers");
+                    }
+                }
+                Some(TxRateShape::Sinusoid { period, amplitude }) => {
+                    if !(period.is_finite() && period > 0.0) {
+                        bail!("tx-load-profile segment {segment:?} must have a positive period");
+                    }
+                    if !(0.0..=1.0).contains(&amplitude) {
+                        bail!("tx-load-profile segment {segment:?} must have an amplitude between 0 and 1");
+                    }
+                }
+                _ => {}
+            }
+            for (group, weight) in &segment.group_weights {
+                if !groups.contains(group.as_str()) {
+                    bail!("tx-load-profile refers to node group \"{group}\", but no node has that tx-generation-group");
+                }
+                if !(weight.is_finite() && *weight >= 0.0) {
+                    bail!("tx-load-profile gives node group \"{group}\" an invalid weight");
+                }
+            }
+            segments.push(TxLoadSegment {
+                start,
+                stop,
+                rate: segment.rate,
+                group_weights: segment.group_weights.clone(),
+            });
+        }
+        Ok(Self { segments })
+    }
+
+    /// Whether any segment changes the weights of node groups.
+    pub fn has_group_weights(&self) -> bool {
+        self.segments.iter().any(|s| !s.group_weights.is_empty())
+    }
+
+    /// How many times faster than normal transactions are generated at this time.
+    /// Overlapping segments multiply together, so a burst can sit on top of a daily cycle.
+    pub fn rate_multiplier(&self, now: Timestamp) -> f64 {
+        self.segments
+            .iter()
+            .filter(|s| s.is_active(now))
+            .map(|s| s.rate_multiplier(now))
+            .product()
+    }
+
+    /// The highest rate multiplier between `now` and the next boundary.
+    pub fn max_rate_multiplier(&self, now: Timestamp) -> f64 {
+        self.segments
+            .iter()
+            .filter(|s| s.is_active(now))
+            .map(|s| s.max_rate_multiplier())
+            .product()
+    }
+
+    /// How much each node group's transaction generation weight is multiplied by at this time.
+    pub fn group_weights(&self, now: Timestamp) -> BTreeMap<&str, f64> {
+        let mut weights = BTreeMap::new();
+        for segment in self.segments.iter().filter(|s| s.is_active(now)) {
+            for (group, weight) in &segment.group_weights {
+                *weights.entry(group.as_str()).or_insert(1.0) *= weight;
+            }
+        }
+        weights
+    }
+
+    /// The first time after `now` when a segment starts or stops.
+    pub fn next_boundary(&self, now: Timestamp) -> Option<Timestamp> {
+        self.segments
+            .iter()
+            .flat_map(|s| [Some(s.start), s.stop])
+            .flatten()
+            .filter(|t| *t > now)
+            .min()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -462,6 +1244,36 @@ impl MockTransactionConfig {
             bytes,
             input_id: id,
             overcollateralization_factor: 0,
+            execution_units: ExecutionUnits::default(),
+        }
+    }
+}
+
+/// How the simulation is driven through time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Every node and the network run as their own task, and a clock coordinator moves time
+    /// forward once all of them are waiting.
+    #[default]
+    Actors,
+    /// A single loop pops events off one queue and hands them to each node in turn.
+    EventLoop,
+    /// Nodes are split between several threads, which run in lockstep. Each step is as long as
+    /// the shortest link latency, so nothing sent during a step can arrive before it ends.
+    Parallel,
+}
+
+impl FromStr for Engine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "actors" => Ok(Self::Actors),
+            "event-loop" => Ok(Self::EventLoop),
+            "parallel" => Ok(Self::Parallel),
+            other => bail!(
+                "unknown engine \"{other}\" (expected \"actors\", \"event-loop\" or \"parallel\")"
+            ),
         }
     }
 }
@@ -469,13 +1281,21 @@ impl MockTransactionConfig {
 #[derive(Debug, Clone)]
 pub struct SimConfiguration {
     pub seed: u64,
+    /// Identifies the inputs (parameters, topology and so on) that this configuration came from.
+    /// Checkpoints are only restored into simulations with the same fingerprint.
+    pub fingerprint: u64,
     pub timestamp_resolution: Duration,
     pub slots: Option<u64>,
+    pub timescale: Option<f64>,
+    pub engine: Engine,
+    /// How many threads the parallel engine splits nodes between. Defaults to one per core.
+    pub threads: Option<usize>,
     pub emit_conformance_events: bool,
     pub aggregate_events: bool,
     pub trace_nodes: HashSet<NodeId>,
     pub nodes: Vec<NodeConfiguration>,
     pub links: Vec<LinkConfiguration>,
+    pub scenario: Scenario,
     pub stage_length: u64,
     pub max_eb_age: u64,
     pub late_ib_inclusion: bool,
@@ -484,17 +1304,23 @@ pub struct SimConfiguration {
     pub(crate) praos_fallback: bool,
     pub(crate) header_diffusion_time: Duration,
     pub(crate) relay_strategy: RelayStrategy,
+    pub(crate) tcp_congestion_control: bool,
     pub(crate) mempool_strategy: MempoolSamplingStrategy,
     pub(crate) mempool_aggressive_pruning: bool,
     pub(crate) praos_chain_quality: u64,
+    pub(crate) lottery_model: LotteryModel,
     pub(crate) block_generation_probability: f64,
     pub(crate) ib_generation_probability: f64,
     pub(crate) eb_generation_probability: f64,
     pub(crate) vote_probability: f64,
+    pub(crate) fait_accompli: Option<FaitAccompliCommittee>,
     pub(crate) vote_slot_length: u64,
     pub(crate) max_block_size: u64,
     pub(crate) max_ib_size: u64,
     pub(crate) max_eb_size: u64,
+    pub(crate) max_block_execution_units: ExecutionUnits,
+    pub(crate) max_ib_execution_units: ExecutionUnits,
+    pub(crate) max_eb_execution_units: ExecutionUnits,
     pub(crate) ib_diffusion_strategy: DiffusionStrategy,
     pub(crate) max_ib_requests_per_peer: usize,
     pub(crate) ib_shards: u64,
@@ -514,15 +1340,28 @@ impl SimConfiguration {
                 params.ib_shard_group_count
             );
         }
+        let fait_accompli = match params.vote_committee_selection {
+            VoteCommitteeSelection::Lottery => None,
+            VoteCommitteeSelection::FaitAccompli => Some(FaitAccompliCommittee::new(
+                &topology.nodes,
+                params.vote_committee_size,
+            )),
+        };
+        let transactions = TransactionConfig::new(&params, &topology.nodes)?;
         Ok(Self {
             seed: 0,
+            fingerprint: 0,
             timestamp_resolution: duration_ms(params.timestamp_resolution_ms),
             slots: None,
+            timescale: None,
+            engine: Engine::default(),
+            threads: None,
             emit_conformance_events: false,
             aggregate_events: false,
             trace_nodes: HashSet::new(),
             nodes: topology.nodes,
             links: topology.links,
+            scenario: Scenario::default(),
             stage_length: params.leios_stage_length_slots,
             max_eb_age: params.eb_max_age_slots,
             late_ib_inclusion: params.leios_late_ib_inclusion,
@@ -530,32 +1369,122 @@ impl SimConfiguration {
             praos_fallback: params.praos_fallback_enabled,
             header_diffusion_time: duration_ms(params.leios_header_diffusion_time_ms),
             relay_strategy: params.relay_strategy,
+            tcp_congestion_control: params.rust_tcp_congestion_control,
             mempool_strategy: params.leios_mempool_sampling_strategy,
             mempool_aggressive_pruning: params.leios_mempool_aggressive_pruning,
             praos_chain_quality: params.praos_chain_quality,
+            lottery_model: params.lottery_model,
             block_generation_probability: params.rb_generation_probability,
             ib_generation_probability: params.ib_generation_probability,
             eb_generation_probability: params.eb_generation_probability,
             vote_probability: params.vote_generation_probability,
+            fait_accompli,
             vote_threshold: params.vote_threshold,
             vote_slot_length: params.leios_stage_active_voting_slots,
             max_block_size: params.rb_body_max_size_bytes,
             max_ib_size: params.ib_body_max_size_bytes,
             max_eb_size: params.eb_referenced_txs_max_size_bytes,
+            max_block_execution_units: execution_limit(
+                params.rb_body_max_execution_steps,
+                params.rb_body_max_execution_memory,
+            ),
+            max_ib_execution_units: execution_limit(
+                params.ib_body_max_execution_steps,
+                params.ib_body_max_execution_memory,
+            ),
+            max_eb_execution_units: execution_limit(
+                params.eb_referenced_txs_max_execution_steps,
+                params.eb_referenced_txs_max_execution_memory,
+            ),
             ib_diffusion_strategy: params.ib_diffusion_strategy,
             max_ib_requests_per_peer: params.ib_diffusion_max_bodies_to_request as usize,
             ib_shards: params.ib_shards,
             ib_shard_period_slots: params.ib_shard_period_length_slots,
             ib_shard_groups: params.ib_shard_group_count,
-            cpu_times: CpuTimeConfig::new(&params),
+            cpu_times: CpuTimeConfig::new(&params)?,
             sizes: BlockSizeConfig::new(&params),
-            transactions: TransactionConfig::new(&params),
+            transactions,
         })
     }
+
+    /// Replays a recorded workload, instead of generating random transactions.
+    pub fn replay_transactions(&mut self, replay: TxReplay) -> Result<()> {
+        let TransactionConfig::Real(config) = &mut self.transactions else {
+            bail!("Transactions can only be replayed when simulate-transactions is true");
+        };
+        config.replay = Some(replay);
+        Ok(())
+    }
+}
+
+// A block without a limit can hold as much work as its transactions need.
+fn execution_limit(steps: Option<u64>, memory: Option<u64>) -> ExecutionUnits {
+    ExecutionUnits {
+        steps: steps.unwrap_or(ExecutionUnits::UNLIMITED.steps),
+        memory: memory.unwrap_or(ExecutionUnits::UNLIMITED.memory),
+    }
 }
 
-fn duration_ms(ms: f64) -> Duration {
-    Duration::from_secs_f64(ms / 1000.0)
+/// Longer than any simulation runs, but short enough to add to a timestamp without overflowing.
+pub(crate) const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Heavy-tailed distributions can sample huge or even infinite times,
+/// so clamp them to something we can represent.
+pub(crate) fn duration_ms(ms: f64) -> Duration {
+    Duration::try_from_secs_f64((ms / 1000.0).clamp(0.0, MAX_DURATION.as_secs_f64()))
+        .unwrap_or_default()
+}
+
+/// A voting committee chosen with the Fait Accompli scheme (wFA^F).
+/// The largest pools hold persistent seats, and vote in every election.
+/// The remaining seats are filled by local sortition among the other pools.
+/// Stake is fixed for the whole run, so the same committee sits for every epoch.
+#[derive(Debug, Clone)]
+pub(crate) struct FaitAccompliCommittee {
+    pub persistent: HashSet<NodeId>,
+    pub nonpersistent_stake: u64,
+    pub nonpersistent_seats: u64,
+}
+
+impl FaitAccompliCommittee {
+    fn new(nodes: &[NodeConfiguration], seats: u64) -> Self {
+        let mut pools: Vec<(u64, NodeId)> = nodes
+            .iter()
+            .filter(|node| node.stake > 0)
+            .map(|node| (node.stake, node.id))
+            .collect();
+        pools.sort_by_key(|(stake, id)| (Reverse(*stake), *id));
+
+        // remaining_stake[i] is the total stake of pool i and every smaller pool
+        let mut remaining_stake = vec![0u64; pools.len() + 1];
+        for (i, (stake, _)) in pools.iter().enumerate().rev() {
+            remaining_stake[i] = remaining_stake[i + 1] + stake;
+        }
+
+        // Pool i gets a persistent seat if (1 - s_i/ρ_i)^2 < (n-i-1)/(n-i).
+        // Both sides are ratios, so this works for any amount of stake.
+        let is_persistent = |i: usize| {
+            if i as u64 + 1 >= seats {
+                return false;
+            }
+            let share = pools[i].0 as f64 / remaining_stake[i] as f64;
+            let n = (seats - i as u64) as f64;
+            (1.0 - share).powi(2) < (n - 1.0) / n
+        };
+        let mut persistent_count = 0;
+        while persistent_count < pools.len() && is_persistent(persistent_count) {
+            persistent_count += 1;
+        }
+
+        Self {
+            persistent: pools[..persistent_count]
+                .iter()
+                .map(|(_, id)| *id)
+                .collect(),
+            nonpersistent_stake: remaining_stake[persistent_count],
+            nonpersistent_seats: seats - persistent_count as u64,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -567,6 +1496,10 @@ pub struct NodeConfiguration {
     pub cores: Option<u64>,
     pub tx_conflict_fraction: Option<f64>,
     pub tx_generation_weight: Option<u64>,
+    pub tx_generation_group: Option<String>,
+    pub adversarial_behaviours: Vec<AdversarialBehaviour>,
+    pub upload_bandwidth_bps: Option<u64>,
+    pub download_bandwidth_bps: Option<u64>,
     pub consumers: Vec<NodeId>,
 }
 
@@ -575,4 +1508,364 @@ pub struct LinkConfiguration {
     pub nodes: (NodeId, NodeId),
     pub latency: Duration,
     pub bandwidth_bps: Option<u64>,
+    pub loss_rate: f64,
+    /// Extra latency added to each message, in milliseconds.
+    pub latency_jitter: Option<FloatDistribution>,
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng as _;
+    use rand_chacha::ChaChaRng;
+    use rand_distr::Distribution as _;
+
+    use std::time::Duration;
+
+    use super::{
+        execution_limit, CpuTime, DistributionConfig, EmpiricalDistribution, FaitAccompliCommittee,
+        LinkConfiguration, NodeConfiguration, NodeId, RawCpuTime, RawReplayedTransaction,
+        RawScenario, RawTxLoadSegment, Scenario, ScenarioChange, TxLoadProfile, TxReplay,
+        MAX_DURATION,
+    };
+    use crate::{clock::Timestamp, model::ExecutionUnits, probability::FloatDistribution, testing};
+
+    fn node(group: Option<&str>) -> NodeConfiguration {
+        NodeConfiguration {
+            id: NodeId::new(0),
+            name: "node-0".to_string(),
+            stake: 0,
+            cpu_multiplier: 1.0,
+            cores: None,
+            tx_conflict_fraction: None,
+            tx_generation_weight: None,
+            tx_generation_group: group.map(String::from),
+            adversarial_behaviours: vec![],
+            upload_bandwidth_bps: None,
+            download_bandwidth_bps: None,
+            consumers: vec![],
+        }
+    }
+
+    fn profile(yaml: &str) -> anyhow::Result<TxLoadProfile> {
+        let raw: Vec<RawTxLoadSegment> = serde_yaml::from_str(yaml)?;
+        TxLoadProfile::build(&raw, &[node(Some("europe"))])
+    }
+
+    fn replayed(yaml: &str) -> anyhow::Result<TxReplay> {
+        let raw: Vec<RawReplayedTransaction> = serde_yaml::from_str(yaml)?;
+        let mut config = testing::config(testing::mesh(1, "[]")?, |_| {})?;
+        config.ib_shards = 2;
+        TxReplay::build(raw, &config)
+    }
+
+    #[test]
+    fn should_build_tx_replays() -> anyhow::Result<()> {
+        let replay = replayed(
+            r#"
+            - { time_s: 2.5, node: node-0, size_bytes: 300 }
+            - { time_s: 1.0, node: node-0, size_bytes: 100, input_id: 4, shard: 1 }
+            - { time_s: 2.0, node: node-0, size_bytes: 200, execution_steps: 7 }
+            "#,
+        )?;
+        // Transactions are replayed in time order
+        let times: Vec<_> = replay.txs.iter().map(|tx| tx.time).collect();
+        assert_eq!(
+            times,
+            vec![
+                Timestamp::from_secs_f64(1.0),
+                Timestamp::from_secs_f64(2.0),
+                Timestamp::from_secs_f64(2.5)
+            ]
+        );
+        let bytes: Vec<_> = replay.txs.iter().map(|tx| tx.bytes).collect();
+        assert_eq!(bytes, vec![100, 200, 300]);
+        assert_eq!(replay.txs[0].shard, Some(1));
+        assert_eq!(replay.txs[1].execution_units.steps, 7);
+
+        // Transactions without an input get new ones, which don't clash with any recorded input
+        let inputs: Vec<_> = replay.txs.iter().map(|tx| tx.input_id).collect();
+        assert_eq!(inputs[0], 4);
+        assert!(inputs[1] > 4 && inputs[2] > 4 && inputs[1] != inputs[2]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_invalid_tx_replays() {
+        assert!(replayed("- { time_s: 1.0, node: node-7, size_bytes: 100 }").is_err());
+        assert!(replayed("- { time_s: -1.0, node: node-0, size_bytes: 100 }").is_err());
+        assert!(replayed("- { time_s: .nan, node: node-0, size_bytes: 100 }").is_err());
+        assert!(replayed("- { time_s: 1.0, node: node-0, size_bytes: 100, shard: 2 }").is_err());
+        assert!(replayed("- { time_s: 1.0, node: node-0, size-bytes: 100 }").is_err());
+    }
+
+    #[test]
+    fn should_combine_overlapping_segments() -> anyhow::Result<()> {
+        let profile = profile(
+            r#"
+            - stop-time: 40
+              rate: { shape: sinusoid, period: 40, amplitude: 0.5 }
+            - start-time: 10
+              stop-time: 20
+              rate: { shape: constant, multiplier: 10 }
+              group-weights: { europe: 3 }
+            - start-time: 40
+              stop-time: 50
+              rate: { shape: linear, from: 0, to: 2 }
+            "#,
+        )?;
+        let secs = Timestamp::from_secs;
+        assert!((profile.rate_multiplier(secs(0)) - 1.0).abs() < 1e-9);
+        assert!((profile.rate_multiplier(secs(10)) - 15.0).abs() < 1e-9);
+        assert!((profile.rate_multiplier(secs(20)) - 1.0).abs() < 1e-9);
+        assert!((profile.rate_multiplier(secs(45)) - 1.0).abs() < 1e-9);
+
+        assert_eq!(profile.group_weights(secs(15)).get("europe"), Some(&3.0));
+        assert!(profile.group_weights(secs(25)).is_empty());
+
+        assert_eq!(profile.next_boundary(secs(0)), Some(secs(10)));
+        assert_eq!(profile.next_boundary(secs(10)), Some(secs(20)));
+        assert_eq!(profile.next_boundary(secs(40)), Some(secs(50)));
+        assert_eq!(profile.next_boundary(secs(50)), None);
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_invalid_segments() {
+        assert!(profile("[{ rate: { shape: linear, from: 1, to: 2 } }]").is_err());
+        assert!(profile("[{ rate: { shape: sinusoid, period: 10, amplitude: 2 } }]").is_err());
+        assert!(profile("[{ start-time: 5, stop-time: 1 }]").is_err());
+        assert!(profile("[{ group-weights: { asia: 2 } }]").is_err());
+        assert!(profile("[{ start-time: -1 }]").is_err());
+        assert!(profile("[{ stop-time: .nan }]").is_err());
+        assert!(profile("[{ rate: { shape: constant, multiplier: .inf } }]").is_err());
+        assert!(profile("[{ rate: { shape: constant, multiplier: .nan } }]").is_err());
+        let linear = "[{ stop-time: 10, rate: { shape: linear, from: 1, to: .nan } }]";
+        assert!(profile(linear).is_err());
+        let sinusoid = "[{ rate: { shape: sinusoid, period: .nan, amplitude: 0.5 } }]";
+        assert!(profile(sinusoid).is_err());
+        assert!(profile("[{ group-weights: { europe: .nan } }]").is_err());
+    }
+
+    fn scenario(yaml: &str) -> anyhow::Result<Scenario> {
+        let raw: RawScenario = serde_yaml::from_str(yaml)?;
+        let nodes = [
+            node(None),
+            NodeConfiguration {
+                id: NodeId::new(1),
+                name: "node-1".to_string(),
+                ..node(None)
+            },
+        ];
+        let links = [LinkConfiguration {
+            nodes: (NodeId::new(0), NodeId::new(1)),
+            latency: Duration::ZERO,
+            bandwidth_bps: None,
+            loss_rate: 0.0,
+            latency_jitter: None,
+        }];
+        Scenario::build(raw, &nodes, &links)
+    }
+
+    #[test]
+    fn should_parse_scenarios() -> anyhow::Result<()> {
+        let scenario = scenario(
+            r#"
+            events:
+              - { type: node-outage, nodes: [node-1], start-time: 5, stop-time: 10 }
+              - { type: partition, groups: [[node-0], [node-1]], start-time: 2.5 }
+              - type: link-bandwidth
+                link: [node-1, node-0]
+                bandwidth-bytes-per-second: 1000
+                stop-time: 3
+            "#,
+        )?;
+        assert_eq!(scenario.events.len(), 3);
+        assert!(!scenario.is_node_offline(NodeId::new(1), Timestamp::from_secs(4)));
+        assert!(scenario.is_node_offline(NodeId::new(1), Timestamp::from_secs(5)));
+        assert!(!scenario.is_node_offline(NodeId::new(1), Timestamp::from_secs(10)));
+        assert_eq!(
+            scenario.events[1].start,
+            Timestamp::zero() + Duration::from_millis(2500)
+        );
+        assert!(matches!(
+            scenario.events[2].change,
+            ScenarioChange::LinkBandwidth {
+                nodes,
+                bandwidth_bps: Some(1000),
+            } if nodes == (NodeId::new(0), NodeId::new(1))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn should_reject_invalid_scenarios() {
+        let event = |event: &str| scenario(&format!("events: [{event}]"));
+        assert!(event("{ type: node-outage, nodes: [node-2] }").is_err());
+        assert!(event("{ type: partition, groups: [[node-0], [node-3]] }").is_err());
+        assert!(event("{ type: link-bandwidth, link: [node-0, node-0] }").is_err());
+        assert!(event("{ type: node-outage, nodes: [node-0], start-time: -1 }").is_err());
+        assert!(event("{ type: node-outage, nodes: [node-0], start-time: .nan }").is_err());
+        assert!(event("{ type: node-outage, nodes: [node-0], stop-time: .inf }").is_err());
+        assert!(
+            event("{ type: node-outage, nodes: [node-0], start-time: 5, stop-time: 5 }").is_err()
+        );
+        assert!(event("{ type: meteor-strike }").is_err());
+    }
+
+    fn distribution(yaml: &str) -> anyhow::Result<FloatDistribution> {
+        let config: DistributionConfig = serde_yaml::from_str(yaml)?;
+        config.try_into()
+    }
+
+    #[test]
+    fn should_reject_invalid_distribution_parameters() {
+        let error = |yaml: &str| distribution(yaml).unwrap_err().to_string();
+        assert!(error("{ distribution: normal, mean: 1, std_dev: .inf }").contains("std_dev inf"));
+        assert!(error("{ distribution: exp, lambda: -2 }").contains("lambda -2"));
+        assert!(error("{ distribution: log-normal, mu: 0, sigma: .nan }").contains("sigma NaN"));
+        assert!(error("{ distribution: uniform, min: 2, max: 1 }").contains("min 2, max 1"));
+        assert!(error("{ distribution: pareto, scale: 0, shape: 1 }").contains("scale 0"));
+        assert!(error("{ distribution: weibull, scale: 1, shape: -1 }").contains("shape -1"));
+        assert!(error("{ distribution: gamma, shape: -1, scale: 1 }").contains("shape -1"));
+        assert!(error("{ distribution: mixture, components: [] }").contains("mixture"));
+        // Components are checked too
+        assert!(error(
+            "{ distribution: mixture, components: [{ weight: 1, distribution: exp, lambda: -1 }] }"
+        )
+        .contains("lambda -1"));
+
+        // Errors say which parameter was wrong
+        let cpu_time: RawCpuTime =
+            serde_yaml::from_str("{ distribution: gamma, shape: -1, scale: 1 }").unwrap();
+        let error = CpuTime::new(&cpu_time, "ib-generation-cpu-time-ms").unwrap_err();
+        assert_eq!(error.to_string(), "invalid ib-generation-cpu-time-ms");
+    }
+
+    #[test]
+    fn should_sample_from_mixtures_and_histograms() -> anyhow::Result<()> {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let mixture = distribution(
+            r#"
+            distribution: mixture
+            components:
+              - { weight: 3, distribution: constant, value: 1 }
+              - { weight: 1, distribution: uniform, min: 10, max: 20 }
+            "#,
+        )?;
+        let samples: Vec<f64> = (0..1000).map(|_| mixture.sample(&mut rng)).collect();
+        let ones = samples.iter().filter(|s| **s == 1.0).count();
+        assert!((650..850).contains(&ones));
+        assert!(samples
+            .iter()
+            .all(|s| *s == 1.0 || (10.0..=20.0).contains(s)));
+
+        let empirical = distribution(
+            r#"
+            distribution: empirical
+            samples: [5]
+            bins: [{ min: 100, max: 200, weight: 0 }]
+            "#,
+        )?;
+        assert!((0..100).all(|_| empirical.sample(&mut rng) == 5.0));
+
+        assert!(distribution("{ distribution: empirical, samples: [] }").is_err());
+        assert!(
+            distribution("{ distribution: empirical, bins: [{ min: 2, max: 1, weight: 1 }] }")
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_only_skip_header_lines_in_empirical_distribution_files() -> anyhow::Result<()> {
+        let bins = EmpiricalDistribution::bins_from_file("min,max,weight\n1,2,3\n\n5\n")?;
+        let bins: Vec<_> = bins.iter().map(|b| (b.min, b.max, b.weight)).collect();
+        assert_eq!(bins, vec![(1.0, 2.0, 3.0), (5.0, 5.0, 1.0)]);
+
+        // A malformed first row of data is an error, not a header
+        let error = EmpiricalDistribution::bins_from_file("1,2\n3\n").unwrap_err();
+        assert_eq!(error.to_string(), "invalid line 1: \"1,2\"");
+        assert!(EmpiricalDistribution::bins_from_file("sample\n3\nx\n").is_err());
+
+        // Files are read along with the rest of the parameters, not while they're parsed
+        let error = distribution("{ distribution: empirical, path: samples.csv }").unwrap_err();
+        assert!(error.to_string().contains("samples.csv"), "{error}");
+        Ok(())
+    }
+
+    #[test]
+    fn should_sample_cpu_times() -> anyhow::Result<()> {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let fixed: RawCpuTime = serde_yaml::from_str("1.5")?;
+        let fixed = CpuTime::new(&fixed, "fixed")?;
+        assert_eq!(fixed.sample(&mut rng), Duration::from_micros(1500));
+
+        let random: RawCpuTime = serde_yaml::from_str("{ distribution: uniform, min: 1, max: 2 }")?;
+        let total = CpuTime::new(&random, "random")?.plus(fixed);
+        for _ in 0..100 {
+            let time = total.sample(&mut rng);
+            assert!((Duration::from_micros(2500)..=Duration::from_micros(3500)).contains(&time));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_clamp_heavy_tailed_cpu_times() -> anyhow::Result<()> {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let raw: RawCpuTime =
+            serde_yaml::from_str("{ distribution: pareto, scale: 1, shape: 0.001 }")?;
+        let cpu_time = CpuTime::new(&raw, "pareto")?.plus(CpuTime::new(&raw, "pareto")?);
+        let times: Vec<Duration> = (0..100).map(|_| cpu_time.sample(&mut rng)).collect();
+        assert!(times.iter().all(|time| *time <= MAX_DURATION));
+        assert!(times.contains(&MAX_DURATION));
+        Ok(())
+    }
+
+    #[test]
+    fn should_limit_execution_units() {
+        let limit = execution_limit(Some(1_000), None);
+        let tx = ExecutionUnits {
+            steps: 600,
+            memory: 1 << 40,
+        };
+        assert!(tx.fits_within(limit));
+        assert!(!tx.saturating_add(tx).fits_within(limit));
+        assert!(ExecutionUnits::UNLIMITED
+            .saturating_add(tx)
+            .fits_within(execution_limit(None, None)));
+    }
+
+    fn pools(stakes: &[u64]) -> Vec<NodeConfiguration> {
+        stakes
+            .iter()
+            .enumerate()
+            .map(|(id, stake)| NodeConfiguration {
+                id: NodeId::new(id),
+                name: format!("node-{id}"),
+                stake: *stake,
+                ..node(None)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_give_large_pools_persistent_seats() {
+        let committee = FaitAccompliCommittee::new(&pools(&[0, 50, 10, 10, 10, 10, 10]), 3);
+        let persistent: Vec<NodeId> = committee.persistent.into_iter().collect();
+        assert_eq!(persistent, vec![NodeId::new(1)]);
+        assert_eq!(committee.nonpersistent_stake, 50);
+        assert_eq!(committee.nonpersistent_seats, 2);
+    }
+
+    #[test]
+    fn should_choose_committees_when_stake_is_near_the_limit() {
+        let max = u64::MAX;
+        let stakes = [max / 2, max / 4, max / 8, max / 16, max / 16];
+        let committee = FaitAccompliCommittee::new(&pools(&stakes), 4);
+        assert_eq!(committee.persistent.len(), 3);
+        assert!(committee.persistent.contains(&NodeId::new(0)));
+        assert!(committee.persistent.contains(&NodeId::new(2)));
+        assert_eq!(committee.nonpersistent_stake, max / 16 * 2);
+        assert_eq!(committee.nonpersistent_seats, 1);
+    }
 }
@@ -1,6 +1,16 @@
-use std::{collections::BTreeMap, fmt::Display, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use serde::{Serialize, Serializer};
+use anyhow::{anyhow, bail, Result};
+use serde::{
+    de::{self, IntoDeserializer},
+    Deserialize, Serialize, Serializer,
+};
 use tokio::sync::mpsc;
 use tracing::warn;
 
@@ -105,6 +115,8 @@ pub enum Event {
         shard: u64,
         input_id: u64,
         overcollateralization_factor: u64,
+        execution_steps: u64,
+        execution_memory: u64,
     },
     TXSent {
         id: TransactionId,
@@ -152,6 +164,13 @@ pub enum Event {
         sender: Node,
         recipient: Node,
     },
+    RBRolledBack {
+        node: Node,
+        depth: usize,
+        old_tip: BlockId<Node>,
+        new_tip: BlockId<Node>,
+        rolled_back: Vec<BlockRef<BlockId<Node>>>,
+    },
     IBLotteryWon {
         id: InputBlockId<Node>,
         slot: u64,
@@ -276,6 +295,130 @@ pub enum Event {
     },
 }
 
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::GlobalSlot { .. } => EventKind::GlobalSlot,
+            Self::Slot { .. } => EventKind::Slot,
+            Self::CpuTaskScheduled { .. } => EventKind::CpuTaskScheduled,
+            Self::CpuTaskFinished { .. } => EventKind::CpuTaskFinished,
+            Self::Cpu { .. } => EventKind::Cpu,
+            Self::TXGenerated { .. } => EventKind::TXGenerated,
+            Self::TXSent { .. } => EventKind::TXSent,
+            Self::TXReceived { .. } => EventKind::TXReceived,
+            Self::TXLost { .. } => EventKind::TXLost,
+            Self::RBLotteryWon { .. } => EventKind::RBLotteryWon,
+            Self::RBGenerated { .. } => EventKind::RBGenerated,
+            Self::RBSent { .. } => EventKind::RBSent,
+            Self::RBReceived { .. } => EventKind::RBReceived,
+            Self::RBRolledBack { .. } => EventKind::RBRolledBack,
+            Self::IBLotteryWon { .. } => EventKind::IBLotteryWon,
+            Self::IBGenerated { .. } => EventKind::IBGenerated,
+            Self::NoIBGenerated { .. } => EventKind::NoIBGenerated,
+            Self::IBSent { .. } => EventKind::IBSent,
+            Self::IBReceived { .. } => EventKind::IBReceived,
+            Self::EBLotteryWon { .. } => EventKind::EBLotteryWon,
+            Self::EBGenerated { .. } => EventKind::EBGenerated,
+            Self::NoEBGenerated { .. } => EventKind::NoEBGenerated,
+            Self::EBSent { .. } => EventKind::EBSent,
+            Self::EBReceived { .. } => EventKind::EBReceived,
+            Self::VTLotteryWon { .. } => EventKind::VTLotteryWon,
+            Self::VTBundleGenerated { .. } => EventKind::VTBundleGenerated,
+            Self::NoVTBundleGenerated { .. } => EventKind::NoVTBundleGenerated,
+            Self::VTBundleNotGenerated { .. } => EventKind::VTBundleNotGenerated,
+            Self::VTBundleSent { .. } => EventKind::VTBundleSent,
+            Self::VTBundleReceived { .. } => EventKind::VTBundleReceived,
+        }
+    }
+
+    /// The node where this event happened, if it happened at any one node.
+    pub fn node(&self) -> Option<NodeId> {
+        match self {
+            Self::GlobalSlot { .. } => None,
+            Self::Slot { node, .. } => Some(node.id),
+            Self::CpuTaskScheduled { task, .. } => Some(task.node.id),
+            Self::CpuTaskFinished { task, .. } => Some(task.node.id),
+            Self::Cpu { task, .. } => Some(task.node.id),
+            Self::TXGenerated { publisher, .. } => Some(publisher.id),
+            Self::TXSent { sender, .. } => Some(sender.id),
+            Self::TXReceived { recipient, .. } => Some(recipient.id),
+            Self::TXLost { .. } => None,
+            Self::RBLotteryWon { producer, .. } => Some(producer.id),
+            Self::RBGenerated { producer, .. } => Some(producer.id),
+            Self::RBSent { sender, .. } => Some(sender.id),
+            Self::RBReceived { recipient, .. } => Some(recipient.id),
+            Self::RBRolledBack { node, .. } => Some(node.id),
+            Self::IBLotteryWon { producer, .. } => Some(producer.id),
+            Self::IBGenerated { producer, .. } => Some(producer.id),
+            Self::NoIBGenerated { node, .. } => Some(node.id),
+            Self::IBSent { sender, .. } => Some(sender.id),
+            Self::IBReceived { recipient, .. } => Some(recipient.id),
+            Self::EBLotteryWon { producer, .. } => Some(producer.id),
+            Self::EBGenerated { producer, .. } => Some(producer.id),
+            Self::NoEBGenerated { node, .. } => Some(node.id),
+            Self::EBSent { sender, .. } => Some(sender.id),
+            Self::EBReceived { recipient, .. } => Some(recipient.id),
+            Self::VTLotteryWon { producer, .. } => Some(producer.id),
+            Self::VTBundleGenerated { producer, .. } => Some(producer.id),
+            Self::NoVTBundleGenerated { node, .. } => Some(node.id),
+            Self::VTBundleNotGenerated { producer, .. } => Some(producer.id),
+            Self::VTBundleSent { sender, .. } => Some(sender.id),
+            Self::VTBundleReceived { recipient, .. } => Some(recipient.id),
+        }
+    }
+}
+
+/// The kinds of event, named like the "type" field of a trace (e.g. "IBGenerated").
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EventKind {
+    GlobalSlot,
+    Slot,
+    CpuTaskScheduled,
+    CpuTaskFinished,
+    Cpu,
+    TXGenerated,
+    TXSent,
+    TXReceived,
+    TXLost,
+    RBLotteryWon,
+    RBGenerated,
+    RBSent,
+    RBReceived,
+    RBRolledBack,
+    IBLotteryWon,
+    IBGenerated,
+    NoIBGenerated,
+    IBSent,
+    IBReceived,
+    EBLotteryWon,
+    EBGenerated,
+    NoEBGenerated,
+    EBSent,
+    EBReceived,
+    VTLotteryWon,
+    VTBundleGenerated,
+    NoVTBundleGenerated,
+    VTBundleNotGenerated,
+    VTBundleSent,
+    VTBundleReceived,
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::deserialize(s.into_deserializer())
+            .map_err(|_: de::value::Error| anyhow!("unknown event kind \"{s}\""))
+    }
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Unit variants are serialized by name, so this matches the trace
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Votes<Node>(pub BTreeMap<EndorserBlockId<Node>, usize>);
 
@@ -288,11 +431,144 @@ impl<Node: Display> Serialize for Votes<Node> {
     }
 }
 
+/// Observes events as the simulation emits them.
+/// Sinks are called from whichever actor emitted the event, so they should be quick.
+pub trait EventSink: Send + Sync {
+    fn handle(&self, event: &Event, time: Timestamp);
+
+    /// Called instead of `handle` when no other sink needs to see the event.
+    fn handle_owned(&self, event: Event, time: Timestamp) {
+        self.handle(&event, time);
+    }
+}
+
+impl EventSink for mpsc::UnboundedSender<(Event, Timestamp)> {
+    fn handle(&self, event: &Event, time: Timestamp) {
+        self.handle_owned(event.clone(), time);
+    }
+
+    fn handle_owned(&self, event: Event, time: Timestamp) {
+        if self.send((event, time)).is_err() {
+            warn!("tried sending event after aggregator finished");
+        }
+    }
+}
+
+impl<F: Fn(&Event, Timestamp) + Send + Sync> EventSink for F {
+    fn handle(&self, event: &Event, time: Timestamp) {
+        self(event, time)
+    }
+}
+
+/// Holds on to events instead of passing them on, so that events from several threads
+/// can be put in order before any other sink sees them.
+#[derive(Clone, Default)]
+pub(crate) struct EventBuffer(Arc<Mutex<Vec<(Event, Timestamp)>>>);
+
+impl EventBuffer {
+    pub fn take(&self) -> Vec<(Event, Timestamp)> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl EventSink for EventBuffer {
+    fn handle(&self, event: &Event, time: Timestamp) {
+        self.handle_owned(event.clone(), time);
+    }
+
+    fn handle_owned(&self, event: Event, time: Timestamp) {
+        self.0.lock().unwrap().push((event, time));
+    }
+}
+
+/// Decides which events a sink sees. Each restriction narrows the filter further.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    kinds: Option<HashSet<EventKind>>,
+    nodes: Option<HashSet<NodeId>>,
+    from: Option<Timestamp>,
+    until: Option<Timestamp>,
+}
+
+impl EventFilter {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// Only events of the given kinds. Kinds can be parsed from their names in a trace.
+    pub fn kinds(mut self, kinds: impl IntoIterator<Item = EventKind>) -> Self {
+        let kinds: HashSet<EventKind> = kinds.into_iter().collect();
+        self.kinds = Some(match self.kinds {
+            Some(existing) => existing.intersection(&kinds).copied().collect(),
+            None => kinds,
+        });
+        self
+    }
+
+    /// Only events which happened at one of the given nodes.
+    pub fn nodes(mut self, nodes: impl IntoIterator<Item = NodeId>) -> Self {
+        let nodes: HashSet<NodeId> = nodes.into_iter().collect();
+        self.nodes = Some(match self.nodes {
+            Some(existing) => existing.intersection(&nodes).copied().collect(),
+            None => nodes,
+        });
+        self
+    }
+
+    /// Only events which happened at or after `from`, and before `until`.
+    pub fn between(mut self, from: Timestamp, until: Timestamp) -> Self {
+        self.from = self.from.max(Some(from));
+        self.until = Some(self.until.map_or(until, |u| u.min(until)));
+        self
+    }
+
+    pub fn matches(&self, event: &Event, time: Timestamp) -> bool {
+        if self.from.is_some_and(|from| time < from) || self.until.is_some_and(|u| time >= u) {
+            return false;
+        }
+        if self
+            .kinds
+            .as_ref()
+            .is_some_and(|kinds| !kinds.contains(&event.kind()))
+        {
+            return false;
+        }
+        if let Some(nodes) = &self.nodes {
+            return event.node().is_some_and(|node| nodes.contains(&node));
+        }
+        true
+    }
+}
+
+struct Subscription {
+    filter: EventFilter,
+    sink: Box<dyn EventSink>,
+}
+
+impl Subscription {
+    fn new(filter: EventFilter, sink: impl EventSink + 'static) -> Self {
+        Self {
+            filter,
+            sink: Box::new(sink),
+        }
+    }
+}
+
+/// Holds on to everything tracked at one instant until time moves past it,
+/// so that actors which run side by side can't change the order events come out in.
+struct InstantBuffer {
+    time: Timestamp,
+    events: Vec<((usize, usize), Event)>,
+}
+
 #[derive(Clone)]
 pub struct EventTracker {
-    sender: mpsc::UnboundedSender<(Event, Timestamp)>,
+    subscriptions: Arc<Vec<Subscription>>,
     clock: Clock,
     node_names: Arc<BTreeMap<NodeId, Arc<String>>>,
+    instant: Option<Arc<Mutex<InstantBuffer>>>,
+    // Where events from this tracker go among the others tracked at the same instant.
+    order: (usize, usize),
 }
 
 impl EventTracker {
@@ -301,6 +577,14 @@ impl EventTracker {
         clock: Clock,
         nodes: &[NodeConfiguration],
     ) -> Self {
+        Self {
+            subscriptions: Arc::new(vec![Subscription::new(EventFilter::all(), sender)]),
+            ..Self::without_sinks(clock, nodes)
+        }
+    }
+
+    /// A tracker which discards everything until sinks are added to it.
+    pub fn without_sinks(clock: Clock, nodes: &[NodeConfiguration]) -> Self {
         let node_names = Arc::new(
             nodes
                 .iter()
@@ -308,9 +592,84 @@ impl EventTracker {
                 .collect(),
         );
         Self {
-            sender,
+            subscriptions: Arc::new(vec![]),
             clock,
             node_names,
+            instant: None,
+            order: (0, 0),
+        }
+    }
+
+    /// Sends every event to the given sink, as well as to any existing ones.
+    pub fn with_sink(self, sink: impl EventSink + 'static) -> Result<Self> {
+        self.with_filtered_sink(EventFilter::all(), sink)
+    }
+
+    /// Sends the events which match the filter to the given sink.
+    /// Sinks must be added before the tracker is cloned, so that every copy sees the same sinks.
+    pub fn with_filtered_sink(
+        mut self,
+        filter: EventFilter,
+        sink: impl EventSink + 'static,
+    ) -> Result<Self> {
+        let Some(subscriptions) = Arc::get_mut(&mut self.subscriptions) else {
+            bail!("sinks must be added before the tracker is shared");
+        };
+        subscriptions.push(Subscription::new(filter, sink));
+        Ok(self)
+    }
+
+    /// A tracker which keeps its own time, and leaves everything it tracks in a buffer.
+    /// Whatever is taken from the buffer can be passed on to this tracker's sinks with `send_at`.
+    pub(crate) fn buffered(&self, clock: Clock) -> (Self, EventBuffer) {
+        let buffer = EventBuffer::default();
+        let tracker = Self {
+            subscriptions: Arc::new(vec![Subscription::new(EventFilter::all(), buffer.clone())]),
+            clock,
+            node_names: self.node_names.clone(),
+            instant: None,
+            order: (0, 0),
+        };
+        (tracker, buffer)
+    }
+
+    /// A tracker which holds on to the events of each instant, and passes them on
+    /// sorted by the `order` of the tracker each came from once time has moved past it.
+    /// Events from trackers with the same `order` stay in the order they were tracked in.
+    pub(crate) fn ordered(self) -> Self {
+        Self {
+            instant: Some(Arc::new(Mutex::new(InstantBuffer {
+                time: self.clock.now(),
+                events: vec![],
+            }))),
+            ..self
+        }
+    }
+
+    /// A copy of this tracker for one actor. Among events tracked at the same instant,
+    /// those with a lower `order` come out first.
+    pub(crate) fn with_order(&self, order: (usize, usize)) -> Self {
+        Self {
+            order,
+            ..self.clone()
+        }
+    }
+
+    /// Passes on everything held back so far. The simulation calls this whenever it pauses,
+    /// because nothing else will happen at the current instant until it's resumed.
+    pub(crate) fn flush(&self) {
+        if let Some(instant) = &self.instant {
+            let mut instant = instant.lock().unwrap();
+            let time = instant.time;
+            self.send_in_order(time, &mut instant.events);
+        }
+    }
+
+    fn send_in_order(&self, time: Timestamp, events: &mut Vec<((usize, usize), Event)>) {
+        // The sort is stable, so each actor's events stay in the order it tracked them in
+        events.sort_by_key(|(order, _)| *order);
+        for (_, event) in events.drain(..) {
+            self.send_at(event, time);
         }
     }
 
@@ -424,6 +783,27 @@ impl EventTracker {
         });
     }
 
+    pub fn track_praos_rollback(
+        &self,
+        node: NodeId,
+        old_tip: BlockId,
+        new_tip: BlockId,
+        rolled_back: &[Arc<Block>],
+    ) {
+        self.send(Event::RBRolledBack {
+            node: self.to_node(node),
+            depth: rolled_back.len(),
+            old_tip: self.to_block(old_tip),
+            new_tip: self.to_block(new_tip),
+            rolled_back: rolled_back
+                .iter()
+                .map(|block| BlockRef {
+                    id: self.to_block(block.id),
+                })
+                .collect(),
+        });
+    }
+
     pub fn track_transaction_generated(&self, transaction: &Transaction, publisher: NodeId) {
         self.send(Event::TXGenerated {
             id: transaction.id,
@@ -432,6 +812,8 @@ impl EventTracker {
             shard: transaction.shard,
             input_id: transaction.input_id,
             overcollateralization_factor: transaction.overcollateralization_factor,
+            execution_steps: transaction.execution_units.steps,
+            execution_memory: transaction.execution_units.memory,
         });
     }
 
@@ -666,8 +1048,35 @@ impl EventTracker {
     }
 
     fn send(&self, event: Event) {
-        if self.sender.send((event, self.clock.now())).is_err() {
-            warn!("tried sending event after aggregator finished");
+        let now = self.clock.now();
+        let Some(instant) = &self.instant else {
+            self.send_at(event, now);
+            return;
+        };
+        let mut instant = instant.lock().unwrap();
+        if instant.time != now {
+            // Time has moved on, so nothing else can happen at the instant before
+            let time = instant.time;
+            self.send_in_order(time, &mut instant.events);
+            instant.time = now;
+        }
+        instant.events.push((self.order, event));
+    }
+
+    /// Passes an event to every matching sink, as if it happened at the given time.
+    pub(crate) fn send_at(&self, event: Event, time: Timestamp) {
+        let mut matching = self
+            .subscriptions
+            .iter()
+            .filter(|s| s.filter.matches(&event, time))
+            .peekable();
+        while let Some(subscription) = matching.next() {
+            if matching.peek().is_some() {
+                subscription.sink.handle(&event, time);
+            } else {
+                subscription.sink.handle_owned(event, time);
+                break;
+            }
         }
     }
 
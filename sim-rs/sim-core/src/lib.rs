@@ -1,3 +1,4 @@
+pub mod checkpoint;
 pub mod clock;
 pub mod config;
 pub mod events;
@@ -5,3 +6,5 @@ pub mod model;
 mod network;
 pub mod probability;
 pub mod sim;
+#[cfg(test)]
+mod testing;
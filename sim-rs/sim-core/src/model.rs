@@ -1,7 +1,12 @@
 use std::{collections::BTreeMap, fmt::Display, sync::Arc};
 
-use crate::{clock::Timestamp, config::NodeId};
-use serde::Serialize;
+use crate::{
+    checkpoint::{persist_struct, CheckpointReader, CheckpointWriter, Persist},
+    clock::Timestamp,
+    config::NodeId,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
 macro_rules! id_wrapper {
     ($outer:ident, $inner:ty) => {
@@ -20,11 +25,23 @@ macro_rules! id_wrapper {
                 serializer.serialize_str(&self.0.to_string())
             }
         }
+        impl Persist for $outer {
+            fn save(&self, w: &mut CheckpointWriter) {
+                self.0.save(w);
+            }
+            fn load(r: &mut CheckpointReader) -> Result<Self> {
+                Ok(Self(Persist::load(r)?))
+            }
+        }
         impl $outer {
             #[allow(unused)]
             pub fn new(value: $inner) -> Self {
                 Self(value)
             }
+            #[allow(unused)]
+            pub fn to_inner(self) -> $inner {
+                self.0
+            }
         }
     };
 }
@@ -62,6 +79,8 @@ impl<Node: Display> Serialize for BlockId<Node> {
     }
 }
 
+persist_struct!(BlockId { slot, producer });
+
 #[derive(Clone, Debug)]
 pub struct Block {
     pub id: BlockId,
@@ -84,6 +103,15 @@ impl Block {
     }
 }
 
+persist_struct!(Block {
+    id,
+    vrf,
+    parent,
+    header_bytes,
+    endorsement,
+    transactions,
+});
+
 id_wrapper!(TransactionId, u64);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -93,8 +121,46 @@ pub struct Transaction {
     pub bytes: u64,
     pub input_id: u64,
     pub overcollateralization_factor: u64,
+    pub execution_units: ExecutionUnits,
 }
 
+persist_struct!(Transaction {
+    id,
+    shard,
+    bytes,
+    input_id,
+    overcollateralization_factor,
+    execution_units,
+});
+
+/// How much work a transaction's scripts may do, like Plutus's CPU steps and memory units.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ExecutionUnits {
+    pub steps: u64,
+    pub memory: u64,
+}
+
+impl ExecutionUnits {
+    /// Room for as much work as anything could need.
+    pub const UNLIMITED: Self = Self {
+        steps: u64::MAX,
+        memory: u64::MAX,
+    };
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self {
+            steps: self.steps.saturating_add(other.steps),
+            memory: self.memory.saturating_add(other.memory),
+        }
+    }
+
+    pub fn fits_within(self, limit: Self) -> bool {
+        self.steps <= limit.steps && self.memory <= limit.memory
+    }
+}
+
+persist_struct!(ExecutionUnits { steps, memory });
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct InputBlockId<Node = NodeId> {
     pub slot: u64,
@@ -123,6 +189,13 @@ impl<Node: Display> Serialize for InputBlockId<Node> {
     }
 }
 
+persist_struct!(InputBlockId {
+    slot,
+    pipeline,
+    producer,
+    index,
+});
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct InputBlockHeader {
     pub id: InputBlockId,
@@ -132,6 +205,14 @@ pub struct InputBlockHeader {
     pub bytes: u64,
 }
 
+persist_struct!(InputBlockHeader {
+    id,
+    vrf,
+    shard,
+    timestamp,
+    bytes,
+});
+
 #[derive(Debug)]
 pub struct InputBlock {
     pub header: InputBlockHeader,
@@ -145,6 +226,13 @@ impl InputBlock {
     }
 }
 
+persist_struct!(InputBlock {
+    header,
+    tx_payload_bytes,
+    transactions,
+    rb_ref,
+});
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct EndorserBlockId<Node = NodeId> {
     pub slot: u64,
@@ -165,6 +253,12 @@ impl<Node: Display> Serialize for EndorserBlockId<Node> {
     }
 }
 
+persist_struct!(EndorserBlockId {
+    slot,
+    pipeline,
+    producer,
+});
+
 #[derive(Debug)]
 pub struct EndorserBlock {
     pub slot: u64,
@@ -185,6 +279,16 @@ impl EndorserBlock {
     }
 }
 
+persist_struct!(EndorserBlock {
+    slot,
+    pipeline,
+    producer,
+    bytes,
+    txs,
+    ibs,
+    ebs,
+});
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct VoteBundleId<Node = NodeId> {
     pub slot: u64,
@@ -205,6 +309,12 @@ impl<Node: Display> Serialize for VoteBundleId<Node> {
     }
 }
 
+persist_struct!(VoteBundleId {
+    slot,
+    pipeline,
+    producer,
+});
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct VoteBundle {
     pub id: VoteBundleId,
@@ -212,7 +322,9 @@ pub struct VoteBundle {
     pub ebs: BTreeMap<EndorserBlockId, usize>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+persist_struct!(VoteBundle { id, bytes, ebs });
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NoVoteReason {
     InvalidSlot,
     ExtraIB,
@@ -223,7 +335,7 @@ pub enum NoVoteReason {
     UncertifiedEBReference,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionLostReason {
     IBExpired,
     EBExpired,
@@ -235,3 +347,9 @@ pub struct Endorsement<Node: Display = NodeId> {
     pub size_bytes: u64,
     pub votes: BTreeMap<Node, usize>,
 }
+
+persist_struct!(Endorsement {
+    eb,
+    size_bytes,
+    votes,
+});
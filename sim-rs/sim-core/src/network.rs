@@ -1,55 +1,81 @@
 use std::{fmt::Debug, hash::Hash, time::Duration};
 
 use anyhow::{bail, Result};
+pub use coordinator::NetworkChange;
 use coordinator::{EdgeConfig, Message, NetworkCoordinator};
 use tokio::sync::mpsc;
 
 use crate::{
-    clock::{Clock, ClockBarrier},
+    checkpoint::{CheckpointReader, CheckpointRequest, CheckpointWriter, Persist},
+    clock::{ClockBarrier, Timestamp},
     config::NodeId,
+    probability::FloatDistribution,
 };
 
 mod connection;
 mod coordinator;
 
+#[derive(Clone)]
+pub struct EdgePolicy {
+    pub latency: Duration,
+    pub bandwidth_bps: Option<u64>,
+    pub loss_rate: f64,
+    /// Extra latency added to each message, in milliseconds.
+    pub latency_jitter: Option<FloatDistribution>,
+    pub tcp_congestion_control: bool,
+}
+
 pub struct Network<TProtocol, TMessage> {
     clock: ClockBarrier,
     coordinator: NetworkCoordinator<TProtocol, TMessage>,
     sink: mpsc::UnboundedSender<Message<TProtocol, TMessage>>,
 }
 
-impl<TProtocol: Clone + Eq + Hash, TMessage: Debug> Network<TProtocol, TMessage> {
-    pub fn new(clock: Clock) -> Self {
+impl<TProtocol: Clone + Eq + Hash + Persist, TMessage: Debug + Persist>
+    Network<TProtocol, TMessage>
+{
+    pub fn new(
+        clock: ClockBarrier,
+        seed: u64,
+        checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
+    ) -> Self {
         let (sink, source) = mpsc::unbounded_channel();
         Self {
-            clock: clock.barrier(),
-            coordinator: NetworkCoordinator::new(source),
+            clock,
+            coordinator: NetworkCoordinator::new(source, seed, checkpoint_source),
             sink,
         }
     }
 
-    pub fn set_edge_policy(
-        &mut self,
-        from: NodeId,
-        to: NodeId,
-        latency: Duration,
-        bandwidth_bps: Option<u64>,
-    ) -> Result<()> {
+    pub fn set_edge_policy(&mut self, from: NodeId, to: NodeId, policy: EdgePolicy) -> Result<()> {
         self.coordinator.add_edge(EdgeConfig {
             from,
             to,
-            latency,
-            bandwidth_bps,
+            policy: policy.clone(),
         });
         self.coordinator.add_edge(EdgeConfig {
             from: to,
             to: from,
-            latency,
-            bandwidth_bps,
+            policy,
         });
         Ok(())
     }
 
+    /// Limits the total bandwidth of a node's network card, which its connections share.
+    pub fn set_node_bandwidth(
+        &mut self,
+        id: NodeId,
+        upload_bps: Option<u64>,
+        download_bps: Option<u64>,
+    ) {
+        self.coordinator
+            .set_node_bandwidth(id, upload_bps, download_bps);
+    }
+
+    pub fn schedule_change(&mut self, at: Timestamp, change: NetworkChange) {
+        self.coordinator.schedule_change(at, change);
+    }
+
     pub fn open(
         &mut self,
         id: NodeId,
@@ -64,12 +90,134 @@ impl<TProtocol: Clone + Eq + Hash, TMessage: Debug> Network<TProtocol, TMessage>
         Ok((sink, source))
     }
 
+    /// Opens a socket whose messages don't go straight to the network. Instead, they wait in
+    /// an outbox until they're collected, so that the order they're sent in doesn't depend on
+    /// which thread the sender runs on.
+    pub(crate) fn open_outbox(
+        &mut self,
+        id: NodeId,
+    ) -> (
+        NetworkSink<TProtocol, TMessage>,
+        NetworkSource<TMessage>,
+        NetworkOutbox<TProtocol, TMessage>,
+    ) {
+        let (sink, source) = mpsc::unbounded_channel();
+        let sink = NetworkSink { id, sink };
+        let outbox = NetworkOutbox { source };
+        let source = NetworkSource {
+            source: self.coordinator.listen(id),
+        };
+        (sink, source, outbox)
+    }
+
+    /// Replaces the state of every connection with the state saved in a checkpoint.
+    pub fn restore(&mut self, r: &mut CheckpointReader) -> Result<()> {
+        let now = self.clock.now();
+        self.coordinator.restore(r, now)
+    }
+
+    pub(crate) fn checkpoint(&self, w: &mut CheckpointWriter) {
+        self.coordinator.checkpoint(w);
+    }
+
     pub async fn run(&mut self) -> Result<()> {
         self.coordinator.run(&mut self.clock).await
     }
 
-    pub fn shutdown(self) -> Result<()> {
-        Ok(())
+    // The methods below drive the network without the clock coordinator,
+    // for engines which call everything from a single loop.
+
+    /// When the network next has something to do, if it ever will.
+    pub(crate) fn next_event_time(&self) -> Option<Timestamp> {
+        self.coordinator.next_event_time()
+    }
+
+    /// Starts sending every message which nodes have sent since the last call.
+    pub(crate) fn accept_messages(&mut self) {
+        self.coordinator.accept_messages(self.clock.now());
+    }
+
+    /// Handles everything which is due at the current time.
+    /// Messages which arrive are added to `deliveries` as (from, to, body).
+    pub(crate) fn deliver_messages(&mut self, deliveries: &mut Vec<(NodeId, NodeId, TMessage)>) {
+        let now = self.clock.now();
+        while self
+            .coordinator
+            .next_event_time()
+            .is_some_and(|timestamp| timestamp <= now)
+        {
+            self.coordinator.handle_next_event(now, deliveries);
+        }
+    }
+
+    // The methods below let the network run in windows no longer than its shortest latency,
+    // for engines which run nodes in parallel. Nothing sent during a window can arrive before
+    // it ends, so every arrival in a window is known before it starts.
+
+    /// Collects every message which arrives before `end`, when rounded up to the given resolution.
+    /// Must be called at the start of a window, before anything is sent in it.
+    /// Messages are added to `arrivals` as (arrival time, from, to, body).
+    pub(crate) fn collect_arrivals(
+        &mut self,
+        now: Timestamp,
+        end: Timestamp,
+        resolution: Duration,
+        arrivals: &mut Vec<(Timestamp, NodeId, NodeId, TMessage)>,
+    ) {
+        self.coordinator.collect_arrivals(
+            now,
+            |timestamp| timestamp.with_resolution(resolution) < end,
+            arrivals,
+        );
+    }
+
+    /// Sends everything which was sent during a window, then handles the rest of the window.
+    /// Messages must be sorted by the time they were sent.
+    pub(crate) fn send_all(
+        &mut self,
+        sent: impl IntoIterator<Item = SentMessage<TProtocol, TMessage>>,
+        end: Timestamp,
+    ) {
+        let mut deliveries = vec![];
+        for SentMessage { time, message } in sent {
+            self.coordinator.send_at(message, time, &mut deliveries);
+        }
+        self.coordinator.handle_events_before(end, &mut deliveries);
+        debug_assert!(
+            deliveries.is_empty(),
+            "messages arrived in the same window they were sent in"
+        );
+    }
+}
+
+/// A message which a node sent, and when it sent it.
+pub struct SentMessage<TProtocol, TMessage> {
+    time: Timestamp,
+    message: Message<TProtocol, TMessage>,
+}
+
+impl<TProtocol, TMessage> SentMessage<TProtocol, TMessage> {
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    #[cfg(test)]
+    pub(crate) fn into_parts(self) -> (NodeId, NodeId, TMessage) {
+        (self.message.from, self.message.to, self.message.body)
+    }
+}
+
+/// Everything one node has sent, waiting to be handed to the network.
+pub struct NetworkOutbox<TProtocol, TMessage> {
+    source: mpsc::UnboundedReceiver<Message<TProtocol, TMessage>>,
+}
+
+impl<TProtocol, TMessage> NetworkOutbox<TProtocol, TMessage> {
+    /// Takes every message sent since the last call, noting that they were sent at `now`.
+    pub fn collect(&mut self, now: Timestamp, sent: &mut Vec<SentMessage<TProtocol, TMessage>>) {
+        while let Ok(message) = self.source.try_recv() {
+            sent.push(SentMessage { time: now, message });
+        }
     }
 }
 
@@ -4,7 +4,22 @@ use std::{
     time::Duration,
 };
 
-use crate::clock::Timestamp;
+use anyhow::Result;
+use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+use rand_distr::{Binomial, Distribution as _};
+
+use crate::{
+    checkpoint::{persist_struct, CheckpointReader, CheckpointWriter, Persist},
+    clock::Timestamp,
+    probability::FloatDistribution,
+};
+
+/// A typical size for a TCP segment.
+const SEGMENT_SIZE: u64 = 1460;
+/// TCP connections start out allowed to send 10 segments per round trip.
+const INITIAL_CONGESTION_WINDOW: u64 = 10 * SEGMENT_SIZE;
+/// The shortest time a TCP sender will wait for an acknowledgement.
+const MIN_RETRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
 
 struct MiniProtocolQueue<T> {
     queue: VecDeque<(T, u64)>,
@@ -18,6 +33,17 @@ impl<T> Default for MiniProtocolQueue<T> {
     }
 }
 
+impl<T: Persist> Persist for MiniProtocolQueue<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.queue.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Self {
+            queue: Persist::load(r)?,
+        })
+    }
+}
+
 impl<T> MiniProtocolQueue<T> {
     fn push_back(&mut self, message: T, bytes: u64) {
         self.queue.push_back((message, bytes));
@@ -51,12 +77,117 @@ impl<T> MiniProtocolQueue<T> {
     }
 }
 
+/// Ways in which a link is less than ideal.
+#[derive(Clone, Default)]
+pub struct LinkConditions {
+    /// The odds of any single segment getting lost and retransmitted.
+    pub loss_rate: f64,
+    /// Extra latency added to each message, in milliseconds.
+    pub latency_jitter: Option<FloatDistribution>,
+    /// Whether to limit throughput with TCP slow start and congestion avoidance.
+    pub tcp_congestion_control: bool,
+}
+
+/// A simplified TCP sender, which sends a congestion window's worth of data every round trip.
+struct TcpState {
+    round_trip: Duration,
+    receiver_window: u64,
+    congestion_window: u64,
+    slow_start_threshold: u64,
+    round_end: Timestamp,
+    bytes_sent_in_round: u64,
+    lost_in_round: bool,
+    idle_since: Option<Timestamp>,
+}
+
+persist_struct!(TcpState {
+    round_trip,
+    receiver_window,
+    congestion_window,
+    slow_start_threshold,
+    round_end,
+    bytes_sent_in_round,
+    lost_in_round,
+    idle_since,
+});
+
+impl TcpState {
+    fn new(latency: Duration, bandwidth_bps: Option<u64>) -> Self {
+        let round_trip = latency * 2;
+        // Make the receiver window big enough that it never limits bandwidth on its own.
+        let receiver_window = bandwidth_bps.map_or(u64::MAX, |bps| {
+            let bandwidth_delay_product = bps * round_trip.as_micros() as u64 / 1_000_000;
+            bandwidth_delay_product.max(INITIAL_CONGESTION_WINDOW)
+        });
+        Self {
+            round_trip,
+            receiver_window,
+            congestion_window: INITIAL_CONGESTION_WINDOW,
+            slow_start_threshold: u64::MAX,
+            round_end: Timestamp::zero(),
+            bytes_sent_in_round: 0,
+            lost_in_round: false,
+            idle_since: None,
+        }
+    }
+
+    fn window_bps(&self) -> u64 {
+        let bps =
+            self.congestion_window.saturating_mul(1_000_000) / self.round_trip.as_micros() as u64;
+        bps.max(1)
+    }
+
+    fn start_sending(&mut self, now: Timestamp) {
+        if now < self.round_end {
+            // We only just ran out of data, so carry on with the current round.
+            return;
+        }
+        let timeout = self.round_trip.max(MIN_RETRANSMISSION_TIMEOUT);
+        if self
+            .idle_since
+            .is_some_and(|idle_since| now - idle_since > timeout)
+        {
+            // After sitting idle for long enough, TCP goes back to slow start.
+            self.congestion_window = self.congestion_window.min(INITIAL_CONGESTION_WINDOW);
+            self.bytes_sent_in_round = 0;
+            self.lost_in_round = false;
+        } else {
+            self.finish_round();
+        }
+        self.round_end = now + self.round_trip;
+    }
+
+    fn finish_round(&mut self) {
+        if self.lost_in_round {
+            // Fast recovery: halve the window, and carry on from there.
+            self.slow_start_threshold = (self.congestion_window / 2).max(2 * SEGMENT_SIZE);
+            self.congestion_window = self.slow_start_threshold;
+        } else if self.congestion_window < self.slow_start_threshold {
+            // Slow start: grow the window by however much was acknowledged.
+            self.congestion_window += self.bytes_sent_in_round;
+        } else if self.bytes_sent_in_round > 0 {
+            // Congestion avoidance: grow the window by one segment per round trip.
+            self.congestion_window += SEGMENT_SIZE;
+        }
+        self.congestion_window = self.congestion_window.min(self.receiver_window);
+        self.bytes_sent_in_round = 0;
+        self.lost_in_round = false;
+        self.round_end += self.round_trip;
+    }
+}
+
 pub struct Connection<TProtocol, TMessage> {
     bandwidth_bps: Option<u64>,
     latency: Duration,
-    bandwidth_queues: HashMap<TProtocol, MiniProtocolQueue<(u64, TMessage)>>,
+    loss_rate: f64,
+    latency_jitter: Option<FloatDistribution>,
+    tcp: Option<TcpState>,
+    rng: ChaChaRng,
+    /// Each message is queued with its id and how many of its bytes will be lost and resent.
+    bandwidth_queues: HashMap<TProtocol, MiniProtocolQueue<(u64, u64, TMessage)>>,
     latency_queue: VecDeque<(TMessage, Timestamp)>,
     last_event: Timestamp,
+    last_arrival: Timestamp,
     next_id: u64,
 }
 
@@ -68,26 +199,101 @@ where
         Self {
             bandwidth_bps,
             latency,
+            loss_rate: 0.0,
+            latency_jitter: None,
+            tcp: None,
+            rng: ChaChaRng::seed_from_u64(0),
             bandwidth_queues: HashMap::new(),
             latency_queue: VecDeque::new(),
             last_event: Timestamp::zero(),
+            last_arrival: Timestamp::zero(),
             next_id: 0,
         }
     }
 
+    pub fn with_conditions(mut self, conditions: LinkConditions, rng: ChaChaRng) -> Self {
+        self.loss_rate = conditions.loss_rate;
+        self.latency_jitter = conditions.latency_jitter;
+        // Without any latency, TCP would never have to wait for acknowledgements.
+        if conditions.tcp_congestion_control && self.latency.as_micros() > 0 {
+            self.tcp = Some(TcpState::new(self.latency, self.bandwidth_bps));
+        }
+        self.rng = rng;
+        self
+    }
+
+    pub fn checkpoint(&self, w: &mut CheckpointWriter)
+    where
+        TProtocol: Persist,
+        TMessage: Persist,
+    {
+        self.bandwidth_bps.save(w);
+        self.tcp.save(w);
+        self.rng.save(w);
+        self.bandwidth_queues.save(w);
+        self.latency_queue.save(w);
+        self.last_event.save(w);
+        self.last_arrival.save(w);
+        self.next_id.save(w);
+    }
+
+    pub fn restore(&mut self, r: &mut CheckpointReader) -> Result<()>
+    where
+        TProtocol: Persist,
+        TMessage: Persist,
+    {
+        self.bandwidth_bps = Persist::load(r)?;
+        self.tcp = Persist::load(r)?;
+        self.rng = Persist::load(r)?;
+        self.bandwidth_queues = Persist::load(r)?;
+        self.latency_queue = Persist::load(r)?;
+        self.last_event = Persist::load(r)?;
+        self.last_arrival = Persist::load(r)?;
+        self.next_id = Persist::load(r)?;
+        Ok(())
+    }
+
     pub fn send(&mut self, message: TMessage, bytes: u64, miniprotocol: TProtocol, now: Timestamp) {
-        if self.bandwidth_bps.is_none() {
-            self.latency_queue.push_back((message, now + self.latency));
+        if !self.is_throttled() {
+            let arrival = now + self.latency + self.retransmission_delay(bytes);
+            self.push_arrival(message, arrival);
         } else {
             self.update_bandwidth_queues(now);
+            if self.bandwidth_queues.is_empty() {
+                if let Some(tcp) = &mut self.tcp {
+                    tcp.start_sending(now);
+                }
+            }
+            // Draw the loss up front, so it doesn't depend on how often the connection is updated.
+            // Resent segments take up bandwidth like any others.
+            let bytes_lost = self.sample_lost_bytes(bytes);
             self.bandwidth_queues
                 .entry(miniprotocol)
                 .or_default()
-                .push_back((self.next_id, message), bytes);
+                .push_back((self.next_id, bytes_lost, message), bytes + bytes_lost);
             self.next_id += 1;
         }
     }
 
+    pub fn set_bandwidth(&mut self, bandwidth_bps: Option<u64>, now: Timestamp) {
+        // Anything sent before now was sent at the old bandwidth.
+        self.update_bandwidth_queues(now);
+        self.last_event = now;
+        self.bandwidth_bps = bandwidth_bps;
+        if !self.is_throttled() {
+            // Without a bandwidth limit, anything still in flight only has to wait for latency.
+            let mut in_flight: Vec<(u64, u64, TMessage)> = self
+                .bandwidth_queues
+                .drain()
+                .flat_map(|(_, queue)| queue.queue.into_iter().map(|(message, _)| message))
+                .collect();
+            in_flight.sort_by_key(|(id, _, _)| *id);
+            for (_, _, message) in in_flight {
+                self.push_arrival(message, now + self.latency);
+            }
+        }
+    }
+
     pub fn next_arrival_time(&self) -> Option<Timestamp> {
         if let Some((_, timestamp)) = self.latency_queue.front() {
             return Some(*timestamp);
@@ -97,37 +303,110 @@ where
             .values()
             .filter_map(|q| q.bytes_in_next_message())
             .min()?;
-        Some(
-            self.last_event
-                + compute_bandwidth_delay(
-                    self.bandwidth_bps?,
-                    self.bandwidth_queues.len() as u64,
-                    bytes_left,
-                )
-                + self.latency,
-        )
+        let departure = self.last_event
+            + compute_bandwidth_delay(
+                self.current_bps()?,
+                self.bandwidth_queues.len() as u64,
+                bytes_left,
+            );
+        if let Some(tcp) = &self.tcp {
+            if departure > tcp.round_end {
+                // The window changes when this round ends, so check back then.
+                return Some(tcp.round_end);
+            }
+        }
+        Some(departure + self.latency)
+    }
+
+    /// When the next message will finish being sent, and start waiting out the latency.
+    pub fn next_departure_time(&self) -> Option<Timestamp> {
+        let bytes_left = self
+            .bandwidth_queues
+            .values()
+            .filter_map(|q| q.bytes_in_next_message())
+            .min()?;
+        let bytes = bytes_left * 1_000_000 * self.bandwidth_queues.len() as u64;
+        // Round up, so that the message has really been sent by then.
+        let delay = Duration::from_micros(bytes.div_ceil(self.current_bps()?));
+        let departure = self.last_event + delay;
+        match &self.tcp {
+            Some(tcp) => Some(departure.min(tcp.round_end)),
+            None => Some(departure),
+        }
+    }
+
+    /// Whether any messages are still waiting to be sent over this connection.
+    pub fn is_sending(&self) -> bool {
+        !self.bandwidth_queues.is_empty()
     }
 
     pub fn recv_many(&mut self, now: Timestamp) -> Vec<(TMessage, Timestamp)> {
+        self.recv_while(now, |arrival| arrival <= now)
+    }
+
+    /// Sends whatever can be sent by `now`, then returns messages in the order they arrive
+    /// for as long as their arrival times pass the check. Later arrivals are left where they are.
+    pub fn recv_while(
+        &mut self,
+        now: Timestamp,
+        arrived: impl Fn(Timestamp) -> bool,
+    ) -> Vec<(TMessage, Timestamp)> {
         self.update_bandwidth_queues(now);
         let mut results = vec![];
-        while self.latency_queue.front().is_some_and(|(_, t)| t <= &now) {
+        while self.latency_queue.front().is_some_and(|(_, t)| arrived(*t)) {
             results.push(self.latency_queue.pop_front().unwrap());
         }
         results
     }
 
-    fn update_bandwidth_queues(&mut self, now: Timestamp) {
-        let Some(total_bps) = self.bandwidth_bps else {
-            return;
-        };
+    // Whether messages have to wait their turn to be sent.
+    fn is_throttled(&self) -> bool {
+        self.bandwidth_bps.is_some() || self.tcp.is_some()
+    }
+
+    fn current_bps(&self) -> Option<u64> {
+        let window_bps = self.tcp.as_ref().map(|tcp| tcp.window_bps());
+        match (self.bandwidth_bps, window_bps) {
+            (Some(bandwidth), Some(window)) => Some(bandwidth.min(window)),
+            (bandwidth, window) => bandwidth.or(window),
+        }
+    }
 
-        if self.last_event == now {
+    fn update_bandwidth_queues(&mut self, now: Timestamp) {
+        if !self.is_throttled() {
             return;
         }
+        while self.last_event < now {
+            if self.bandwidth_queues.is_empty() {
+                self.last_event = now;
+                break;
+            }
+            let until = match &self.tcp {
+                Some(tcp) => tcp.round_end.min(now),
+                None => now,
+            };
+            self.consume_bandwidth(until);
+            if let Some(tcp) = &mut self.tcp {
+                if until == tcp.round_end {
+                    tcp.finish_round();
+                }
+            }
+        }
+    }
+
+    fn consume_bandwidth(&mut self, until: Timestamp) {
+        let total_bps = self.current_bps().unwrap();
 
-        let mut bytes_to_consume =
-            (now - self.last_event).as_micros() as u64 * total_bps / 1_000_000;
+        let bytes_available = (until - self.last_event).as_micros() as u64 * total_bps / 1_000_000;
+        let bytes_queued = self.bandwidth_queues.values().map(|q| q.bytes()).sum();
+        let bytes_sent = bytes_available.min(bytes_queued);
+        if let Some(tcp) = &mut self.tcp {
+            tcp.bytes_sent_in_round += bytes_sent;
+        }
+        // Tracks when the bytes we've handled so far finished sending.
+        let mut sent_until = self.last_event;
+        let mut bytes_to_consume = bytes_sent;
+        let mut lost_in_round = false;
 
         let mut messages_received = vec![];
         while bytes_to_consume > 0 && !self.bandwidth_queues.is_empty() {
@@ -142,9 +421,10 @@ where
                 };
                 for (message, size) in queue.consume(bytes_to_consume_next) {
                     bytes_consumed += size;
+                    lost_in_round |= message.1 > 0;
                     messages_received.push((
                         message,
-                        self.last_event
+                        sent_until
                             + compute_bandwidth_delay(total_bps, queues, bytes_consumed)
                             + self.latency,
                     ));
@@ -152,14 +432,64 @@ where
                 bytes_to_consume -= bytes_to_consume_next;
                 !queue.is_empty()
             });
-            self.last_event += compute_bandwidth_delay(total_bps, 1, total_bytes_consumed);
+            sent_until += compute_bandwidth_delay(total_bps, 1, total_bytes_consumed);
         }
-        messages_received.sort_by_key(|((id, _), ts)| (*ts, *id));
-        for ((_, message), arrival) in messages_received {
-            self.latency_queue.push_back((message, arrival));
+        messages_received.sort_by_key(|((id, _, _), ts)| (*ts, *id));
+        for ((_, _, message), arrival) in messages_received {
+            self.push_arrival(message, arrival);
         }
 
-        self.last_event = now;
+        if let Some(tcp) = &mut self.tcp {
+            tcp.lost_in_round |= lost_in_round;
+            if self.bandwidth_queues.is_empty() {
+                tcp.idle_since = Some(sent_until);
+            }
+        }
+        self.last_event = until;
+    }
+
+    fn push_arrival(&mut self, message: TMessage, arrival: Timestamp) {
+        let mut arrival = arrival;
+        if let Some(jitter) = &self.latency_jitter {
+            let jitter_ms = jitter.sample(&mut self.rng).max(0.0);
+            arrival += Duration::from_secs_f64(jitter_ms / 1000.0);
+        }
+        // Messages share a single stream, so they can't overtake each other.
+        arrival = arrival.max(self.last_arrival);
+        self.last_arrival = arrival;
+        self.latency_queue.push_back((message, arrival));
+    }
+
+    fn sample_lost_bytes(&mut self, bytes: u64) -> u64 {
+        self.sample_losses(bytes).0
+    }
+
+    // Returns how many bytes were resent, and over how many rounds of resending.
+    fn sample_losses(&mut self, bytes: u64) -> (u64, u32) {
+        if self.loss_rate == 0.0 || bytes == 0 {
+            return (0, 0);
+        }
+        // Resent segments can get lost too, so keep resending until everything gets through.
+        let mut segments = bytes.div_ceil(SEGMENT_SIZE);
+        let mut lost_bytes = 0;
+        let mut rounds = 0;
+        loop {
+            segments = Binomial::new(segments, self.loss_rate)
+                .unwrap()
+                .sample(&mut self.rng);
+            if segments == 0 {
+                return (lost_bytes, rounds);
+            }
+            lost_bytes += (segments * SEGMENT_SIZE).min(bytes);
+            rounds += 1;
+        }
+    }
+
+    // On an unthrottled link, lost segments are resent once the sender notices, a round trip later.
+    // Every round of resending can lose segments again, and costs another round trip.
+    fn retransmission_delay(&mut self, bytes: u64) -> Duration {
+        let (_, rounds) = self.sample_losses(bytes.max(1));
+        self.latency * 2 * rounds
     }
 
     fn split_bytes_amongst_queues(&self, bytes: u64) -> HashMap<TProtocol, u64> {
@@ -202,9 +532,11 @@ fn compute_bandwidth_delay(total_bps: u64, split: u64, bytes: u64) -> Duration {
 mod tests {
     use std::time::Duration;
 
-    use crate::clock::Timestamp;
+    use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+
+    use crate::{clock::Timestamp, probability::FloatDistribution};
 
-    use super::Connection;
+    use super::{Connection, LinkConditions};
 
     #[derive(Clone, PartialEq, Eq, Hash)]
     enum MiniProtocol {
@@ -500,6 +832,55 @@ mod tests {
         assert_eq!(conn.next_arrival_time(), None);
     }
 
+    #[test]
+    fn should_respect_bandwidth_changes_for_messages_in_flight() {
+        let latency = Duration::ZERO;
+        let bandwidth_bps = Some(1000);
+        let mut conn = Connection::new(latency, bandwidth_bps);
+        assert_eq!(conn.next_arrival_time(), None);
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 1000, MiniProtocol::One, start);
+        assert_eq!(
+            conn.next_arrival_time(),
+            Some(start + Duration::from_secs(1))
+        );
+
+        let change_time = start + Duration::from_millis(500);
+        conn.set_bandwidth(Some(500), change_time);
+
+        let arrival_time = change_time + Duration::from_secs(1);
+        assert_eq!(conn.next_arrival_time(), Some(arrival_time));
+        assert_eq!(
+            conn.recv_many(arrival_time),
+            vec![("message 1", arrival_time)]
+        );
+        assert_eq!(conn.next_arrival_time(), None);
+    }
+
+    #[test]
+    fn should_release_messages_in_flight_when_bandwidth_is_unlimited() {
+        let latency = Duration::from_millis(100);
+        let bandwidth_bps = Some(1000);
+        let mut conn = Connection::new(latency, bandwidth_bps);
+        assert_eq!(conn.next_arrival_time(), None);
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 1000, MiniProtocol::One, start);
+        conn.send("message 2", 1000, MiniProtocol::Two, start);
+
+        let change_time = start + Duration::from_millis(500);
+        conn.set_bandwidth(None, change_time);
+
+        let arrival_time = change_time + latency;
+        assert_eq!(conn.next_arrival_time(), Some(arrival_time));
+        assert_eq!(
+            conn.recv_many(arrival_time),
+            vec![("message 1", arrival_time), ("message 2", arrival_time)]
+        );
+        assert_eq!(conn.next_arrival_time(), None);
+    }
+
     #[test]
     fn should_accept_timestamps_from_later_than_next_event() {
         let latency = Duration::ZERO;
@@ -526,4 +907,216 @@ mod tests {
         );
         assert_eq!(conn.next_arrival_time(), None);
     }
+
+    #[test]
+    fn should_finish_sending_before_latency_elapses() {
+        let latency = Duration::from_millis(500);
+        let bandwidth_bps = Some(1000);
+        let mut conn = Connection::new(latency, bandwidth_bps);
+        assert_eq!(conn.next_departure_time(), None);
+        assert!(!conn.is_sending());
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 1000, MiniProtocol::One, start);
+        assert!(conn.is_sending());
+
+        let departure_time = start + Duration::from_secs(1);
+        let arrival_time = departure_time + latency;
+        assert_eq!(conn.next_departure_time(), Some(departure_time));
+        assert_eq!(conn.next_arrival_time(), Some(arrival_time));
+
+        assert_eq!(conn.recv_many(departure_time), vec![]);
+        assert!(!conn.is_sending());
+        assert_eq!(conn.next_departure_time(), None);
+        assert_eq!(
+            conn.recv_many(arrival_time),
+            vec![("message 1", arrival_time)]
+        );
+    }
+
+    #[test]
+    fn should_return_future_arrivals_once_sent() {
+        let latency = Duration::from_millis(500);
+        let bandwidth_bps = Some(1000);
+        let mut conn = Connection::new(latency, bandwidth_bps);
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 1000, MiniProtocol::One, start);
+        conn.send("message 2", 1000, MiniProtocol::One, start);
+
+        let first_departure = start + Duration::from_secs(1);
+        let first_arrival = first_departure + latency;
+        let horizon = first_arrival + latency;
+        // Until a message has been sent, we can't know when it will arrive.
+        assert_eq!(conn.recv_while(start, |t| t < horizon), vec![]);
+        assert_eq!(
+            conn.recv_while(first_departure, |t| t < horizon),
+            vec![("message 1", first_arrival)]
+        );
+        assert!(conn.is_sending());
+    }
+
+    fn recv_next<T>(conn: &mut Connection<MiniProtocol, T>) -> Vec<(T, Timestamp)> {
+        // TCP may need to wake up a few times before anything arrives.
+        loop {
+            let now = conn.next_arrival_time().unwrap();
+            let messages = conn.recv_many(now);
+            if !messages.is_empty() {
+                return messages;
+            }
+        }
+    }
+
+    #[test]
+    fn should_add_jitter_without_reordering_messages() {
+        let latency = Duration::from_millis(10);
+        let conditions = LinkConditions {
+            latency_jitter: Some(FloatDistribution::constant(5.0)),
+            ..LinkConditions::default()
+        };
+        let mut conn =
+            Connection::new(latency, None).with_conditions(conditions, ChaChaRng::seed_from_u64(0));
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 8, MiniProtocol::One, start);
+        conn.send("message 2", 8, MiniProtocol::Two, start);
+
+        let arrival_time = start + Duration::from_millis(15);
+        assert_eq!(conn.next_arrival_time(), Some(arrival_time));
+        assert_eq!(
+            conn.recv_many(arrival_time),
+            vec![("message 1", arrival_time), ("message 2", arrival_time)]
+        );
+    }
+
+    #[test]
+    fn should_grow_congestion_window_during_slow_start() {
+        let latency = Duration::from_millis(50);
+        let conditions = LinkConditions {
+            tcp_congestion_control: true,
+            ..LinkConditions::default()
+        };
+        let mut conn =
+            Connection::new(latency, None).with_conditions(conditions, ChaChaRng::seed_from_u64(0));
+
+        // The first round trip sends 10 segments, and the second sends 20.
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 30 * 1460, MiniProtocol::One, start);
+
+        let arrival_time = start + Duration::from_millis(250);
+        assert_eq!(recv_next(&mut conn), vec![("message 1", arrival_time)]);
+        assert_eq!(conn.next_arrival_time(), None);
+    }
+
+    #[test]
+    fn should_not_let_tcp_exceed_bandwidth() {
+        let latency = Duration::from_millis(50);
+        let bandwidth_bps = Some(1460);
+        let conditions = LinkConditions {
+            tcp_congestion_control: true,
+            ..LinkConditions::default()
+        };
+        let mut conn = Connection::new(latency, bandwidth_bps)
+            .with_conditions(conditions, ChaChaRng::seed_from_u64(0));
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 1460, MiniProtocol::One, start);
+
+        let arrival_time = start + Duration::from_secs(1) + latency;
+        assert_eq!(recv_next(&mut conn), vec![("message 1", arrival_time)]);
+    }
+
+    #[test]
+    fn should_restart_slow_start_after_idling() {
+        let latency = Duration::from_millis(50);
+        let conditions = LinkConditions {
+            tcp_congestion_control: true,
+            ..LinkConditions::default()
+        };
+        let mut conn =
+            Connection::new(latency, None).with_conditions(conditions, ChaChaRng::seed_from_u64(0));
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 30 * 1460, MiniProtocol::One, start);
+        recv_next(&mut conn);
+
+        // Long after the connection went quiet, it has to start slow again.
+        let restart = start + Duration::from_secs(10);
+        conn.send("message 2", 30 * 1460, MiniProtocol::One, restart);
+        let arrival_time = restart + Duration::from_millis(250);
+        assert_eq!(recv_next(&mut conn), vec![("message 2", arrival_time)]);
+    }
+
+    #[test]
+    fn should_retransmit_lost_segments() {
+        let latency = Duration::from_millis(50);
+        let bandwidth_bps = Some(1000);
+        let conditions = LinkConditions {
+            loss_rate: 0.5,
+            ..LinkConditions::default()
+        };
+        let mut conn = Connection::new(latency, bandwidth_bps)
+            .with_conditions(conditions, ChaChaRng::seed_from_u64(0));
+
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        conn.send("message 1", 100_000, MiniProtocol::One, start);
+
+        // Without any loss, this would have arrived in 100 seconds.
+        let (message, arrival_time) = recv_next(&mut conn).pop().unwrap();
+        assert_eq!(message, "message 1");
+        assert!(arrival_time > start + Duration::from_secs(150));
+    }
+
+    #[test]
+    fn should_wait_a_round_trip_per_round_of_retransmission_when_unthrottled() {
+        let latency = Duration::from_millis(50);
+        let conditions = LinkConditions {
+            loss_rate: 0.9,
+            ..LinkConditions::default()
+        };
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        let round_trips = (0..10).map(|seed| {
+            let mut conn = Connection::new(latency, None)
+                .with_conditions(conditions.clone(), ChaChaRng::seed_from_u64(seed));
+            conn.send("message 1", 100, MiniProtocol::One, start);
+            let (_, arrival_time) = recv_next(&mut conn).pop().unwrap();
+            let delay = arrival_time - (start + latency);
+            // Every round of resending the one segment waits a whole round trip.
+            assert_eq!(delay.as_micros() % (latency * 2).as_micros(), 0);
+            delay.as_micros() / (latency * 2).as_micros()
+        });
+
+        // The segment is usually lost several times before it gets through.
+        assert!(round_trips.max().unwrap() > 1);
+    }
+
+    #[test]
+    fn should_lose_the_same_segments_however_often_the_link_is_checked() {
+        let latency = Duration::from_millis(50);
+        let bandwidth_bps = Some(1000);
+        let conditions = LinkConditions {
+            loss_rate: 0.5,
+            ..LinkConditions::default()
+        };
+        let start = Timestamp::zero() + Duration::from_secs(1);
+        let arrival_time = |step: Option<Duration>| {
+            let mut conn = Connection::new(latency, bandwidth_bps)
+                .with_conditions(conditions.clone(), ChaChaRng::seed_from_u64(0));
+            conn.send("message 1", 100_000, MiniProtocol::One, start);
+            let Some(step) = step else {
+                return recv_next(&mut conn).pop().unwrap().1;
+            };
+            let mut now = start;
+            loop {
+                now += step;
+                if let Some((_, arrival)) = conn.recv_many(now).pop() {
+                    return arrival;
+                }
+            }
+        };
+
+        let checked_once = arrival_time(None);
+        assert_eq!(arrival_time(Some(Duration::from_millis(700))), checked_once);
+        assert_eq!(arrival_time(Some(Duration::from_secs(3))), checked_once);
+    }
 }
@@ -1,43 +1,170 @@
-use std::{cmp::Reverse, collections::HashMap, fmt::Debug, hash::Hash, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    iter,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use priority_queue::PriorityQueue;
+use rand::Rng as _;
+use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
 use tokio::{select, sync::mpsc};
 
 use crate::{
+    checkpoint::{persist_struct, CheckpointReader, CheckpointRequest, CheckpointWriter, Persist},
     clock::{ClockBarrier, Timestamp},
     config::NodeId,
 };
 
-use super::connection::Connection;
+use super::{
+    connection::{Connection, LinkConditions},
+    EdgePolicy,
+};
 
 pub struct NetworkCoordinator<TProtocol, TMessage> {
     source: mpsc::UnboundedReceiver<Message<TProtocol, TMessage>>,
     sinks: HashMap<NodeId, mpsc::UnboundedSender<(NodeId, TMessage)>>,
     connections: HashMap<Link, Connection<TProtocol, TMessage>>,
-    events: PriorityQueue<Link, Reverse<Timestamp>>,
+    link_bandwidth: HashMap<Link, Option<u64>>,
+    /// Temporary bandwidth limits on each link, as (id, bandwidth). The newest one applies.
+    bandwidth_limits: HashMap<Link, Vec<(usize, Option<u64>)>>,
+    nics: HashMap<NodeId, Nic>,
+    /// When each link next has something to do. Links break ties, so that the order in which
+    /// simultaneous events happen doesn't depend on the shape of the queue.
+    events: PriorityQueue<Link, Reverse<(Timestamp, Link)>>,
+    changes: BTreeMap<Timestamp, Vec<NetworkChange>>,
+    offline_nodes: HashMap<NodeId, usize>,
+    partitions: BTreeMap<usize, HashMap<NodeId, usize>>,
+    rng: ChaChaRng,
+    checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct Link {
     from: NodeId,
     to: NodeId,
 }
 
+persist_struct!(Link { from, to });
+
+pub enum NetworkChange {
+    DisconnectNode(NodeId),
+    ReconnectNode(NodeId),
+    StartPartition {
+        id: usize,
+        groups: Vec<Vec<NodeId>>,
+    },
+    EndPartition {
+        id: usize,
+    },
+    LimitBandwidth {
+        id: usize,
+        from: NodeId,
+        to: NodeId,
+        bandwidth_bps: Option<u64>,
+    },
+    RestoreBandwidth {
+        id: usize,
+        from: NodeId,
+        to: NodeId,
+    },
+}
+
+/// The network card of a node, which shares its capacity between every connection currently using it.
+#[derive(Default)]
+struct Nic {
+    upload_bps: Option<u64>,
+    download_bps: Option<u64>,
+    uploading_to: HashSet<NodeId>,
+    downloading_from: HashSet<NodeId>,
+}
+
+persist_struct!(Nic {
+    upload_bps,
+    download_bps,
+    uploading_to,
+    downloading_from,
+});
+
+impl Nic {
+    fn upload_share(&self, to: NodeId, link_cap: impl Fn(NodeId) -> Option<u64>) -> Option<u64> {
+        self.upload_bps
+            .map(|bps| fair_share(bps, &self.uploading_to, to, link_cap))
+    }
+
+    fn download_share(
+        &self,
+        from: NodeId,
+        link_cap: impl Fn(NodeId) -> Option<u64>,
+    ) -> Option<u64> {
+        self.download_bps
+            .map(|bps| fair_share(bps, &self.downloading_from, from, link_cap))
+    }
+}
+
+/// Splits a NIC's capacity max-min fairly (by water-filling) between `peers` and `peer`.
+/// Connections capped below an equal share keep their cap, and the rest split what's left over.
+fn fair_share(
+    capacity: u64,
+    peers: &HashSet<NodeId>,
+    peer: NodeId,
+    link_cap: impl Fn(NodeId) -> Option<u64>,
+) -> u64 {
+    let mut caps: Vec<(Option<u64>, NodeId)> = peers
+        .iter()
+        .copied()
+        .filter(|&other| other != peer)
+        .chain(iter::once(peer))
+        .map(|node| (link_cap(node), node))
+        .collect();
+    // The most tightly capped connections go first, and uncapped ones go last.
+    caps.sort_by_key(|(cap, node)| (cap.is_none(), *cap, *node));
+    let mut capacity_left = capacity;
+    let mut connections_left = caps.len() as u64;
+    for (cap, node) in caps {
+        let equal_share = (capacity_left / connections_left).max(1);
+        let share = cap.map_or(equal_share, |cap| cap.min(equal_share));
+        if node == peer {
+            return share;
+        }
+        capacity_left = capacity_left.saturating_sub(share);
+        connections_left -= 1;
+    }
+    unreachable!("the peer is always one of the connections")
+}
+
 pub struct EdgeConfig {
     pub from: NodeId,
     pub to: NodeId,
-    pub latency: Duration,
-    pub bandwidth_bps: Option<u64>,
+    pub policy: EdgePolicy,
 }
 
-impl<TProtocol: Clone + Eq + Hash, TMessage: Debug> NetworkCoordinator<TProtocol, TMessage> {
-    pub fn new(source: mpsc::UnboundedReceiver<Message<TProtocol, TMessage>>) -> Self {
+impl<TProtocol: Clone + Eq + Hash + Persist, TMessage: Debug + Persist>
+    NetworkCoordinator<TProtocol, TMessage>
+{
+    pub fn new(
+        source: mpsc::UnboundedReceiver<Message<TProtocol, TMessage>>,
+        seed: u64,
+        checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
+    ) -> Self {
+        let mut rng = ChaChaRng::seed_from_u64(seed);
+        // Keep the network's randomness separate from the nodes', which use the same seed.
+        rng.set_stream(1);
         Self {
             source,
             sinks: HashMap::new(),
             connections: HashMap::new(),
+            link_bandwidth: HashMap::new(),
+            bandwidth_limits: HashMap::new(),
+            nics: HashMap::new(),
             events: PriorityQueue::new(),
+            changes: BTreeMap::new(),
+            offline_nodes: HashMap::new(),
+            partitions: BTreeMap::new(),
+            rng,
+            checkpoint_source,
         }
     }
 
@@ -52,39 +179,242 @@ impl<TProtocol: Clone + Eq + Hash, TMessage: Debug> NetworkCoordinator<TProtocol
             from: config.from,
             to: config.to,
         };
-        let connection = Connection::new(config.latency, config.bandwidth_bps);
-        self.connections.insert(link, connection);
+        let policy = config.policy;
+        let conditions = LinkConditions {
+            loss_rate: policy.loss_rate,
+            latency_jitter: policy.latency_jitter,
+            tcp_congestion_control: policy.tcp_congestion_control,
+        };
+        let rng = ChaChaRng::seed_from_u64(self.rng.random());
+        let connection =
+            Connection::new(policy.latency, policy.bandwidth_bps).with_conditions(conditions, rng);
+        self.connections.insert(link.clone(), connection);
+        self.link_bandwidth.insert(link, policy.bandwidth_bps);
+    }
+
+    pub fn set_node_bandwidth(
+        &mut self,
+        node: NodeId,
+        upload_bps: Option<u64>,
+        download_bps: Option<u64>,
+    ) {
+        if upload_bps.is_none() && download_bps.is_none() {
+            self.nics.remove(&node);
+            return;
+        }
+        self.nics.insert(
+            node,
+            Nic {
+                upload_bps,
+                download_bps,
+                ..Nic::default()
+            },
+        );
+    }
+
+    pub fn schedule_change(&mut self, at: Timestamp, change: NetworkChange) {
+        self.changes.entry(at).or_default().push(change);
     }
 
     pub async fn run(&mut self, clock: &mut ClockBarrier) -> Result<()> {
+        let mut deliveries = vec![];
         loop {
-            let waiter = match self.events.peek() {
-                Some((_, Reverse(timestamp))) => clock.wait_until(*timestamp),
+            let waiter = match self.next_event_time() {
+                Some(timestamp) => clock.wait_until(timestamp),
                 None => clock.wait_forever(),
             };
             select! {
                 () = waiter => {
-                    let (link, Reverse(timestamp)) = self.events.pop().unwrap();
-                    assert!(clock.now() >= timestamp);
-                    let connection = self.connections.get_mut(&link).unwrap();
-                    for (body, _) in connection.recv_many(timestamp) {
+                    self.handle_next_event(clock.now(), &mut deliveries);
+                    for (from, to, body) in deliveries.drain(..) {
                         clock.start_task();
-                        let _ = self
-                            .sinks
-                            .get(&link.to)
-                            .unwrap()
-                            .send((link.from, body));
-                    };
-                    if let Some(timestamp) = connection.next_arrival_time() {
-                        self.events.push(link, Reverse(timestamp));
+                        let _ = self.sinks.get(&to).unwrap().send((from, body));
                     }
                 },
                 Some(message) = self.source.recv() => {
                     self.schedule_message(message, clock.now());
                     clock.finish_task();
                 }
+                Some((mut writer, reply)) = self.checkpoint_source.recv() => {
+                    self.checkpoint(&mut writer);
+                    let _ = reply.send(writer);
+                }
+            }
+        }
+    }
+
+    /// When the network next has something to do, if it ever will.
+    pub fn next_event_time(&self) -> Option<Timestamp> {
+        let next_event = self
+            .events
+            .peek()
+            .map(|(_, Reverse((timestamp, _)))| *timestamp);
+        let next_change = self
+            .changes
+            .first_key_value()
+            .map(|(timestamp, _)| *timestamp);
+        next_event.into_iter().chain(next_change).min()
+    }
+
+    /// Applies any network changes which are due, then handles the next event on any link if it is due.
+    /// Messages which arrive are added to `deliveries` as (from, to, body).
+    pub fn handle_next_event(
+        &mut self,
+        now: Timestamp,
+        deliveries: &mut Vec<(NodeId, NodeId, TMessage)>,
+    ) {
+        while let Some(entry) = self.changes.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            for change in entry.remove() {
+                self.apply_change(change, now);
+            }
+        }
+        if self
+            .events
+            .peek()
+            .is_none_or(|(_, Reverse((timestamp, _)))| *timestamp > now)
+        {
+            return;
+        }
+        let (link, Reverse((timestamp, _))) = self.events.pop().unwrap();
+        let blocked = self.is_blocked(&link);
+        let connection = self.connections.get_mut(&link).unwrap();
+        for (body, _) in connection.recv_many(timestamp) {
+            if blocked {
+                // This message was lost to an outage or a partition.
+                continue;
+            }
+            deliveries.push((link.from, link.to, body));
+        }
+        if !connection.is_sending() && self.is_transmitting(&link) {
+            // This connection is done with the NIC, so its peers can have its share.
+            self.set_transmitting(&link, false, now);
+        }
+        self.schedule_next_event(link, now);
+    }
+
+    /// Sends every message which nodes have passed to the network since the last call.
+    pub fn accept_messages(&mut self, now: Timestamp) {
+        while let Ok(message) = self.source.try_recv() {
+            self.schedule_message(message, now);
+        }
+    }
+
+    /// Handles everything which happens before `end`.
+    pub fn handle_events_before(
+        &mut self,
+        end: Timestamp,
+        deliveries: &mut Vec<(NodeId, NodeId, TMessage)>,
+    ) {
+        while let Some(timestamp) = self.next_event_time().filter(|t| *t < end) {
+            self.handle_next_event(timestamp, deliveries);
+        }
+    }
+
+    /// Starts sending a message at the given time, after everything which happens before then.
+    pub fn send_at(
+        &mut self,
+        message: Message<TProtocol, TMessage>,
+        now: Timestamp,
+        deliveries: &mut Vec<(NodeId, NodeId, TMessage)>,
+    ) {
+        while let Some(timestamp) = self.next_event_time().filter(|t| *t <= now) {
+            self.handle_next_event(timestamp, deliveries);
+        }
+        self.schedule_message(message, now);
+    }
+
+    /// Collects every message which arrives before the time when `arrived` first returns false.
+    /// Only messages which have already been sent by `now` are collected, so this is only complete
+    /// if every message arriving that early was sent by `now`.
+    /// Messages are added to `arrivals` as (arrival time, from, to, body).
+    pub fn collect_arrivals(
+        &mut self,
+        now: Timestamp,
+        arrived: impl Fn(Timestamp) -> bool,
+        arrivals: &mut Vec<(Timestamp, NodeId, NodeId, TMessage)>,
+    ) {
+        while let Some(entry) = self.changes.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            for change in entry.remove() {
+                self.apply_change(change, now);
+            }
+        }
+        // The next event on each link is never later than its next arrival.
+        let mut links = vec![];
+        while self
+            .events
+            .peek()
+            .is_some_and(|(_, Reverse((timestamp, _)))| arrived(*timestamp))
+        {
+            links.push(self.events.pop().unwrap().0);
+        }
+        for link in links {
+            let connection = self.connections.get_mut(&link).unwrap();
+            for (body, timestamp) in connection.recv_while(now, &arrived) {
+                if self.is_blocked_at(&link, timestamp) {
+                    // This message will be lost to an outage or a partition.
+                    continue;
+                }
+                arrivals.push((timestamp, link.from, link.to, body));
+            }
+            let connection = self.connections.get(&link).unwrap();
+            if !connection.is_sending() && self.is_transmitting(&link) {
+                self.set_transmitting(&link, false, now);
             }
+            self.schedule_next_event(link, now);
+        }
+    }
+
+    pub fn checkpoint(&self, w: &mut CheckpointWriter) {
+        // Each connection's configuration comes from the topology, so only save what it's doing
+        self.connections.len().save(w);
+        for (link, connection) in &self.connections {
+            link.save(w);
+            connection.checkpoint(w);
+        }
+        self.link_bandwidth.save(w);
+        self.bandwidth_limits.save(w);
+        self.nics.save(w);
+        self.events.save(w);
+        self.offline_nodes.save(w);
+        self.partitions.save(w);
+        self.rng.save(w);
+    }
+
+    pub fn restore(&mut self, r: &mut CheckpointReader, now: Timestamp) -> Result<()> {
+        let links = usize::load(r)?;
+        if links != self.connections.len() {
+            bail!(
+                "checkpoint has {links} connections, but the topology has {}",
+                self.connections.len()
+            );
+        }
+        for _ in 0..links {
+            let link = Link::load(r)?;
+            let Some(connection) = self.connections.get_mut(&link) else {
+                bail!(
+                    "checkpoint has a connection from node {} to node {}, which is not in the topology",
+                    link.from,
+                    link.to
+                );
+            };
+            connection.restore(r)?;
         }
+        self.link_bandwidth = Persist::load(r)?;
+        self.bandwidth_limits = Persist::load(r)?;
+        self.nics = Persist::load(r)?;
+        self.events = Persist::load(r)?;
+        self.offline_nodes = Persist::load(r)?;
+        self.partitions = Persist::load(r)?;
+        self.rng = Persist::load(r)?;
+        // Anything scheduled up to the checkpoint has already happened
+        self.changes.retain(|timestamp, _| *timestamp > now);
+        Ok(())
     }
 
     fn schedule_message(&mut self, message: Message<TProtocol, TMessage>, now: Timestamp) {
@@ -92,12 +422,234 @@ impl<TProtocol: Clone + Eq + Hash, TMessage: Debug> NetworkCoordinator<TProtocol
             from: message.from,
             to: message.to,
         };
+        if self.is_blocked(&link) {
+            // Nobody on the other end to hear this message.
+            return;
+        }
+        if self.uses_nic(&link) && !self.is_transmitting(&link) {
+            self.set_transmitting(&link, true, now);
+        }
         let connection = self.connections.get_mut(&link).unwrap();
         connection.send(message.body, message.bytes, message.protocol, now);
-        if let Some(timestamp) = connection.next_arrival_time() {
-            self.events.push(link, Reverse(timestamp));
+        self.schedule_next_event(link, now);
+    }
+
+    fn schedule_next_event(&mut self, link: Link, now: Timestamp) {
+        let connection = self.connections.get(&link).unwrap();
+        let mut next_event = connection.next_arrival_time();
+        if self.is_transmitting(&link) {
+            // We need to know when this connection stops sending, to free up its share of the NIC.
+            let departure = connection.next_departure_time().unwrap_or(now);
+            next_event = Some(next_event.map_or(departure, |arrival| arrival.min(departure)));
+        }
+        if let Some(timestamp) = next_event {
+            self.events.push(link.clone(), Reverse((timestamp, link)));
         }
     }
+
+    fn uses_nic(&self, link: &Link) -> bool {
+        let uploads = self
+            .nics
+            .get(&link.from)
+            .is_some_and(|n| n.upload_bps.is_some());
+        let downloads = self
+            .nics
+            .get(&link.to)
+            .is_some_and(|n| n.download_bps.is_some());
+        uploads || downloads
+    }
+
+    fn is_transmitting(&self, link: &Link) -> bool {
+        self.nics
+            .get(&link.from)
+            .is_some_and(|n| n.uploading_to.contains(&link.to))
+            || self
+                .nics
+                .get(&link.to)
+                .is_some_and(|n| n.downloading_from.contains(&link.from))
+    }
+
+    fn set_transmitting(&mut self, link: &Link, transmitting: bool, now: Timestamp) {
+        if let Some(nic) = self.nics.get_mut(&link.from) {
+            if transmitting {
+                nic.uploading_to.insert(link.to);
+            } else {
+                nic.uploading_to.remove(&link.to);
+            }
+        }
+        if let Some(nic) = self.nics.get_mut(&link.to) {
+            if transmitting {
+                nic.downloading_from.insert(link.from);
+            } else {
+                nic.downloading_from.remove(&link.from);
+            }
+        }
+        // Every connection sharing a NIC with this one now gets a different share of it.
+        for link in self.links_sharing_nics(link) {
+            self.update_bandwidth(link, now);
+        }
+    }
+
+    /// Every connection currently sending through either of the NICs this link uses.
+    fn links_sharing_nics(&self, link: &Link) -> Vec<Link> {
+        let mut links = vec![];
+        if let Some(nic) = self.nics.get(&link.from) {
+            links.extend(nic.uploading_to.iter().map(|&to| Link {
+                from: link.from,
+                to,
+            }));
+        }
+        if let Some(nic) = self.nics.get(&link.to) {
+            links.extend(
+                nic.downloading_from
+                    .iter()
+                    .map(|&from| Link { from, to: link.to }),
+            );
+        }
+        links
+    }
+
+    /// A new cap on one connection changes how the NICs it uses are shared out.
+    fn update_link_cap(&mut self, link: Link, now: Timestamp) {
+        let mut affected = self.links_sharing_nics(&link);
+        if !affected.contains(&link) {
+            affected.push(link);
+        }
+        for link in affected {
+            self.update_bandwidth(link, now);
+        }
+    }
+
+    /// The bandwidth of a link on its own, before sharing any NICs.
+    fn link_cap(&self, link: &Link) -> Option<u64> {
+        match self.bandwidth_limits.get(link).and_then(|l| l.last()) {
+            Some((_, limit)) => *limit,
+            None => *self.link_bandwidth.get(link).unwrap(),
+        }
+    }
+
+    fn update_bandwidth(&mut self, link: Link, now: Timestamp) {
+        let mut bandwidth_bps = self.link_cap(&link);
+        if let Some(nic) = self.nics.get(&link.from) {
+            let upload_share = nic.upload_share(link.to, |to| {
+                self.link_cap(&Link {
+                    from: link.from,
+                    to,
+                })
+            });
+            bandwidth_bps = min_bandwidth(bandwidth_bps, upload_share);
+        }
+        if let Some(nic) = self.nics.get(&link.to) {
+            let download_share =
+                nic.download_share(link.from, |from| self.link_cap(&Link { from, to: link.to }));
+            bandwidth_bps = min_bandwidth(bandwidth_bps, download_share);
+        }
+        let connection = self.connections.get_mut(&link).unwrap();
+        connection.set_bandwidth(bandwidth_bps, now);
+        self.schedule_next_event(link, now);
+    }
+
+    fn apply_change(&mut self, change: NetworkChange, now: Timestamp) {
+        match change {
+            NetworkChange::LimitBandwidth {
+                id,
+                from,
+                to,
+                bandwidth_bps,
+            } => {
+                let link = Link { from, to };
+                self.bandwidth_limits
+                    .entry(link.clone())
+                    .or_default()
+                    .push((id, bandwidth_bps));
+                self.update_link_cap(link, now);
+            }
+            NetworkChange::RestoreBandwidth { id, from, to } => {
+                let link = Link { from, to };
+                if let Some(limits) = self.bandwidth_limits.get_mut(&link) {
+                    limits.retain(|(limit_id, _)| *limit_id != id);
+                    if limits.is_empty() {
+                        self.bandwidth_limits.remove(&link);
+                    }
+                }
+                self.update_link_cap(link, now);
+            }
+            change => update_reachability(&mut self.offline_nodes, &mut self.partitions, &change),
+        }
+    }
+
+    fn is_blocked(&self, link: &Link) -> bool {
+        is_blocked(&self.offline_nodes, &self.partitions, link)
+    }
+
+    /// Whether a message on this link would be lost at the given time,
+    /// counting any outages or partitions which start or end before then.
+    fn is_blocked_at(&self, link: &Link, at: Timestamp) -> bool {
+        let mut upcoming = self.changes.range(..=at).flat_map(|(_, changes)| changes);
+        let Some(first) = upcoming.next() else {
+            return self.is_blocked(link);
+        };
+        let mut offline_nodes = self.offline_nodes.clone();
+        let mut partitions = self.partitions.clone();
+        for change in std::iter::once(first).chain(upcoming) {
+            update_reachability(&mut offline_nodes, &mut partitions, change);
+        }
+        is_blocked(&offline_nodes, &partitions, link)
+    }
+}
+
+fn update_reachability(
+    offline_nodes: &mut HashMap<NodeId, usize>,
+    partitions: &mut BTreeMap<usize, HashMap<NodeId, usize>>,
+    change: &NetworkChange,
+) {
+    match change {
+        NetworkChange::DisconnectNode(node) => {
+            *offline_nodes.entry(*node).or_default() += 1;
+        }
+        NetworkChange::ReconnectNode(node) => {
+            if let Some(outages) = offline_nodes.get_mut(node) {
+                *outages -= 1;
+                if *outages == 0 {
+                    offline_nodes.remove(node);
+                }
+            }
+        }
+        NetworkChange::StartPartition { id, groups } => {
+            let group_by_node = groups
+                .iter()
+                .enumerate()
+                .flat_map(|(group, nodes)| nodes.iter().map(move |node| (*node, group)))
+                .collect();
+            partitions.insert(*id, group_by_node);
+        }
+        NetworkChange::EndPartition { id } => {
+            partitions.remove(id);
+        }
+        NetworkChange::LimitBandwidth { .. } | NetworkChange::RestoreBandwidth { .. } => {}
+    }
+}
+
+fn is_blocked(
+    offline_nodes: &HashMap<NodeId, usize>,
+    partitions: &BTreeMap<usize, HashMap<NodeId, usize>>,
+    link: &Link,
+) -> bool {
+    if offline_nodes.contains_key(&link.from) || offline_nodes.contains_key(&link.to) {
+        return true;
+    }
+    partitions.values().any(|groups| {
+        let from_group = groups.get(&link.from);
+        let to_group = groups.get(&link.to);
+        from_group.is_some() && to_group.is_some() && from_group != to_group
+    })
+}
+
+fn min_bandwidth(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
 }
 
 pub struct Message<TProtocol, TMessage> {
@@ -107,3 +659,193 @@ pub struct Message<TProtocol, TMessage> {
     pub body: TMessage,
     pub bytes: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use rand::seq::SliceRandom as _;
+    use rand_chacha::{rand_core::SeedableRng as _, ChaChaRng};
+    use tokio::sync::mpsc;
+
+    use crate::{
+        checkpoint::{Checkpoint, CheckpointWriter},
+        clock::Timestamp,
+        config::NodeId,
+        network::EdgePolicy,
+    };
+
+    use super::{EdgeConfig, Message, NetworkChange, NetworkCoordinator};
+
+    /// A network where node 0 is connected to nodes 1 and 2.
+    fn coordinator(bandwidth_bps: Option<u64>) -> NetworkCoordinator<u8, u64> {
+        fan_out(2, bandwidth_bps)
+    }
+
+    /// A network where node 0 is connected to nodes 1 through `peers`.
+    fn fan_out(peers: usize, bandwidth_bps: Option<u64>) -> NetworkCoordinator<u8, u64> {
+        let (_, source) = mpsc::unbounded_channel();
+        let (_, checkpoint_source) = mpsc::unbounded_channel();
+        let mut coordinator = NetworkCoordinator::new(source, 0, checkpoint_source);
+        for to in 1..=peers {
+            coordinator.add_edge(EdgeConfig {
+                from: NodeId::new(0),
+                to: NodeId::new(to),
+                policy: EdgePolicy {
+                    latency: Duration::ZERO,
+                    bandwidth_bps,
+                    loss_rate: 0.0,
+                    latency_jitter: None,
+                    tcp_congestion_control: false,
+                },
+            });
+        }
+        coordinator
+    }
+
+    fn secs(secs: f64) -> Timestamp {
+        Timestamp::zero() + Duration::from_secs_f64(secs)
+    }
+
+    fn send(
+        coordinator: &mut NetworkCoordinator<u8, u64>,
+        to: usize,
+        body: u64,
+        bytes: u64,
+        at: Timestamp,
+    ) {
+        let message = Message {
+            from: NodeId::new(0),
+            to: NodeId::new(to),
+            protocol: 0,
+            body,
+            bytes,
+        };
+        coordinator.send_at(message, at, &mut vec![]);
+    }
+
+    /// Runs the network until it's idle, returning when each message arrived.
+    fn arrivals(coordinator: &mut NetworkCoordinator<u8, u64>) -> Vec<(u64, Timestamp)> {
+        let mut arrivals = vec![];
+        let mut deliveries = vec![];
+        while let Some(now) = coordinator.next_event_time() {
+            coordinator.handle_next_event(now, &mut deliveries);
+            arrivals.extend(deliveries.drain(..).map(|(_, _, body)| (body, now)));
+        }
+        arrivals
+    }
+
+    fn limit(id: usize, bandwidth_bps: Option<u64>) -> NetworkChange {
+        NetworkChange::LimitBandwidth {
+            id,
+            from: NodeId::new(0),
+            to: NodeId::new(1),
+            bandwidth_bps,
+        }
+    }
+
+    fn restore(id: usize) -> NetworkChange {
+        NetworkChange::RestoreBandwidth {
+            id,
+            from: NodeId::new(0),
+            to: NodeId::new(1),
+        }
+    }
+
+    #[test]
+    fn should_change_bandwidth_for_messages_in_flight() {
+        let mut coordinator = coordinator(Some(1000));
+        coordinator.schedule_change(secs(0.5), limit(0, Some(100)));
+        coordinator.schedule_change(secs(10.0), restore(0));
+        send(&mut coordinator, 1, 1, 1000, secs(0.0));
+        // Half the message is sent at full speed, then the rest is sent at a tenth of it.
+        assert_eq!(arrivals(&mut coordinator), vec![(1, secs(5.5))]);
+    }
+
+    #[test]
+    fn should_restore_overlapping_bandwidth_limits_in_order() {
+        let mut coordinator = coordinator(Some(1000));
+        coordinator.schedule_change(secs(0.0), limit(0, Some(100)));
+        coordinator.schedule_change(secs(2.0), limit(1, Some(10)));
+        coordinator.schedule_change(secs(4.0), restore(1));
+        coordinator.schedule_change(secs(10.0), restore(0));
+
+        // The outer limit still applies once the inner one is over.
+        send(&mut coordinator, 1, 1, 100, secs(5.0));
+        assert_eq!(arrivals(&mut coordinator), vec![(1, secs(6.0))]);
+
+        // Once both are over, the link is back to normal.
+        send(&mut coordinator, 1, 2, 100, secs(11.0));
+        assert_eq!(arrivals(&mut coordinator), vec![(2, secs(11.1))]);
+    }
+
+    #[test]
+    fn should_share_a_nic_between_connections() {
+        let mut coordinator = coordinator(None);
+        coordinator.set_node_bandwidth(NodeId::new(0), Some(1000), None);
+
+        // Both connections get half of the NIC until the first message is through,
+        // then the second one gets all of it.
+        send(&mut coordinator, 1, 1, 500, secs(0.0));
+        send(&mut coordinator, 2, 2, 1500, secs(0.0));
+        assert_eq!(
+            arrivals(&mut coordinator),
+            vec![(1, secs(1.0)), (2, secs(2.0))]
+        );
+
+        // A connection on its own gets the whole NIC.
+        send(&mut coordinator, 1, 3, 1000, secs(3.0));
+        assert_eq!(arrivals(&mut coordinator), vec![(3, secs(4.0))]);
+    }
+
+    #[test]
+    fn should_give_spare_nic_capacity_to_uncapped_connections() {
+        let mut coordinator = fan_out(3, None);
+        coordinator.set_node_bandwidth(NodeId::new(0), Some(1000), None);
+        coordinator.schedule_change(secs(0.0), limit(0, Some(100)));
+
+        // The capped link can only use 100 of its 333 bps share,
+        // so the other two split the remaining 900 bps between them.
+        send(&mut coordinator, 1, 1, 100, secs(0.0));
+        send(&mut coordinator, 2, 2, 450, secs(0.0));
+        send(&mut coordinator, 3, 3, 450, secs(0.0));
+        assert_eq!(
+            arrivals(&mut coordinator),
+            vec![(1, secs(1.0)), (2, secs(1.0)), (3, secs(1.0))]
+        );
+    }
+
+    #[test]
+    fn should_deliver_simultaneous_messages_in_the_same_order_after_a_checkpoint() -> Result<()> {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        for _ in 0..20 {
+            // Each link delivers one message at its own time, then another at the same time as
+            // every other link, so the queue has been reshuffled by the time they tie.
+            let mut peers: Vec<usize> = (1..=8).collect();
+            peers.shuffle(&mut rng);
+            let mut original = fan_out(8, Some(1000));
+            for (i, to) in peers.into_iter().enumerate() {
+                let bytes = 100 * (i as u64 + 1);
+                send(&mut original, to, 0, bytes, secs(0.0));
+                send(&mut original, to, to as u64, 1000 - bytes, secs(0.0));
+            }
+            original.handle_events_before(secs(1.0), &mut vec![]);
+            let mut writer = CheckpointWriter::new();
+            original.checkpoint(&mut writer);
+            let checkpoint = Checkpoint::new(secs(0.8), 0, writer);
+
+            let mut resumed = fan_out(8, Some(1000));
+            let mut reader = checkpoint.reader();
+            resumed.restore(&mut reader, secs(0.8))?;
+            reader.finish()?;
+
+            // Every message arrives at once, so only the tie-breaker decides their order.
+            let expected = arrivals(&mut original);
+            assert!(expected.iter().all(|(_, time)| *time == secs(1.0)));
+            assert!(expected.is_sorted());
+            assert_eq!(arrivals(&mut resumed), expected);
+        }
+        Ok(())
+    }
+}
@@ -1,27 +1,88 @@
-use rand::Rng;
-use rand_distr::{Distribution, Exp, LogNormal, Normal};
-use std::fmt::Debug;
+use anyhow::{bail, Result};
+use rand::{distr::weighted::WeightedIndex, Rng};
+use rand_distr::{Distribution, Exp, Gamma, LogNormal, Normal, Pareto, Uniform, Weibull};
+use std::{fmt::Debug, sync::Arc};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum FloatDistribution {
     Normal(Normal<f64>),
     ScaledExp(Exp<f64>, f64),
     LogNormal(LogNormal<f64>),
     Constant(Constant<f64>),
+    Uniform(Uniform<f64>),
+    Pareto(Pareto<f64>),
+    Weibull(Weibull<f64>),
+    Gamma(Gamma<f64>),
+    Mixture(Mixture),
+    Histogram(Histogram),
 }
 impl FloatDistribution {
-    pub fn normal(mean: f64, std_dev: f64) -> Self {
-        Self::Normal(Normal::new(mean, std_dev).unwrap())
+    pub fn normal(mean: f64, std_dev: f64) -> Result<Self> {
+        match Normal::new(mean, std_dev) {
+            Ok(d) => Ok(Self::Normal(d)),
+            Err(e) => bail!("invalid normal distribution (mean {mean}, std_dev {std_dev}): {e}"),
+        }
     }
-    pub fn scaled_exp(lambda: f64, scale: f64) -> Self {
-        Self::ScaledExp(Exp::new(lambda).unwrap(), scale)
+    pub fn scaled_exp(lambda: f64, scale: f64) -> Result<Self> {
+        match Exp::new(lambda) {
+            Ok(d) => Ok(Self::ScaledExp(d, scale)),
+            Err(e) => bail!("invalid exp distribution (lambda {lambda}): {e}"),
+        }
     }
-    pub fn log_normal(mu: f64, sigma: f64) -> Self {
-        Self::LogNormal(LogNormal::new(mu, sigma).unwrap())
+    pub fn log_normal(mu: f64, sigma: f64) -> Result<Self> {
+        match LogNormal::new(mu, sigma) {
+            Ok(d) => Ok(Self::LogNormal(d)),
+            Err(e) => bail!("invalid log-normal distribution (mu {mu}, sigma {sigma}): {e}"),
+        }
     }
     pub fn constant(value: f64) -> Self {
         Self::Constant(Constant::new(value))
     }
+    pub fn uniform(min: f64, max: f64) -> Result<Self> {
+        match Uniform::new_inclusive(min, max) {
+            Ok(d) => Ok(Self::Uniform(d)),
+            Err(e) => bail!("invalid uniform distribution (min {min}, max {max}): {e}"),
+        }
+    }
+    pub fn pareto(scale: f64, shape: f64) -> Result<Self> {
+        match Pareto::new(scale, shape) {
+            Ok(d) => Ok(Self::Pareto(d)),
+            Err(e) => bail!("invalid pareto distribution (scale {scale}, shape {shape}): {e}"),
+        }
+    }
+    pub fn weibull(scale: f64, shape: f64) -> Result<Self> {
+        match Weibull::new(scale, shape) {
+            Ok(d) => Ok(Self::Weibull(d)),
+            Err(e) => bail!("invalid weibull distribution (scale {scale}, shape {shape}): {e}"),
+        }
+    }
+    pub fn gamma(shape: f64, scale: f64) -> Result<Self> {
+        match Gamma::new(shape, scale) {
+            Ok(d) => Ok(Self::Gamma(d)),
+            Err(e) => bail!("invalid gamma distribution (shape {shape}, scale {scale}): {e}"),
+        }
+    }
+    pub fn mixture(components: impl IntoIterator<Item = (f64, FloatDistribution)>) -> Result<Self> {
+        let (weights, components): (Vec<_>, Vec<_>) = components.into_iter().unzip();
+        match WeightedIndex::new(weights) {
+            Ok(weights) => Ok(Self::Mixture(Mixture {
+                weights,
+                components: components.into(),
+            })),
+            Err(e) => bail!("invalid mixture weights: {e}"),
+        }
+    }
+    /// Each bin is a range of values, and a weight.
+    pub fn histogram(bins: impl IntoIterator<Item = ((f64, f64), f64)>) -> Result<Self> {
+        let (ranges, weights): (Vec<_>, Vec<_>) = bins.into_iter().unzip();
+        match WeightedIndex::new(weights) {
+            Ok(weights) => Ok(Self::Histogram(Histogram {
+                weights,
+                ranges: ranges.into(),
+            })),
+            Err(e) => bail!("invalid histogram weights: {e}"),
+        }
+    }
 }
 
 impl Distribution<f64> for FloatDistribution {
@@ -31,6 +92,44 @@ impl Distribution<f64> for FloatDistribution {
             Self::ScaledExp(d, scale) => d.sample(rng) * scale,
             Self::LogNormal(d) => d.sample(rng),
             Self::Constant(d) => d.sample(rng),
+            Self::Uniform(d) => d.sample(rng),
+            Self::Pareto(d) => d.sample(rng),
+            Self::Weibull(d) => d.sample(rng),
+            Self::Gamma(d) => d.sample(rng),
+            Self::Mixture(d) => d.sample(rng),
+            Self::Histogram(d) => d.sample(rng),
+        }
+    }
+}
+
+/// Picks one of several distributions by weight, and samples from that.
+#[derive(Debug, Clone)]
+pub struct Mixture {
+    weights: WeightedIndex<f64>,
+    components: Arc<[FloatDistribution]>,
+}
+
+impl Distribution<f64> for Mixture {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        self.components[self.weights.sample(rng)].sample(rng)
+    }
+}
+
+/// Picks a bin by weight, and a value uniformly from within that bin.
+/// A bin with no width always gives the same value, so a list of samples is a histogram too.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    weights: WeightedIndex<f64>,
+    ranges: Arc<[(f64, f64)]>,
+}
+
+impl Distribution<f64> for Histogram {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> f64 {
+        let (min, max) = self.ranges[self.weights.sample(rng)];
+        if min < max {
+            rng.random_range(min..max)
+        } else {
+            min
         }
     }
 }
@@ -1,37 +1,119 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use event_loop::EventLoop;
 use netsim_async::HasBytesSize;
 use node::Node;
+use parallel::{NodeContext, ParallelEngine};
 use rand::RngCore;
 use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
 use slot::SlotWitness;
-use tokio::{select, sync::mpsc, task::JoinSet};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+    task::JoinSet,
+};
 use tokio_util::sync::CancellationToken;
 use tx::TransactionProducer;
 
 use crate::{
-    clock::ClockCoordinator,
-    config::SimConfiguration,
+    checkpoint::{persist_enum, Checkpoint, CheckpointRequest, CheckpointWriter, Persist as _},
+    clock::{Clock, ClockCoordinator, Timestamp},
+    config::{Engine, NodeId, ScenarioChange, SimConfiguration},
     events::EventTracker,
     model::{
         Block, BlockId, EndorserBlock, EndorserBlockId, InputBlock, InputBlockHeader, InputBlockId,
         Transaction, TransactionId, VoteBundle, VoteBundleId,
     },
-    network::Network,
+    network::{EdgePolicy, Network, NetworkChange},
 };
 
 mod cpu;
+mod event_loop;
+mod lottery;
 mod node;
+mod parallel;
 mod slot;
 mod tx;
 
 pub struct Simulation {
+    clock: Clock,
+    config: Arc<SimConfiguration>,
+    tracker: EventTracker,
+    actors: Option<Actors>,
+    running: JoinSet<Result<()>>,
+    driver: Option<Driver>,
+    /// When the last slot ends, if the simulation doesn't run forever.
+    end: Option<Timestamp>,
+    finished: bool,
+    snapshot_sinks: Vec<mpsc::UnboundedSender<oneshot::Sender<NodeSnapshot>>>,
+    /// Every actor with state to save, in the order it's saved in.
+    checkpoint_sinks: Vec<mpsc::UnboundedSender<CheckpointRequest>>,
+}
+
+// When several actors are due at the same instant, the clock wakes them in this order,
+// which is the order the event loop handles them in. Events tracked at the same instant
+// come out in this order too, and then in order of node.
+const SLOT_PHASE: usize = 0;
+const NETWORK_PHASE: usize = 1;
+const TX_PHASE: usize = 2;
+const NODE_PHASE: usize = 3;
+
+// Everything which runs concurrently once the simulation has started.
+struct Actors {
     clock_coordinator: ClockCoordinator,
     network: Network<MiniProtocol, SimulationMessage>,
     tx_producer: TransactionProducer,
     slot_witness: SlotWitness,
     nodes: Vec<Node>,
+    tracker: EventTracker,
+    /// Only used by the parallel engine, which gives each node its own context.
+    contexts: Vec<NodeContext>,
+}
+
+// Engines which the simulation runs from its own task, instead of giving every actor a task.
+enum Driver {
+    EventLoop(EventLoop),
+    Parallel(ParallelEngine),
+}
+
+impl Driver {
+    async fn run(&mut self, limit: Option<Timestamp>, token: &CancellationToken) -> Result<()> {
+        match self {
+            Self::EventLoop(event_loop) => event_loop.run(limit, token).await,
+            Self::Parallel(engine) => engine.run(limit, token).await,
+        }
+    }
+
+    fn node_snapshot(&self, index: usize) -> Option<NodeSnapshot> {
+        match self {
+            Self::EventLoop(event_loop) => event_loop.node_snapshot(index),
+            Self::Parallel(engine) => engine.node_snapshot(index),
+        }
+    }
+
+    fn checkpoint(&self, w: &mut CheckpointWriter) {
+        match self {
+            Self::EventLoop(event_loop) => event_loop.checkpoint(w),
+            Self::Parallel(engine) => engine.checkpoint(w),
+        }
+    }
+}
+
+/// A read-only view of one node's state, taken while the simulation is paused.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeSnapshot {
+    pub id: NodeId,
+    pub name: String,
+    /// How many transactions are waiting in the node's mempool, and how big they are.
+    pub mempool_txs: usize,
+    pub mempool_bytes: u64,
+    /// Every IB and EB which this node has received in full.
+    pub ibs: Vec<InputBlockId>,
+    pub ebs: Vec<EndorserBlockId>,
+    /// The tip of this node's selected chain, and how many blocks are on that chain.
+    pub tip: Option<BlockId>,
+    pub chain_length: usize,
 }
 
 impl Simulation {
@@ -42,26 +124,130 @@ impl Simulation {
     ) -> Result<Self> {
         let clock = clock_coordinator.clock();
         let config = Arc::new(config);
+        if config.engine == Engine::Parallel
+            && config.links.iter().any(|link| link.latency.is_zero())
+        {
+            bail!("the parallel engine needs every link to have some latency");
+        }
         let total_stake = config.nodes.iter().map(|p| p.stake).sum();
+        // The parallel engine puts events in order itself
+        let tracker = if config.engine == Engine::Parallel {
+            tracker
+        } else {
+            tracker.ordered()
+        };
+
+        let (network_checkpoint_sink, network_checkpoint_source) = mpsc::unbounded_channel();
+        let (tx_checkpoint_sink, tx_checkpoint_source) = mpsc::unbounded_channel();
+        let (slot_checkpoint_sink, slot_checkpoint_source) = mpsc::unbounded_channel();
+        let mut checkpoint_sinks = vec![
+            network_checkpoint_sink,
+            tx_checkpoint_sink,
+            slot_checkpoint_sink,
+        ];
 
-        let mut network = Network::new(clock.clone());
+        let mut network = Network::new(
+            clock.barrier_in_phase(NETWORK_PHASE),
+            config.seed,
+            network_checkpoint_source,
+        );
 
         let mut rng = ChaChaRng::seed_from_u64(config.seed);
         let mut nodes = vec![];
         let mut node_tx_sinks = HashMap::new();
+        let mut snapshot_sinks = vec![];
         for link_config in config.links.iter() {
-            network.set_edge_policy(
-                link_config.nodes.0,
-                link_config.nodes.1,
-                link_config.latency,
-                link_config.bandwidth_bps,
-            )?;
+            let policy = EdgePolicy {
+                latency: link_config.latency,
+                bandwidth_bps: link_config.bandwidth_bps,
+                loss_rate: link_config.loss_rate,
+                latency_jitter: link_config.latency_jitter.clone(),
+                tcp_congestion_control: config.tcp_congestion_control,
+            };
+            network.set_edge_policy(link_config.nodes.0, link_config.nodes.1, policy)?;
+        }
+        for node_config in config.nodes.iter() {
+            network.set_node_bandwidth(
+                node_config.id,
+                node_config.upload_bandwidth_bps,
+                node_config.download_bandwidth_bps,
+            );
+        }
+        for (id, event) in config.scenario.events.iter().enumerate() {
+            match &event.change {
+                ScenarioChange::NodeOutage(nodes) => {
+                    for node in nodes {
+                        network.schedule_change(event.start, NetworkChange::DisconnectNode(*node));
+                        if let Some(stop) = event.stop {
+                            network.schedule_change(stop, NetworkChange::ReconnectNode(*node));
+                        }
+                    }
+                }
+                ScenarioChange::Partition(groups) => {
+                    let groups = groups.clone();
+                    network
+                        .schedule_change(event.start, NetworkChange::StartPartition { id, groups });
+                    if let Some(stop) = event.stop {
+                        network.schedule_change(stop, NetworkChange::EndPartition { id });
+                    }
+                }
+                ScenarioChange::LinkBandwidth {
+                    nodes,
+                    bandwidth_bps,
+                } => {
+                    for (from, to) in [(nodes.0, nodes.1), (nodes.1, nodes.0)] {
+                        network.schedule_change(
+                            event.start,
+                            NetworkChange::LimitBandwidth {
+                                id,
+                                from,
+                                to,
+                                bandwidth_bps: *bandwidth_bps,
+                            },
+                        );
+                        if let Some(stop) = event.stop {
+                            network.schedule_change(
+                                stop,
+                                NetworkChange::RestoreBandwidth { id, from, to },
+                            );
+                        }
+                    }
+                }
+            }
         }
+        let mut contexts = vec![];
         for node_config in &config.nodes {
             let id = node_config.id;
-            let (msg_sink, msg_source) = network.open(id).context("could not open socket")?;
+            let (msg_sink, msg_source, node_tracker, node_clock) = if config.engine
+                == Engine::Parallel
+            {
+                // Each node keeps its own time, and holds on to what it sends and tracks
+                // until the engine puts it in order.
+                let (msg_sink, msg_source, outbox) = network.open_outbox(id);
+                let node_clock = ClockCoordinator::new(config.timestamp_resolution);
+                let (node_tracker, events) = tracker.buffered(node_clock.clock());
+                let barrier = node_clock.clock().barrier();
+                contexts.push(NodeContext {
+                    clock: node_clock,
+                    events,
+                    outbox,
+                });
+                (msg_sink, msg_source, node_tracker, barrier)
+            } else {
+                let (msg_sink, msg_source) = network.open(id).context("could not open socket")?;
+                (
+                    msg_sink,
+                    msg_source,
+                    tracker.with_order((NODE_PHASE, id.to_inner())),
+                    clock.barrier_in_phase(NODE_PHASE),
+                )
+            };
             let (tx_sink, tx_source) = mpsc::unbounded_channel();
             node_tx_sinks.insert(id, tx_sink);
+            let (snapshot_sink, snapshot_source) = mpsc::unbounded_channel();
+            snapshot_sinks.push(snapshot_sink);
+            let (checkpoint_sink, checkpoint_source) = mpsc::unbounded_channel();
+            checkpoint_sinks.push(checkpoint_sink);
             let node = Node::new(
                 node_config,
                 config.clone(),
@@ -69,64 +255,290 @@ impl Simulation {
                 msg_source,
                 msg_sink,
                 tx_source,
-                tracker.clone(),
+                snapshot_source,
+                checkpoint_source,
+                node_tracker,
                 ChaChaRng::seed_from_u64(rng.next_u64()),
-                clock.barrier(),
+                node_clock,
             );
             nodes.push(node);
         }
         let tx_producer = TransactionProducer::new(
             ChaChaRng::seed_from_u64(rng.next_u64()),
-            clock.barrier(),
+            clock.barrier_in_phase(TX_PHASE),
             node_tx_sinks,
+            tx_checkpoint_source,
             &config,
         );
 
-        let slot_witness = SlotWitness::new(clock.barrier(), tracker, &config);
+        let slot_witness = SlotWitness::new(
+            clock.barrier_in_phase(SLOT_PHASE),
+            tracker.with_order((SLOT_PHASE, 0)),
+            slot_checkpoint_source,
+        );
+
+        // The simulation stops just before its first slot past the end would begin.
+        let end = config.slots.map(|slots| {
+            Timestamp::from_secs(slots)
+                .checked_sub_duration(Duration::from_nanos(1))
+                .unwrap_or_default()
+        });
 
         Ok(Self {
-            clock_coordinator,
-            network,
-            tx_producer,
-            slot_witness,
-            nodes,
+            clock,
+            config,
+            tracker: tracker.clone(),
+            actors: Some(Actors {
+                clock_coordinator,
+                network,
+                tx_producer,
+                slot_witness,
+                nodes,
+                tracker,
+                contexts,
+            }),
+            running: JoinSet::new(),
+            driver: None,
+            end,
+            finished: false,
+            snapshot_sinks,
+            checkpoint_sinks,
         })
     }
 
+    /// Picks up from where a checkpoint left off, instead of starting from scratch.
+    /// The checkpoint must come from a simulation with the same configuration fingerprint,
+    /// and this one must not have started yet.
+    pub fn restore(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        if checkpoint.fingerprint() != self.config.fingerprint {
+            bail!("the checkpoint was taken with different parameters, topology or scenario");
+        }
+        self.branch(checkpoint)
+    }
+
+    /// Like `restore`, but the configuration can differ from the one the checkpoint was taken with.
+    /// The topology must be the same, and only parameters which take effect as the simulation runs
+    /// (such as block generation rates) make a difference.
+    pub fn branch(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        let Some(actors) = &mut self.actors else {
+            bail!("the simulation has already started");
+        };
+        let mut reader = checkpoint.reader();
+        let nodes = usize::load(&mut reader)?;
+        if nodes != actors.nodes.len() {
+            bail!(
+                "checkpoint has {nodes} nodes, but the topology has {}",
+                actors.nodes.len()
+            );
+        }
+        actors.clock_coordinator.set_time(checkpoint.time());
+        actors.network.restore(&mut reader)?;
+        actors.tx_producer.restore(&mut reader)?;
+        actors.slot_witness.restore(&mut reader)?;
+        for node in &mut actors.nodes {
+            node.restore(&mut reader)?;
+        }
+        reader.finish()
+    }
+
+    /// Saves the complete state of the simulation, so that it can be resumed later.
+    /// Anything already scheduled for the current time happens before the state is saved.
+    pub async fn checkpoint(&self) -> Result<Checkpoint> {
+        if self.actors.is_some() {
+            bail!("the simulation has not started yet");
+        }
+        let time = self.now();
+        let mut writer = CheckpointWriter::new();
+        self.snapshot_sinks.len().save(&mut writer);
+        if let Some(driver) = &self.driver {
+            // These engines only ever stop once everything up to now has happened
+            driver.checkpoint(&mut writer);
+            return Ok(Checkpoint::new(time, self.config.fingerprint, writer));
+        }
+        self.clock
+            .pause_at(Some(time))
+            .await
+            .map_err(|_| anyhow!("the simulation has stopped running"))?;
+        for sink in &self.checkpoint_sinks {
+            let (reply_sink, reply_source) = oneshot::channel();
+            sink.send((writer, reply_sink))
+                .map_err(|_| anyhow!("the simulation has stopped running"))?;
+            writer = reply_source
+                .await
+                .map_err(|_| anyhow!("the simulation has stopped running"))?;
+        }
+        Ok(Checkpoint::new(time, self.config.fingerprint, writer))
+    }
+
     // Run the simulation indefinitely.
     pub async fn run(&mut self, token: CancellationToken) -> Result<()> {
-        let mut set = JoinSet::new();
+        self.run_to(None, token).await
+    }
+
+    /// Runs the simulation until everything scheduled up to the given time has happened,
+    /// then pauses it. Returns early if the simulation finishes first.
+    pub async fn run_until(&mut self, timestamp: Timestamp) -> Result<()> {
+        self.run_to(Some(timestamp), CancellationToken::new()).await
+    }
+
+    /// Runs the simulation until the given slot has begun, then pauses it.
+    /// Stops at the end of the simulation's last slot, if it has one.
+    pub async fn run_until_slot(&mut self, slot: u64) -> Result<()> {
+        self.run_until(Timestamp::from_secs(slot)).await
+    }
+
+    /// The current simulated time.
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    /// Whether the simulation has run all of its slots, or stopped for some other reason.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Inspects the state of every node. Only meaningful while the simulation is paused.
+    pub async fn node_snapshots(&self) -> Result<Vec<NodeSnapshot>> {
+        let mut snapshots = vec![];
+        for (index, sink) in self.snapshot_sinks.iter().enumerate() {
+            snapshots.push(self.request_snapshot(index, sink).await?);
+        }
+        Ok(snapshots)
+    }
+
+    /// Inspects the state of a single node. Only meaningful while the simulation is paused.
+    pub async fn node_snapshot(&self, id: NodeId) -> Result<NodeSnapshot> {
+        let index = id.to_inner();
+        let sink = self
+            .snapshot_sinks
+            .get(index)
+            .ok_or_else(|| anyhow!("node {id} does not exist"))?;
+        self.request_snapshot(index, sink).await
+    }
+
+    async fn request_snapshot(
+        &self,
+        index: usize,
+        sink: &mpsc::UnboundedSender<oneshot::Sender<NodeSnapshot>>,
+    ) -> Result<NodeSnapshot> {
+        if self.actors.is_some() {
+            bail!("the simulation has not started yet");
+        }
+        if let Some(driver) = &self.driver {
+            return driver
+                .node_snapshot(index)
+                .ok_or_else(|| anyhow!("node {index} does not exist"));
+        }
+        let (reply_sink, reply_source) = oneshot::channel();
+        sink.send(reply_sink)
+            .map_err(|_| anyhow!("node {index} has stopped running"))?;
+        reply_source
+            .await
+            .map_err(|_| anyhow!("node {index} has stopped running"))
+    }
 
-        for node in self.nodes.drain(..) {
-            set.spawn(node.run());
+    async fn run_to(&mut self, limit: Option<Timestamp>, token: CancellationToken) -> Result<()> {
+        if self.finished {
+            return Ok(());
         }
+        let limit = match (limit, self.end) {
+            (Some(limit), Some(end)) => Some(limit.min(end)),
+            (limit, end) => limit.or(end),
+        };
+        if self.config.engine != Engine::Actors {
+            let result = self.run_driver(limit, token).await;
+            self.tracker.flush();
+            return result;
+        }
+        // Set the limit before anything starts running, so that time can't get past it
+        let limit_reached = self.clock.pause_at(limit);
+        self.start();
 
         select! {
             biased;
-            _ = token.cancelled() => {}
-            _ = self.slot_witness.run() => {}
-            _ = self.clock_coordinator.run() => {}
-            result = self.network.run() => {
-                result?
+            _ = token.cancelled() => {
+                // Freeze the simulation where it is
+                drop(self.clock.pause_at(Some(self.clock.now())));
             }
-            result = self.tx_producer.run() => {
-                result?;
+            _ = limit_reached => {
+                if limit.is_some() && limit == self.end {
+                    self.finished = true;
+                }
             }
-            result = set.join_next() => {
+            result = self.running.join_next() => {
+                // One of the actors has stopped, so the simulation is over
+                self.finished = true;
                 result.unwrap()??;
             }
         };
+        // Nothing else happens at this instant until the simulation is resumed
+        self.tracker.flush();
 
         Ok(())
     }
 
-    pub fn shutdown(self) -> Result<()> {
-        self.network.shutdown()
+    async fn run_driver(
+        &mut self,
+        limit: Option<Timestamp>,
+        token: CancellationToken,
+    ) -> Result<()> {
+        self.start();
+        let driver = self.driver.as_mut().unwrap();
+        let result = driver.run(limit, &token).await;
+        if result.is_err() || !token.is_cancelled() && (limit.is_none() || limit == self.end) {
+            self.finished = true;
+        }
+        result
+    }
+
+    fn start(&mut self) {
+        let Some(actors) = self.actors.take() else {
+            return;
+        };
+        match self.config.engine {
+            Engine::Actors => {}
+            Engine::EventLoop => {
+                let event_loop = EventLoop::new(actors, self.config.timestamp_resolution);
+                self.driver = Some(Driver::EventLoop(event_loop));
+                return;
+            }
+            Engine::Parallel => {
+                let engine = ParallelEngine::new(actors, &self.config);
+                self.driver = Some(Driver::Parallel(engine));
+                return;
+            }
+        }
+        let Actors {
+            mut clock_coordinator,
+            mut network,
+            mut tx_producer,
+            mut slot_witness,
+            nodes,
+            ..
+        } = actors;
+        self.running.spawn(async move {
+            slot_witness.run().await;
+            Ok(())
+        });
+        self.running.spawn(async move {
+            clock_coordinator.run().await;
+            Ok(())
+        });
+        self.running.spawn(async move { network.run().await });
+        self.running.spawn(async move { tx_producer.run().await });
+        for node in nodes {
+            self.running.spawn(node.run());
+        }
+    }
+
+    pub fn shutdown(mut self) -> Result<()> {
+        self.running.abort_all();
+        Ok(())
     }
 }
 
 #[derive(Clone, Debug)]
-enum SimulationMessage {
+pub(crate) enum SimulationMessage {
     // tx "propagation"
     AnnounceTx(TransactionId),
     RequestTx(TransactionId),
@@ -153,6 +565,27 @@ enum SimulationMessage {
     Votes(Arc<VoteBundle>),
 }
 
+persist_enum!(SimulationMessage {
+    AnnounceTx(id),
+    RequestTx(id),
+    Tx(tx),
+    RollForward(id),
+    RequestBlock(id),
+    Block(block),
+    AnnounceIBHeader(id),
+    RequestIBHeader(id),
+    IBHeader(header, has_body),
+    AnnounceIB(id),
+    RequestIB(id),
+    IB(ib),
+    AnnounceEB(id),
+    RequestEB(id),
+    EB(eb),
+    AnnounceVotes(id),
+    RequestVotes(id),
+    Votes(votes),
+});
+
 impl HasBytesSize for SimulationMessage {
     fn bytes_size(&self) -> u64 {
         match self {
@@ -221,3 +654,329 @@ pub enum MiniProtocol {
     EB,
     Vote,
 }
+
+persist_enum!(MiniProtocol {
+    Tx,
+    Block,
+    IB,
+    EB,
+    Vote,
+});
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use anyhow::Result;
+    use tokio::time::{self, Instant};
+
+    use super::Simulation;
+    use crate::{
+        checkpoint::Checkpoint,
+        clock::{ClockCoordinator, Timestamp},
+        config::{Engine, NodeId, RawTopology, SimConfiguration, Topology},
+        events::{Event, EventFilter, EventKind, EventTracker},
+        testing,
+    };
+
+    async fn simulation(
+        engine: Engine,
+        add_sinks: impl FnOnce(EventTracker) -> Result<EventTracker>,
+    ) -> Result<Simulation> {
+        configured_simulation(|config| config.engine = engine, add_sinks).await
+    }
+
+    async fn configured_simulation(
+        configure: impl FnOnce(&mut SimConfiguration),
+        add_sinks: impl FnOnce(EventTracker) -> Result<EventTracker>,
+    ) -> Result<Simulation> {
+        let raw_topology: RawTopology =
+            serde_yaml::from_str(include_str!("../../test_data/simple.yaml"))?;
+        let topology: Topology = raw_topology.try_into()?;
+        let mut config = testing::config(topology, |_| {})?;
+        configure(&mut config);
+
+        let mut clock_coordinator = ClockCoordinator::new(config.timestamp_resolution);
+        if let Some(timescale) = config.timescale {
+            clock_coordinator = clock_coordinator.with_timescale(timescale)?;
+        }
+        let tracker = add_sinks(EventTracker::without_sinks(
+            clock_coordinator.clock(),
+            &config.nodes,
+        ))?;
+        Simulation::new(config, tracker, clock_coordinator).await
+    }
+
+    #[tokio::test]
+    async fn should_pause_and_resume() -> Result<()> {
+        let mut sim = simulation(Engine::Actors, Ok).await?;
+        assert!(sim.node_snapshots().await.is_err()); // nothing to see before it starts
+
+        sim.run_until_slot(5).await?;
+        assert_eq!(sim.now(), Timestamp::from_secs(5));
+        let early = sim.node_snapshots().await?;
+
+        sim.run_until_slot(30).await?;
+        assert_eq!(sim.now(), Timestamp::from_secs(30));
+        let late = sim.node_snapshots().await?;
+        assert_eq!(early.len(), late.len());
+        let ibs = |snapshots: &[super::NodeSnapshot]| {
+            snapshots.iter().map(|s| s.ibs.len()).sum::<usize>()
+        };
+        assert!(ibs(&late) > ibs(&early));
+        assert!(late.iter().all(|s| s.tip.is_some() == (s.chain_length > 0)));
+
+        // Taking a snapshot doesn't move the simulation along
+        let node = sim.node_snapshot(NodeId::new(0)).await?;
+        assert_eq!(sim.now(), Timestamp::from_secs(30));
+        assert_eq!(node.ibs, late[0].ibs);
+
+        sim.shutdown()
+    }
+
+    #[tokio::test]
+    async fn should_resume_from_a_checkpoint() -> Result<()> {
+        let mut original = simulation(Engine::Actors, Ok).await?;
+        original.run_until_slot(20).await?;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("checkpoint");
+        original.checkpoint().await?.write(&path)?;
+        original.run_until_slot(40).await?;
+        let expected = original.node_snapshots().await?;
+        original.shutdown()?;
+
+        let checkpoint = Checkpoint::read(&path)?;
+        assert_eq!(checkpoint.time(), Timestamp::from_secs(20));
+        let mut resumed = simulation(Engine::Actors, Ok).await?;
+        resumed.restore(&checkpoint)?;
+        assert_eq!(resumed.now(), Timestamp::from_secs(20));
+        resumed.run_until_slot(40).await?;
+        let actual = resumed.node_snapshots().await?;
+        resumed.shutdown()?;
+
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_only_branch_into_a_different_configuration_when_asked() -> Result<()> {
+        let mut original = simulation(Engine::EventLoop, Ok).await?;
+        original.run_until_slot(5).await?;
+        let checkpoint = original.checkpoint().await?;
+        original.shutdown()?;
+
+        let mut resumed = configured_simulation(|config| config.fingerprint = 1, Ok).await?;
+        assert!(resumed.restore(&checkpoint).is_err());
+        resumed.branch(&checkpoint)?;
+        assert_eq!(resumed.now(), Timestamp::from_secs(5));
+        resumed.shutdown()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_pace_from_where_each_run_starts() -> Result<()> {
+        let mut original = simulation(Engine::EventLoop, Ok).await?;
+        original.run_until_slot(20).await?;
+        let checkpoint = original.checkpoint().await?;
+        original.shutdown()?;
+
+        for engine in [Engine::Actors, Engine::EventLoop, Engine::Parallel] {
+            let mut resumed = configured_simulation(
+                |config| {
+                    config.engine = engine;
+                    config.timescale = Some(10.0);
+                },
+                Ok,
+            )
+            .await?;
+            resumed.restore(&checkpoint)?;
+
+            // Two slots take a fifth of a second, however late in the run they come
+            let started = Instant::now();
+            resumed.run_until_slot(22).await?;
+            let elapsed = started.elapsed();
+            assert!(
+                elapsed <= Duration::from_millis(200),
+                "{engine:?}: {elapsed:?}"
+            );
+
+            // Time spent paused isn't made up for afterwards
+            time::advance(Duration::from_secs(10)).await;
+            let started = Instant::now();
+            resumed.run_until_slot(24).await?;
+            let elapsed = started.elapsed();
+            assert!(
+                elapsed >= Duration::from_millis(190),
+                "{engine:?}: {elapsed:?}"
+            );
+            resumed.shutdown()?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_match_the_actors_when_run_from_an_event_loop() -> Result<()> {
+        let mut traces = vec![];
+        let mut snapshots = vec![];
+        for engine in [Engine::Actors, Engine::EventLoop] {
+            let trace = Arc::new(Mutex::new(vec![]));
+            let mut sim = simulation(engine, |tracker| {
+                let trace = trace.clone();
+                tracker.with_sink(move |event: &Event, time| {
+                    trace.lock().unwrap().push((time, format!("{event:?}")))
+                })
+            })
+            .await?;
+            sim.run_until_slot(40).await?;
+            snapshots.push(sim.node_snapshots().await?);
+            sim.shutdown()?;
+            traces.push(std::mem::take(&mut *trace.lock().unwrap()));
+        }
+        assert!(!traces[0].is_empty());
+        assert_eq!(traces[0], traces[1]);
+        assert_eq!(snapshots[0], snapshots[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_resume_an_event_loop_from_a_checkpoint() -> Result<()> {
+        let mut original = simulation(Engine::EventLoop, Ok).await?;
+        original.run_until_slot(20).await?;
+        let checkpoint = original.checkpoint().await?;
+        original.run_until_slot(40).await?;
+        let expected = original.node_snapshots().await?;
+        original.shutdown()?;
+
+        // Checkpoints from either engine can be resumed with the other
+        let mut resumed = simulation(Engine::Actors, Ok).await?;
+        resumed.restore(&checkpoint)?;
+        resumed.run_until_slot(40).await?;
+        let actual = resumed.node_snapshots().await?;
+        resumed.shutdown()?;
+
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_not_depend_on_thread_count_when_run_in_parallel() -> Result<()> {
+        let mut traces = vec![];
+        let mut snapshots = vec![];
+        for threads in [1, 3] {
+            let trace = Arc::new(Mutex::new(vec![]));
+            let mut sim = configured_simulation(
+                |config| {
+                    config.engine = Engine::Parallel;
+                    config.threads = Some(threads);
+                },
+                |tracker| {
+                    let trace = trace.clone();
+                    tracker.with_sink(move |event: &Event, time| {
+                        trace.lock().unwrap().push((time, format!("{event:?}")))
+                    })
+                },
+            )
+            .await?;
+            sim.run_until_slot(40).await?;
+            assert_eq!(sim.now(), Timestamp::from_secs(40));
+            snapshots.push(sim.node_snapshots().await?);
+            sim.shutdown()?;
+            traces.push(std::mem::take(&mut *trace.lock().unwrap()));
+        }
+        assert!(!traces[0].is_empty());
+        // Events come out in time order, and in the same order every time
+        assert!(traces[0].is_sorted_by_key(|(time, _)| *time));
+        assert_eq!(traces[0], traces[1]);
+        assert_eq!(snapshots[0], snapshots[1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_resume_a_parallel_run_from_a_checkpoint() -> Result<()> {
+        let mut original = simulation(Engine::Parallel, Ok).await?;
+        original.run_until_slot(20).await?;
+        let checkpoint = original.checkpoint().await?;
+        original.run_until_slot(40).await?;
+        let expected = original.node_snapshots().await?;
+        original.shutdown()?;
+
+        let mut resumed = simulation(Engine::Parallel, Ok).await?;
+        resumed.restore(&checkpoint)?;
+        resumed.run_until_slot(40).await?;
+        let actual = resumed.node_snapshots().await?;
+        resumed.shutdown()?;
+
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn should_only_send_matching_events_to_filtered_sinks() -> Result<()> {
+        let node = NodeId::new(1);
+        let seen = Arc::new(Mutex::new(vec![]));
+        let all = Arc::new(Mutex::new(0usize));
+        let mut sim = simulation(Engine::Actors, |tracker| {
+            let seen = seen.clone();
+            let all = all.clone();
+            let filter = EventFilter::all()
+                .kinds([EventKind::IBGenerated, EventKind::IBReceived])
+                .kinds(["IBGenerated".parse().unwrap()])
+                .nodes([node])
+                .between(Timestamp::from_secs(5), Timestamp::from_secs(60));
+            tracker
+                .with_sink(move |_: &Event, _| *all.lock().unwrap() += 1)?
+                .with_filtered_sink(filter, move |event: &Event, time| {
+                    seen.lock().unwrap().push((event.clone(), time))
+                })
+        })
+        .await?;
+        sim.run_until_slot(80).await?;
+        sim.shutdown()?;
+
+        let seen = seen.lock().unwrap();
+        assert!(!seen.is_empty());
+        assert!(seen.len() < *all.lock().unwrap());
+        for (event, time) in seen.iter() {
+            assert!(matches!(event, Event::IBGenerated { .. }));
+            assert_eq!(event.node(), Some(node));
+            assert!(*time >= Timestamp::from_secs(5) && *time < Timestamp::from_secs(60));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_sinks_once_the_tracker_is_shared() {
+        let clock = ClockCoordinator::new(Duration::from_nanos(1)).clock();
+        let tracker = EventTracker::without_sinks(clock, &[]);
+        let _shared = tracker.clone();
+        assert!(tracker.with_sink(|_: &Event, _| {}).is_err());
+    }
+
+    #[tokio::test]
+    async fn should_name_event_kinds_like_their_trace_type() -> Result<()> {
+        let types = Arc::new(Mutex::new(HashSet::new()));
+        let mut sim = simulation(Engine::Actors, |tracker| {
+            let types = types.clone();
+            tracker.with_sink(move |event: &Event, _| {
+                let serialized = serde_yaml::to_value(event).unwrap();
+                let name = serialized["type"].as_str().unwrap().to_string();
+                types.lock().unwrap().insert((name, event.kind()));
+            })
+        })
+        .await?;
+        sim.run_until_slot(20).await?;
+        sim.shutdown()?;
+
+        let types = types.lock().unwrap();
+        assert!(types.len() > 5);
+        for (name, kind) in types.iter() {
+            assert_eq!(name, &kind.to_string());
+            assert_eq!(name.parse::<EventKind>()?, *kind);
+        }
+        assert!("IBGenerted".parse::<EventKind>().is_err());
+        Ok(())
+    }
+}
@@ -3,6 +3,10 @@ use std::{
     time::Duration,
 };
 
+use anyhow::Result;
+
+use crate::checkpoint::{persist_struct, CheckpointReader, CheckpointWriter, Persist};
+
 struct TaskState<T> {
     task: T,
     subtasks: usize,
@@ -15,6 +19,12 @@ pub struct Subtask {
     pub duration: Duration,
 }
 
+persist_struct!(Subtask {
+    task_id,
+    subtask_id,
+    duration,
+});
+
 pub struct CpuTaskQueue<T> {
     next_task_id: u64,
     tasks: HashMap<u64, TaskState<T>>,
@@ -23,6 +33,38 @@ pub struct CpuTaskQueue<T> {
     multiplier: f64,
 }
 
+impl<T: Persist> Persist for TaskState<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.task.save(w);
+        self.subtasks.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Self {
+            task: T::load(r)?,
+            subtasks: usize::load(r)?,
+        })
+    }
+}
+
+impl<T: Persist> Persist for CpuTaskQueue<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        self.next_task_id.save(w);
+        self.tasks.save(w);
+        self.pending_subtasks.save(w);
+        self.available_cores.save(w);
+        self.multiplier.save(w);
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(Self {
+            next_task_id: Persist::load(r)?,
+            tasks: Persist::load(r)?,
+            pending_subtasks: Persist::load(r)?,
+            available_cores: Persist::load(r)?,
+            multiplier: Persist::load(r)?,
+        })
+    }
+}
+
 impl<T> CpuTaskQueue<T> {
     pub fn new(cores: Option<u64>, multiplier: f64) -> Self {
         Self {
@@ -0,0 +1,177 @@
+use std::{cmp::Reverse, collections::BinaryHeap, mem, time::Duration};
+
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    checkpoint::CheckpointWriter,
+    clock::{Clock, ClockCoordinator, Timestamp},
+    config::NodeId,
+    network::Network,
+};
+
+use super::{
+    node::Node, slot::SlotWitness, tx::TransactionProducer, Actors, MiniProtocol, NodeSnapshot,
+    SimulationMessage,
+};
+
+// Everything which can have something to do at some point in time.
+// When several actors are due at once, they go in this order.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Actor {
+    Slots,
+    Network,
+    Transactions,
+    Node(usize),
+}
+
+impl Actor {
+    fn index(self) -> usize {
+        match self {
+            Self::Slots => 0,
+            Self::Network => 1,
+            Self::Transactions => 2,
+            Self::Node(index) => 3 + index,
+        }
+    }
+}
+
+/// Runs the whole simulation from one loop. Rather than waiting on the clock in their own tasks,
+/// actors are called directly whenever the earliest thing left to do is theirs.
+pub struct EventLoop {
+    clock: Clock,
+    clock_coordinator: ClockCoordinator,
+    timestamp_resolution: Duration,
+    network: Network<MiniProtocol, SimulationMessage>,
+    tx_producer: TransactionProducer,
+    slot_witness: SlotWitness,
+    nodes: Vec<Node>,
+    // Each actor appears in the queue at the time it next has something to do.
+    // If that time changes, the old entry stays behind, so entries which don't match `scheduled` are skipped.
+    queue: BinaryHeap<Reverse<(Timestamp, Actor)>>,
+    scheduled: Vec<Option<Timestamp>>,
+    deliveries: Vec<(NodeId, NodeId, SimulationMessage)>,
+}
+
+impl EventLoop {
+    pub fn new(actors: Actors, timestamp_resolution: Duration) -> Self {
+        let Actors {
+            clock_coordinator,
+            network,
+            tx_producer,
+            slot_witness,
+            nodes,
+            ..
+        } = actors;
+        let node_count = nodes.len();
+        let mut event_loop = Self {
+            clock: clock_coordinator.clock(),
+            clock_coordinator,
+            timestamp_resolution,
+            network,
+            tx_producer,
+            slot_witness,
+            nodes,
+            queue: BinaryHeap::new(),
+            scheduled: vec![None; 3 + node_count],
+            deliveries: vec![],
+        };
+        for actor in [Actor::Slots, Actor::Network, Actor::Transactions] {
+            event_loop.schedule(actor);
+        }
+        for index in 0..node_count {
+            event_loop.schedule(Actor::Node(index));
+        }
+        event_loop
+    }
+
+    /// Handles everything scheduled up to `limit` (or forever, if it's None), then moves the clock to it.
+    /// If the token is cancelled, stops as soon as everything scheduled for the current time has happened.
+    pub async fn run(&mut self, limit: Option<Timestamp>, token: &CancellationToken) -> Result<()> {
+        let pacer = self.clock_coordinator.pacer();
+        while let Some(&Reverse((timestamp, actor))) = self.queue.peek() {
+            if limit.is_some_and(|limit| timestamp > limit) {
+                break;
+            }
+            if timestamp > self.clock.now() {
+                if token.is_cancelled() {
+                    return Ok(());
+                }
+                if !pacer.wait_until(timestamp, token).await {
+                    return Ok(());
+                }
+                self.clock_coordinator.set_time(timestamp);
+            }
+            self.queue.pop();
+            if self.scheduled[actor.index()] != Some(timestamp) {
+                continue;
+            }
+            self.scheduled[actor.index()] = None;
+            self.handle(actor)?;
+            self.schedule(actor);
+        }
+        if let Some(limit) = limit.filter(|limit| *limit > self.clock.now()) {
+            self.clock_coordinator.set_time(limit);
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, actor: Actor) -> Result<()> {
+        match actor {
+            Actor::Slots => self.slot_witness.start_slot(),
+            Actor::Network => {
+                let mut deliveries = mem::take(&mut self.deliveries);
+                self.network.deliver_messages(&mut deliveries);
+                for (from, to, msg) in deliveries.drain(..) {
+                    let index = to.to_inner();
+                    self.nodes[index].handle_message(from, msg)?;
+                    self.schedule(Actor::Node(index));
+                }
+                self.deliveries = deliveries;
+            }
+            Actor::Transactions => {
+                if let Some((node_id, tx)) = self.tx_producer.generate_tx() {
+                    let index = node_id.to_inner();
+                    self.nodes[index].generate_tx(tx)?;
+                    self.schedule(Actor::Node(index));
+                }
+            }
+            Actor::Node(index) => self.nodes[index].handle_due_events()?,
+        }
+        // Whatever just happened might have sent some messages
+        self.network.accept_messages();
+        self.schedule(Actor::Network);
+        Ok(())
+    }
+
+    fn schedule(&mut self, actor: Actor) {
+        let next = match actor {
+            Actor::Slots => Some(self.slot_witness.next_slot_time()),
+            Actor::Network => self.network.next_event_time(),
+            Actor::Transactions => self.tx_producer.next_tx_time(),
+            Actor::Node(index) => self.nodes[index].next_event_time(),
+        }
+        .map(|timestamp| timestamp.with_resolution(self.timestamp_resolution));
+        let scheduled = &mut self.scheduled[actor.index()];
+        if next != *scheduled {
+            *scheduled = next;
+            if let Some(timestamp) = next {
+                self.queue.push(Reverse((timestamp, actor)));
+            }
+        }
+    }
+
+    pub fn node_snapshot(&self, index: usize) -> Option<NodeSnapshot> {
+        self.nodes.get(index).map(|node| node.snapshot())
+    }
+
+    /// Saves the state of every actor, in the same order as the actors would save it themselves.
+    pub fn checkpoint(&self, w: &mut CheckpointWriter) {
+        self.network.checkpoint(w);
+        self.tx_producer.checkpoint(w);
+        self.slot_witness.checkpoint(w);
+        for node in &self.nodes {
+            node.checkpoint(w);
+        }
+    }
+}
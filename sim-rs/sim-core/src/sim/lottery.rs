@@ -0,0 +1,228 @@
+use rand::Rng as _;
+use rand_chacha::ChaChaRng;
+use rand_distr::{Distribution as _, Poisson};
+
+use crate::config::{LotteryModel, NodeConfiguration, SimConfiguration};
+
+/// Decides how often a node wins the lotteries to produce blocks and votes,
+/// based on its share of the total stake.
+pub struct Lottery {
+    model: LotteryModel,
+    stake: u64,
+    total_stake: u64,
+    voter: VoterRole,
+}
+
+/// How a node earns seats on the voting committee.
+#[derive(Clone, Copy)]
+pub enum VoterRole {
+    /// Every pool runs the vote lottery.
+    Lottery,
+    /// A fait-accompli persistent voter, which votes in every election.
+    Persistent,
+    /// A fait-accompli non-persistent voter, which runs local sortition
+    /// for a share of the non-persistent seats.
+    NonPersistent {
+        nonpersistent_stake: u64,
+        nonpersistent_seats: u64,
+    },
+}
+
+impl Lottery {
+    pub fn new(model: LotteryModel, stake: u64, total_stake: u64, voter: VoterRole) -> Self {
+        Self {
+            model,
+            stake,
+            total_stake,
+            voter,
+        }
+    }
+
+    pub fn for_node(config: &SimConfiguration, node: &NodeConfiguration, total_stake: u64) -> Self {
+        let voter = match &config.fait_accompli {
+            None => VoterRole::Lottery,
+            Some(committee) if committee.persistent.contains(&node.id) => VoterRole::Persistent,
+            Some(committee) => VoterRole::NonPersistent {
+                nonpersistent_stake: committee.nonpersistent_stake,
+                nonpersistent_seats: committee.nonpersistent_seats,
+            },
+        };
+        Self::new(config.lottery_model, node.stake, total_stake, voter)
+    }
+
+    pub fn is_persistent_voter(&self) -> bool {
+        matches!(self.voter, VoterRole::Persistent)
+    }
+
+    /// The Praos leader check, for a lottery with active slot coefficient `f`.
+    /// Returns our VRF output if we won.
+    pub fn leader_check(&self, rng: &mut ChaChaRng, f: f64) -> Option<u64> {
+        let probability = match self.model {
+            LotteryModel::Approximate => self.relative_stake() * f,
+            LotteryModel::Sortition => 1.0 - (1.0 - f).powf(self.relative_stake()),
+        };
+        self.draw(rng, probability)
+    }
+
+    /// Runs a lottery where the whole network expects to win `expected` times.
+    /// Returns our VRF output for every win.
+    pub fn wins(&self, rng: &mut ChaChaRng, expected: f64) -> Vec<u64> {
+        match self.model {
+            LotteryModel::Approximate => vrf_probabilities(expected)
+                .filter_map(|p| self.draw(rng, self.relative_stake() * p))
+                .collect(),
+            LotteryModel::Sortition => {
+                let count = self.poisson_wins(rng, expected);
+                (0..count)
+                    .map(|_| rng.random_range(0..self.total_stake))
+                    .collect()
+            }
+        }
+    }
+
+    /// Runs a lottery where the whole network expects to win `expected` times.
+    /// Returns our VRF output if we won at least once.
+    pub fn wins_any(&self, rng: &mut ChaChaRng, expected: f64) -> Option<u64> {
+        match self.model {
+            LotteryModel::Approximate => {
+                vrf_probabilities(expected).find_map(|p| self.draw(rng, self.relative_stake() * p))
+            }
+            LotteryModel::Sortition => {
+                // The odds of winning a Poisson-distributed lottery at least once
+                let probability = 1.0 - (-self.relative_stake() * expected).exp();
+                self.draw(rng, probability)
+            }
+        }
+    }
+
+    /// The Leios voter check, for a committee of (on average) `committee_size` votes.
+    /// Returns how many votes we are entitled to.
+    pub fn votes(&self, rng: &mut ChaChaRng, committee_size: f64) -> usize {
+        match (self.voter, self.model) {
+            (VoterRole::Lottery, LotteryModel::Approximate) => self.wins(rng, committee_size).len(),
+            (VoterRole::Lottery, LotteryModel::Sortition) => {
+                // Nobody can hold more votes than there are seats on the committee.
+                let max_votes = committee_size.ceil() as u64;
+                let mean = self.relative_stake() * committee_size;
+                poisson_sample(rng, mean).min(max_votes) as usize
+            }
+            (VoterRole::Persistent, _) => 1,
+            (
+                VoterRole::NonPersistent {
+                    nonpersistent_stake,
+                    nonpersistent_seats,
+                },
+                _,
+            ) => {
+                // Local sortition is run against the stake of only the non-persistent pools.
+                let share = self.stake as f64 / nonpersistent_stake as f64;
+                let mean = share * nonpersistent_seats as f64;
+                poisson_sample(rng, mean).min(nonpersistent_seats) as usize
+            }
+        }
+    }
+
+    fn relative_stake(&self) -> f64 {
+        self.stake as f64 / self.total_stake as f64
+    }
+
+    // Simulates the output of a VRF which succeeds with the given probability.
+    fn draw(&self, rng: &mut ChaChaRng, probability: f64) -> Option<u64> {
+        let target_vrf_stake = (self.total_stake as f64 * probability) as u64;
+        let result = rng.random_range(0..self.total_stake);
+        if result < target_vrf_stake {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    // With sortition, the number of wins for a pool is Poisson-distributed around its share of the total.
+    fn poisson_wins(&self, rng: &mut ChaChaRng, expected: f64) -> u64 {
+        poisson_sample(rng, self.relative_stake() * expected)
+    }
+}
+
+fn poisson_sample(rng: &mut ChaChaRng, mean: f64) -> u64 {
+    let Ok(dist) = Poisson::new(mean) else {
+        // the mean is zero, because we have no stake
+        return 0;
+    };
+    dist.sample(rng) as u64
+}
+
+fn vrf_probabilities(probability: f64) -> impl Iterator<Item = f64> {
+    let final_success_rate = Some(probability.fract()).filter(|f| *f > 0.0);
+    std::iter::repeat_n(1.0, probability.trunc() as usize).chain(final_success_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_chacha::{rand_core::SeedableRng, ChaChaRng};
+
+    use crate::config::LotteryModel;
+
+    use super::{Lottery, VoterRole};
+
+    const TRIALS: usize = 100_000;
+
+    fn win_rate(mut run: impl FnMut(&mut ChaChaRng) -> bool) -> f64 {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let wins = (0..TRIALS).filter(|_| run(&mut rng)).count();
+        wins as f64 / TRIALS as f64
+    }
+
+    #[test]
+    fn should_match_praos_leader_check_for_large_pools() {
+        let f = 0.05;
+        let lottery = Lottery::new(
+            LotteryModel::Sortition,
+            500_000_000,
+            1_000_000_000,
+            VoterRole::Lottery,
+        );
+        let rate = win_rate(|rng| lottery.leader_check(rng, f).is_some());
+        let expected = 1.0 - (1.0f64 - f).powf(0.5);
+        assert!((rate - expected).abs() < 0.002, "{rate} != {expected}");
+    }
+
+    #[test]
+    fn should_keep_linear_leader_check_for_approximate_model() {
+        let f = 0.05;
+        let lottery = Lottery::new(
+            LotteryModel::Approximate,
+            500_000_000,
+            1_000_000_000,
+            VoterRole::Lottery,
+        );
+        let rate = win_rate(|rng| lottery.leader_check(rng, f).is_some());
+        assert!((rate - 0.025).abs() < 0.002, "{rate} != 0.025");
+    }
+
+    #[test]
+    fn should_assign_votes_proportional_to_stake() {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let lottery = Lottery::new(LotteryModel::Sortition, 1, 10, VoterRole::Lottery);
+        let votes: usize = (0..TRIALS).map(|_| lottery.votes(&mut rng, 500.0)).sum();
+        let mean = votes as f64 / TRIALS as f64;
+        assert!((mean - 50.0).abs() < 0.1, "{mean} != 50");
+    }
+
+    #[test]
+    fn should_cap_votes_at_committee_size() {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let lottery = Lottery::new(LotteryModel::Sortition, 1, 1, VoterRole::Lottery);
+        for _ in 0..1000 {
+            assert!(lottery.votes(&mut rng, 10.0) <= 10);
+        }
+    }
+
+    #[test]
+    fn should_always_give_persistent_voters_a_seat() {
+        let mut rng = ChaChaRng::seed_from_u64(0);
+        let lottery = Lottery::new(LotteryModel::Sortition, 1, 10, VoterRole::Persistent);
+        for _ in 0..1000 {
+            assert_eq!(lottery.votes(&mut rng, 500.0), 1);
+        }
+    }
+}
@@ -6,32 +6,41 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use netsim_async::HasBytesSize as _;
 use priority_queue::PriorityQueue;
 use rand::{seq::SliceRandom as _, Rng as _};
 use rand_chacha::ChaChaRng;
-use tokio::{select, sync::mpsc};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot},
+};
 use tracing::{info, trace};
 
 use crate::{
+    checkpoint::{
+        persist_enum, persist_struct, CheckpointReader, CheckpointRequest, CheckpointWriter,
+        Persist,
+    },
     clock::{ClockBarrier, FutureEvent, Timestamp},
     config::{
-        DiffusionStrategy, LeiosVariant, MempoolSamplingStrategy, NodeConfiguration, NodeId,
-        RelayStrategy, SimConfiguration, TransactionConfig,
+        duration_ms, AdversarialBehaviour, CpuTimeConfig, DiffusionStrategy, LeiosVariant,
+        MempoolSamplingStrategy, NodeConfiguration, NodeId, RelayStrategy, SimConfiguration,
+        TransactionConfig, MAX_DURATION,
     },
     events::EventTracker,
     model::{
-        Block, BlockId, CpuTaskId, Endorsement, EndorserBlock, EndorserBlockId, InputBlock,
-        InputBlockHeader, InputBlockId, NoVoteReason, Transaction, TransactionId, VoteBundle,
-        VoteBundleId,
+        Block, BlockId, CpuTaskId, Endorsement, EndorserBlock, EndorserBlockId, ExecutionUnits,
+        InputBlock, InputBlockHeader, InputBlockId, NoVoteReason, Transaction, TransactionId,
+        VoteBundle, VoteBundleId,
     },
     network::{NetworkSink, NetworkSource},
 };
 
 use super::{
     cpu::{CpuTaskQueue, Subtask},
-    MiniProtocol, SimulationMessage,
+    lottery::Lottery,
+    MiniProtocol, NodeSnapshot, SimulationMessage,
 };
 
 enum TransactionView {
@@ -39,12 +48,20 @@ enum TransactionView {
     Received(Arc<Transaction>),
 }
 
+persist_enum!(TransactionView { Pending, Received(tx) });
+
 struct CpuTask {
     task_type: CpuTaskType,
     start_time: Timestamp,
     cpu_time: Duration,
 }
 
+persist_struct!(CpuTask {
+    task_type,
+    start_time,
+    cpu_time,
+});
+
 enum CpuTaskType {
     /// A transaction has been received and validated, and is ready to propagate
     TransactionValidated(NodeId, Arc<Transaction>),
@@ -68,6 +85,19 @@ enum CpuTaskType {
     VTBundleValidated(NodeId, Arc<VoteBundle>),
 }
 
+persist_enum!(CpuTaskType {
+    TransactionValidated(from, tx),
+    RBBlockGenerated(block),
+    RBBlockValidated(from, block),
+    IBBlockGenerated(ib),
+    IBHeaderValidated(from, header, has_body),
+    IBBlockValidated(from, ib),
+    EBBlockGenerated(eb),
+    EBBlockValidated(from, eb),
+    VTBundleGenerated(votes),
+    VTBundleValidated(from, votes),
+});
+
 impl CpuTaskType {
     fn name(&self) -> String {
         match self {
@@ -109,6 +139,11 @@ enum NodeEvent {
     CpuSubtaskCompleted(Subtask),
 }
 
+persist_enum!(NodeEvent {
+    NewSlot(slot),
+    CpuSubtaskCompleted(subtask),
+});
+
 #[derive(Clone, Default)]
 struct LedgerState {
     spent_inputs: HashSet<u64>,
@@ -116,6 +151,12 @@ struct LedgerState {
     seen_ebs: HashSet<EndorserBlockId>,
 }
 
+persist_struct!(LedgerState {
+    spent_inputs,
+    seen_blocks,
+    seen_ebs,
+});
+
 pub struct Node {
     id: NodeId,
     name: String,
@@ -124,34 +165,79 @@ pub struct Node {
     msg_source: Option<NetworkSource<SimulationMessage>>,
     msg_sink: NetworkSink<MiniProtocol, SimulationMessage>,
     tx_source: Option<mpsc::UnboundedReceiver<Arc<Transaction>>>,
+    snapshot_source: Option<mpsc::UnboundedReceiver<oneshot::Sender<NodeSnapshot>>>,
+    checkpoint_source: Option<mpsc::UnboundedReceiver<CheckpointRequest>>,
     events: BinaryHeap<FutureEvent<NodeEvent>>,
     tracker: EventTracker,
     rng: ChaChaRng,
+    cpu_rng: ChaChaRng,
     clock: ClockBarrier,
-    stake: u64,
-    total_stake: u64,
+    lottery: Lottery,
     cpu: CpuTaskQueue<CpuTask>,
     consumers: Vec<NodeId>,
+    behaviours: NodeBehaviours,
     txs: HashMap<TransactionId, TransactionView>,
     ledger_states: BTreeMap<BlockId, Arc<LedgerState>>,
     praos: NodePraosState,
     leios: NodeLeiosState,
 }
 
+#[derive(Default)]
+struct NodeBehaviours {
+    withhold_ib_bodies: bool,
+    equivocate_ibs: bool,
+    equivocate_ebs: bool,
+    refuse_vote_relay: bool,
+    vote_for_invalid_ebs: bool,
+}
+impl NodeBehaviours {
+    fn new(behaviours: &[AdversarialBehaviour]) -> Self {
+        let mut result = Self::default();
+        for behaviour in behaviours {
+            match behaviour {
+                AdversarialBehaviour::WithholdIbBodies => result.withhold_ib_bodies = true,
+                AdversarialBehaviour::EquivocateIbs => result.equivocate_ibs = true,
+                AdversarialBehaviour::EquivocateEbs => result.equivocate_ebs = true,
+                AdversarialBehaviour::RefuseVoteRelay => result.refuse_vote_relay = true,
+                AdversarialBehaviour::VoteForInvalidEbs => result.vote_for_invalid_ebs = true,
+            }
+        }
+        result
+    }
+}
+
 #[derive(Default)]
 struct NodePraosState {
     mempool: BTreeMap<TransactionId, Arc<Transaction>>,
-    peer_heads: BTreeMap<NodeId, u64>,
     blocks_seen: BTreeSet<BlockId>,
+    /// Every block we have validated, on any fork.
     blocks: BTreeMap<BlockId, Arc<Block>>,
-    block_ids_by_slot: BTreeMap<u64, BlockId>,
+    heights: BTreeMap<BlockId, u64>,
+    /// Blocks which we can't connect to a chain yet, keyed by the parent they are waiting for.
+    orphans: BTreeMap<BlockId, Vec<(NodeId, Arc<Block>)>>,
+    /// The tip of our selected chain.
+    tip: Option<BlockId>,
+    /// The blocks on our selected chain, by slot.
+    chain: BTreeMap<u64, BlockId>,
 }
 
+persist_struct!(NodePraosState {
+    mempool,
+    blocks_seen,
+    blocks,
+    heights,
+    orphans,
+    tip,
+    chain,
+});
+
 struct SeenTransaction {
     tx: Arc<Transaction>,
     seen_at: Timestamp,
 }
 
+persist_struct!(SeenTransaction { tx, seen_at });
+
 #[derive(Default)]
 struct NodeLeiosState {
     mempool: BTreeMap<TransactionId, SeenTransaction>,
@@ -166,14 +252,52 @@ struct NodeLeiosState {
     votes_to_generate: BTreeMap<u64, usize>,
     votes_by_eb: BTreeMap<EndorserBlockId, BTreeMap<NodeId, usize>>,
     votes: BTreeMap<VoteBundleId, VoteBundleState>,
+    equivocations: EquivocationState,
 }
 
+persist_struct!(NodeLeiosState {
+    mempool,
+    input_ids_from_ibs,
+    ibs_to_generate,
+    ibs,
+    ib_requests,
+    ibs_by_pipeline,
+    ebs,
+    ebs_by_pipeline,
+    earliest_eb_cert_times_by_pipeline,
+    votes_to_generate,
+    votes_by_eb,
+    votes,
+    equivocations,
+});
+
+/// The "other half" of any blocks which this node has equivocated on.
+#[derive(Default)]
+struct EquivocationState {
+    ib_txs_to_generate: BTreeMap<InputBlockId, Vec<Arc<Transaction>>>,
+    ibs: BTreeMap<InputBlockId, Arc<InputBlock>>,
+    ebs: BTreeMap<EndorserBlockId, Arc<EndorserBlock>>,
+}
+
+persist_struct!(EquivocationState {
+    ib_txs_to_generate,
+    ibs,
+    ebs,
+});
+
 enum InputBlockState {
     HeaderPending,
     Pending(InputBlockHeader),
     Requested(InputBlockHeader),
     Received(Arc<InputBlock>),
 }
+persist_enum!(InputBlockState {
+    HeaderPending,
+    Pending(header),
+    Requested(header),
+    Received(ib),
+});
+
 impl InputBlockState {
     fn header(&self) -> Option<&InputBlockHeader> {
         match self {
@@ -193,21 +317,62 @@ enum EndorserBlockState {
     },
 }
 
+persist_enum!(EndorserBlockState {
+    Pending,
+    Received { eb, finalized },
+});
+
 enum VoteBundleState {
     Requested,
     Received(Arc<VoteBundle>),
 }
 
+persist_enum!(VoteBundleState {
+    Requested,
+    Received(votes),
+});
+
 struct PeerInputBlockRequests {
     pending: PendingQueue<InputBlockId>,
     active: HashSet<InputBlockId>,
 }
-enum PendingQueue<T: Hash + Eq> {
+persist_struct!(PeerInputBlockRequests { pending, active });
+
+/// Values which were queued at the same time come out in order, so that the order doesn't depend
+/// on the shape of the queue.
+enum PendingQueue<T: Hash + Eq + Ord> {
     PeerOrder(VecDeque<T>),
-    FreshestFirst(PriorityQueue<T, Timestamp>),
-    OldestFirst(PriorityQueue<T, Reverse<Timestamp>>),
+    FreshestFirst(PriorityQueue<T, (Timestamp, Reverse<T>)>),
+    OldestFirst(PriorityQueue<T, Reverse<(Timestamp, T)>>),
 }
-impl<T: Hash + Eq> PendingQueue<T> {
+impl<T: Persist + Hash + Eq + Ord> Persist for PendingQueue<T> {
+    fn save(&self, w: &mut CheckpointWriter) {
+        match self {
+            Self::PeerOrder(queue) => {
+                0u8.save(w);
+                queue.save(w);
+            }
+            Self::FreshestFirst(queue) => {
+                1u8.save(w);
+                queue.save(w);
+            }
+            Self::OldestFirst(queue) => {
+                2u8.save(w);
+                queue.save(w);
+            }
+        }
+    }
+    fn load(r: &mut CheckpointReader) -> Result<Self> {
+        Ok(match u8::load(r)? {
+            0 => Self::PeerOrder(Persist::load(r)?),
+            1 => Self::FreshestFirst(Persist::load(r)?),
+            2 => Self::OldestFirst(Persist::load(r)?),
+            tag => bail!("unknown PendingQueue variant {tag} in checkpoint"),
+        })
+    }
+}
+
+impl<T: Hash + Eq + Ord + Clone> PendingQueue<T> {
     fn new(strategy: DiffusionStrategy) -> Self {
         match strategy {
             DiffusionStrategy::PeerOrder => Self::PeerOrder(VecDeque::new()),
@@ -219,10 +384,10 @@ impl<T: Hash + Eq> PendingQueue<T> {
         match self {
             Self::PeerOrder(queue) => queue.push_back(value),
             Self::FreshestFirst(queue) => {
-                queue.push(value, timestamp);
+                queue.push(value.clone(), (timestamp, Reverse(value)));
             }
             Self::OldestFirst(queue) => {
-                queue.push(value, Reverse(timestamp));
+                queue.push(value.clone(), Reverse((timestamp, value)));
             }
         }
     }
@@ -261,16 +426,23 @@ impl Node {
         msg_source: NetworkSource<SimulationMessage>,
         msg_sink: NetworkSink<MiniProtocol, SimulationMessage>,
         tx_source: mpsc::UnboundedReceiver<Arc<Transaction>>,
+        snapshot_source: mpsc::UnboundedReceiver<oneshot::Sender<NodeSnapshot>>,
+        checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
         tracker: EventTracker,
         rng: ChaChaRng,
         clock: ClockBarrier,
     ) -> Self {
         let id = config.id;
-        let stake = config.stake;
+        let lottery = Lottery::for_node(&sim_config, config, total_stake);
         let cpu = CpuTaskQueue::new(config.cores, config.cpu_multiplier);
         let consumers = config.consumers.clone();
+        let behaviours = NodeBehaviours::new(&config.adversarial_behaviours);
         let mut events = BinaryHeap::new();
         events.push(FutureEvent(clock.now(), NodeEvent::NewSlot(0)));
+        // Sample CPU times from their own stream, so that changing them
+        // can't change the outcome of any lottery.
+        let mut cpu_rng = rng.clone();
+        cpu_rng.set_stream(1);
 
         Self {
             id,
@@ -280,14 +452,17 @@ impl Node {
             msg_source: Some(msg_source),
             msg_sink,
             tx_source: Some(tx_source),
+            snapshot_source: Some(snapshot_source),
+            checkpoint_source: Some(checkpoint_source),
             events,
             tracker,
             rng,
+            cpu_rng,
             clock,
-            stake,
-            total_stake,
+            lottery,
             cpu,
             consumers,
+            behaviours,
             txs: HashMap::new(),
             ledger_states: BTreeMap::new(),
             praos: NodePraosState::default(),
@@ -343,41 +518,68 @@ impl Node {
         ))
     }
 
-    fn task_cpu_times(&self, task: &CpuTaskType) -> Vec<Duration> {
+    fn task_cpu_times(&mut self, task: &CpuTaskType) -> Vec<Duration> {
         let cpu_times = &self.sim_config.cpu_times;
+        let rng = &mut self.cpu_rng;
         match task {
-            CpuTaskType::TransactionValidated(_, _) => vec![cpu_times.tx_validation],
+            CpuTaskType::TransactionValidated(_, tx) => vec![
+                cpu_times.tx_validation.sample(rng)
+                    + script_cpu_time(cpu_times, rng, tx.execution_units.steps),
+            ],
             CpuTaskType::RBBlockGenerated(block) => {
-                let mut time = cpu_times.rb_generation;
+                let mut time = cpu_times.rb_generation.sample(rng);
                 if let Some(endorsement) = &block.endorsement {
                     let nodes = endorsement.votes.len();
-                    time += cpu_times.cert_generation_constant
-                        + (cpu_times.cert_generation_per_node * nodes as u32)
+                    time += cpu_times.cert_generation_constant.sample(rng)
+                        + per_unit_cpu_time(
+                            cpu_times.cert_generation_per_node.sample(rng),
+                            nodes as u64,
+                        )
                 }
                 vec![time]
             }
             CpuTaskType::RBBlockValidated(_, rb) => {
-                let mut time = cpu_times.rb_validation_constant;
+                let mut time = cpu_times.rb_validation_constant.sample(rng);
                 let bytes: u64 = rb.transactions.iter().map(|tx| tx.bytes).sum();
-                time += cpu_times.rb_validation_per_byte * (bytes as u32);
+                time += per_unit_cpu_time(cpu_times.rb_validation_per_byte.sample(rng), bytes);
+                let steps = rb
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.execution_units.steps)
+                    .fold(0, u64::saturating_add);
+                time += script_cpu_time(cpu_times, rng, steps);
                 if let Some(endorsement) = &rb.endorsement {
                     let nodes = endorsement.votes.len();
-                    time += cpu_times.cert_validation_constant
-                        + (cpu_times.cert_validation_per_node * nodes as u32)
+                    time += cpu_times.cert_validation_constant.sample(rng)
+                        + per_unit_cpu_time(
+                            cpu_times.cert_validation_per_node.sample(rng),
+                            nodes as u64,
+                        )
                 }
                 vec![time]
             }
-            CpuTaskType::IBBlockGenerated(_) => vec![cpu_times.ib_generation],
-            CpuTaskType::IBHeaderValidated(_, _, _) => vec![cpu_times.ib_head_validation],
+            CpuTaskType::IBBlockGenerated(_) => vec![cpu_times.ib_generation.sample(rng)],
+            CpuTaskType::IBHeaderValidated(_, _, _) => {
+                vec![cpu_times.ib_head_validation.sample(rng)]
+            }
             CpuTaskType::IBBlockValidated(_, ib) => {
                 let total_tx_bytes: u64 = ib.transactions.iter().map(|tx| tx.bytes).sum();
+                let total_tx_steps = ib
+                    .transactions
+                    .iter()
+                    .map(|tx| tx.execution_units.steps)
+                    .fold(0, u64::saturating_add);
                 vec![
-                    cpu_times.ib_body_validation_constant
-                        + (cpu_times.ib_body_validation_per_byte * total_tx_bytes as u32),
+                    cpu_times.ib_body_validation_constant.sample(rng)
+                        + per_unit_cpu_time(
+                            cpu_times.ib_body_validation_per_byte.sample(rng),
+                            total_tx_bytes,
+                        )
+                        + script_cpu_time(cpu_times, rng, total_tx_steps),
                 ]
             }
-            CpuTaskType::EBBlockGenerated(_) => vec![cpu_times.eb_generation],
-            CpuTaskType::EBBlockValidated(_, _) => vec![cpu_times.eb_validation],
+            CpuTaskType::EBBlockGenerated(_) => vec![cpu_times.eb_generation.sample(rng)],
+            CpuTaskType::EBBlockValidated(_, _) => vec![cpu_times.eb_validation.sample(rng)],
             CpuTaskType::VTBundleGenerated(votes) => votes
                 .ebs
                 .keys()
@@ -386,13 +588,16 @@ impl Node {
                     else {
                         panic!("node tried voting for an unknown EB");
                     };
-                    cpu_times.vote_generation_constant
-                        + (cpu_times.vote_generation_per_ib * eb.ibs.len() as u32)
+                    cpu_times.vote_generation_constant.sample(rng)
+                        + per_unit_cpu_time(
+                            cpu_times.vote_generation_per_ib.sample(rng),
+                            eb.ibs.len() as u64,
+                        )
                 })
                 .collect(),
-            CpuTaskType::VTBundleValidated(_, votes) => {
-                std::iter::repeat_n(cpu_times.vote_validation, votes.ebs.len()).collect()
-            }
+            CpuTaskType::VTBundleValidated(_, votes) => (0..votes.ebs.len())
+                .map(|_| cpu_times.vote_validation.sample(rng))
+                .collect(),
         }
     }
 
@@ -401,6 +606,8 @@ impl Node {
         // (then we could remove these Option shenanigans)
         let mut msg_source = self.msg_source.take().unwrap();
         let mut tx_source = self.tx_source.take().unwrap();
+        let mut snapshot_source = self.snapshot_source.take().unwrap();
+        let mut checkpoint_source = self.checkpoint_source.take().unwrap();
 
         loop {
             select! {
@@ -418,43 +625,141 @@ impl Node {
                         break;
                     };
                     self.generate_tx(tx)?;
+                    self.clock.finish_task();
+                }
+                Some(reply) = snapshot_source.recv() => {
+                    let _ = reply.send(self.snapshot());
+                }
+                Some((mut writer, reply)) = checkpoint_source.recv() => {
+                    self.checkpoint(&mut writer);
+                    let _ = reply.send(writer);
                 }
                 event = self.next_event() => {
-                    match event {
-                        NodeEvent::NewSlot(slot) => self.handle_new_slot(slot)?,
-                        NodeEvent::CpuSubtaskCompleted(subtask) => {
-                            let task_id = CpuTaskId { node: self.id, index: subtask.task_id };
-                            let (finished_task, next_subtask) = self.cpu.complete_subtask(subtask);
-                            if let Some((subtask, task)) = next_subtask {
-                                let task_type = task.task_type.name();
-                                self.start_cpu_subtask(subtask, task_type);
-                            }
-                            let Some(task) = finished_task else {
-                                continue;
-                            };
-                            let wall_time = self.clock.now() - task.start_time;
-                            self.tracker.track_cpu_task_finished(task_id, task.task_type.name(), task.cpu_time, wall_time, task.task_type.extra());
-                            match task.task_type {
-                                CpuTaskType::TransactionValidated(from, tx) => self.propagate_tx(from, tx)?,
-                                CpuTaskType::RBBlockGenerated(block) => self.finish_generating_block(block)?,
-                                CpuTaskType::RBBlockValidated(from, block) => self.finish_validating_block(from, block)?,
-                                CpuTaskType::IBBlockGenerated(ib) => self.finish_generating_ib(ib)?,
-                                CpuTaskType::IBHeaderValidated(from, ib, has_body) => self.finish_validating_ib_header(from, ib, has_body)?,
-                                CpuTaskType::IBBlockValidated(from, ib) => self.finish_validating_ib(from, ib)?,
-                                CpuTaskType::EBBlockGenerated(eb) => self.finish_generating_eb(eb)?,
-                                CpuTaskType::EBBlockValidated(from, eb) => self.finish_validating_eb(from, eb)?,
-                                CpuTaskType::VTBundleGenerated(votes) => self.finish_generating_vote_bundle(votes)?,
-                                CpuTaskType::VTBundleValidated(from, votes) => self.finish_validating_vote_bundle(from, votes)?,
-                            }
-                        }
-                    }
+                    self.handle_event(event)?;
                 }
             };
         }
         Ok(())
     }
 
-    fn handle_message(&mut self, from: NodeId, msg: SimulationMessage) -> Result<()> {
+    /// When this node next has something to do, if it ever will.
+    pub fn next_event_time(&self) -> Option<Timestamp> {
+        self.events.peek().map(|e| e.0)
+    }
+
+    /// Handles every event which is due by now, without waiting on the clock.
+    pub fn handle_due_events(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        while self.events.peek().is_some_and(|e| e.0 <= now) {
+            let event = self.events.pop().unwrap().1;
+            self.handle_event(event)?;
+        }
+        Ok(())
+    }
+
+    fn handle_event(&mut self, event: NodeEvent) -> Result<()> {
+        match event {
+            NodeEvent::NewSlot(slot) => self.handle_new_slot(slot)?,
+            NodeEvent::CpuSubtaskCompleted(subtask) => {
+                let task_id = CpuTaskId {
+                    node: self.id,
+                    index: subtask.task_id,
+                };
+                let (finished_task, next_subtask) = self.cpu.complete_subtask(subtask);
+                if let Some((subtask, task)) = next_subtask {
+                    let task_type = task.task_type.name();
+                    self.start_cpu_subtask(subtask, task_type);
+                }
+                let Some(task) = finished_task else {
+                    return Ok(());
+                };
+                let wall_time = self.clock.now() - task.start_time;
+                self.tracker.track_cpu_task_finished(
+                    task_id,
+                    task.task_type.name(),
+                    task.cpu_time,
+                    wall_time,
+                    task.task_type.extra(),
+                );
+                match task.task_type {
+                    CpuTaskType::TransactionValidated(from, tx) => self.propagate_tx(from, tx)?,
+                    CpuTaskType::RBBlockGenerated(block) => self.finish_generating_block(block)?,
+                    CpuTaskType::RBBlockValidated(from, block) => {
+                        self.finish_validating_block(from, block)?
+                    }
+                    CpuTaskType::IBBlockGenerated(ib) => self.finish_generating_ib(ib)?,
+                    CpuTaskType::IBHeaderValidated(from, ib, has_body) => {
+                        self.finish_validating_ib_header(from, ib, has_body)?
+                    }
+                    CpuTaskType::IBBlockValidated(from, ib) => {
+                        self.finish_validating_ib(from, ib)?
+                    }
+                    CpuTaskType::EBBlockGenerated(eb) => self.finish_generating_eb(eb)?,
+                    CpuTaskType::EBBlockValidated(from, eb) => {
+                        self.finish_validating_eb(from, eb)?
+                    }
+                    CpuTaskType::VTBundleGenerated(votes) => {
+                        self.finish_generating_vote_bundle(votes)?
+                    }
+                    CpuTaskType::VTBundleValidated(from, votes) => {
+                        self.finish_validating_vote_bundle(from, votes)?
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> NodeSnapshot {
+        NodeSnapshot {
+            id: self.id,
+            name: self.name.clone(),
+            mempool_txs: self.praos.mempool.len(),
+            mempool_bytes: self.praos.mempool.values().map(|tx| tx.bytes).sum(),
+            ibs: self
+                .leios
+                .ibs
+                .iter()
+                .filter(|(_, ib)| matches!(ib, InputBlockState::Received(_)))
+                .map(|(id, _)| *id)
+                .collect(),
+            ebs: self
+                .leios
+                .ebs
+                .iter()
+                .filter(|(_, eb)| matches!(eb, EndorserBlockState::Received { .. }))
+                .map(|(id, _)| *id)
+                .collect(),
+            tip: self.praos.tip,
+            chain_length: self.praos.chain.len(),
+        }
+    }
+
+    pub fn checkpoint(&self, w: &mut CheckpointWriter) {
+        self.events.save(w);
+        self.rng.save(w);
+        self.cpu_rng.save(w);
+        self.cpu.save(w);
+        self.txs.save(w);
+        self.ledger_states.save(w);
+        self.praos.save(w);
+        self.leios.save(w);
+    }
+
+    /// Replaces everything this node has done so far with the state saved in a checkpoint.
+    pub fn restore(&mut self, r: &mut CheckpointReader) -> Result<()> {
+        self.events = Persist::load(r)?;
+        self.rng = Persist::load(r)?;
+        self.cpu_rng = Persist::load(r)?;
+        self.cpu = Persist::load(r)?;
+        self.txs = Persist::load(r)?;
+        self.ledger_states = Persist::load(r)?;
+        self.praos = Persist::load(r)?;
+        self.leios = Persist::load(r)?;
+        Ok(())
+    }
+
+    pub fn handle_message(&mut self, from: NodeId, msg: SimulationMessage) -> Result<()> {
         match msg {
             // TX propagation
             SimulationMessage::AnnounceTx(id) => {
@@ -529,6 +834,16 @@ impl Node {
         if self.sim_config.emit_conformance_events && slot > 0 {
             self.tracker.track_slot(self.id, slot - 1);
         }
+        self.events.push(FutureEvent(
+            self.clock.now() + Duration::from_secs(1),
+            NodeEvent::NewSlot(slot + 1),
+        ));
+        self.prune_equivocations(slot);
+        if self.is_offline() {
+            // While this node is down, it can't produce anything.
+            return Ok(());
+        }
+
         if slot % self.sim_config.stage_length == 0 {
             // A new stage has begun.
 
@@ -550,14 +865,23 @@ impl Node {
 
         self.try_generate_praos_block(slot)?;
 
-        self.events.push(FutureEvent(
-            self.clock.now() + Duration::from_secs(1),
-            NodeEvent::NewSlot(slot + 1),
-        ));
-
         Ok(())
     }
 
+    fn prune_equivocations(&mut self, slot: u64) {
+        // Nobody will ask for a twin once its block is too old to be endorsed.
+        // IBs can still be referenced by EBs up to 4 pipelines after they were produced.
+        let max_eb_age = self.sim_config.max_eb_age;
+        let max_ib_age = max_eb_age + 4 * self.sim_config.stage_length;
+        let equivocations = &mut self.leios.equivocations;
+        equivocations
+            .ibs
+            .retain(|id, _| id.slot + max_ib_age >= slot);
+        equivocations
+            .ebs
+            .retain(|id, _| id.slot + max_eb_age >= slot);
+    }
+
     fn schedule_input_block_generation(&mut self, slot: u64) {
         if self.sim_config.variant == LeiosVariant::FullWithoutIbs {
             // In this variant, IB generation is completely disabled
@@ -566,9 +890,9 @@ impl Node {
         let mut slot_vrfs: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
         // IBs are generated at the start of any slot within this stage
         for stage_slot in slot..slot + self.sim_config.stage_length {
-            let vrfs: Vec<u64> = vrf_probabilities(self.sim_config.ib_generation_probability)
-                .filter_map(|p| self.run_vrf(p))
-                .collect();
+            let vrfs = self
+                .lottery
+                .wins(&mut self.rng, self.sim_config.ib_generation_probability);
             if !vrfs.is_empty() {
                 slot_vrfs.insert(stage_slot, vrfs);
             }
@@ -610,34 +934,57 @@ impl Node {
             // Don't generate EBs before that pipeline, because they would just be empty.
             return;
         }
-        for next_p in vrf_probabilities(self.sim_config.eb_generation_probability) {
-            if self.run_vrf(next_p).is_some() {
-                self.tracker.track_eb_lottery_won(EndorserBlockId {
-                    slot,
-                    pipeline,
-                    producer: self.id,
-                });
-                let txs = self.select_txs_for_eb(pipeline);
-                let ibs = self.select_ibs_for_eb(pipeline);
-                let ebs = self.select_ebs_for_eb(pipeline);
-                let bytes = self.sim_config.sizes.eb(txs.len(), ibs.len(), ebs.len());
-                let eb = EndorserBlock {
-                    slot,
-                    pipeline,
-                    producer: self.id,
-                    bytes,
-                    txs,
-                    ibs,
-                    ebs,
-                };
-                self.schedule_cpu_task(CpuTaskType::EBBlockGenerated(eb));
-                // A node should only generate at most 1 EB per slot
-                return;
+        // A node should only generate at most 1 EB per slot
+        if self
+            .lottery
+            .wins_any(&mut self.rng, self.sim_config.eb_generation_probability)
+            .is_none()
+        {
+            if self.sim_config.emit_conformance_events {
+                self.tracker.track_no_eb_generated(self.id, slot);
             }
+            return;
         }
-        if self.sim_config.emit_conformance_events {
-            self.tracker.track_no_eb_generated(self.id, slot);
+        self.tracker.track_eb_lottery_won(EndorserBlockId {
+            slot,
+            pipeline,
+            producer: self.id,
+        });
+        let txs = self.select_txs_for_eb(pipeline);
+        let ibs = self.select_ibs_for_eb(pipeline);
+        let ebs = self.select_ebs_for_eb(pipeline);
+        let bytes = self.sim_config.sizes.eb(txs.len(), ibs.len(), ebs.len());
+        let eb = EndorserBlock {
+            slot,
+            pipeline,
+            producer: self.id,
+            bytes,
+            txs,
+            ibs,
+            ebs,
+        };
+        // Our twin EB endorses only half of the IBs which the "real" one does.
+        // Without any IBs, it would be the same EB, so there's nothing to equivocate on.
+        if self.behaviours.equivocate_ebs && !eb.ibs.is_empty() {
+            let ibs = eb.ibs[..eb.ibs.len() / 2].to_vec();
+            let twin = EndorserBlock {
+                slot,
+                pipeline,
+                producer: self.id,
+                bytes: self
+                    .sim_config
+                    .sizes
+                    .eb(eb.txs.len(), ibs.len(), eb.ebs.len()),
+                txs: eb.txs.clone(),
+                ibs,
+                ebs: eb.ebs.clone(),
+            };
+            self.leios
+                .equivocations
+                .ebs
+                .insert(twin.id(), Arc::new(twin));
         }
+        self.schedule_cpu_task(CpuTaskType::EBBlockGenerated(eb));
     }
 
     fn schedule_endorser_block_votes(&mut self, slot: u64) {
@@ -647,9 +994,9 @@ impl Node {
             // Don't run the VT lottery before that pipeline, because there's nothing to vote on.
             return;
         }
-        let vrf_wins = vrf_probabilities(self.sim_config.vote_probability)
-            .filter_map(|f| self.run_vrf(f))
-            .count();
+        let vrf_wins = self
+            .lottery
+            .votes(&mut self.rng, self.sim_config.vote_probability);
         if vrf_wins == 0 {
             return;
         }
@@ -695,6 +1042,7 @@ impl Node {
             };
             match self.should_vote_for(eb) {
                 Ok(()) => true,
+                Err(_) if self.behaviours.vote_for_invalid_ebs => true,
                 Err(reason) => {
                     self.tracker.track_no_vote(
                         slot,
@@ -718,13 +1066,22 @@ impl Node {
                 pipeline: self.slot_to_pipeline(slot),
                 producer: self.id,
             },
-            bytes: self.sim_config.sizes.vote_bundle(ebs.len()),
+            bytes: self.vote_bundle_size(ebs.len()),
             ebs: ebs.into_iter().map(|eb| (eb, votes_allowed)).collect(),
         };
         self.schedule_cpu_task(CpuTaskType::VTBundleGenerated(votes));
         true
     }
 
+    fn vote_bundle_size(&self, ebs: usize) -> u64 {
+        let sizes = &self.sim_config.sizes;
+        if self.sim_config.fait_accompli.is_some() {
+            sizes.fait_accompli_vote_bundle(ebs, self.lottery.is_persistent_voter())
+        } else {
+            sizes.vote_bundle(ebs)
+        }
+    }
+
     fn generate_input_blocks(&mut self, slot: u64) {
         let Some(headers) = self.leios.ibs_to_generate.remove(&slot) else {
             if self.sim_config.emit_conformance_events {
@@ -736,6 +1093,18 @@ impl Node {
             self.tracker.track_ib_lottery_won(header.id);
             let rb_ref = self.latest_rb_ref();
             let transactions = self.select_txs_for_ib(header.shard, rb_ref);
+            if self.behaviours.equivocate_ibs {
+                // Our twin IB contains whatever TXs are left over after filling the "real" one.
+                // If nothing is left over for either, it would be the same IB, so there's nothing to equivocate on.
+                let twin_transactions = self.select_txs_for_ib(header.shard, rb_ref);
+                let ids = |txs: &[Arc<Transaction>]| txs.iter().map(|tx| tx.id).collect::<Vec<_>>();
+                if ids(&twin_transactions) != ids(&transactions) {
+                    self.leios
+                        .equivocations
+                        .ib_txs_to_generate
+                        .insert(header.id, twin_transactions);
+                }
+            }
             let ib = InputBlock {
                 header,
                 tx_payload_bytes: self.sim_config.sizes.ib_payload(&transactions),
@@ -748,7 +1117,10 @@ impl Node {
 
     fn try_generate_praos_block(&mut self, slot: u64) -> Result<()> {
         // L1 block generation
-        let Some(vrf) = self.run_vrf(self.sim_config.block_generation_probability) else {
+        let Some(vrf) = self
+            .lottery
+            .leader_check(&mut self.rng, self.sim_config.block_generation_probability)
+        else {
             return Ok(());
         };
 
@@ -768,12 +1140,18 @@ impl Node {
                 transactions.push(Arc::new(tx));
             } else {
                 let mut size = 0;
+                let mut execution_units = ExecutionUnits::default();
                 // Fill a block with as many pending transactions as can fit
                 while let Some((id, tx)) = self.praos.mempool.first_key_value() {
                     if size + tx.bytes > self.sim_config.max_block_size {
                         break;
                     }
+                    let new_execution_units = execution_units.saturating_add(tx.execution_units);
+                    if !new_execution_units.fits_within(self.sim_config.max_block_execution_units) {
+                        break;
+                    }
                     size += tx.bytes;
+                    execution_units = new_execution_units;
                     let id = *id;
                     transactions.push(self.praos.mempool.remove(&id).unwrap());
                     self.leios.mempool.remove(&id);
@@ -809,8 +1187,9 @@ impl Node {
         // (NB: all EBs produced in a pipeline are produced during the same slot)
         let forbidden_slots: HashSet<u64> = self
             .praos
-            .blocks
+            .chain
             .values()
+            .filter_map(|id| self.praos.blocks.get(id))
             .flat_map(|b| b.endorsement.iter())
             .map(|e| e.eb.slot)
             .collect();
@@ -844,7 +1223,7 @@ impl Node {
         };
 
         let votes = self.leios.votes_by_eb.get(&block)?.clone();
-        let size_bytes = self.sim_config.sizes.cert(votes.len());
+        let size_bytes = self.cert_size(&votes);
 
         Some(Endorsement {
             eb: block,
@@ -853,6 +1232,20 @@ impl Node {
         })
     }
 
+    fn cert_size(&self, votes: &BTreeMap<NodeId, usize>) -> u64 {
+        let sizes = &self.sim_config.sizes;
+        match &self.sim_config.fait_accompli {
+            None => sizes.cert(votes.len()),
+            Some(committee) => {
+                let nonpersistent_voters = votes
+                    .keys()
+                    .filter(|voter| !committee.persistent.contains(voter))
+                    .count();
+                sizes.fait_accompli_cert(committee.persistent.len(), nonpersistent_voters)
+            }
+        }
+    }
+
     fn choose_endorsed_block_from_pipeline(&self, pipeline: u64) -> Option<EndorserBlockId> {
         // an EB is eligible for endorsement if it has this many votes
         let vote_threshold = self.sim_config.vote_threshold;
@@ -883,6 +1276,24 @@ impl Node {
         Some(block)
     }
 
+    fn unfinalize_endorsed_ebs(&mut self, endorsement: &Endorsement) {
+        // The block which endorsed these EBs is no longer on our chain,
+        // so they're eligible to be endorsed again.
+        let mut eb_queue = vec![endorsement.eb];
+        while let Some(eb_id) = eb_queue.pop() {
+            let Some(EndorserBlockState::Received { eb, finalized }) =
+                self.leios.ebs.get_mut(&eb_id)
+            else {
+                continue;
+            };
+            if !*finalized {
+                continue;
+            }
+            *finalized = false;
+            eb_queue.extend(eb.ebs.iter().copied());
+        }
+    }
+
     fn remove_endorsed_txs_from_mempools(&mut self, endorsement: &Endorsement) {
         let mut eb_queue = vec![endorsement.eb];
         while let Some(eb_id) = eb_queue.pop() {
@@ -952,31 +1363,134 @@ impl Node {
     fn finish_generating_block(&mut self, block: Block) -> Result<()> {
         self.tracker.track_praos_block_generated(&block);
 
-        self.publish_block(Arc::new(block))
+        self.add_block(self.id, Arc::new(block))
     }
 
-    fn publish_block(&mut self, block: Arc<Block>) -> Result<()> {
-        // Remove TXs in these blocks from the mempools.
-        for tx in &block.transactions {
-            self.praos.mempool.remove(&tx.id);
-            self.leios.mempool.remove(&tx.id);
+    fn add_block(&mut self, from: NodeId, block: Arc<Block>) -> Result<()> {
+        self.praos.blocks_seen.insert(block.id);
+        if self.praos.blocks.contains_key(&block.id) {
+            return Ok(());
         }
-        if let Some(endorsement) = &block.endorsement {
-            self.remove_endorsed_txs_from_mempools(endorsement);
+        if let Some(parent) = block.parent {
+            if !self.praos.blocks.contains_key(&parent) {
+                // We can't judge this block until we have the chain it extends.
+                if self.praos.blocks_seen.insert(parent) {
+                    self.send_to(from, SimulationMessage::RequestBlock(parent))?;
+                }
+                self.praos
+                    .orphans
+                    .entry(parent)
+                    .or_default()
+                    .push((from, block));
+                return Ok(());
+            }
         }
-        for peer in &self.consumers {
-            if self
-                .praos
-                .peer_heads
-                .get(peer)
-                .is_none_or(|&s| s < block.id.slot)
+
+        // Connect this block, and any orphans which were waiting on it.
+        let mut best: Option<(NodeId, Arc<Block>)> = None;
+        let mut queue = vec![(from, block)];
+        while let Some((from, block)) = queue.pop() {
+            let height = block.parent.map_or(0, |parent| self.praos.heights[&parent]) + 1;
+            self.praos.heights.insert(block.id, height);
+            self.praos.blocks.insert(block.id, block.clone());
+            if let Some(children) = self.praos.orphans.remove(&block.id) {
+                queue.extend(children);
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(_, best)| self.is_preferred_over(&block, best))
             {
-                self.send_to(*peer, SimulationMessage::RollForward(block.id))?;
-                self.praos.peer_heads.insert(*peer, block.id.slot);
+                best = Some((from, block));
+            }
+        }
+
+        let Some((from, best)) = best else {
+            return Ok(());
+        };
+        let tip = self.praos.tip.map(|id| self.praos.blocks[&id].clone());
+        if tip.is_none_or(|tip| self.is_preferred_over(&best, &tip)) {
+            self.switch_chain(from, best.id)?;
+        }
+        Ok(())
+    }
+
+    /// Praos chain selection: longer chains win, and lower VRFs break ties.
+    fn is_preferred_over(&self, block: &Block, other: &Block) -> bool {
+        let height = self.praos.heights[&block.id];
+        let other_height = self.praos.heights[&other.id];
+        (height, Reverse(block.vrf)) > (other_height, Reverse(other.vrf))
+    }
+
+    fn switch_chain(&mut self, from: NodeId, new_tip: BlockId) -> Result<()> {
+        // Walk back from the new tip until we find a block on our current chain.
+        let mut new_blocks = vec![];
+        let mut fork_point = Some(new_tip);
+        while let Some(id) = fork_point {
+            if self.praos.chain.get(&id.slot) == Some(&id) {
+                break;
+            }
+            let block = self.praos.blocks[&id].clone();
+            fork_point = block.parent;
+            new_blocks.push(block);
+        }
+
+        // Anything on our chain after that point gets rolled back.
+        let rolled_back_ids = match fork_point {
+            Some(id) => self.praos.chain.split_off(&(id.slot + 1)),
+            None => std::mem::take(&mut self.praos.chain),
+        };
+        let rolled_back: Vec<Arc<Block>> = rolled_back_ids
+            .values()
+            .map(|id| self.praos.blocks[id].clone())
+            .collect();
+        for block in &rolled_back {
+            if let Some(endorsement) = &block.endorsement {
+                self.unfinalize_endorsed_ebs(endorsement);
+            }
+        }
+
+        for block in new_blocks.iter().rev() {
+            // Remove TXs in these blocks from the mempools.
+            for tx in &block.transactions {
+                self.praos.mempool.remove(&tx.id);
+                self.leios.mempool.remove(&tx.id);
+            }
+            if let Some(endorsement) = &block.endorsement {
+                self.remove_endorsed_txs_from_mempools(endorsement);
+            }
+            self.praos.chain.insert(block.id.slot, block.id);
+        }
+        let old_tip = self.praos.tip.replace(new_tip);
+
+        if let Some(old_tip) = old_tip.filter(|_| !rolled_back.is_empty()) {
+            self.tracker
+                .track_praos_rollback(self.id, old_tip, new_tip, &rolled_back);
+            if !matches!(self.sim_config.transactions, TransactionConfig::Mock(_)) {
+                // TXs from the abandoned fork can go back into the mempool,
+                // unless the new chain has already spent their inputs.
+                let ledger_state = self.resolve_ledger_state(Some(new_tip));
+                for tx in rolled_back.iter().flat_map(|b| b.transactions.iter()) {
+                    if ledger_state.spent_inputs.contains(&tx.input_id) {
+                        continue;
+                    }
+                    self.praos.mempool.insert(tx.id, tx.clone());
+                    self.leios.mempool.insert(
+                        tx.id,
+                        SeenTransaction {
+                            seen_at: self.clock.now(),
+                            tx: tx.clone(),
+                        },
+                    );
+                }
+            }
+        }
+
+        for peer in &self.consumers {
+            if *peer == from {
+                continue;
             }
+            self.send_to(*peer, SimulationMessage::RollForward(new_tip))?;
         }
-        self.praos.block_ids_by_slot.insert(block.id.slot, block.id);
-        self.praos.blocks.insert(block.id, block);
         Ok(())
     }
 
@@ -1005,7 +1519,11 @@ impl Node {
         self.schedule_cpu_task(CpuTaskType::TransactionValidated(from, tx));
     }
 
-    fn generate_tx(&mut self, tx: Arc<Transaction>) -> Result<()> {
+    pub fn generate_tx(&mut self, tx: Arc<Transaction>) -> Result<()> {
+        if self.is_offline() {
+            // Nobody can submit transactions to a node which is down.
+            return Ok(());
+        }
         self.tracker.track_transaction_generated(&tx, self.id);
         self.propagate_tx(self.id, tx)
     }
@@ -1083,25 +1601,7 @@ impl Node {
     }
 
     fn finish_validating_block(&mut self, from: NodeId, block: Arc<Block>) -> Result<()> {
-        if let Some(old_block_id) = self.praos.block_ids_by_slot.get(&block.id.slot) {
-            // SLOT BATTLE!!! lower VRF wins
-            if let Some(old_block) = self.praos.blocks.get(old_block_id) {
-                if old_block.vrf <= block.vrf {
-                    // We like our block better than this new one.
-                    return Ok(());
-                }
-                self.praos.blocks.remove(old_block_id);
-            }
-        }
-        self.praos.block_ids_by_slot.insert(block.id.slot, block.id);
-        self.praos.blocks.insert(block.id, block.clone());
-
-        let head = self.praos.peer_heads.entry(from).or_default();
-        if *head < block.id.slot {
-            *head = block.id.slot
-        }
-        self.publish_block(block)?;
-        Ok(())
+        self.add_block(from, block)
     }
 
     fn receive_announce_ib_header(&mut self, from: NodeId, id: InputBlockId) -> Result<()> {
@@ -1194,7 +1694,15 @@ impl Node {
     }
 
     fn receive_request_ib(&mut self, from: NodeId, id: InputBlockId) -> Result<()> {
+        if self.behaviours.withhold_ib_bodies && id.producer == self.id {
+            // We announced this IB, but have no intention of letting anyone see it.
+            return Ok(());
+        }
         if let Some(InputBlockState::Received(ib)) = self.leios.ibs.get(&id) {
+            let ib = match self.leios.equivocations.ibs.get(&id) {
+                Some(twin) if self.is_in_twin_half(from) => twin,
+                _ => ib,
+            };
             self.tracker.track_ib_sent(ib, self.id, from);
             self.send_to(from, SimulationMessage::IB(ib.clone()))?;
         }
@@ -1291,6 +1799,10 @@ impl Node {
 
     fn receive_request_eb(&mut self, from: NodeId, id: EndorserBlockId) -> Result<()> {
         if let Some(EndorserBlockState::Received { eb, .. }) = self.leios.ebs.get(&id) {
+            let eb = match self.leios.equivocations.ebs.get(&id) {
+                Some(twin) if self.is_in_twin_half(from) => twin,
+                _ => eb,
+            };
             self.tracker.track_eb_sent(eb, self.id, from);
             self.send_to(from, SimulationMessage::EB(eb.clone()))?;
         }
@@ -1375,6 +1887,13 @@ impl Node {
             return Ok(());
         }
         for (eb, count) in votes.ebs.iter() {
+            if let Some(EndorserBlockState::Received { eb, .. }) = self.leios.ebs.get(eb) {
+                if self.check_eb_references(eb).is_err() {
+                    // Honest nodes don't count votes for EBs which could never be valid.
+                    // We can't tell for EBs we haven't seen yet, so those votes still count.
+                    continue;
+                }
+            }
             let eb_votes = self
                 .leios
                 .votes_by_eb
@@ -1390,6 +1909,9 @@ impl Node {
                     .or_insert(self.clock.now());
             }
         }
+        if self.behaviours.refuse_vote_relay {
+            return Ok(());
+        }
         // We haven't seen these votes before, so propagate them to our neighbors
         for peer in &self.consumers {
             if *peer == from {
@@ -1423,6 +1945,7 @@ impl Node {
                         && !ledger_state.spent_inputs.contains(&seen.tx.input_id)
                 },
                 self.sim_config.max_ib_size,
+                self.sim_config.max_ib_execution_units,
             )
         }
     }
@@ -1442,13 +1965,19 @@ impl Node {
         self.select_txs(
             |seen| seen.seen_at <= max_seen_at,
             self.sim_config.max_eb_size,
+            self.sim_config.max_eb_execution_units,
         )
         .into_iter()
         .map(|tx| tx.id)
         .collect()
     }
 
-    fn select_txs<C>(&mut self, condition: C, max_size: u64) -> Vec<Arc<Transaction>>
+    fn select_txs<C>(
+        &mut self,
+        condition: C,
+        max_size: u64,
+        max_execution_units: ExecutionUnits,
+    ) -> Vec<Arc<Transaction>>
     where
         C: Fn(&SeenTransaction) -> bool,
     {
@@ -1458,7 +1987,12 @@ impl Node {
             .values()
             .filter_map(|seen| {
                 if condition(seen) {
-                    Some((seen.tx.id, seen.tx.bytes, seen.tx.input_id))
+                    Some((
+                        seen.tx.id,
+                        seen.tx.bytes,
+                        seen.tx.execution_units,
+                        seen.tx.input_id,
+                    ))
                 } else {
                     None
                 }
@@ -1472,24 +2006,30 @@ impl Node {
         }
         let mut txs = vec![];
         let mut size = 0;
+        let mut execution_units = ExecutionUnits::default();
         let mut spent_inputs = HashSet::new();
-        for (id, bytes, input_id) in candidate_txs {
+        for (id, bytes, tx_execution_units, input_id) in candidate_txs {
             let remaining_capacity = max_size - size;
             if remaining_capacity < bytes {
                 continue;
             }
+            let new_execution_units = execution_units.saturating_add(tx_execution_units);
+            if !new_execution_units.fits_within(max_execution_units) {
+                continue;
+            }
             if !spent_inputs.insert(input_id) {
                 continue;
             }
             let tx = self.leios.mempool.remove(&id).unwrap().tx;
             size += tx.bytes;
+            execution_units = new_execution_units;
             txs.push(tx);
         }
         txs
     }
 
     fn latest_rb_ref(&self) -> Option<BlockId> {
-        self.praos.blocks.last_key_value().map(|(k, _)| *k)
+        self.praos.tip
     }
 
     fn resolve_ledger_state(&mut self, rb_ref: Option<BlockId>) -> Arc<LedgerState> {
@@ -1500,23 +2040,26 @@ impl Node {
             return state.clone();
         };
 
-        let mut state = self
-            .ledger_states
-            .last_key_value()
-            .map(|(_, v)| v.as_ref().clone())
-            .unwrap_or_default();
-
-        let mut block_queue = vec![block_id];
-        while let Some(block_id) = block_queue.pop() {
-            if !state.seen_blocks.insert(block_id) {
-                continue;
+        // Start from the state of the closest ancestor which we've already resolved,
+        // and apply every block after it in chain order.
+        let mut blocks = vec![];
+        let mut state = LedgerState::default();
+        let mut cursor = Some(block_id);
+        while let Some(id) = cursor {
+            if let Some(ancestor_state) = self.ledger_states.get(&id) {
+                state = ancestor_state.as_ref().clone();
+                break;
             }
-            let Some(block) = self.praos.blocks.get(&block_id) else {
-                continue;
+            let Some(block) = self.praos.blocks.get(&id) else {
+                break;
             };
-            if let Some(parent) = block.parent {
-                block_queue.push(parent);
-            }
+            cursor = block.parent;
+            blocks.push(block.clone());
+        }
+
+        state.seen_blocks.insert(block_id);
+        for block in blocks.iter().rev() {
+            state.seen_blocks.insert(block.id);
             for tx in &block.transactions {
                 state.spent_inputs.insert(tx.input_id);
             }
@@ -1554,7 +2097,10 @@ impl Node {
         }
 
         let state = Arc::new(state);
-        self.ledger_states.insert(block_id, state.clone());
+        if self.praos.blocks.contains_key(&block_id) {
+            // Don't cache states for blocks we haven't seen yet; they'd be missing history.
+            self.ledger_states.insert(block_id, state.clone());
+        }
         state
     }
 
@@ -1565,6 +2111,15 @@ impl Node {
         self.tracker.track_ib_generated(&ib);
 
         let id = ib.header.id;
+        if let Some(transactions) = self.leios.equivocations.ib_txs_to_generate.remove(&id) {
+            let twin = InputBlock {
+                header: ib.header.clone(),
+                tx_payload_bytes: self.sim_config.sizes.ib_payload(&transactions),
+                transactions,
+                rb_ref: ib.rb_ref,
+            };
+            self.leios.equivocations.ibs.insert(id, Arc::new(twin));
+        }
         self.leios
             .ibs_by_pipeline
             .entry(ib.header.id.pipeline)
@@ -1661,10 +2216,35 @@ impl Node {
         Ok(())
     }
 
-    fn should_vote_for(&self, eb: &EndorserBlock) -> Result<(), NoVoteReason> {
-        let mut ib_set = HashSet::new();
+    /// Checks that an EB only references blocks from the pipelines it's allowed to.
+    fn check_eb_references(&self, eb: &EndorserBlock) -> Result<(), NoVoteReason> {
         let expected_ib_pipelines: HashSet<u64> =
             self.pipelines_for_ib_references(eb.pipeline).collect();
+        if eb
+            .ibs
+            .iter()
+            .any(|id| !expected_ib_pipelines.contains(&id.pipeline))
+        {
+            return Err(NoVoteReason::InvalidSlot);
+        }
+        let expected_eb_pipelines: HashSet<u64> = self
+            .pipelines_for_eb_references(eb.pipeline)
+            .into_iter()
+            .flatten()
+            .collect();
+        if eb
+            .ebs
+            .iter()
+            .any(|id| !expected_eb_pipelines.contains(&id.pipeline))
+        {
+            return Err(NoVoteReason::InvalidSlot);
+        }
+        Ok(())
+    }
+
+    fn should_vote_for(&self, eb: &EndorserBlock) -> Result<(), NoVoteReason> {
+        self.check_eb_references(eb)?;
+        let mut ib_set = HashSet::new();
 
         for tx in &eb.txs {
             if !matches!(self.txs.get(tx), Some(TransactionView::Received(_))) {
@@ -1676,9 +2256,6 @@ impl Node {
             let Some(InputBlockState::Received(ib)) = self.leios.ibs.get(ib_id) else {
                 return Err(NoVoteReason::MissingIB);
             };
-            if !expected_ib_pipelines.contains(&ib_id.pipeline) {
-                return Err(NoVoteReason::InvalidSlot);
-            }
             if matches!(self.sim_config.variant, LeiosVariant::FullWithTxReferences) {
                 for tx in &ib.transactions {
                     if !matches!(self.txs.get(&tx.id), Some(TransactionView::Received(_))) {
@@ -1752,17 +2329,6 @@ impl Node {
         Ok(())
     }
 
-    // Simulates the output of a VRF using this node's stake (if any).
-    fn run_vrf(&mut self, success_rate: f64) -> Option<u64> {
-        let target_vrf_stake = compute_target_vrf_stake(self.stake, self.total_stake, success_rate);
-        let result = self.rng.random_range(0..self.total_stake);
-        if result < target_vrf_stake {
-            Some(result)
-        } else {
-            None
-        }
-    }
-
     fn send_to(&self, to: NodeId, msg: SimulationMessage) -> Result<()> {
         if self.trace {
             trace!(
@@ -1776,17 +2342,535 @@ impl Node {
             .send_to(to, msg.bytes_size(), msg.protocol(), msg)
     }
 
+    fn is_offline(&self) -> bool {
+        self.sim_config
+            .scenario
+            .is_node_offline(self.id, self.clock.now())
+    }
+
     fn slot_to_pipeline(&self, slot: u64) -> u64 {
         slot / self.sim_config.stage_length
     }
+
+    // When equivocating, we send our "twin" blocks to every other peer.
+    fn is_in_twin_half(&self, peer: NodeId) -> bool {
+        self.consumers
+            .iter()
+            .position(|p| *p == peer)
+            .is_some_and(|index| index % 2 == 1)
+    }
+}
+
+/// How long it takes to run scripts which use this many execution steps.
+fn script_cpu_time(cpu_times: &CpuTimeConfig, rng: &mut ChaChaRng, steps: u64) -> Duration {
+    if steps == 0 {
+        return Duration::ZERO;
+    }
+    let per_million_steps = cpu_times.tx_validation_per_million_steps.sample(rng);
+    duration_ms(per_million_steps.as_secs_f64() * 1000.0 * (steps as f64 / 1_000_000.0))
 }
 
-fn compute_target_vrf_stake(stake: u64, total_stake: u64, success_rate: f64) -> u64 {
-    let ratio = stake as f64 / total_stake as f64;
-    (total_stake as f64 * ratio * success_rate) as u64
+/// How long it takes to do something which takes `per_unit` this many times.
+/// Like any other CPU time, it can't take longer than the simulation could run for.
+fn per_unit_cpu_time(per_unit: Duration, units: u64) -> Duration {
+    u32::try_from(units)
+        .ok()
+        .and_then(|units| per_unit.checked_mul(units))
+        .unwrap_or_else(|| duration_ms(per_unit.as_secs_f64() * 1000.0 * units as f64))
+        .min(MAX_DURATION)
 }
 
-fn vrf_probabilities(probability: f64) -> impl Iterator<Item = f64> {
-    let final_success_rate = Some(probability.fract()).filter(|f| *f > 0.0);
-    std::iter::repeat_n(1.0, probability.trunc() as usize).chain(final_success_rate)
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use anyhow::Result;
+    use rand::SeedableRng as _;
+    use rand_chacha::ChaChaRng;
+    use tokio::sync::mpsc;
+
+    use super::{per_unit_cpu_time, EndorserBlockState, InputBlockState, Node, SeenTransaction};
+    use crate::{
+        clock::{ClockCoordinator, Timestamp},
+        config::{NodeId, SimConfiguration, MAX_DURATION},
+        events::{Event, EventBuffer, EventTracker},
+        model::{
+            Block, BlockId, Endorsement, EndorserBlock, EndorserBlockId, ExecutionUnits,
+            InputBlock, InputBlockHeader, InputBlockId, Transaction, TransactionId, VoteBundle,
+            VoteBundleId,
+        },
+        network::{Network, NetworkOutbox},
+        sim::{MiniProtocol, SimulationMessage},
+        testing,
+    };
+
+    /// Three fully-connected nodes, where node-0 holds all of the stake.
+    fn config(
+        behaviours: &str,
+        configure: impl FnOnce(&mut SimConfiguration),
+    ) -> Result<Arc<SimConfiguration>> {
+        let mut config = testing::config(testing::mesh(3, behaviours)?, |_| {})?;
+        configure(&mut config);
+        Ok(Arc::new(config))
+    }
+
+    /// A node which runs outside of any simulation, handling messages one at a time.
+    struct TestNode {
+        node: Node,
+        clock: ClockCoordinator,
+        outbox: NetworkOutbox<MiniProtocol, SimulationMessage>,
+        events: EventBuffer,
+        _network: Network<MiniProtocol, SimulationMessage>,
+    }
+
+    impl TestNode {
+        fn new(config: &Arc<SimConfiguration>, id: usize) -> Self {
+            let clock = ClockCoordinator::new(config.timestamp_resolution);
+            let (_, checkpoint_source) = mpsc::unbounded_channel();
+            let mut network = Network::new(clock.clock().barrier(), 0, checkpoint_source);
+            let (msg_sink, msg_source, outbox) = network.open_outbox(NodeId::new(id));
+            let (tracker, events) =
+                EventTracker::without_sinks(clock.clock(), &config.nodes).buffered(clock.clock());
+            let total_stake = config.nodes.iter().map(|n| n.stake).sum();
+            let mut node = Node::new(
+                &config.nodes[id],
+                config.clone(),
+                total_stake,
+                msg_source,
+                msg_sink,
+                mpsc::unbounded_channel().1,
+                mpsc::unbounded_channel().1,
+                mpsc::unbounded_channel().1,
+                tracker,
+                ChaChaRng::seed_from_u64(0),
+                clock.clock().barrier(),
+            );
+            // Tests decide what happens in which slot.
+            node.events.clear();
+            Self {
+                node,
+                clock,
+                outbox,
+                events,
+                _network: network,
+            }
+        }
+
+        fn receive(&mut self, from: usize, msg: SimulationMessage) -> Result<()> {
+            self.node.handle_message(NodeId::new(from), msg)?;
+            self.finish_tasks()
+        }
+
+        fn finish_tasks(&mut self) -> Result<()> {
+            while let Some(time) = self.node.next_event_time() {
+                self.clock.set_time(time);
+                self.node.handle_due_events()?;
+            }
+            Ok(())
+        }
+
+        fn sent(&mut self) -> Vec<(NodeId, SimulationMessage)> {
+            let mut sent = vec![];
+            self.outbox.collect(self.clock.clock().now(), &mut sent);
+            sent.into_iter()
+                .map(|msg| {
+                    let (_, to, body) = msg.into_parts();
+                    (to, body)
+                })
+                .collect()
+        }
+    }
+
+    fn ib_id(slot: u64, pipeline: u64, producer: usize) -> InputBlockId {
+        InputBlockId {
+            slot,
+            pipeline,
+            producer: NodeId::new(producer),
+            index: 0,
+        }
+    }
+
+    fn eb_sent_to(sent: &[(NodeId, SimulationMessage)], peer: usize) -> Arc<EndorserBlock> {
+        sent.iter()
+            .find_map(|(to, msg)| match msg {
+                SimulationMessage::EB(eb) if *to == NodeId::new(peer) => Some(eb.clone()),
+                _ => None,
+            })
+            .expect("EB was not sent")
+    }
+
+    fn ib_sent_to(sent: &[(NodeId, SimulationMessage)], peer: usize) -> Option<Arc<InputBlock>> {
+        sent.iter().find_map(|(to, msg)| match msg {
+            SimulationMessage::IB(ib) if *to == NodeId::new(peer) => Some(ib.clone()),
+            _ => None,
+        })
+    }
+
+    fn block(slot: u64, producer: usize, vrf: u64, parent: Option<&Block>) -> Block {
+        Block {
+            id: BlockId {
+                slot,
+                producer: NodeId::new(producer),
+            },
+            vrf,
+            parent: parent.map(|p| p.id),
+            header_bytes: 1024,
+            endorsement: None,
+            transactions: vec![],
+        }
+    }
+
+    fn tx(id: u64, input_id: u64) -> Arc<Transaction> {
+        Arc::new(Transaction {
+            id: TransactionId::new(id),
+            shard: 0,
+            bytes: 1024,
+            input_id,
+            overcollateralization_factor: 0,
+            execution_units: ExecutionUnits::default(),
+        })
+    }
+
+    /// The slots of the blocks which were rolled back, for every rollback.
+    fn rollbacks(node: &TestNode) -> Vec<Vec<u64>> {
+        node.events
+            .take()
+            .into_iter()
+            .filter_map(|(event, _)| match event {
+                Event::RBRolledBack { rolled_back, .. } => {
+                    Some(rolled_back.iter().map(|b| b.id.slot).collect())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_send_conflicting_ebs_when_equivocating() -> Result<()> {
+        let config = config("[{ behaviour: equivocate-ebs }]", |c| {
+            c.eb_generation_probability = 1.0
+        })?;
+        let mut node = TestNode::new(&config, 0);
+        let pipeline = 5;
+        node.node.leios.ibs_by_pipeline.insert(
+            pipeline,
+            vec![ib_id(100, pipeline, 1), ib_id(101, pipeline, 2)],
+        );
+        node.node
+            .generate_endorser_blocks((pipeline - 1) * config.stage_length);
+        node.finish_tasks()?;
+        let id = node.node.leios.ebs_by_pipeline[&pipeline][0];
+        node.sent();
+
+        node.receive(1, SimulationMessage::RequestEB(id))?;
+        node.receive(2, SimulationMessage::RequestEB(id))?;
+        let sent = node.sent();
+        let (eb, twin) = (eb_sent_to(&sent, 1), eb_sent_to(&sent, 2));
+        assert_eq!(eb.id(), twin.id());
+        assert_eq!(eb.ibs.len(), 2);
+        assert_eq!(twin.ibs.len(), 1);
+
+        // Without any IBs to leave out, the twin would be the same EB
+        let empty_pipeline = pipeline + 10;
+        node.node
+            .generate_endorser_blocks((empty_pipeline - 1) * config.stage_length);
+        node.finish_tasks()?;
+        assert_eq!(node.node.leios.ebs_by_pipeline[&empty_pipeline].len(), 1);
+        assert_eq!(node.node.leios.equivocations.ebs.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn should_send_conflicting_ibs_when_equivocating() -> Result<()> {
+        let config = config("[{ behaviour: equivocate-ibs }]", |_| {})?;
+        let mut node = TestNode::new(&config, 0);
+        // Only one of these fits in an IB, so each IB gets a different one.
+        for id in 0..2 {
+            let tx = Arc::new(Transaction {
+                id: TransactionId::new(id),
+                shard: 0,
+                bytes: config.max_ib_size * 2 / 3,
+                input_id: id,
+                overcollateralization_factor: 0,
+                execution_units: ExecutionUnits::default(),
+            });
+            node.node.leios.mempool.insert(
+                tx.id,
+                SeenTransaction {
+                    tx,
+                    seen_at: Timestamp::zero(),
+                },
+            );
+        }
+        let header = InputBlockHeader {
+            id: ib_id(100, 9, 0),
+            vrf: 0,
+            shard: 0,
+            timestamp: Timestamp::zero(),
+            bytes: config.sizes.ib_header,
+        };
+        node.node
+            .leios
+            .ibs_to_generate
+            .insert(100, vec![header.clone()]);
+        node.node.generate_input_blocks(100);
+        node.finish_tasks()?;
+        node.sent();
+
+        node.receive(1, SimulationMessage::RequestIB(header.id))?;
+        node.receive(2, SimulationMessage::RequestIB(header.id))?;
+        let sent = node.sent();
+        let ib = ib_sent_to(&sent, 1).expect("IB was not sent");
+        let twin = ib_sent_to(&sent, 2).expect("twin IB was not sent");
+        assert_eq!(ib.header.id, twin.header.id);
+        assert_eq!(ib.transactions.len(), 1);
+        assert_eq!(twin.transactions.len(), 1);
+        assert_ne!(ib.transactions[0].id, twin.transactions[0].id);
+
+        // Without any TXs to split between them, the twin would be the same IB
+        let mut node = TestNode::new(&config, 0);
+        node.node.leios.ibs_to_generate.insert(100, vec![header]);
+        node.node.generate_input_blocks(100);
+        node.finish_tasks()?;
+        assert!(node.node.leios.ibs.contains_key(&ib.header.id));
+        assert!(node.node.leios.equivocations.ibs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_never_serve_withheld_ibs() -> Result<()> {
+        let config = config("[{ behaviour: withhold-ib-bodies }]", |_| {})?;
+        let mut node = TestNode::new(&config, 0);
+        for producer in [0, 1] {
+            let ib = InputBlock {
+                header: InputBlockHeader {
+                    id: ib_id(100, 9, producer),
+                    vrf: 0,
+                    shard: 0,
+                    timestamp: Timestamp::zero(),
+                    bytes: config.sizes.ib_header,
+                },
+                tx_payload_bytes: 0,
+                transactions: vec![],
+                rb_ref: None,
+            };
+            node.node
+                .leios
+                .ibs
+                .insert(ib.header.id, InputBlockState::Received(Arc::new(ib)));
+        }
+
+        node.receive(1, SimulationMessage::RequestIB(ib_id(100, 9, 0)))?;
+        node.receive(2, SimulationMessage::RequestIB(ib_id(100, 9, 0)))?;
+        let sent = node.sent();
+        assert!(ib_sent_to(&sent, 1).is_none());
+        assert!(ib_sent_to(&sent, 2).is_none());
+
+        // IBs which other nodes produced are still relayed
+        node.receive(2, SimulationMessage::RequestIB(ib_id(100, 9, 1)))?;
+        assert!(ib_sent_to(&node.sent(), 2).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_count_votes_for_invalid_ebs() -> Result<()> {
+        let config = config("[{ behaviour: vote-for-invalid-ebs }]", |_| {})?;
+        let mut node = TestNode::new(&config, 1);
+        let eb = |producer: usize, ib_pipeline: u64| EndorserBlock {
+            slot: 100,
+            pipeline: 5,
+            producer: NodeId::new(producer),
+            bytes: config.sizes.eb(0, 1, 0),
+            txs: vec![],
+            ibs: vec![ib_id(80, ib_pipeline, producer)],
+            ebs: vec![],
+        };
+        let valid = Arc::new(eb(0, 5));
+        let invalid = Arc::new(eb(2, 50));
+        node.receive(0, SimulationMessage::EB(valid.clone()))?;
+        node.receive(2, SimulationMessage::EB(invalid.clone()))?;
+        assert!(matches!(
+            node.node.leios.ebs.get(&invalid.id()),
+            Some(EndorserBlockState::Received { .. })
+        ));
+
+        let votes = VoteBundle {
+            id: VoteBundleId {
+                slot: 120,
+                pipeline: 6,
+                producer: NodeId::new(0),
+            },
+            bytes: config.sizes.vote_bundle(2),
+            ebs: [(valid.id(), 1), (invalid.id(), 1)].into_iter().collect(),
+        };
+        node.receive(0, SimulationMessage::Votes(Arc::new(votes)))?;
+        assert!(node.node.leios.votes_by_eb.contains_key(&valid.id()));
+        assert!(!node.node.leios.votes_by_eb.contains_key(&invalid.id()));
+        Ok(())
+    }
+
+    #[test]
+    fn should_forget_equivocations_once_they_are_too_old() -> Result<()> {
+        let config = config("[{ behaviour: equivocate-ebs }]", |_| {})?;
+        let mut node = TestNode::new(&config, 0);
+        let id = EndorserBlockId {
+            slot: 100,
+            pipeline: 5,
+            producer: NodeId::new(0),
+        };
+        let twin = EndorserBlock {
+            slot: id.slot,
+            pipeline: id.pipeline,
+            producer: id.producer,
+            bytes: 0,
+            txs: vec![],
+            ibs: vec![],
+            ebs: vec![],
+        };
+        node.node.leios.equivocations.ebs.insert(id, Arc::new(twin));
+
+        node.node.prune_equivocations(100 + config.max_eb_age);
+        assert!(node.node.leios.equivocations.ebs.contains_key(&id));
+        node.node.prune_equivocations(101 + config.max_eb_age);
+        assert!(node.node.leios.equivocations.ebs.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_switch_to_a_longer_fork() -> Result<()> {
+        let config = config("[]", |_| {})?;
+        let mut node = TestNode::new(&config, 1);
+        let a1 = Block {
+            transactions: vec![tx(1, 7)],
+            ..block(1, 0, 10, None)
+        };
+        let a2 = block(2, 0, 10, Some(&a1));
+        let b1 = block(1, 2, 20, None);
+        let b2 = block(3, 2, 20, Some(&b1));
+        let b3 = block(4, 2, 20, Some(&b2));
+        for block in [&a1, &a2, &b1, &b2] {
+            node.receive(0, SimulationMessage::Block(Arc::new(block.clone())))?;
+        }
+        // The forks are the same length, and ours has the better VRF.
+        assert_eq!(node.node.praos.tip, Some(a2.id));
+        assert!(rollbacks(&node).is_empty());
+
+        node.receive(2, SimulationMessage::Block(Arc::new(b3.clone())))?;
+        assert_eq!(node.node.praos.tip, Some(b3.id));
+        let chain: Vec<BlockId> = node.node.praos.chain.values().copied().collect();
+        assert_eq!(chain, vec![b1.id, b2.id, b3.id]);
+        assert_eq!(rollbacks(&node), vec![vec![1, 2]]);
+
+        // The rolled back TX is unspent on the new chain, so it's back in the mempool.
+        assert!(node.node.praos.mempool.contains_key(&TransactionId::new(1)));
+        let old_state = node.node.resolve_ledger_state(Some(a2.id));
+        assert!(old_state.spent_inputs.contains(&7));
+        let new_state = node.node.resolve_ledger_state(Some(b3.id));
+        assert!(!new_state.spent_inputs.contains(&7));
+        Ok(())
+    }
+
+    #[test]
+    fn should_break_ties_with_the_lowest_vrf() -> Result<()> {
+        let config = config("[]", |_| {})?;
+        let mut node = TestNode::new(&config, 1);
+        let first = block(1, 0, 50, None);
+        let better = block(1, 2, 30, None);
+        let worse = block(2, 0, 40, None);
+        node.receive(0, SimulationMessage::Block(Arc::new(first.clone())))?;
+        assert_eq!(node.node.praos.tip, Some(first.id));
+
+        node.receive(2, SimulationMessage::Block(Arc::new(better.clone())))?;
+        assert_eq!(node.node.praos.tip, Some(better.id));
+        assert_eq!(rollbacks(&node), vec![vec![1]]);
+
+        node.receive(0, SimulationMessage::Block(Arc::new(worse)))?;
+        assert_eq!(node.node.praos.tip, Some(better.id));
+        assert!(rollbacks(&node).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_adopt_orphans_once_their_parent_arrives() -> Result<()> {
+        let config = config("[]", |_| {})?;
+        let mut node = TestNode::new(&config, 1);
+        let parent = block(1, 0, 10, None);
+        let child = block(2, 0, 10, Some(&parent));
+
+        node.receive(0, SimulationMessage::Block(Arc::new(child.clone())))?;
+        assert_eq!(node.node.praos.tip, None);
+        assert!(node.node.praos.orphans.contains_key(&parent.id));
+        let requested = node.sent().into_iter().any(|(to, msg)| {
+            to == NodeId::new(0)
+                && matches!(msg, SimulationMessage::RequestBlock(id) if id == parent.id)
+        });
+        assert!(requested);
+
+        node.receive(0, SimulationMessage::Block(Arc::new(parent.clone())))?;
+        assert_eq!(node.node.praos.tip, Some(child.id));
+        assert!(node.node.praos.orphans.is_empty());
+        assert_eq!(node.node.praos.heights[&child.id], 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_unfinalize_ebs_when_their_block_is_rolled_back() -> Result<()> {
+        let config = config("[]", |_| {})?;
+        let mut node = TestNode::new(&config, 1);
+        let eb = EndorserBlock {
+            slot: 0,
+            pipeline: 0,
+            producer: NodeId::new(0),
+            bytes: 0,
+            txs: vec![],
+            ibs: vec![],
+            ebs: vec![],
+        };
+        let eb_id = eb.id();
+        node.node.leios.ebs.insert(
+            eb_id,
+            EndorserBlockState::Received {
+                eb: Arc::new(eb),
+                finalized: false,
+            },
+        );
+        let is_finalized = |node: &TestNode| {
+            matches!(
+                node.node.leios.ebs[&eb_id],
+                EndorserBlockState::Received {
+                    finalized: true,
+                    ..
+                }
+            )
+        };
+
+        let endorsing = Block {
+            endorsement: Some(Endorsement {
+                eb: eb_id,
+                size_bytes: 0,
+                votes: Default::default(),
+            }),
+            ..block(1, 0, 10, None)
+        };
+        node.receive(0, SimulationMessage::Block(Arc::new(endorsing)))?;
+        assert!(is_finalized(&node));
+
+        let b1 = block(1, 2, 20, None);
+        let b2 = block(2, 2, 20, Some(&b1));
+        node.receive(2, SimulationMessage::Block(Arc::new(b1)))?;
+        node.receive(2, SimulationMessage::Block(Arc::new(b2.clone())))?;
+        assert_eq!(node.node.praos.tip, Some(b2.id));
+        assert_eq!(rollbacks(&node), vec![vec![1]]);
+        assert!(!is_finalized(&node));
+        Ok(())
+    }
+
+    #[test]
+    fn should_clamp_the_cpu_time_of_enormous_tasks() {
+        let per_unit = Duration::from_millis(2);
+        assert_eq!(per_unit_cpu_time(per_unit, 3), Duration::from_millis(6));
+        assert_eq!(per_unit_cpu_time(per_unit, u64::MAX), MAX_DURATION);
+        assert_eq!(per_unit_cpu_time(Duration::MAX, 2), MAX_DURATION);
+        assert_eq!(per_unit_cpu_time(Duration::ZERO, u64::MAX), Duration::ZERO);
+    }
 }
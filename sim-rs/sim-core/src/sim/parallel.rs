@@ -0,0 +1,293 @@
+use std::{mem, sync::Arc, thread, time::Duration};
+
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    checkpoint::CheckpointWriter,
+    clock::{ClockCoordinator, Timestamp},
+    config::{NodeId, SimConfiguration},
+    events::{EventBuffer, EventTracker},
+    model::Transaction,
+    network::{Network, NetworkOutbox, SentMessage},
+};
+
+use super::{
+    node::Node, slot::SlotWitness, tx::TransactionProducer, Actors, MiniProtocol, NodeSnapshot,
+    SimulationMessage,
+};
+
+/// What a node needs to run on a thread of its own: a clock which only it follows,
+/// and somewhere to leave what it sends and tracks until everything can be put in order.
+pub struct NodeContext {
+    pub clock: ClockCoordinator,
+    pub events: EventBuffer,
+    pub outbox: NetworkOutbox<MiniProtocol, SimulationMessage>,
+}
+
+enum Input {
+    Message(NodeId, SimulationMessage),
+    Transaction(Arc<Transaction>),
+}
+
+struct NodeRunner {
+    node: Node,
+    context: NodeContext,
+    // Everything which reaches this node during the current window, and when
+    inbox: Vec<(Timestamp, Input)>,
+    sent: Vec<SentMessage<MiniProtocol, SimulationMessage>>,
+}
+
+impl NodeRunner {
+    /// Handles everything this node has to do before `end`.
+    fn run_until(&mut self, end: Timestamp, resolution: Duration) -> Result<()> {
+        // The sort is stable, so inputs which arrive at the same time keep their order
+        self.inbox.sort_by_key(|(timestamp, _)| *timestamp);
+        let mut inbox = mem::take(&mut self.inbox).into_iter().peekable();
+        loop {
+            let next_input = inbox.peek().map(|(timestamp, _)| *timestamp);
+            let next_event = self
+                .node
+                .next_event_time()
+                .map(|timestamp| timestamp.with_resolution(resolution));
+            let Some(now) = next_input
+                .into_iter()
+                .chain(next_event)
+                .min()
+                .filter(|timestamp| *timestamp < end)
+            else {
+                break;
+            };
+            self.context.clock.set_time(now);
+            while let Some((_, input)) = inbox.next_if(|(timestamp, _)| *timestamp <= now) {
+                match input {
+                    Input::Message(from, msg) => self.node.handle_message(from, msg)?,
+                    Input::Transaction(tx) => self.node.generate_tx(tx)?,
+                }
+            }
+            self.node.handle_due_events()?;
+            self.context.outbox.collect(now, &mut self.sent);
+        }
+        self.inbox = inbox.collect();
+        Ok(())
+    }
+}
+
+/// Runs the simulation in windows no longer than the shortest link latency. Nothing a node sends
+/// during a window can reach another node before the window ends, so within a window,
+/// every node can run independently of the others.
+///
+/// Nodes are split between threads, and the network, transactions and slots are handled
+/// between windows. What nodes send and track is put in order by time and then by node,
+/// so the results don't depend on how many threads there are.
+pub struct ParallelEngine {
+    clock_coordinator: ClockCoordinator,
+    timestamp_resolution: Duration,
+    lookahead: Duration,
+    threads: usize,
+    tracker: EventTracker,
+    network: Network<MiniProtocol, SimulationMessage>,
+    tx_producer: TransactionProducer,
+    slot_witness: SlotWitness,
+    runners: Vec<NodeRunner>,
+    // Everything before this time has happened
+    done_until: Timestamp,
+    arrivals: Vec<(Timestamp, NodeId, NodeId, SimulationMessage)>,
+}
+
+impl ParallelEngine {
+    pub fn new(actors: Actors, config: &SimConfiguration) -> Self {
+        let Actors {
+            clock_coordinator,
+            network,
+            tx_producer,
+            slot_witness,
+            nodes,
+            tracker,
+            contexts,
+        } = actors;
+        let now = clock_coordinator.clock().now();
+        let runners = nodes
+            .into_iter()
+            .zip(contexts)
+            .map(|(node, mut context)| {
+                context.clock.set_time(now);
+                NodeRunner {
+                    node,
+                    context,
+                    inbox: vec![],
+                    sent: vec![],
+                }
+            })
+            .collect();
+        // Without any links, nodes never hear from each other, so any window is safe
+        let lookahead = config
+            .links
+            .iter()
+            .map(|link| link.latency)
+            .min()
+            .unwrap_or(Duration::from_secs(1));
+        let threads = config
+            .threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1);
+        Self {
+            clock_coordinator,
+            timestamp_resolution: config.timestamp_resolution,
+            lookahead,
+            threads,
+            tracker,
+            network,
+            tx_producer,
+            slot_witness,
+            runners,
+            done_until: now,
+            arrivals: vec![],
+        }
+    }
+
+    /// Handles everything scheduled up to `limit` (or forever, if it's None), then moves the clock to it.
+    /// If the token is cancelled, stops at the end of the current window.
+    pub async fn run(&mut self, limit: Option<Timestamp>, token: &CancellationToken) -> Result<()> {
+        let pacer = self.clock_coordinator.pacer();
+        let stop = limit.map(|limit| limit + Duration::from_nanos(1));
+        while let Some(next) = self.next_activity() {
+            let start = next.max(self.done_until);
+            if stop.is_some_and(|stop| start >= stop) {
+                break;
+            }
+            if token.is_cancelled() {
+                return Ok(());
+            }
+            if !pacer.wait_until(start, token).await {
+                return Ok(());
+            }
+            let end = start + self.lookahead;
+            let end = stop.map_or(end, |stop| end.min(stop));
+            self.run_window(start, end)?;
+        }
+        if let Some(limit) = limit.filter(|limit| *limit > self.clock_coordinator.clock().now()) {
+            self.clock_coordinator.set_time(limit);
+        }
+        Ok(())
+    }
+
+    // When anything next has something to do. Slots never stop, so there is always something.
+    fn next_activity(&self) -> Option<Timestamp> {
+        let nodes = self
+            .runners
+            .iter()
+            .filter_map(|runner| runner.node.next_event_time());
+        [
+            Some(self.slot_witness.next_slot_time()),
+            self.network.next_event_time(),
+            self.tx_producer.next_tx_time(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(nodes)
+        .min()
+    }
+
+    fn run_window(&mut self, start: Timestamp, end: Timestamp) -> Result<()> {
+        let resolution = self.timestamp_resolution;
+        self.clock_coordinator.set_time(start);
+
+        // Everything which arrives during this window was sent before it started
+        self.network
+            .collect_arrivals(start, end, resolution, &mut self.arrivals);
+        for (timestamp, from, to, msg) in self.arrivals.drain(..) {
+            let input = Input::Message(from, msg);
+            self.runners[to.to_inner()]
+                .inbox
+                .push((timestamp.with_resolution(resolution), input));
+        }
+        while let Some(timestamp) = self
+            .tx_producer
+            .next_tx_time()
+            .map(|timestamp| timestamp.with_resolution(resolution))
+            .filter(|timestamp| *timestamp < end)
+        {
+            let Some((node_id, tx)) = self.tx_producer.generate_tx() else {
+                break;
+            };
+            let input = Input::Transaction(tx);
+            self.runners[node_id.to_inner()]
+                .inbox
+                .push((timestamp, input));
+        }
+
+        let chunk_size = self.runners.len().div_ceil(self.threads).max(1);
+        if chunk_size >= self.runners.len() {
+            for runner in &mut self.runners {
+                runner.run_until(end, resolution)?;
+            }
+        } else {
+            thread::scope(|scope| -> Result<()> {
+                let workers: Vec<_> = self
+                    .runners
+                    .chunks_mut(chunk_size)
+                    .map(|runners| {
+                        scope.spawn(move || {
+                            runners
+                                .iter_mut()
+                                .try_for_each(|runner| runner.run_until(end, resolution))
+                        })
+                    })
+                    .collect();
+                for worker in workers {
+                    worker.join().expect("node thread panicked")?;
+                }
+                Ok(())
+            })?;
+        }
+
+        // The sorts are stable, so anything which happened at the same time stays in order of node
+        let mut sent: Vec<_> = self
+            .runners
+            .iter_mut()
+            .flat_map(|runner| runner.sent.drain(..))
+            .collect();
+        sent.sort_by_key(|message| message.time());
+        self.network.send_all(sent, end);
+
+        let mut events: Vec<_> = self
+            .runners
+            .iter()
+            .flat_map(|runner| runner.context.events.take())
+            .collect();
+        events.sort_by_key(|(_, time)| *time);
+        for (event, time) in events {
+            // A new slot is tracked before anything else which happens at the same time
+            self.start_slots_before(time + Duration::from_nanos(1));
+            self.tracker.send_at(event, time);
+        }
+        self.start_slots_before(end);
+
+        self.done_until = end;
+        Ok(())
+    }
+
+    fn start_slots_before(&mut self, end: Timestamp) {
+        while self.slot_witness.next_slot_time() < end {
+            self.clock_coordinator
+                .set_time(self.slot_witness.next_slot_time());
+            self.slot_witness.start_slot();
+        }
+    }
+
+    pub fn node_snapshot(&self, index: usize) -> Option<NodeSnapshot> {
+        self.runners.get(index).map(|runner| runner.node.snapshot())
+    }
+
+    /// Saves the state of every actor, in the same order as the actors would save it themselves.
+    /// The engine only ever stops between windows, when nothing is waiting in any node's inbox.
+    pub fn checkpoint(&self, w: &mut CheckpointWriter) {
+        self.network.checkpoint(w);
+        self.tx_producer.checkpoint(w);
+        self.slot_witness.checkpoint(w);
+        for runner in &self.runners {
+            runner.node.checkpoint(w);
+        }
+    }
+}
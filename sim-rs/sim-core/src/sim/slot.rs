@@ -1,37 +1,62 @@
-use std::time::Duration;
+use anyhow::Result;
+use tokio::{select, sync::mpsc};
 
 use crate::{
+    checkpoint::{CheckpointReader, CheckpointRequest, CheckpointWriter, Persist},
     clock::{ClockBarrier, Timestamp},
-    config::SimConfiguration,
     events::EventTracker,
 };
 
 pub struct SlotWitness {
     clock: ClockBarrier,
     tracker: EventTracker,
-    slots: Option<u64>,
+    next_slot: u64,
+    checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
 }
 
 impl SlotWitness {
-    pub fn new(clock: ClockBarrier, tracker: EventTracker, config: &SimConfiguration) -> Self {
+    pub fn new(
+        clock: ClockBarrier,
+        tracker: EventTracker,
+        checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
+    ) -> Self {
         Self {
             clock,
             tracker,
-            slots: config.slots,
+            next_slot: 0,
+            checkpoint_source,
         }
     }
 
+    pub fn restore(&mut self, r: &mut CheckpointReader) -> Result<()> {
+        self.next_slot = Persist::load(r)?;
+        Ok(())
+    }
+
+    pub fn checkpoint(&self, w: &mut CheckpointWriter) {
+        self.next_slot.save(w);
+    }
+
+    pub fn next_slot_time(&self) -> Timestamp {
+        Timestamp::from_secs(self.next_slot)
+    }
+
+    pub fn start_slot(&mut self) {
+        self.tracker.track_global_slot(self.next_slot);
+        self.next_slot += 1;
+    }
+
     pub async fn run(&mut self) {
-        let mut slot = 0;
-        let mut next_slot_at = Timestamp::zero();
         loop {
-            if self.slots == Some(slot) {
-                return;
+            select! {
+                () = self.clock.wait_until(Timestamp::from_secs(self.next_slot)) => {
+                    self.start_slot();
+                }
+                Some((mut writer, reply)) = self.checkpoint_source.recv() => {
+                    self.checkpoint(&mut writer);
+                    let _ = reply.send(writer);
+                }
             }
-            self.tracker.track_global_slot(slot);
-            slot += 1;
-            next_slot_at += Duration::from_secs(1);
-            self.clock.wait_until(next_slot_at).await;
         }
     }
 }
@@ -1,28 +1,47 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rand::Rng;
 use rand_chacha::ChaChaRng;
 use rand_distr::Distribution;
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::sync::mpsc;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{select, sync::mpsc};
 
 use crate::{
+    checkpoint::{CheckpointReader, CheckpointRequest, CheckpointWriter, Persist},
     clock::{ClockBarrier, Timestamp},
-    config::{NodeId, RealTransactionConfig, SimConfiguration, TransactionConfig},
-    model::{Transaction, TransactionId},
+    config::{
+        duration_ms, NodeId, RealTransactionConfig, SimConfiguration, TransactionConfig,
+        MAX_DURATION,
+    },
+    model::{ExecutionUnits, Transaction, TransactionId},
 };
 
 struct NodeState {
     sink: mpsc::UnboundedSender<Arc<Transaction>>,
     tx_conflict_fraction: Option<f64>,
     tx_generation_weight: u64,
+    tx_generation_group: Option<String>,
 }
 
 pub struct TransactionProducer {
     rng: ChaChaRng,
     clock: ClockBarrier,
-    nodes: HashMap<NodeId, NodeState>,
+    nodes: BTreeMap<NodeId, NodeState>,
+    node_lookup: WeightedLookup<NodeId>,
+    /// When the load profile next changes the weights in `node_lookup`, if it ever does.
+    node_lookup_expires: Option<Timestamp>,
     ib_shards: u64,
     config: Option<RealTransactionConfig>,
+    next_tx_id: u64,
+    /// Which recorded transaction to replay next, when replaying a workload.
+    next_replayed_tx: usize,
+    /// When to generate the next transaction, if we are going to generate any more.
+    next_tx_at: Option<Timestamp>,
+    next_input_id: u64,
+    checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
 }
 
 impl TransactionProducer {
@@ -30,9 +49,10 @@ impl TransactionProducer {
         rng: ChaChaRng,
         clock: ClockBarrier,
         mut node_tx_sinks: HashMap<NodeId, mpsc::UnboundedSender<Arc<Transaction>>>,
+        checkpoint_source: mpsc::UnboundedReceiver<CheckpointRequest>,
         config: &SimConfiguration,
     ) -> Self {
-        let nodes = config
+        let nodes: BTreeMap<_, _> = config
             .nodes
             .iter()
             .map(|node| {
@@ -44,88 +64,267 @@ impl TransactionProducer {
                         tx_generation_weight: node
                             .tx_generation_weight
                             .unwrap_or(if node.stake > 0 { 0 } else { 1 }),
+                        tx_generation_group: node.tx_generation_group.clone(),
                     };
                 (node.id, state)
             })
             .collect();
-        Self {
+        let node_weights = nodes.iter().filter_map(|(id, node)| {
+            let weight = node.tx_generation_weight;
+            (weight != 0).then_some((*id, weight))
+        });
+        let node_lookup = WeightedLookup::new(node_weights);
+        let tx_config = match &config.transactions {
+            TransactionConfig::Real(config) => Some(config.as_ref().clone()),
+            _ => None,
+        };
+        let start_time = tx_config
+            .as_ref()
+            .map(|config| config.start_time.unwrap_or_default());
+        let first_replayed_tx = tx_config
+            .as_ref()
+            .and_then(|config| config.replay.as_ref())
+            .map(|replay| replay.txs.first().map(|tx| tx.time));
+        let node_lookup_expires = tx_config
+            .as_ref()
+            .filter(|config| config.load_profile.has_group_weights())
+            .map(|_| Timestamp::zero());
+        let mut producer = Self {
             rng,
             clock,
             nodes,
+            node_lookup,
+            node_lookup_expires,
             ib_shards: config.ib_shards,
-            config: match &config.transactions {
-                TransactionConfig::Real(config) => Some(config.clone()),
-                _ => None,
-            },
-        }
-    }
-
-    pub async fn run(&mut self) -> Result<()> {
-        let Some(config) = self.config.take() else {
-            self.clock.wait_forever().await;
-            return Ok(());
+            config: tx_config,
+            next_tx_id: 0,
+            next_replayed_tx: 0,
+            next_tx_at: None,
+            next_input_id: 0,
+            checkpoint_source,
         };
-        let mut next_tx_id = 0;
-        let mut next_tx_at = Timestamp::zero();
-        let mut next_input_id = 0;
-        let mut rng = &mut self.rng;
-
-        if let Some(start) = config.start_time {
-            self.clock.wait_until(start).await;
-            next_tx_at = start;
+        producer.next_tx_at = match first_replayed_tx {
+            Some(time) => time,
+            None => start_time.and_then(|start| producer.schedule_tx(start, false)),
         };
+        producer
+    }
 
-        let node_weights = self.nodes.iter().filter_map(|(id, node)| {
-            let weight = node.tx_generation_weight;
-            (weight != 0).then_some((*id, weight))
-        });
-        let node_lookup = WeightedLookup::new(node_weights);
+    /// Carries on generating transactions from where a checkpoint left off.
+    pub fn restore(&mut self, r: &mut CheckpointReader) -> Result<()> {
+        self.rng = Persist::load(r)?;
+        self.next_tx_id = Persist::load(r)?;
+        self.next_tx_at = Persist::load(r)?;
+        self.next_input_id = Persist::load(r)?;
+        let next_replayed_tx: Option<usize> = Persist::load(r)?;
+        let replay = self
+            .config
+            .as_ref()
+            .and_then(|config| config.replay.as_ref());
+        match (replay, next_replayed_tx) {
+            (Some(replay), Some(next)) => {
+                // The checkpoint only knows where it was in the replay, so make sure it's the same replay
+                let next_time = replay.txs.get(next).map(|tx| tx.time);
+                if next > replay.txs.len() || next_time != self.next_tx_at {
+                    bail!("the checkpoint was taken while replaying a different workload");
+                }
+                self.next_replayed_tx = next;
+            }
+            (Some(_), None) => {
+                bail!("the checkpoint was taken without a transaction replay, so it can't be resumed with one");
+            }
+            (None, Some(_)) => {
+                bail!("the checkpoint was taken while replaying transactions, so it can only be resumed with the same replay");
+            }
+            (None, None) => {}
+        }
+        // The node weights depend on where the load profile has got to
+        if let (Some(config), Some(now)) = (&self.config, self.next_tx_at) {
+            if config.load_profile.has_group_weights() {
+                let group_weights = config.load_profile.group_weights(now);
+                self.node_lookup = weighted_nodes(&self.nodes, &group_weights);
+                self.node_lookup_expires = config.load_profile.next_boundary(now);
+            }
+        }
+        Ok(())
+    }
 
+    pub fn checkpoint(&self, w: &mut CheckpointWriter) {
+        self.rng.save(w);
+        self.next_tx_id.save(w);
+        self.next_tx_at.save(w);
+        self.next_input_id.save(w);
+        let replaying = self.config.as_ref().is_some_and(|c| c.replay.is_some());
+        replaying.then_some(self.next_replayed_tx).save(w);
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
         loop {
-            let node_id = node_lookup.sample(rng).unwrap();
-            let node = self.nodes.get(node_id).unwrap();
-
-            let conflict_fraction = node
-                .tx_conflict_fraction
-                .unwrap_or(config.conflict_fraction);
-
-            let id = TransactionId::new(next_tx_id);
-            let shard = rng.random_range(0..self.ib_shards);
-            let bytes = (config.size_bytes.sample(&mut rng) as u64).min(config.max_size);
-            let input_id = if next_input_id > 0 && rng.random_bool(conflict_fraction) {
-                next_input_id - 1
-            } else {
-                let id = next_input_id;
-                next_input_id += 1;
-                id
+            let waiter = match self.next_tx_at {
+                Some(timestamp) => self.clock.wait_until(timestamp),
+                None => self.clock.wait_forever(),
             };
-            let overcollateralization_factor =
-                config.overcollateralization_factor.sample(&mut rng) as u64;
+            select! {
+                () = waiter => {
+                    if let Some((node_id, tx)) = self.generate_tx() {
+                        // Keep the clock where it is until the node has the transaction
+                        self.clock.start_task();
+                        self.nodes.get(&node_id).unwrap().sink.send(tx)?;
+                    }
+                }
+                Some((mut writer, reply)) = self.checkpoint_source.recv() => {
+                    self.checkpoint(&mut writer);
+                    let _ = reply.send(writer);
+                }
+            }
+        }
+    }
+
+    /// When the next transaction is due, if there will be any more.
+    pub fn next_tx_time(&self) -> Option<Timestamp> {
+        self.next_tx_at
+    }
 
+    /// Generates the transaction which is due, and picks the node which should receive it.
+    pub fn generate_tx(&mut self) -> Option<(NodeId, Arc<Transaction>)> {
+        let (Some(config), Some(now)) = (&self.config, self.next_tx_at) else {
+            return None;
+        };
+        if let Some(replay) = &config.replay {
+            // Every field of a replayed transaction comes from the recording, apart from an unrecorded shard
+            let Some(recorded) = replay.txs.get(self.next_replayed_tx) else {
+                self.next_tx_at = None;
+                return None;
+            };
             let tx = Transaction {
-                id,
-                shard,
-                bytes,
-                input_id,
-                overcollateralization_factor,
+                id: TransactionId::new(self.next_tx_id),
+                shard: recorded
+                    .shard
+                    .unwrap_or_else(|| self.rng.random_range(0..self.ib_shards)),
+                bytes: recorded.bytes,
+                input_id: recorded.input_id,
+                overcollateralization_factor: recorded.overcollateralization_factor,
+                execution_units: recorded.execution_units,
             };
+            self.next_tx_id += 1;
+            self.next_replayed_tx += 1;
+            self.next_tx_at = replay.txs.get(self.next_replayed_tx).map(|tx| tx.time);
+            return Some((recorded.node, Arc::new(tx)));
+        }
+        let mut rng = &mut self.rng;
+
+        let node_id = *self.node_lookup.sample(rng).unwrap();
+        let node = self.nodes.get(&node_id).unwrap();
 
-            node.sink.send(Arc::new(tx))?;
+        let conflict_fraction = node
+            .tx_conflict_fraction
+            .unwrap_or(config.conflict_fraction);
+
+        let id = TransactionId::new(self.next_tx_id);
+        let shard = rng.random_range(0..self.ib_shards);
+        let bytes = (config.size_bytes.sample(&mut rng) as u64).min(config.max_size);
+        let input_id = if self.next_input_id > 0 && rng.random_bool(conflict_fraction) {
+            self.next_input_id - 1
+        } else {
+            let id = self.next_input_id;
+            self.next_input_id += 1;
+            id
+        };
+        let overcollateralization_factor =
+            config.overcollateralization_factor.sample(&mut rng) as u64;
+        // Only sample script budgets when they're configured, so that workloads without them are unchanged
+        let execution_units = ExecutionUnits {
+            steps: config
+                .execution_steps
+                .as_ref()
+                .map_or(0, |dist| dist.sample(&mut rng) as u64),
+            memory: config
+                .execution_memory
+                .as_ref()
+                .map_or(0, |dist| dist.sample(&mut rng) as u64),
+        };
+
+        let tx = Transaction {
+            id,
+            shard,
+            bytes,
+            input_id,
+            overcollateralization_factor,
+            execution_units,
+        };
 
-            next_tx_id += 1;
-            let millis_until_tx = config.frequency_ms.sample(&mut rng) as u64;
-            next_tx_at += Duration::from_millis(millis_until_tx);
+        self.next_tx_id += 1;
+        let stop_time = config.stop_time;
+        self.next_tx_at = self
+            .schedule_tx(now, true)
+            .filter(|next_tx_at| stop_time.is_none_or(|t| *next_tx_at <= t));
+        Some((node_id, Arc::new(tx)))
+    }
 
-            if config.stop_time.is_some_and(|t| next_tx_at > t) {
-                self.clock.wait_forever().await;
-                return Ok(());
-            } else {
-                self.clock.wait_until(next_tx_at).await;
+    /// Finds when the next transaction is due, starting from `from` (after a random delay if `wait` is set).
+    /// The delay never crosses a point where the load profile changes. Instead, we start again from that point,
+    /// so that bursts and quiet periods begin on time. Between those points, the rate can still vary, so
+    /// candidate transactions come at the highest rate it reaches, and each is kept in proportion to the rate
+    /// when it comes.
+    fn schedule_tx(&mut self, from: Timestamp, wait: bool) -> Option<Timestamp> {
+        let config = self.config.as_ref()?;
+        let profile = &config.load_profile;
+        let mut at = from;
+        loop {
+            let boundary = profile.next_boundary(at);
+            if self.node_lookup_expires.is_some_and(|t| at >= t) {
+                self.node_lookup = weighted_nodes(&self.nodes, &profile.group_weights(at));
+                self.node_lookup_expires = boundary;
+            }
+            let max_rate = profile.max_rate_multiplier(at);
+            if max_rate > 0.0 && !self.node_lookup.is_empty() {
+                if !wait && profile.rate_multiplier(at) > 0.0 {
+                    return Some(at);
+                }
+                let mut next = at;
+                loop {
+                    let millis_until_tx = config.frequency_ms.sample(&mut self.rng);
+                    next += if max_rate == 1.0 {
+                        Duration::from_millis(millis_until_tx as u64).min(MAX_DURATION)
+                    } else {
+                        // Short gaps would lose too much to rounding once they're scaled down.
+                        duration_ms(millis_until_tx / max_rate)
+                    };
+                    if boundary.is_some_and(|b| next >= b) {
+                        break;
+                    }
+                    let rate = profile.rate_multiplier(next);
+                    if rate >= max_rate || self.rng.random_bool((rate / max_rate).max(0.0)) {
+                        return Some(next);
+                    }
+                }
             }
+            at = boundary?;
         }
     }
 }
 
+// Group weights can be fractional, so node weights are scaled up to keep them precise.
+const GROUP_WEIGHT_SCALE: f64 = 1000.0;
+
+fn weighted_nodes(
+    nodes: &BTreeMap<NodeId, NodeState>,
+    group_weights: &BTreeMap<&str, f64>,
+) -> WeightedLookup<NodeId> {
+    let node_weights = nodes.iter().filter_map(|(id, node)| {
+        let multiplier = node
+            .tx_generation_group
+            .as_deref()
+            .and_then(|group| group_weights.get(group))
+            .copied()
+            .unwrap_or(1.0);
+        let weight =
+            (node.tx_generation_weight as f64 * multiplier * GROUP_WEIGHT_SCALE).round() as u64;
+        (weight != 0).then_some((*id, weight))
+    });
+    WeightedLookup::new(node_weights)
+}
+
 struct WeightedLookup<T> {
     elements: Vec<(T, u64)>,
     total_weight: u64,
@@ -150,6 +349,10 @@ impl<T> WeightedLookup<T> {
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.total_weight == 0
+    }
+
     pub fn sample<R: Rng>(&self, rng: &mut R) -> Option<&T> {
         let choice = rng.random_range(0..self.total_weight);
         match self
@@ -161,3 +364,189 @@ impl<T> WeightedLookup<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use rand::SeedableRng as _;
+    use rand_chacha::ChaChaRng;
+    use tokio::sync::mpsc;
+
+    use super::TransactionProducer;
+    use crate::{
+        checkpoint::{Checkpoint, CheckpointWriter},
+        clock::{ClockCoordinator, Timestamp},
+        config::{NodeId, RawParameters, RawReplayedTransaction, SimConfiguration, TxReplay},
+        model::TransactionId,
+        testing,
+    };
+
+    fn config(configure: impl FnOnce(&mut RawParameters)) -> Result<SimConfiguration> {
+        testing::config(testing::mesh(2, "[]")?, configure)
+    }
+
+    fn producer(config: &SimConfiguration) -> TransactionProducer {
+        let clock = ClockCoordinator::new(config.timestamp_resolution);
+        let sinks = config
+            .nodes
+            .iter()
+            .map(|node| (node.id, mpsc::unbounded_channel().0))
+            .collect::<HashMap<_, _>>();
+        let (_, checkpoint_source) = mpsc::unbounded_channel();
+        TransactionProducer::new(
+            ChaChaRng::seed_from_u64(0),
+            clock.clock().barrier(),
+            sinks,
+            checkpoint_source,
+            config,
+        )
+    }
+
+    // A config which replays one transaction at each of the given times, from node-1.
+    fn replaying(times: &[f64]) -> Result<SimConfiguration> {
+        let mut config = config(|_| {})?;
+        let txs = times
+            .iter()
+            .map(|&time_s| RawReplayedTransaction {
+                time_s,
+                node: "node-1".to_string(),
+                size_bytes: 1000,
+                input_id: None,
+                overcollateralization_factor: None,
+                shard: None,
+                execution_steps: None,
+                execution_memory: None,
+            })
+            .collect();
+        let replay = TxReplay::build(txs, &config)?;
+        config.replay_transactions(replay)?;
+        Ok(config)
+    }
+
+    fn checkpoint(producer: &TransactionProducer) -> Checkpoint {
+        let mut writer = CheckpointWriter::new();
+        producer.checkpoint(&mut writer);
+        Checkpoint::new(Timestamp::zero(), 0, writer)
+    }
+
+    // Generates every transaction due before `until`, and returns when each was generated.
+    fn generate_until(producer: &mut TransactionProducer, until: Timestamp) -> Vec<Timestamp> {
+        let mut times = vec![];
+        while let Some(time) = producer.next_tx_time().filter(|t| *t < until) {
+            producer.generate_tx().unwrap();
+            times.push(time);
+        }
+        times
+    }
+
+    #[test]
+    fn should_follow_the_rate_within_a_segment() -> Result<()> {
+        let config = config(|params| {
+            // 10 transactions a second, on average
+            params.tx_generation_distribution =
+                serde_yaml::from_str("{ distribution: exp, lambda: 10, scale: 1000 }").unwrap();
+            params.tx_load_profile = Some(
+                serde_yaml::from_str(
+                    r#"
+                    - stop-time: 100
+                      rate: { shape: linear, from: 0, to: 2 }
+                    - start-time: 100
+                      stop-time: 200
+                      rate: { shape: constant, multiplier: 0.5 }
+                    "#,
+                )
+                .unwrap(),
+            );
+        })?;
+        let mut producer = producer(&config);
+        let times = generate_until(&mut producer, Timestamp::from_secs(200));
+        let count = |from: u64, to: u64| {
+            let (from, to) = (Timestamp::from_secs(from), Timestamp::from_secs(to));
+            times.iter().filter(|t| **t >= from && **t < to).count() as f64
+        };
+        // The rate ramps up through the first segment, so three times as many come in its second half
+        let close = |actual: f64, expected: f64| (actual - expected).abs() < expected * 0.15;
+        assert!(close(count(0, 50), 250.0), "{}", count(0, 50));
+        assert!(close(count(50, 100), 750.0), "{}", count(50, 100));
+        assert!(close(count(100, 200), 500.0), "{}", count(100, 200));
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_round_short_gaps_in_a_burst() -> Result<()> {
+        let config = config(|params| {
+            // 85 transactions a second, about 12ms apart
+            params.tx_generation_distribution =
+                serde_yaml::from_str("{ distribution: exp, lambda: 85, scale: 1000 }").unwrap();
+            params.tx_load_profile = Some(
+                serde_yaml::from_str(
+                    r#"
+                    - stop-time: 10
+                      rate: { shape: constant, multiplier: 10 }
+                    "#,
+                )
+                .unwrap(),
+            );
+        })?;
+        let mut producer = producer(&config);
+        let count = generate_until(&mut producer, Timestamp::from_secs(10)).len() as f64;
+        assert!((count - 8500.0).abs() < 8500.0 * 0.05, "{count}");
+        Ok(())
+    }
+
+    #[test]
+    fn should_replay_recorded_transactions() -> Result<()> {
+        let config = replaying(&[2.0, 1.0, 3.0])?;
+        let mut producer = producer(&config);
+        let mut txs = vec![];
+        while let Some(time) = producer.next_tx_time() {
+            let (node, tx) = producer.generate_tx().unwrap();
+            txs.push((time, node, tx.id, tx.bytes));
+        }
+        assert_eq!(txs.len(), 3);
+        for (index, (time, node, id, bytes)) in txs.into_iter().enumerate() {
+            assert_eq!(time, Timestamp::from_secs(index as u64 + 1));
+            assert_eq!(node, NodeId::new(1));
+            assert_eq!(id, TransactionId::new(index as u64));
+            assert_eq!(bytes, 1000);
+        }
+        assert!(producer.generate_tx().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn should_resume_a_replay_from_a_checkpoint() -> Result<()> {
+        let replay = replaying(&[1.0, 2.0, 3.0])?;
+        let mut original = producer(&replay);
+        generate_until(&mut original, Timestamp::from_secs(2));
+        let saved = checkpoint(&original);
+
+        let mut resumed = producer(&replay);
+        let mut reader = saved.reader();
+        resumed.restore(&mut reader)?;
+        reader.finish()?;
+        assert_eq!(
+            generate_until(&mut resumed, Timestamp::from_secs(10)),
+            vec![Timestamp::from_secs(2), Timestamp::from_secs(3)]
+        );
+
+        // The checkpoint can't be resumed with a different workload, or without one
+        let other = replaying(&[1.0, 2.5, 3.0])?;
+        assert!(producer(&other).restore(&mut saved.reader()).is_err());
+        let shorter = replaying(&[1.0])?;
+        assert!(producer(&shorter).restore(&mut saved.reader()).is_err());
+        let random = config(|_| {})?;
+        assert!(producer(&random).restore(&mut saved.reader()).is_err());
+
+        // Nor can a run which generated random transactions be resumed with a replay
+        let mut random_producer = producer(&random);
+        generate_until(&mut random_producer, Timestamp::from_secs(10));
+        let random_checkpoint = checkpoint(&random_producer);
+        assert!(producer(&replay)
+            .restore(&mut random_checkpoint.reader())
+            .is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+//! Configuration which tests across the crate build on.
+
+use anyhow::Result;
+
+use crate::config::{RawParameters, RawTopology, SimConfiguration, Topology};
+
+/// The parameters which the CLI starts from.
+pub(crate) fn default_params() -> Result<RawParameters> {
+    Ok(serde_yaml::from_str(include_str!(
+        "../../parameters/config.default.yaml"
+    ))?)
+}
+
+/// Fully-connected nodes, 1ms apart from each other, where node-0 holds all of the stake
+/// and behaves as `behaviours` (a YAML list) says.
+pub(crate) fn mesh(nodes: usize, behaviours: &str) -> Result<Topology> {
+    let mut yaml = String::from("nodes:\n");
+    for node in 0..nodes {
+        let producers: Vec<String> = (0..nodes)
+            .filter(|peer| *peer != node)
+            .map(|peer| format!("node-{peer}: {{ latency-ms: 1.0 }}"))
+            .collect();
+        yaml.push_str(&format!("  node-{node}:\n"));
+        if node == 0 {
+            yaml.push_str(&format!(
+                "    stake: 100\n    adversarial-behaviours: {behaviours}\n"
+            ));
+        }
+        yaml.push_str("    location: [0.0, 0.0]\n");
+        yaml.push_str(&format!("    producers: {{ {} }}\n", producers.join(", ")));
+    }
+    let raw_topology: RawTopology = serde_yaml::from_str(&yaml)?;
+    raw_topology.try_into()
+}
+
+/// The default parameters, changed as a test needs them, on the given topology.
+pub(crate) fn config(
+    topology: Topology,
+    configure: impl FnOnce(&mut RawParameters),
+) -> Result<SimConfiguration> {
+    let mut params = default_params()?;
+    configure(&mut params);
+    SimConfiguration::build(params, topology)
+}